@@ -1,6 +1,6 @@
 use async_trait::async_trait;
 use bitswap::Control as Bitswap_Control;
-use bitswap::{Bitswap, Block, BsBlockStore};
+use bitswap::{Bitswap, BitswapConfig, Block, BsBlockStore};
 use cid::Cid;
 use libp2p_rs::core::identity::Keypair;
 use libp2p_rs::core::transport::upgrade::TransportUpgrade;
@@ -108,7 +108,7 @@ fn setup_swarm(keys: Keypair, listen_addr: Multiaddr) -> (Swarm_Control, Bitswap
     let kad_ctrl = kad.control();
 
     // bitswap protocol
-    let bitswap = Bitswap::new(TestRepo, kad_ctrl.clone());
+    let bitswap = Bitswap::new(TestRepo, kad_ctrl.clone(), BitswapConfig::default());
     let bitswap_control = bitswap.control();
 
     // register kad and bitswap
@@ -0,0 +1,276 @@
+use cid::{Cid, Codec};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use libp2p_rs::core::PeerId;
+
+use crate::block::Block;
+use crate::ledger::Ledger;
+use crate::stat::Stats;
+
+/// The number of unsolicited blocks a peer may send within the ledger window before it gets
+/// banned.
+const MAX_UNSOLICITED_BLOCKS: usize = 32;
+
+/// The largest block accepted from other peers, matching the go-ipfs limit.
+const MAX_BLOCK_SIZE: usize = 2 * 1024 * 1024;
+
+/// How long a block is still expected after its want ended, as the peers we asked may already
+/// be sending it.
+const LATE_BLOCK_WINDOW: Duration = Duration::from_secs(60);
+
+/// How long a misbehaving peer stays banned.
+const BAN_DURATION: Duration = Duration::from_secs(10 * 60);
+
+/// What to do with a received block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Verdict {
+    /// The block is on our wantlist and passes the block policy.
+    Accept,
+    /// The block was wanted until recently, most likely another peer delivered it first.
+    Late,
+    /// The block was never wanted.
+    Unsolicited,
+    /// The block was wanted but does not pass the block policy.
+    Rejected,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Verdict::Accept => "accepted",
+            Verdict::Late => "want already ended",
+            Verdict::Unsolicited => "unsolicited",
+            Verdict::Rejected => "rejected by block policy",
+        };
+        fmt.write_str(s)
+    }
+}
+
+/// Checks the size and codec of a received block before it is stored.
+fn block_policy_allows(block: &Block) -> bool {
+    let codec_ok = matches!(
+        block.cid().codec(),
+        Codec::Raw | Codec::DagProtobuf | Codec::DagCBOR | Codec::DagJSON
+    );
+    codec_ok && block.data().len() <= MAX_BLOCK_SIZE
+}
+
+/// Decides which received blocks are stored, and bans the peers which keep sending us blocks
+/// we never asked for.
+///
+/// Only blocks on our wantlist are accepted, anything else would allow any connected peer to
+/// fill up our blockstore. Blocks whose want ended only recently are dropped without counting
+/// against the peer, as with several peers asked for the same block all but the first one
+/// are bound to arrive after the want was satisfied.
+#[derive(Debug, Default)]
+pub(crate) struct Admission {
+    /// The wants which ended recently, with the time they ended.
+    ended_wants: HashMap<Cid, Instant>,
+    /// The banned peers, with the time their ban expires.
+    banned: HashMap<PeerId, Instant>,
+}
+
+impl Admission {
+    /// Records that we no longer want the block, because it was received, cancelled or timed
+    /// out.
+    pub fn want_ended(&mut self, cid: &Cid, now: Instant) {
+        self.ended_wants.insert(cid.to_owned(), now);
+    }
+
+    /// Classifies a received block, `wanted` telling whether it is on our wantlist.
+    pub fn check(&self, block: &Block, wanted: bool, now: Instant) -> Verdict {
+        if wanted {
+            if block_policy_allows(block) {
+                Verdict::Accept
+            } else {
+                Verdict::Rejected
+            }
+        } else {
+            match self.ended_wants.get(block.cid()) {
+                Some(ended) if now.duration_since(*ended) < LATE_BLOCK_WINDOW => Verdict::Late,
+                _ => Verdict::Unsolicited,
+            }
+        }
+    }
+
+    /// Updates the statistics and the ledger of the peer which sent a block we are not going to
+    /// store. Returns true if the peer should be banned.
+    pub fn record_dropped(
+        verdict: Verdict,
+        block: &Block,
+        stats: &Stats,
+        ledger: Option<&mut Ledger>,
+        now: Instant,
+    ) -> bool {
+        let bytes = block.data().len() as u64;
+        match verdict {
+            Verdict::Accept => false,
+            Verdict::Late => {
+                stats.update_incoming_duplicate(bytes);
+                false
+            }
+            Verdict::Unsolicited | Verdict::Rejected => {
+                stats.update_incoming_unsolicited(bytes);
+                ledger
+                    .map(|l| l.record_unsolicited(now) > MAX_UNSOLICITED_BLOCKS)
+                    .unwrap_or(false)
+            }
+        }
+    }
+
+    /// Bans the peer for a while. Returns false if the peer was already banned.
+    pub fn ban(&mut self, peer_id: PeerId, now: Instant) -> bool {
+        self.banned
+            .insert(peer_id, now + BAN_DURATION)
+            .map(|until| until <= now)
+            .unwrap_or(true)
+    }
+
+    /// Returns true if the peer is banned at the moment.
+    pub fn is_banned(&self, peer_id: &PeerId, now: Instant) -> bool {
+        self.banned
+            .get(peer_id)
+            .map(|until| now < *until)
+            .unwrap_or(false)
+    }
+
+    /// Forgets the expired bans and the wants which ended long enough ago.
+    pub fn prune(&mut self, now: Instant) {
+        self.banned.retain(|_, until| now < *until);
+        self.ended_wants
+            .retain(|_, ended| now.duration_since(*ended) < LATE_BLOCK_WINDOW);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multihash::Sha2_256;
+    use std::sync::atomic::Ordering;
+
+    fn block(i: u8) -> Block {
+        let data = vec![i; 16];
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+        Block::new(data.into_boxed_slice(), cid)
+    }
+
+    #[test]
+    fn wanted_blocks_are_accepted() {
+        let admission = Admission::default();
+        assert_eq!(
+            admission.check(&block(0), true, Instant::now()),
+            Verdict::Accept
+        );
+    }
+
+    #[test]
+    fn blocks_failing_the_policy_are_rejected() {
+        let admission = Admission::default();
+        let now = Instant::now();
+
+        let data = vec![0u8; MAX_BLOCK_SIZE + 1];
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+        let oversized = Block::new(data.into_boxed_slice(), cid);
+        assert_eq!(admission.check(&oversized, true, now), Verdict::Rejected);
+
+        let data = vec![0u8; 16];
+        let cid = Cid::new_v1(Codec::GitRaw, Sha2_256::digest(&data));
+        let git = Block::new(data.into_boxed_slice(), cid);
+        assert_eq!(admission.check(&git, true, now), Verdict::Rejected);
+    }
+
+    #[test]
+    fn blocks_of_ended_wants_are_late() {
+        let mut admission = Admission::default();
+        let now = Instant::now();
+        let block = block(0);
+
+        assert_eq!(admission.check(&block, false, now), Verdict::Unsolicited);
+
+        admission.want_ended(block.cid(), now);
+        assert_eq!(admission.check(&block, false, now), Verdict::Late);
+
+        let later = now + LATE_BLOCK_WINDOW;
+        assert_eq!(admission.check(&block, false, later), Verdict::Unsolicited);
+        admission.prune(later);
+        assert!(admission.ended_wants.is_empty());
+    }
+
+    #[test]
+    fn dropped_blocks_update_stats() {
+        let stats = Stats::default();
+        let mut ledger = Ledger::new();
+        let now = Instant::now();
+        let block = block(0);
+
+        Admission::record_dropped(Verdict::Late, &block, &stats, Some(&mut ledger), now);
+        assert_eq!(stats.duplicate_blocks.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.duplicate_data.load(Ordering::Relaxed), 16);
+        assert_eq!(stats.unsolicited_blocks.load(Ordering::Relaxed), 0);
+
+        Admission::record_dropped(Verdict::Unsolicited, &block, &stats, Some(&mut ledger), now);
+        Admission::record_dropped(Verdict::Rejected, &block, &stats, None, now);
+        assert_eq!(stats.unsolicited_blocks.load(Ordering::Relaxed), 2);
+        assert_eq!(stats.unsolicited_data.load(Ordering::Relaxed), 32);
+        assert_eq!(stats.duplicate_blocks.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn late_blocks_never_ban() {
+        let stats = Stats::default();
+        let mut ledger = Ledger::new();
+        let now = Instant::now();
+
+        for _ in 0..MAX_UNSOLICITED_BLOCKS * 2 {
+            let ban =
+                Admission::record_dropped(Verdict::Late, &block(0), &stats, Some(&mut ledger), now);
+            assert!(!ban);
+        }
+    }
+
+    #[test]
+    fn ban_threshold() {
+        let stats = Stats::default();
+        let mut ledger = Ledger::new();
+        let now = Instant::now();
+
+        for _ in 0..MAX_UNSOLICITED_BLOCKS {
+            let ban = Admission::record_dropped(
+                Verdict::Unsolicited,
+                &block(0),
+                &stats,
+                Some(&mut ledger),
+                now,
+            );
+            assert!(!ban);
+        }
+        let ban = Admission::record_dropped(
+            Verdict::Unsolicited,
+            &block(0),
+            &stats,
+            Some(&mut ledger),
+            now,
+        );
+        assert!(ban);
+    }
+
+    #[test]
+    fn bans_expire() {
+        let mut admission = Admission::default();
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        assert!(admission.ban(peer, now));
+        assert!(!admission.ban(peer, now));
+        assert!(admission.is_banned(&peer, now));
+        assert!(!admission.is_banned(&PeerId::random(), now));
+
+        let later = now + BAN_DURATION;
+        assert!(!admission.is_banned(&peer, later));
+        assert!(admission.ban(peer, later));
+        admission.prune(later + BAN_DURATION);
+        assert!(admission.banned.is_empty());
+    }
+}
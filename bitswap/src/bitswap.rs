@@ -1,28 +1,42 @@
 use cid::Cid;
 use futures::channel::{mpsc, oneshot};
-use futures::StreamExt;
+use futures::future::{self, FutureExt};
+use futures::stream::{self, StreamExt};
 use futures::{select, SinkExt};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use libp2p_rs::core::PeerId;
 use libp2p_rs::runtime::task;
 use libp2p_rs::swarm::Control as SwarmControl;
 
+use crate::admission::{Admission, Verdict};
 use crate::block::Block;
+use crate::config::BitswapConfig;
 use crate::control::Control;
 use crate::error::BitswapError;
 use crate::ledger::{Ledger, Message, Priority};
 use crate::protocol::{send_message, Handler, ProtocolEvent};
+use crate::provider::{ProviderAction, ProviderQueryManager, PROVIDERS_PER_QUERY};
+use crate::serving::RateLimiter;
 use crate::stat::Stats;
+use crate::wants::{PendingWants, Rebroadcast};
 use crate::BsBlockStore;
 use libp2p_rs::core::routing::Routing;
 use libp2p_rs::swarm::protocol_handler::{IProtocolHandler, ProtocolImpl};
-const WANT_DEADLINE: Duration = Duration::from_secs(30);
+
+/// How often the main loop advances the timer driven work, like the provider searches. The
+/// want deadlines are not bound to the ticks.
+const TICK_INTERVAL: Duration = Duration::from_millis(500);
 
 pub(crate) enum ControlCommand {
-    WantBlock(Cid, oneshot::Sender<Result<Block>>),
+    WantBlock(
+        Cid,
+        Priority,
+        Option<Duration>,
+        oneshot::Sender<Result<Block>>,
+    ),
     HasBlock(Cid, oneshot::Sender<Result<()>>),
     CancelBlock(Cid, oneshot::Sender<Result<()>>),
     WantList(
@@ -55,18 +69,30 @@ pub struct Bitswap<TBlockStore, TRouting> {
     control_tx: mpsc::UnboundedSender<ControlCommand>,
     control_rx: mpsc::UnboundedReceiver<ControlCommand>,
 
-    want_deadline: Duration,
+    config: BitswapConfig,
+
+    /// Paces sending the wantlist to all connected peers.
+    rebroadcast: Rebroadcast,
 
     /// Wanted blocks
     ///
-    /// The oneshot::Sender is used to send the block back to the API users.
-    wanted_blocks: HashMap<Cid, Vec<oneshot::Sender<Block>>>,
+    /// The requests are answered when the block arrives, or failed when their deadline passes.
+    wanted_blocks: PendingWants,
 
     /// Ledger
     connected_peers: HashMap<PeerId, Ledger>,
 
     /// Statistics related to peers.
     stats: HashMap<PeerId, Arc<Stats>>,
+
+    /// Searches providers for the wants the connected peers cannot satisfy.
+    providers: ProviderQueryManager,
+
+    /// Decides which received blocks are stored and which peers are banned.
+    admission: Admission,
+
+    /// Limits the upload over all peers.
+    global_limiter: Option<RateLimiter>,
 }
 
 type Result<T> = std::result::Result<T, BitswapError>;
@@ -76,10 +102,14 @@ where
     TBlockStore: BsBlockStore,
     TRouting: Routing + Clone + 'static,
 {
-    pub fn new(blockstore: TBlockStore, routing: TRouting) -> Self {
+    pub fn new(blockstore: TBlockStore, routing: TRouting, config: BitswapConfig) -> Self {
         let (peer_tx, peer_rx) = mpsc::unbounded();
         let (incoming_tx, incoming_rx) = mpsc::unbounded();
         let (control_tx, control_rx) = mpsc::unbounded();
+        let global_limiter = config
+            .serving
+            .global_rate_limit
+            .map(|rate| RateLimiter::new(rate, Instant::now()));
         Bitswap {
             swarm: None,
             routing,
@@ -90,10 +120,14 @@ where
             incoming_rx,
             control_tx,
             control_rx,
-            want_deadline: WANT_DEADLINE,
+            rebroadcast: Rebroadcast::new(config.rebroadcast_interval, Instant::now()),
+            config,
             wanted_blocks: Default::default(),
             connected_peers: Default::default(),
             stats: Default::default(),
+            providers: Default::default(),
+            admission: Default::default(),
+            global_limiter,
         }
     }
 
@@ -104,8 +138,29 @@ where
 
     /// Message Process Loop.
     pub async fn process_loop(&mut self) -> Result<()> {
+        let mut ticker = stream::unfold((), |()| async {
+            task::sleep(TICK_INTERVAL).await;
+            Some(((), ()))
+        })
+        .boxed()
+        .fuse();
+
         loop {
+            // wake up right when the next want times out
+            let mut deadline = match self.wanted_blocks.next_deadline() {
+                Some(at) => task::sleep(at.saturating_duration_since(Instant::now()))
+                    .boxed()
+                    .fuse(),
+                None => future::pending::<()>().boxed().fuse(),
+            };
+
             select! {
+                _ = deadline => {
+                    self.expire_wants(Instant::now());
+                }
+                _ = ticker.next() => {
+                    self.handle_tick();
+                }
                 cmd = self.peer_rx.next() => {
                     self.handle_event(cmd);
                 }
@@ -121,6 +176,77 @@ where
         }
     }
 
+    fn handle_tick(&mut self) {
+        let now = Instant::now();
+
+        // the API users which went away are not waited for
+        let abandoned = self.wanted_blocks.drop_abandoned();
+        self.end_wants(abandoned, now);
+        self.expire_wants(now);
+        self.release_deferred_blocks();
+        self.admission.prune(now);
+
+        if self.rebroadcast.due(now) {
+            self.rebroadcast_want_list();
+        }
+
+        let connected = self.connected_peers.keys().copied().collect::<HashSet<_>>();
+        for action in self.providers.poll(now, &connected) {
+            match action {
+                ProviderAction::Dial(peers) => self.dial_providers(peers),
+                ProviderAction::FindProviders(cid) => self.find_providers(cid),
+            }
+        }
+    }
+
+    /// Fails the requests whose deadline has passed, dropping the wants nobody waits for anymore.
+    fn expire_wants(&mut self, now: Instant) {
+        let expired = self.wanted_blocks.expire(now);
+        self.end_wants(expired, now);
+    }
+
+    /// Cancels the wants no request waits for anymore.
+    fn end_wants(&mut self, cids: Vec<Cid>, now: Instant) {
+        if cids.is_empty() {
+            return;
+        }
+
+        for cid in cids {
+            log::debug!("want for {} ended", cid);
+            self.admission.want_ended(&cid, now);
+            self.providers.remove(&cid);
+            for (_peer_id, ledger) in self.connected_peers.iter_mut() {
+                ledger.cancel_block(&cid);
+            }
+        }
+        self.broadcast_messages();
+    }
+
+    fn find_providers(&mut self, cid: Cid) {
+        let mut routing = self.routing.clone();
+        let mut poster = self.peer_tx.clone();
+        task::spawn(async move {
+            let key = cid.to_bytes();
+            let r = routing.find_providers(key, PROVIDERS_PER_QUERY).await;
+            log::debug!("find_providers: {} got {:?}", cid, r);
+            let providers = r.unwrap_or_default();
+            let _ = poster.send(ProtocolEvent::Providers(cid, providers)).await;
+        });
+    }
+
+    fn dial_providers(&mut self, peers: Vec<PeerId>) {
+        for peer in peers {
+            // open a connection toward the providers, so that bitswap could be happy
+            // to fetch the wanted blocks
+            let mut swarm = self.swarm.clone().expect("swarm??");
+            let mut poster = self.peer_tx.clone();
+            task::spawn(async move {
+                let r = swarm.new_connection(peer).await;
+                let _ = poster.send(ProtocolEvent::Dialed(peer, r.is_ok())).await;
+            });
+        }
+    }
+
     fn send_message_to(&mut self, peer_id: PeerId, message: Message) {
         if let Some(peer_stats) = self.stats.get_mut(&peer_id) {
             peer_stats.update_outgoing(
@@ -137,8 +263,9 @@ where
     }
 
     fn broadcast_messages(&mut self) {
+        let max_message_size = self.config.max_message_size;
         for (peer_id, ledger) in &mut self.connected_peers {
-            if let Some(message) = ledger.send() {
+            for message in ledger.send(max_message_size) {
                 if let Some(peer_stats) = self.stats.get_mut(peer_id) {
                     peer_stats.update_outgoing(
                         message.num_of_blocks() as u64,
@@ -156,6 +283,42 @@ where
         }
     }
 
+    /// Sends the blocks wanted by the peer, as fast as the rate limits allow.
+    fn serve_blocks(&mut self, peer_id: PeerId, blocks: Vec<Block>) {
+        let ledger = if let Some(l) = self.connected_peers.get_mut(&peer_id) {
+            l
+        } else {
+            log::info!("got incoming message from {:?} without ledge", peer_id);
+            return;
+        };
+
+        let count = blocks.len();
+        let dropped = ledger.defer_blocks(blocks);
+        let deferred = ledger.release_deferred(&mut self.global_limiter, Instant::now());
+
+        // the deferred queue is in order, so whatever is left over is from the new blocks
+        let throttled = deferred.min(count - dropped) + dropped;
+        if throttled > 0 {
+            log::debug!("throttled {} block(s) for {:?}", throttled, peer_id);
+            if let Some(peer_stats) = self.stats.get(&peer_id) {
+                peer_stats.update_throttled(throttled as u64);
+            }
+        }
+
+        for message in ledger.send(self.config.max_message_size) {
+            self.send_message_to(peer_id, message);
+        }
+    }
+
+    /// Sends the deferred blocks to all peers, as the rate limits allow.
+    fn release_deferred_blocks(&mut self) {
+        let now = Instant::now();
+        for ledger in self.connected_peers.values_mut() {
+            ledger.release_deferred(&mut self.global_limiter, now);
+        }
+        self.broadcast_messages();
+    }
+
     fn handle_event(&mut self, evt: Option<ProtocolEvent>) {
         match evt {
             Some(ProtocolEvent::Blocks(peer, blocks)) => {
@@ -164,24 +327,20 @@ where
                     blocks.len(),
                     peer
                 );
-                let ledger = if let Some(l) = self.connected_peers.get_mut(&peer) {
-                    l
-                } else {
-                    log::info!("got incoming message from {:?} without ledge", peer);
-                    return;
-                };
-
-                //self.s
-                blocks.into_iter().for_each(|block| ledger.add_block(block));
-
-                if let Some(message) = ledger.send() {
-                    self.send_message_to(peer, message);
-                }
+                self.serve_blocks(peer, blocks);
             }
             Some(ProtocolEvent::NewPeer(p)) => {
                 log::debug!("{:?} connected", p);
+                if self.admission.is_banned(&p, Instant::now()) {
+                    log::debug!("{:?} is banned, disconnecting", p);
+                    self.disconnect(p);
+                    return;
+                }
                 // make a ledge for the peer and send wantlist to it
-                self.connected_peers.entry(p).or_default();
+                let peer_rate_limit = self.config.serving.peer_rate_limit;
+                self.connected_peers
+                    .entry(p)
+                    .or_insert_with(|| Ledger::with_rate_limit(peer_rate_limit));
                 self.stats.entry(p).or_default();
                 self.send_want_list(p);
             }
@@ -189,6 +348,15 @@ where
                 log::debug!("{:?} disconnected", p);
                 self.connected_peers.remove(&p);
             }
+            Some(ProtocolEvent::Providers(cid, providers)) => {
+                let to_dial = self
+                    .providers
+                    .query_finished(&cid, providers, Instant::now());
+                self.dial_providers(to_dial);
+            }
+            Some(ProtocolEvent::Dialed(p, success)) => {
+                self.providers.dial_finished(p, success);
+            }
             None => {}
         }
     }
@@ -214,6 +382,7 @@ where
         // Process the incoming cancel list.
         for cid in message.cancel() {
             ledger.received_want_list.remove(cid);
+            ledger.cancel_deferred(cid);
         }
 
         // block presences had added into bitswap proto when 2020.1
@@ -237,7 +406,16 @@ where
             to_check.push(cid.to_owned());
         }
 
-        if !to_check.is_empty() {
+        if !to_check.is_empty() && !self.config.serving.allows(&source) {
+            log::debug!(
+                "{:?} asking for {} block(s), refused by the serving policy",
+                source,
+                to_check.len()
+            );
+            if let Some(peer_stats) = self.stats.get(&source) {
+                peer_stats.update_refused_wants(to_check.len() as u64);
+            }
+        } else if !to_check.is_empty() {
             // ask blockstore for the wanted blocks
             log::debug!(
                 "{:?} asking for {} block(s), checking blockstore",
@@ -271,28 +449,61 @@ where
     fn handle_received_blocks(&mut self, source: PeerId, blocks: Vec<Block>) {
         log::debug!("received {} block(s) from {:?}", blocks.len(), source);
 
-        for block in &blocks {
+        let peer_stats = Arc::clone(&self.stats.get(&source).unwrap());
+        let mut accepted = Vec::with_capacity(blocks.len());
+        let mut misbehaving = false;
+        let now = Instant::now();
+
+        for block in blocks {
+            let wanted = self.wanted_blocks.contains(&block.cid);
+            let verdict = self.admission.check(&block, wanted, now);
+            if verdict != Verdict::Accept {
+                log::debug!(
+                    "dropping block {} from {:?}: {}",
+                    block.cid,
+                    source,
+                    verdict
+                );
+                misbehaving |= Admission::record_dropped(
+                    verdict,
+                    &block,
+                    &peer_stats,
+                    self.connected_peers.get_mut(&source),
+                    now,
+                );
+                continue;
+            }
+
             // publish block to all pending API users
-            let _ = self.wanted_blocks.remove(&block.cid).map(|txs| {
-                txs.into_iter().for_each(|tx| {
-                    // some tx may be dropped, regardless
-                    log::debug!("wake up API client with {:?} from {:?}", block.cid, source);
-                    let _ = tx.send(block.clone());
-                })
-            });
+            log::debug!("wake up API clients with {:?} from {:?}", block.cid, source);
+            self.admission.want_ended(&block.cid, now);
+            self.wanted_blocks.complete(&block);
 
             // cancel want
             for (_peer_id, ledger) in self.connected_peers.iter_mut() {
                 ledger.cancel_block(&block.cid);
             }
+            self.providers.remove(&block.cid);
+
+            accepted.push(block);
         }
 
-        // put all blocks onto blockstore
-        // note that 'blocks' are moved into the task
+        if misbehaving {
+            self.ban_peer(source);
+        }
+
+        if accepted.is_empty() {
+            return;
+        }
+
+        // the peer is likely to have the rest of the DAG as well
+        self.providers.useful_peer(source);
+
+        // put all accepted blocks onto blockstore
+        // note that 'accepted' are moved into the task
         let blockstore = self.blockstore.clone();
-        let peer_stats = Arc::clone(&self.stats.get(&source).unwrap());
         task::spawn(async move {
-            for block in blocks {
+            for block in accepted {
                 let bytes = block.data().len() as u64;
                 let res = blockstore.put(block).await;
                 match res {
@@ -310,10 +521,34 @@ where
         });
     }
 
+    /// Bans a peer which keeps sending us blocks we never asked for, closing the connections
+    /// toward it.
+    ///
+    /// Until the ban expires, banned peers are not given a ledger when they reconnect, so any
+    /// further messages from them are ignored.
+    fn ban_peer(&mut self, peer_id: PeerId) {
+        if !self.admission.ban(peer_id, Instant::now()) {
+            return;
+        }
+        log::info!(
+            "banning {:?} for sending too many unsolicited blocks",
+            peer_id
+        );
+        self.connected_peers.remove(&peer_id);
+        self.disconnect(peer_id);
+    }
+
+    fn disconnect(&self, peer_id: PeerId) {
+        let mut swarm = self.swarm.clone().expect("swarm??");
+        task::spawn(async move {
+            let _ = swarm.disconnect(peer_id).await;
+        });
+    }
+
     fn handle_control_command(&mut self, cmd: Option<ControlCommand>) -> Result<()> {
         match cmd {
-            Some(ControlCommand::WantBlock(cid, reply)) => {
-                self.want_block(cid, 1, reply);
+            Some(ControlCommand::WantBlock(cid, priority, deadline, reply)) => {
+                self.want_block(cid, priority, deadline, reply);
             }
             Some(ControlCommand::HasBlock(cid, reply)) => {
                 self.has_block(cid, reply);
@@ -346,55 +581,35 @@ where
         Ok(())
     }
 
-    /// Retrieves the wanted block.
+    /// Retrieves the wanted block, failing the request with a timeout once the `deadline` or the
+    /// configured default deadline has passed.
     ///
     /// A user request
     pub fn want_block(
         &mut self,
         cid: Cid,
         priority: Priority,
+        deadline: Option<Duration>,
         reply: oneshot::Sender<Result<Block>>,
     ) {
         log::debug!("bitswap want block {} ", cid);
 
-        // TODO: should run a dedicated peer manager for find_providers...
-        let mut routing = self.routing.clone();
-        let mut swarm = self.swarm.clone().expect("Swarm??");
-        let cid_clone = cid.clone();
-        task::spawn(async move {
-            let key = cid_clone.to_bytes();
-            let r = routing.find_providers(key, 2).await;
-            log::debug!("want_block find_providers: {} got {:?}", cid_clone, r);
-            if let Ok(peers) = r {
-                // open a connection toward the providers, so that bitswap could be happy
-                // to fetch the wanted blocks
-                for peer in peers {
-                    let _ = swarm.new_connection(peer).await;
-                }
-            }
-        });
+        if let Some(block) = Block::inlined(&cid) {
+            let _ = reply.send(Ok(block));
+            return;
+        }
+
+        // providers are searched for only if the connected peers cannot satisfy the want
+        self.providers.want(&cid, Instant::now());
 
         for (_peer_id, ledger) in self.connected_peers.iter_mut() {
             ledger.want_block(&cid, priority);
         }
-        let (tx, rx) = oneshot::channel();
-        self.wanted_blocks
-            .entry(cid)
-            .or_insert_with(Vec::new)
-            .push(tx);
+        let deadline = Instant::now() + deadline.unwrap_or(self.config.want_deadline);
+        self.wanted_blocks.add(cid, deadline, reply);
 
         // ask all known peers for the wanted block
         self.broadcast_messages();
-
-        let deadline = self.want_deadline;
-        task::spawn(async move {
-            let r = task::timeout(deadline, rx).await;
-            if let Ok(block) = r {
-                let _ = reply.send(block.map_err(BitswapError::Cancel));
-            } else {
-                let _ = reply.send(Err(BitswapError::Timeout));
-            }
-        });
     }
 
     /// Announces a new block.
@@ -407,7 +622,17 @@ where
         for (_peer_id, ledger) in self.connected_peers.iter_mut() {
             ledger.cancel_block(&cid);
         }
-        self.wanted_blocks.remove(&cid);
+        if self.wanted_blocks.remove(&cid) {
+            self.admission.want_ended(&cid, Instant::now());
+        }
+        self.providers.remove(&cid);
+
+        // there is no point attracting requests we are not going to serve, and nobody needs to
+        // request the inlined blocks
+        if self.config.serving.leech_only || Block::inlined(&cid).is_some() {
+            let _ = reply.send(Ok(()));
+            return;
+        }
 
         // announce via routing
         let mut routing = self.routing.clone();
@@ -428,7 +653,10 @@ where
         for (_peer_id, ledger) in self.connected_peers.iter_mut() {
             ledger.cancel_block(cid);
         }
-        self.wanted_blocks.remove(cid);
+        if self.wanted_blocks.remove(cid) {
+            self.admission.want_ended(cid, Instant::now());
+        }
+        self.providers.remove(cid);
         let _ = reply.send(Ok(()));
     }
 
@@ -439,10 +667,7 @@ where
 
     /// Returns the wantlist of the local node
     pub fn local_wantlist(&self) -> Vec<Cid> {
-        self.wanted_blocks
-            .iter()
-            .map(|(cid, _)| cid.clone())
-            .collect()
+        self.wanted_blocks.cids().cloned().collect()
     }
 
     /// Returns the connected peers.
@@ -452,31 +677,54 @@ where
 
     /// Returns the statistics of bitswap.
     pub fn stats(&self) -> Stats {
-        self.stats
+        let stats = self
+            .stats
             .values()
             .fold(Stats::default(), |acc, peer_stats| {
                 acc.add_assign(&peer_stats);
                 acc
-            })
+            });
+        stats.update_providers(
+            self.providers.queries_started(),
+            self.providers.dials_started(),
+        );
+        stats
+    }
+
+    /// Resends the wantlist to all connected peers.
+    fn rebroadcast_want_list(&mut self) {
+        if self.wanted_blocks.is_empty() {
+            return;
+        }
+        log::debug!(
+            "rebroadcasting {} want(s) to {} peer(s)",
+            self.wanted_blocks.len(),
+            self.connected_peers.len()
+        );
+        let peers = self.connected_peers.keys().copied().collect::<Vec<_>>();
+        for peer_id in peers {
+            self.send_want_list(peer_id);
+        }
     }
 
     /// Sends the wantlist to the peer.
     fn send_want_list(&mut self, peer_id: PeerId) {
         if !self.wanted_blocks.is_empty() {
-            // FIXME: this can produce too long a message
             // FIXME: we should shard these across all of our peers by some logic; also, peers may
             // have been discovered to provide some specific wantlist item
             let mut message = Message::default();
-            for cid in self.wanted_blocks.keys() {
+            for cid in self.wanted_blocks.cids() {
                 // TODO: set priority
                 message.want_block(cid, 1);
             }
 
-            // spwan a task to send the message
-            let swarm = self.swarm.clone().expect("swarm??");
-            task::spawn(async move {
-                let _ = send_message(swarm, peer_id, message).await;
-            });
+            for message in message.split(self.config.max_message_size) {
+                // spwan a task to send the message
+                let swarm = self.swarm.clone().expect("swarm??");
+                task::spawn(async move {
+                    let _ = send_message(swarm, peer_id, message).await;
+                });
+            }
         }
     }
 }
@@ -488,7 +736,11 @@ where
 {
     /// Get handler of floodsub, swarm will call "handle" func after muxer negotiate success.
     fn handler(&self) -> IProtocolHandler {
-        Box::new(Handler::new(self.incoming_tx.clone(), self.peer_tx.clone()))
+        Box::new(Handler::new(
+            self.incoming_tx.clone(),
+            self.peer_tx.clone(),
+            self.config.max_message_size,
+        ))
     }
 
     /// Start message process loop.
@@ -40,6 +40,16 @@ impl Block {
     pub fn into_vec(self) -> Vec<u8> {
         self.data.into()
     }
+
+    /// Returns the block inlined in an identity hashed [`Cid`], or `None` for any other [`Cid`].
+    /// Inlined blocks never need to be stored or exchanged.
+    pub fn inlined(cid: &Cid) -> Option<Self> {
+        if cid.hash().algorithm() == multihash::Code::Identity {
+            Some(Block::new(cid.hash().digest().into(), cid.to_owned()))
+        } else {
+            None
+        }
+    }
 }
 
 /// BlockStore TRait used by Bitswap.
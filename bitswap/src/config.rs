@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use crate::ServingPolicy;
+
+/// The default interval for resending the wantlist to all connected peers.
+const REBROADCAST_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The default time a wanted block is waited for.
+const WANT_DEADLINE: Duration = Duration::from_secs(30);
+
+/// The default maximum size of a message, the same limit go-ipfs enforces.
+const MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Bitswap configuration, given to [`crate::Bitswap::new`].
+#[derive(Clone, Debug)]
+pub struct BitswapConfig {
+    /// How often the whole wantlist is resent to all connected peers, so that lost messages or
+    /// peers which obtained the blocks later still get to serve them.
+    pub rebroadcast_interval: Duration,
+    /// How long a wanted block is waited for when the want was made without a deadline.
+    pub want_deadline: Duration,
+    /// The maximum size of a message in bytes. Outgoing messages are split to fit and larger
+    /// incoming messages are rejected.
+    pub max_message_size: usize,
+    /// Decides whom and how fast we serve blocks to.
+    pub serving: ServingPolicy,
+}
+
+impl Default for BitswapConfig {
+    fn default() -> Self {
+        BitswapConfig {
+            rebroadcast_interval: REBROADCAST_INTERVAL,
+            want_deadline: WANT_DEADLINE,
+            max_message_size: MAX_MESSAGE_SIZE,
+            serving: ServingPolicy::default(),
+        }
+    }
+}
+
+impl BitswapConfig {
+    /// Sets the interval of resending the wantlist to all connected peers.
+    pub fn with_rebroadcast_interval(mut self, interval: Duration) -> Self {
+        self.rebroadcast_interval = interval;
+        self
+    }
+
+    /// Sets the default deadline for the wants.
+    pub fn with_want_deadline(mut self, deadline: Duration) -> Self {
+        self.want_deadline = deadline;
+        self
+    }
+
+    /// Sets the maximum size of the messages sent and received.
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Sets the serving policy.
+    pub fn with_serving_policy(mut self, serving: ServingPolicy) -> Self {
+        self.serving = serving;
+        self
+    }
+}
@@ -1,6 +1,7 @@
 use cid::Cid;
 use futures::channel::{mpsc, oneshot};
 use futures::SinkExt;
+use std::time::Duration;
 
 use libp2p_rs::core::PeerId;
 
@@ -24,16 +25,31 @@ impl Control {
         self.0.close_channel();
     }
 
-    /// Retrieves the wanted block.
+    /// Retrieves the wanted block, waiting for it at most the configured default deadline.
     ///
     /// A user request
     pub async fn want_block(
         &mut self,
         cid: Cid,
-        _priority: Priority,
+        priority: Priority,
+    ) -> Result<Block, BitswapError> {
+        self.want_block_with_deadline(cid, priority, None).await
+    }
+
+    /// Retrieves the wanted block, waiting for it at most `deadline` or the configured default
+    /// deadline when `None`.
+    ///
+    /// A user request
+    pub async fn want_block_with_deadline(
+        &mut self,
+        cid: Cid,
+        priority: Priority,
+        deadline: Option<Duration>,
     ) -> Result<Block, BitswapError> {
         let (tx, rx) = oneshot::channel();
-        self.0.send(ControlCommand::WantBlock(cid, tx)).await?;
+        self.0
+            .send(ControlCommand::WantBlock(cid, priority, deadline, tx))
+            .await?;
         rx.await?
     }
 
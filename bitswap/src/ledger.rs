@@ -1,15 +1,25 @@
 use crate::bitswap_pb;
+use crate::bitswap_pb::message::BlockPresenceType;
 use crate::block::Block;
 use crate::error::BitswapError;
 use crate::prefix::Prefix;
+use crate::serving::RateLimiter;
 use cid::Cid;
+use prost::encoding::encoded_len_varint;
 use prost::Message as ProstMessage;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::TryFrom;
 use std::mem;
+use std::time::{Duration, Instant};
 
 pub type Priority = i32;
 
+/// The maximum number of blocks waiting for the rate limits to allow sending them to a peer.
+const MAX_DEFERRED_BLOCKS: usize = 64;
+
+/// The window over which the unsolicited blocks received from a peer are counted.
+const UNSOLICITED_WINDOW: Duration = Duration::from_secs(60);
+
 /// The Ledger contains the history of transactions with a peer.
 #[derive(Debug, Default)]
 pub struct Ledger {
@@ -19,6 +29,15 @@ pub struct Ledger {
     pub(crate) received_want_list: HashMap<Cid, Priority>,
     /// Queued message.
     message: Message,
+    /// The number of blocks received from the peer which we never asked for, since
+    /// `unsolicited_since`.
+    unsolicited: usize,
+    /// The start of the current window of counting the unsolicited blocks.
+    unsolicited_since: Option<Instant>,
+    /// Limits the upload to the peer.
+    limiter: Option<RateLimiter>,
+    /// Blocks wanted by the peer, waiting for the rate limits to allow sending them.
+    deferred: VecDeque<Block>,
 }
 
 impl Ledger {
@@ -27,6 +46,55 @@ impl Ledger {
         Self::default()
     }
 
+    /// Creates a new `Ledger` limiting the upload to the peer to `rate_limit` bytes per second.
+    pub fn with_rate_limit(rate_limit: Option<u64>) -> Self {
+        Ledger {
+            limiter: rate_limit.map(|rate| RateLimiter::new(rate, Instant::now())),
+            ..Default::default()
+        }
+    }
+
+    /// Queues the blocks wanted by the peer until the rate limits allow sending them. Returns the
+    /// number of blocks dropped because the queue was full; the peer will ask for them again.
+    pub fn defer_blocks(&mut self, blocks: Vec<Block>) -> usize {
+        let room = MAX_DEFERRED_BLOCKS.saturating_sub(self.deferred.len());
+        let dropped = blocks.len().saturating_sub(room);
+        self.deferred.extend(blocks.into_iter().take(room));
+        dropped
+    }
+
+    /// Moves the deferred blocks into the queued message as long as both the peer and the
+    /// `global` rate limits allow. Returns the number of blocks still deferred.
+    pub fn release_deferred(&mut self, global: &mut Option<RateLimiter>, now: Instant) -> usize {
+        while !self.deferred.is_empty() {
+            let peer_ok = self
+                .limiter
+                .as_mut()
+                .map(|l| l.has_capacity(now))
+                .unwrap_or(true);
+            let global_ok = global.as_mut().map(|l| l.has_capacity(now)).unwrap_or(true);
+            if !peer_ok || !global_ok {
+                break;
+            }
+
+            let block = self.deferred.pop_front().expect("checked above");
+            let bytes = block.data().len();
+            if let Some(l) = self.limiter.as_mut() {
+                l.consume(bytes);
+            }
+            if let Some(l) = global.as_mut() {
+                l.consume(bytes);
+            }
+            self.add_block(block);
+        }
+        self.deferred.len()
+    }
+
+    /// Drops a deferred block the peer no longer wants.
+    pub fn cancel_deferred(&mut self, cid: &Cid) {
+        self.deferred.retain(|block| block.cid() != cid);
+    }
+
     pub fn add_block(&mut self, block: Block) {
         self.message.add_block(block);
     }
@@ -39,6 +107,20 @@ impl Ledger {
         self.message.cancel_block(cid);
     }
 
+    /// Records an unsolicited block received from the peer, returning the number of them
+    /// received within the current window.
+    pub fn record_unsolicited(&mut self, now: Instant) -> usize {
+        match self.unsolicited_since {
+            Some(since) if now.duration_since(since) < UNSOLICITED_WINDOW => {}
+            _ => {
+                self.unsolicited = 0;
+                self.unsolicited_since = Some(now);
+            }
+        }
+        self.unsolicited += 1;
+        self.unsolicited
+    }
+
     /// Returns the blocks wanted by the peer in unspecified order
     pub fn wantlist(&self) -> Vec<(Cid, Priority)> {
         self.received_want_list
@@ -47,11 +129,11 @@ impl Ledger {
             .collect()
     }
 
-    pub fn send(&mut self) -> Option<Message> {
+    /// Takes the queued message, split into messages of at most `max_message_size` bytes.
+    pub fn send(&mut self, max_message_size: usize) -> Vec<Message> {
         if self.message.is_empty() {
-            return None;
+            return vec![];
         }
-        // FIXME: this might produce too large message
         for cid in self.message.cancel() {
             self.sent_want_list.remove(cid);
         }
@@ -59,7 +141,7 @@ impl Ledger {
             self.sent_want_list.insert(cid.clone(), *priority);
         }
 
-        Some(mem::take(&mut self.message))
+        mem::take(&mut self.message).split(max_message_size)
     }
 }
 
@@ -83,7 +165,12 @@ pub struct Message {
 impl Message {
     /// Checks whether the queued message is empty.
     pub fn is_empty(&self) -> bool {
-        self.want.is_empty() && self.cancel.is_empty() && self.blocks.is_empty()
+        self.want.is_empty()
+            && self.cancel.is_empty()
+            && self.haves.is_empty()
+            && self.dont_haves.is_empty()
+            && !self.full
+            && self.blocks.is_empty()
     }
 
     /// Returns the list of blocks.
@@ -128,6 +215,16 @@ impl Message {
         &self.dont_haves
     }
 
+    /// Returns whether the wantlist of the message is the full wantlist.
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    /// Marks the wantlist of the message as the full wantlist, replacing the one sent earlier.
+    pub fn set_full(&mut self, full: bool) {
+        self.full = full;
+    }
+
     /// Adds a block to the have list.
     pub fn have_block(&mut self, cid: &Cid) {
         self.haves.insert(cid.to_owned());
@@ -163,6 +260,152 @@ impl Message {
     pub fn remove_want_block(&mut self, cid: &Cid) {
         self.want.remove(cid);
     }
+
+    /// Returns the length of the message once encoded.
+    pub fn encoded_len(&self) -> usize {
+        let blocks = self.blocks.iter().map(block_len).sum::<usize>();
+        let want = self
+            .want
+            .iter()
+            .map(|(cid, priority)| want_len(cid, *priority))
+            .sum::<usize>();
+        let cancel = self.cancel.iter().map(cancel_len).sum::<usize>();
+        let full = if self.full { FULL_LEN } else { 0 };
+        let presences = self
+            .haves
+            .iter()
+            .map(|cid| presence_len(cid, BlockPresenceType::Have))
+            .chain(
+                self.dont_haves
+                    .iter()
+                    .map(|cid| presence_len(cid, BlockPresenceType::DontHave)),
+            )
+            .sum::<usize>();
+
+        let wantlist = want + cancel + full;
+        if wantlist > 0 {
+            blocks + presences + field_len(wantlist)
+        } else {
+            blocks + presences
+        }
+    }
+
+    /// Splits the message into messages of at most `max_size` bytes once encoded, packing the
+    /// blocks first, then the wantlist entries and the block presences.
+    ///
+    /// A single block larger than `max_size` cannot be split and is sent in a message of its own.
+    /// A full wantlist is marked full only in the first message, so that the rest of the entries
+    /// are added to it instead of replacing it.
+    pub fn split(mut self, max_size: usize) -> Vec<Message> {
+        if self.encoded_len() <= max_size {
+            return vec![self];
+        }
+
+        // room for the wantlist field header, whichever message the entries end up in
+        let max_size = max_size.saturating_sub(WANTLIST_OVERHEAD);
+
+        let mut messages = vec![];
+        let mut current = Message::default();
+        let mut current_size = 0;
+
+        let mut next = |size: usize, current: &mut Message, current_size: &mut usize| {
+            if *current_size + size > max_size && !current.is_empty() {
+                messages.push(mem::take(current));
+                *current_size = 0;
+            }
+            *current_size += size;
+        };
+
+        for block in self.take_blocks() {
+            let size = block_len(&block);
+            if size > max_size {
+                log::warn!(
+                    "block {} of {} bytes exceeds the maximum message size",
+                    block.cid(),
+                    block.data().len()
+                );
+            }
+            next(size, &mut current, &mut current_size);
+            current.add_block(block);
+        }
+
+        for (cid, priority) in mem::take(&mut self.want) {
+            next(want_len(&cid, priority), &mut current, &mut current_size);
+            current.want_block(&cid, priority);
+        }
+
+        for cid in mem::take(&mut self.cancel) {
+            next(cancel_len(&cid), &mut current, &mut current_size);
+            current.cancel_block(&cid);
+        }
+
+        for cid in mem::take(&mut self.haves) {
+            let size = presence_len(&cid, BlockPresenceType::Have);
+            next(size, &mut current, &mut current_size);
+            current.have_block(&cid);
+        }
+
+        for cid in mem::take(&mut self.dont_haves) {
+            let size = presence_len(&cid, BlockPresenceType::DontHave);
+            next(size, &mut current, &mut current_size);
+            current.dont_have_block(&cid);
+        }
+
+        if !current.is_empty() {
+            messages.push(current);
+        }
+
+        if let Some(first) = messages.first_mut() {
+            first.full = self.full;
+        }
+
+        messages
+    }
+}
+
+/// The upper bound of the bytes needed for the wantlist field header and the `full` flag.
+const WANTLIST_OVERHEAD: usize = 8;
+
+/// The encoded length of the `full` flag of the wantlist.
+const FULL_LEN: usize = 2;
+
+/// Returns the encoded length of a length delimited field with a single byte key.
+fn field_len(len: usize) -> usize {
+    1 + encoded_len_varint(len as u64) + len
+}
+
+fn block_len(block: &Block) -> usize {
+    let prefix = Prefix::from(&block.cid).to_bytes();
+    field_len(field_len(prefix.len()) + field_len(block.data().len()))
+}
+
+fn want_len(cid: &Cid, priority: Priority) -> usize {
+    let entry = bitswap_pb::message::wantlist::Entry {
+        block: cid.to_bytes(),
+        priority,
+        ..Default::default()
+    };
+    field_len(entry.encoded_len())
+}
+
+fn cancel_len(cid: &Cid) -> usize {
+    let entry = bitswap_pb::message::wantlist::Entry {
+        block: cid.to_bytes(),
+        cancel: true,
+        ..Default::default()
+    };
+    field_len(entry.encoded_len())
+}
+
+fn presence(cid: &Cid, ty: BlockPresenceType) -> bitswap_pb::message::BlockPresence {
+    bitswap_pb::message::BlockPresence {
+        cid: cid.to_bytes(),
+        r#type: ty as i32,
+    }
+}
+
+fn presence_len(cid: &Cid, ty: BlockPresenceType) -> usize {
+    field_len(presence(cid, ty).encoded_len())
 }
 
 impl From<&Message> for Vec<u8> {
@@ -185,6 +428,7 @@ impl From<&Message> for Vec<u8> {
             };
             wantlist.entries.push(entry);
         }
+        wantlist.full = msg.is_full();
         for block in msg.blocks() {
             let payload = bitswap_pb::message::Block {
                 prefix: Prefix::from(&block.cid).to_bytes(),
@@ -192,7 +436,17 @@ impl From<&Message> for Vec<u8> {
             };
             proto.payload.push(payload);
         }
-        if !wantlist.entries.is_empty() {
+        for cid in msg.have() {
+            proto
+                .block_presences
+                .push(presence(cid, BlockPresenceType::Have));
+        }
+        for cid in msg.dont_have() {
+            proto
+                .block_presences
+                .push(presence(cid, BlockPresenceType::DontHave));
+        }
+        if !wantlist.entries.is_empty() || wantlist.full {
             proto.wantlist = Some(wantlist);
         }
         let mut res = Vec::with_capacity(proto.encoded_len());
@@ -226,7 +480,9 @@ impl TryFrom<&[u8]> for Message {
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
         let proto: bitswap_pb::Message = bitswap_pb::Message::decode(bytes)?;
         let mut message = Message::default();
-        for entry in proto.wantlist.unwrap_or_default().entries {
+        let wantlist = proto.wantlist.unwrap_or_default();
+        message.set_full(wantlist.full);
+        for entry in wantlist.entries {
             let cid = Cid::try_from(entry.block)?;
             if entry.cancel {
                 message.cancel_block(&cid);
@@ -238,14 +494,14 @@ impl TryFrom<&[u8]> for Message {
         // But it still doesn't work now in go-ipfs v0.7
         for bp in proto.block_presences {
             let cid = Cid::try_from(bp.cid)?;
-            let msg_type = bitswap_pb::message::BlockPresenceType::from_i32(bp.r#type)
-                .ok_or_else(|| BitswapError::InvalidData)?;
+            let msg_type =
+                BlockPresenceType::from_i32(bp.r#type).ok_or(BitswapError::InvalidData)?;
 
             match msg_type {
-                bitswap_pb::message::BlockPresenceType::Have => {
+                BlockPresenceType::Have => {
                     message.have_block(&cid);
                 }
-                bitswap_pb::message::BlockPresenceType::DontHave => {
+                BlockPresenceType::DontHave => {
                     message.dont_have_block(&cid);
                 }
             }
@@ -298,3 +554,171 @@ impl std::fmt::Debug for Message {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cid::Codec;
+    use multihash::Sha2_256;
+
+    const MAX_SIZE: usize = 4 * 1024 * 1024;
+
+    fn block(i: u8, len: usize) -> Block {
+        let mut data = vec![0u8; len];
+        data[0] = i;
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+        Block::new(data.into_boxed_slice(), cid)
+    }
+
+    fn cid(i: u32) -> Cid {
+        Cid::new_v1(Codec::Raw, Sha2_256::digest(&i.to_be_bytes()))
+    }
+
+    #[test]
+    fn encoded_len_matches_encoding() {
+        let mut message = Message::default();
+        message.add_block(block(0, 1000));
+        message.want_block(&cid(1), 1);
+        message.cancel_block(&cid(2));
+
+        assert_eq!(message.encoded_len(), message.to_bytes().len());
+
+        message.have_block(&cid(3));
+        message.dont_have_block(&cid(4));
+        message.set_full(true);
+
+        assert_eq!(message.encoded_len(), message.to_bytes().len());
+    }
+
+    #[test]
+    fn presences_and_full_are_encoded() {
+        let mut message = Message::default();
+        message.have_block(&cid(0));
+        message.dont_have_block(&cid(1));
+        message.set_full(true);
+
+        let decoded = Message::from_bytes(&message.to_bytes()).unwrap();
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn small_message_is_not_split() {
+        let mut message = Message::default();
+        message.add_block(block(0, 1024));
+        message.want_block(&cid(1), 1);
+
+        let messages = message.clone().split(MAX_SIZE);
+        assert_eq!(messages, vec![message]);
+    }
+
+    #[test]
+    fn message_at_the_limit_is_not_split() {
+        let mut message = Message::default();
+        message.add_block(block(0, 1024));
+        let limit = message.to_bytes().len();
+
+        assert_eq!(message.clone().split(limit).len(), 1);
+        assert_eq!(message.split(limit - 1).len(), 1);
+    }
+
+    #[test]
+    fn blocks_are_split() {
+        let mut message = Message::default();
+        for i in 0..5 {
+            message.add_block(block(i, 1024 * 1024));
+        }
+
+        let messages = message.split(MAX_SIZE);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].num_of_blocks(), 3);
+        assert_eq!(messages[1].num_of_blocks(), 2);
+        for message in &messages {
+            assert!(message.to_bytes().len() <= MAX_SIZE);
+        }
+    }
+
+    #[test]
+    fn wantlist_is_split_after_blocks() {
+        let max_size = 1024;
+        let mut message = Message::default();
+        message.add_block(block(0, 512));
+        for i in 0..100 {
+            message.want_block(&cid(i), 1);
+        }
+        for i in 100..150 {
+            message.cancel_block(&cid(i));
+        }
+
+        let messages = message.split(max_size);
+        assert!(messages.len() > 1);
+        assert_eq!(messages[0].num_of_blocks(), 1);
+
+        let mut wants = 0;
+        let mut cancels = 0;
+        for message in &messages {
+            assert!(message.to_bytes().len() <= max_size);
+            wants += message.want().len();
+            cancels += message.cancel().len();
+        }
+        assert_eq!(wants, 100);
+        assert_eq!(cancels, 50);
+    }
+
+    #[test]
+    fn unsolicited_blocks_are_counted_per_window() {
+        let mut ledger = Ledger::new();
+        let now = Instant::now();
+
+        assert_eq!(ledger.record_unsolicited(now), 1);
+        assert_eq!(ledger.record_unsolicited(now + Duration::from_secs(1)), 2);
+        assert_eq!(ledger.record_unsolicited(now + UNSOLICITED_WINDOW), 1);
+    }
+
+    #[test]
+    fn presences_and_full_are_split() {
+        let max_size = 1024;
+        let mut message = Message::default();
+        message.add_block(block(0, 512));
+        for i in 0..50 {
+            message.want_block(&cid(i), 1);
+        }
+        for i in 50..100 {
+            message.have_block(&cid(i));
+        }
+        for i in 100..150 {
+            message.dont_have_block(&cid(i));
+        }
+        message.set_full(true);
+
+        let messages = message.split(max_size);
+        assert!(messages.len() > 1);
+        assert!(messages[0].is_full());
+
+        let mut wants = 0;
+        let mut haves = 0;
+        let mut dont_haves = 0;
+        for message in &messages {
+            assert!(message.to_bytes().len() <= max_size);
+            assert_eq!(message.encoded_len(), message.to_bytes().len());
+            wants += message.want().len();
+            haves += message.have().len();
+            dont_haves += message.dont_have().len();
+        }
+        assert!(messages[1..].iter().all(|message| !message.is_full()));
+        assert_eq!(wants, 50);
+        assert_eq!(haves, 50);
+        assert_eq!(dont_haves, 50);
+    }
+
+    #[test]
+    fn oversized_block_is_sent_alone() {
+        let mut message = Message::default();
+        message.add_block(block(0, 100));
+        message.add_block(block(1, 4096));
+        message.add_block(block(2, 100));
+
+        let messages = message.split(1024);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1].blocks()[0].data().len(), 4096);
+    }
+}
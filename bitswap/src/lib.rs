@@ -1,17 +1,24 @@
+mod admission;
 mod bitswap;
 mod block;
+mod config;
 mod control;
 mod error;
 mod ledger;
 mod prefix;
 mod protocol;
+mod provider;
+mod serving;
 mod stat;
+mod wants;
 
 pub use crate::bitswap::Bitswap;
 pub use block::Block;
 pub use block::BsBlockStore;
+pub use config::BitswapConfig;
 pub use control::Control;
 pub use ledger::Priority;
+pub use serving::ServingPolicy;
 pub use stat::Stats;
 
 //pub use error::BitswapError;
@@ -1,41 +1,46 @@
 use async_trait::async_trait;
+use cid::Cid;
 use futures::channel::mpsc;
 use futures::SinkExt;
 use std::error::Error;
+use std::io;
 
 use libp2p_rs::core::upgrade::UpgradeInfo;
 use libp2p_rs::core::{PeerId, ProtocolId};
+use libp2p_rs::core::{ReadEx, WriteEx};
 use libp2p_rs::swarm::connection::Connection;
 use libp2p_rs::swarm::protocol_handler::{IProtocolHandler, Notifiee, ProtocolHandler};
 use libp2p_rs::swarm::substream::Substream;
 use libp2p_rs::swarm::Control as SwarmControl;
-use libp2p_rs::core::{ReadEx, WriteEx};
 
 use crate::ledger::Message;
 use crate::{Block, BS_PROTO_ID};
 
-const MAX_BUF_SIZE: usize = 524_288;
-
 pub(crate) enum ProtocolEvent {
     NewPeer(PeerId),
     DeadPeer(PeerId),
     Blocks(PeerId, Vec<Block>),
+    Providers(Cid, Vec<PeerId>),
+    Dialed(PeerId, bool),
 }
 
 #[derive(Clone)]
 pub struct Handler {
     incoming_tx: mpsc::UnboundedSender<(PeerId, Message)>,
     new_peer: mpsc::UnboundedSender<ProtocolEvent>,
+    max_message_size: usize,
 }
 
 impl Handler {
     pub(crate) fn new(
         incoming_tx: mpsc::UnboundedSender<(PeerId, Message)>,
         new_peer: mpsc::UnboundedSender<ProtocolEvent>,
+        max_message_size: usize,
     ) -> Self {
         Handler {
             incoming_tx,
             new_peer,
+            max_message_size,
         }
     }
 }
@@ -70,7 +75,20 @@ impl ProtocolHandler for Handler {
     ) -> Result<(), Box<dyn Error>> {
         log::trace!("Handle stream from {}", stream.remote_peer());
         loop {
-            let packet = stream.read_one(MAX_BUF_SIZE).await?;
+            let packet = match stream.read_one(self.max_message_size).await {
+                Ok(packet) => packet,
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    // the length prefix exceeded the limit, don't bother reading the rest
+                    log::info!(
+                        "rejecting a message over {} bytes from {}: {}",
+                        self.max_message_size,
+                        stream.remote_peer(),
+                        e
+                    );
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            };
             let message = Message::from_bytes(&packet)?;
             let peer = stream.remote_peer();
             self.incoming_tx.send((peer, message)).await?;
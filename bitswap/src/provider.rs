@@ -0,0 +1,431 @@
+use cid::Cid;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use libp2p_rs::core::PeerId;
+
+/// How long a want may stay unsatisfied before we start looking for providers.
+const SEARCH_DELAY: Duration = Duration::from_secs(1);
+
+/// How long to wait before searching again for a want which is still unsatisfied.
+const RESEARCH_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The maximum number of concurrent provider queries.
+const MAX_IN_FLIGHT_QUERIES: usize = 6;
+
+/// The maximum number of provider queries started per tick.
+const MAX_QUERIES_PER_TICK: usize = 2;
+
+/// The number of providers to ask the routing for per query.
+pub(crate) const PROVIDERS_PER_QUERY: usize = 2;
+
+/// The number of recently seen providers kept around for reuse.
+const MAX_RECENT_PROVIDERS: usize = 16;
+
+/// The number of consecutive dial failures after which a provider is no longer reused.
+const MAX_DIAL_FAILURES: u32 = 3;
+
+/// What the `ProviderQueryManager` asks the bitswap main loop to do.
+#[derive(Debug, PartialEq)]
+pub(crate) enum ProviderAction {
+    /// Connect to the given peers, they have recently provided blocks of the same DAG.
+    Dial(Vec<PeerId>),
+    /// Run a routing query for providers of the block.
+    FindProviders(Cid),
+}
+
+/// The search progress of a single want.
+#[derive(Debug, Clone, Copy)]
+enum SearchState {
+    /// Waiting for the want to be satisfied by the already connected peers.
+    Waiting(Instant),
+    /// Recent providers were dialed, waiting for them to deliver the block.
+    DialedRecent(Instant),
+    /// Waiting for a free query slot.
+    Queued,
+    /// A routing query is running.
+    Querying,
+    /// The routing query completed but the want is still unsatisfied.
+    Queried(Instant),
+}
+
+/// Dial outcomes for a single provider.
+#[derive(Debug, Default, Clone, Copy)]
+struct DialOutcomes {
+    successes: u64,
+    failures: u64,
+    consecutive_failures: u32,
+}
+
+/// Provider query manager.
+///
+/// Instead of launching a routing query for every single want, the searches are started only
+/// for the wants which stay unsatisfied for a while, at most one query per `Cid` is running at
+/// a time and the number of queries is bounded.
+///
+/// Wants usually come in bursts while walking a DAG, so the providers which recently delivered
+/// blocks or were found for other wants are dialed first, before falling back to the routing.
+#[derive(Debug, Default)]
+pub(crate) struct ProviderQueryManager {
+    /// The wants we might need to search providers for.
+    wants: HashMap<Cid, SearchState>,
+    /// The wants waiting for a free query slot, in order.
+    queue: VecDeque<Cid>,
+    /// The number of running routing queries.
+    in_flight: usize,
+    /// Most recently useful providers, latest first.
+    recent_providers: VecDeque<PeerId>,
+    /// Peers being dialed at the moment.
+    dialing: HashSet<PeerId>,
+    /// Outcomes of the dials made on behalf of the wants, kept only for the recent providers
+    /// and the peers being dialed.
+    dials: HashMap<PeerId, DialOutcomes>,
+    /// The number of routing queries started so far.
+    queries_started: u64,
+    /// The number of dials started so far.
+    dials_started: u64,
+}
+
+impl ProviderQueryManager {
+    /// Starts tracking a new want.
+    pub fn want(&mut self, cid: &Cid, now: Instant) {
+        self.wants
+            .entry(cid.to_owned())
+            .or_insert(SearchState::Waiting(now));
+    }
+
+    /// Stops tracking a want, because it was either satisfied or cancelled.
+    pub fn remove(&mut self, cid: &Cid) {
+        if let Some(SearchState::Queued) = self.wants.remove(cid) {
+            self.queue.retain(|queued| queued != cid);
+        }
+    }
+
+    /// Records a peer which delivered a block we wanted, making it a candidate for the sibling
+    /// wants.
+    pub fn useful_peer(&mut self, peer_id: PeerId) {
+        self.recent_providers.retain(|p| p != &peer_id);
+        self.recent_providers.push_front(peer_id);
+        self.recent_providers.truncate(MAX_RECENT_PROVIDERS);
+        self.forget_stale_dials();
+    }
+
+    /// Records the completion of a routing query, returning the providers to dial.
+    pub fn query_finished(
+        &mut self,
+        cid: &Cid,
+        providers: Vec<PeerId>,
+        now: Instant,
+    ) -> Vec<PeerId> {
+        self.in_flight = self.in_flight.saturating_sub(1);
+
+        if let Some(state) = self.wants.get_mut(cid) {
+            *state = SearchState::Queried(now);
+        }
+
+        for peer_id in providers.iter().rev() {
+            self.useful_peer(*peer_id);
+        }
+
+        let to_dial = providers
+            .into_iter()
+            .filter(|p| self.dialing.insert(*p))
+            .collect::<Vec<_>>();
+        self.dials_started += to_dial.len() as u64;
+        to_dial
+    }
+
+    /// Records the outcome of a dial toward a provider.
+    pub fn dial_finished(&mut self, peer_id: PeerId, success: bool) {
+        self.dialing.remove(&peer_id);
+
+        let outcomes = self.dials.entry(peer_id).or_default();
+        if success {
+            outcomes.successes += 1;
+            outcomes.consecutive_failures = 0;
+        } else {
+            outcomes.failures += 1;
+            outcomes.consecutive_failures += 1;
+            if outcomes.consecutive_failures >= MAX_DIAL_FAILURES {
+                self.recent_providers.retain(|p| p != &peer_id);
+            }
+        }
+        log::debug!(
+            "dialed provider {:?}: success={}, {} succeeded and {} failed so far",
+            peer_id,
+            success,
+            outcomes.successes,
+            outcomes.failures
+        );
+        self.forget_stale_dials();
+    }
+
+    /// Drops the dial outcomes of the peers which are neither recent providers nor being
+    /// dialed, so that the outcomes do not pile up for every peer ever dialed.
+    fn forget_stale_dials(&mut self) {
+        let recent = &self.recent_providers;
+        let dialing = &self.dialing;
+        self.dials
+            .retain(|peer_id, _| recent.contains(peer_id) || dialing.contains(peer_id));
+    }
+
+    /// Returns the number of routing queries started so far.
+    pub fn queries_started(&self) -> u64 {
+        self.queries_started
+    }
+
+    /// Returns the number of dials started so far.
+    pub fn dials_started(&self) -> u64 {
+        self.dials_started
+    }
+
+    /// Advances the searches, returning the actions the main loop should take.
+    pub fn poll(&mut self, now: Instant, connected: &HashSet<PeerId>) -> Vec<ProviderAction> {
+        let mut actions = vec![];
+        let mut to_dial = vec![];
+
+        for (cid, state) in self.wants.iter_mut() {
+            match *state {
+                SearchState::Waiting(since) if now.duration_since(since) >= SEARCH_DELAY => {
+                    let dialing = &mut self.dialing;
+                    let recent = self
+                        .recent_providers
+                        .iter()
+                        .filter(|p| !connected.contains(*p) && !dialing.contains(*p))
+                        .copied()
+                        .collect::<Vec<_>>();
+
+                    if recent.is_empty() {
+                        *state = SearchState::Queued;
+                        self.queue.push_back(cid.to_owned());
+                    } else {
+                        dialing.extend(recent.iter().copied());
+                        to_dial.extend(recent);
+                        *state = SearchState::DialedRecent(now);
+                    }
+                }
+                SearchState::DialedRecent(at) if now.duration_since(at) >= SEARCH_DELAY => {
+                    *state = SearchState::Queued;
+                    self.queue.push_back(cid.to_owned());
+                }
+                SearchState::Queried(at) if now.duration_since(at) >= RESEARCH_INTERVAL => {
+                    *state = SearchState::Queued;
+                    self.queue.push_back(cid.to_owned());
+                }
+                _ => {}
+            }
+        }
+
+        if !to_dial.is_empty() {
+            self.dials_started += to_dial.len() as u64;
+            actions.push(ProviderAction::Dial(to_dial));
+        }
+
+        let mut started = 0;
+        while self.in_flight < MAX_IN_FLIGHT_QUERIES && started < MAX_QUERIES_PER_TICK {
+            let cid = match self.queue.pop_front() {
+                Some(cid) => cid,
+                None => break,
+            };
+            if let Some(state) = self.wants.get_mut(&cid) {
+                *state = SearchState::Querying;
+                self.in_flight += 1;
+                self.queries_started += 1;
+                started += 1;
+                actions.push(ProviderAction::FindProviders(cid));
+            }
+        }
+
+        actions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cid::Codec;
+    use multihash::Sha2_256;
+
+    fn cid(i: u32) -> Cid {
+        Cid::new_v1(Codec::Raw, Sha2_256::digest(&i.to_be_bytes()))
+    }
+
+    fn queries(actions: &[ProviderAction]) -> Vec<Cid> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                ProviderAction::FindProviders(cid) => Some(cid.to_owned()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn search_starts_after_delay() {
+        let mut manager = ProviderQueryManager::default();
+        let connected = HashSet::new();
+        let now = Instant::now();
+
+        manager.want(&cid(0), now);
+        assert!(manager.poll(now, &connected).is_empty());
+        assert_eq!(
+            manager.poll(now + SEARCH_DELAY, &connected),
+            vec![ProviderAction::FindProviders(cid(0))]
+        );
+        // at most one query per want
+        assert!(manager.poll(now + 2 * SEARCH_DELAY, &connected).is_empty());
+        assert_eq!(manager.queries_started(), 1);
+    }
+
+    #[test]
+    fn satisfied_wants_are_not_searched() {
+        let mut manager = ProviderQueryManager::default();
+        let connected = HashSet::new();
+        let now = Instant::now();
+
+        manager.want(&cid(0), now);
+        manager.remove(&cid(0));
+        assert!(manager.poll(now + SEARCH_DELAY, &connected).is_empty());
+        assert_eq!(manager.queries_started(), 0);
+    }
+
+    #[test]
+    fn queries_are_bounded() {
+        let mut manager = ProviderQueryManager::default();
+        let connected = HashSet::new();
+        let now = Instant::now();
+
+        for i in 0..10 {
+            manager.want(&cid(i), now);
+        }
+
+        let mut started = vec![];
+        for tick in 1..=10 {
+            let actions = manager.poll(now + SEARCH_DELAY * tick, &connected);
+            assert!(actions.len() <= MAX_QUERIES_PER_TICK);
+            started.extend(queries(&actions));
+        }
+        assert_eq!(started.len(), MAX_IN_FLIGHT_QUERIES);
+
+        // a finished query frees a slot for the queued wants
+        let later = now + SEARCH_DELAY * 11;
+        manager.query_finished(&started[0], vec![], later);
+        assert_eq!(queries(&manager.poll(later, &connected)).len(), 1);
+        assert_eq!(manager.queries_started(), MAX_IN_FLIGHT_QUERIES as u64 + 1);
+    }
+
+    #[test]
+    fn unsatisfied_want_is_searched_again() {
+        let mut manager = ProviderQueryManager::default();
+        let connected = HashSet::new();
+        let now = Instant::now();
+
+        manager.want(&cid(0), now);
+        manager.poll(now + SEARCH_DELAY, &connected);
+        let finished = now + SEARCH_DELAY;
+        manager.query_finished(&cid(0), vec![], finished);
+
+        assert!(manager.poll(finished, &connected).is_empty());
+        assert_eq!(
+            manager.poll(finished + RESEARCH_INTERVAL, &connected),
+            vec![ProviderAction::FindProviders(cid(0))]
+        );
+        assert_eq!(manager.queries_started(), 2);
+    }
+
+    #[test]
+    fn found_providers_are_dialed_once() {
+        let mut manager = ProviderQueryManager::default();
+        let connected = HashSet::new();
+        let now = Instant::now();
+        let provider = PeerId::random();
+
+        manager.want(&cid(0), now);
+        manager.want(&cid(1), now);
+        manager.poll(now + SEARCH_DELAY, &connected);
+
+        assert_eq!(
+            manager.query_finished(&cid(0), vec![provider], now),
+            vec![provider]
+        );
+        // already being dialed
+        assert!(manager
+            .query_finished(&cid(1), vec![provider], now)
+            .is_empty());
+        assert_eq!(manager.dials_started(), 1);
+    }
+
+    #[test]
+    fn recent_providers_are_dialed_before_searching() {
+        let mut manager = ProviderQueryManager::default();
+        let mut connected = HashSet::new();
+        let now = Instant::now();
+        let provider = PeerId::random();
+
+        manager.useful_peer(provider);
+        manager.want(&cid(0), now);
+        assert_eq!(
+            manager.poll(now + SEARCH_DELAY, &connected),
+            vec![ProviderAction::Dial(vec![provider])]
+        );
+        manager.dial_finished(provider, true);
+        connected.insert(provider);
+
+        // the provider is given some time to deliver
+        let half = SEARCH_DELAY / 2;
+        assert!(manager
+            .poll(now + SEARCH_DELAY + half, &connected)
+            .is_empty());
+
+        // the provider did not deliver, fall back to the routing
+        assert_eq!(
+            manager.poll(now + 2 * SEARCH_DELAY, &connected),
+            vec![ProviderAction::FindProviders(cid(0))]
+        );
+        assert_eq!(manager.dials_started(), 1);
+        assert_eq!(manager.queries_started(), 1);
+    }
+
+    #[test]
+    fn connected_recent_providers_are_not_dialed() {
+        let mut manager = ProviderQueryManager::default();
+        let now = Instant::now();
+        let provider = PeerId::random();
+        let connected = vec![provider].into_iter().collect();
+
+        manager.useful_peer(provider);
+        manager.want(&cid(0), now);
+        assert_eq!(
+            manager.poll(now + SEARCH_DELAY, &connected),
+            vec![ProviderAction::FindProviders(cid(0))]
+        );
+        assert_eq!(manager.dials_started(), 0);
+    }
+
+    #[test]
+    fn failing_providers_are_forgotten() {
+        let mut manager = ProviderQueryManager::default();
+        let provider = PeerId::random();
+
+        manager.useful_peer(provider);
+        for _ in 0..MAX_DIAL_FAILURES {
+            assert!(manager.recent_providers.contains(&provider));
+            manager.dial_finished(provider, false);
+        }
+        assert!(!manager.recent_providers.contains(&provider));
+        assert!(manager.dials.is_empty());
+    }
+
+    #[test]
+    fn dial_outcomes_are_bounded() {
+        let mut manager = ProviderQueryManager::default();
+
+        for _ in 0..MAX_RECENT_PROVIDERS * 4 {
+            let provider = PeerId::random();
+            manager.useful_peer(provider);
+            manager.dial_finished(provider, true);
+        }
+        assert_eq!(manager.recent_providers.len(), MAX_RECENT_PROVIDERS);
+        assert!(manager.dials.len() <= MAX_RECENT_PROVIDERS);
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashSet;
+use std::time::Instant;
+
+use libp2p_rs::core::PeerId;
+
+/// Decides whom and how fast we serve blocks to.
+#[derive(Clone, Debug, Default)]
+pub struct ServingPolicy {
+    /// Never serve any blocks, only fetch them.
+    pub leech_only: bool,
+    /// When set, only these peers are served.
+    pub allowlist: Option<HashSet<PeerId>>,
+    /// Peers which are never served.
+    pub denylist: HashSet<PeerId>,
+    /// Upload limit over all peers, in bytes per second.
+    pub global_rate_limit: Option<u64>,
+    /// Upload limit for every peer, in bytes per second.
+    pub peer_rate_limit: Option<u64>,
+}
+
+impl ServingPolicy {
+    /// Creates a policy which never serves any blocks.
+    pub fn leech_only() -> Self {
+        ServingPolicy {
+            leech_only: true,
+            ..Default::default()
+        }
+    }
+
+    /// Serves only the given peers.
+    pub fn with_allowlist(mut self, peers: impl IntoIterator<Item = PeerId>) -> Self {
+        self.allowlist = Some(peers.into_iter().collect());
+        self
+    }
+
+    /// Never serves the given peers.
+    pub fn with_denylist(mut self, peers: impl IntoIterator<Item = PeerId>) -> Self {
+        self.denylist = peers.into_iter().collect();
+        self
+    }
+
+    /// Limits the upload over all peers to `bytes_per_sec`.
+    pub fn with_global_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.global_rate_limit = Some(bytes_per_sec);
+        self
+    }
+
+    /// Limits the upload to every peer to `bytes_per_sec`.
+    pub fn with_peer_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.peer_rate_limit = Some(bytes_per_sec);
+        self
+    }
+
+    /// Returns true if the peer may fetch blocks from us.
+    pub fn allows(&self, peer_id: &PeerId) -> bool {
+        if self.leech_only || self.denylist.contains(peer_id) {
+            return false;
+        }
+        self.allowlist
+            .as_ref()
+            .map(|allowed| allowed.contains(peer_id))
+            .unwrap_or(true)
+    }
+}
+
+/// Token bucket limiting the bytes sent per second.
+///
+/// The bucket holds at most one second worth of tokens. A block is let through whenever there
+/// are any tokens left, even if it is larger than the tokens available; the debt is paid back
+/// before the next block is let through.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    /// Bytes per second.
+    rate: u64,
+    tokens: i64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u64, now: Instant) -> Self {
+        RateLimiter {
+            rate,
+            tokens: rate as i64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let refill = (u128::from(self.rate) * elapsed.as_nanos() / 1_000_000_000) as i64;
+        // keep the fractions for the next refill
+        if refill > 0 {
+            self.tokens = (self.tokens + refill).min(self.rate as i64);
+            self.last_refill = now;
+        }
+    }
+
+    /// Returns true if something can be sent right now.
+    pub fn has_capacity(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens > 0
+    }
+
+    /// Takes the tokens for the sent bytes.
+    pub fn consume(&mut self, bytes: usize) {
+        self.tokens -= bytes as i64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn default_policy_serves_everyone() {
+        let policy = ServingPolicy::default();
+        assert!(policy.allows(&PeerId::random()));
+    }
+
+    #[test]
+    fn leech_only_serves_nobody() {
+        let peer = PeerId::random();
+        let policy = ServingPolicy::leech_only().with_allowlist(vec![peer]);
+        assert!(!policy.allows(&peer));
+        assert!(!policy.allows(&PeerId::random()));
+    }
+
+    #[test]
+    fn allowlist_and_denylist() {
+        let allowed = PeerId::random();
+        let denied = PeerId::random();
+
+        let policy = ServingPolicy::default().with_denylist(vec![denied]);
+        assert!(policy.allows(&allowed));
+        assert!(!policy.allows(&denied));
+
+        let policy = policy.with_allowlist(vec![allowed, denied]);
+        assert!(policy.allows(&allowed));
+        // the denylist wins
+        assert!(!policy.allows(&denied));
+        assert!(!policy.allows(&PeerId::random()));
+    }
+
+    #[test]
+    fn rate_limiter_starts_full() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(1000, now);
+
+        assert!(limiter.has_capacity(now));
+        limiter.consume(999);
+        assert!(limiter.has_capacity(now));
+        limiter.consume(1);
+        assert!(!limiter.has_capacity(now));
+    }
+
+    #[test]
+    fn rate_limiter_refills_over_time() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(1000, now);
+
+        // a large block goes through, the debt is paid back before the next one
+        limiter.consume(1500);
+        assert!(!limiter.has_capacity(now + Duration::from_millis(500)));
+        assert!(limiter.has_capacity(now + Duration::from_millis(501)));
+        assert_eq!(limiter.tokens, 1);
+    }
+
+    #[test]
+    fn rate_limiter_keeps_fractions() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(10, now);
+        limiter.consume(10);
+
+        // less than a token per check, which must still add up
+        for ms in (50..=1000).step_by(50) {
+            limiter.has_capacity(now + Duration::from_millis(ms));
+        }
+        assert_eq!(limiter.tokens, 10);
+    }
+
+    #[test]
+    fn rate_limiter_holds_at_most_a_second() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(1000, now);
+        limiter.consume(1000);
+
+        assert!(limiter.has_capacity(now + Duration::from_secs(60)));
+        assert_eq!(limiter.tokens, 1000);
+    }
+}
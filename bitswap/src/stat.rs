@@ -9,6 +9,12 @@ pub struct Stats {
     pub received_data: AtomicU64,
     pub duplicate_blocks: AtomicU64,
     pub duplicate_data: AtomicU64,
+    pub unsolicited_blocks: AtomicU64,
+    pub unsolicited_data: AtomicU64,
+    pub refused_wants: AtomicU64,
+    pub throttled_blocks: AtomicU64,
+    pub provider_queries: AtomicU64,
+    pub provider_dials: AtomicU64,
 }
 
 impl Stats {
@@ -27,6 +33,25 @@ impl Stats {
         self.duplicate_data.fetch_add(bytes, Ordering::Relaxed);
     }
 
+    pub fn update_incoming_unsolicited(&self, bytes: u64) {
+        self.unsolicited_blocks.fetch_add(1, Ordering::Relaxed);
+        self.unsolicited_data.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn update_refused_wants(&self, num_wants: u64) {
+        self.refused_wants.fetch_add(num_wants, Ordering::Relaxed);
+    }
+
+    pub fn update_throttled(&self, num_blocks: u64) {
+        self.throttled_blocks
+            .fetch_add(num_blocks, Ordering::Relaxed);
+    }
+
+    pub fn update_providers(&self, queries: u64, dials: u64) {
+        self.provider_queries.fetch_add(queries, Ordering::Relaxed);
+        self.provider_dials.fetch_add(dials, Ordering::Relaxed);
+    }
+
     pub fn add_assign(&self, other: &Stats) {
         self.sent_blocks
             .fetch_add(other.sent_blocks.load(Ordering::Relaxed), Ordering::Relaxed);
@@ -48,5 +73,29 @@ impl Stats {
             other.duplicate_data.load(Ordering::Relaxed),
             Ordering::Relaxed,
         );
+        self.unsolicited_blocks.fetch_add(
+            other.unsolicited_blocks.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.unsolicited_data.fetch_add(
+            other.unsolicited_data.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.refused_wants.fetch_add(
+            other.refused_wants.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.throttled_blocks.fetch_add(
+            other.throttled_blocks.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.provider_queries.fetch_add(
+            other.provider_queries.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.provider_dials.fetch_add(
+            other.provider_dials.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
     }
 }
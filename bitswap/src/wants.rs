@@ -0,0 +1,256 @@
+use cid::Cid;
+use futures::channel::oneshot;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::block::Block;
+use crate::error::BitswapError;
+
+/// A user request waiting for a wanted block.
+struct WantRequest {
+    /// When the request fails with a timeout.
+    deadline: Instant,
+    /// Used to send the block back to the API user.
+    reply: oneshot::Sender<Result<Block, BitswapError>>,
+}
+
+/// The blocks wanted by the local node, with the user requests waiting for them.
+///
+/// The deadlines are kept in order, so that the main loop can wake up right when the next
+/// request times out. A deadline is left in place when its request is answered earlier, and is
+/// simply skipped when it comes due.
+#[derive(Default)]
+pub(crate) struct PendingWants {
+    requests: HashMap<Cid, Vec<WantRequest>>,
+    deadlines: BTreeMap<Instant, Vec<Cid>>,
+}
+
+impl PendingWants {
+    /// Adds a request for the block, failing with a timeout once the `deadline` has passed.
+    pub fn add(
+        &mut self,
+        cid: Cid,
+        deadline: Instant,
+        reply: oneshot::Sender<Result<Block, BitswapError>>,
+    ) {
+        self.deadlines
+            .entry(deadline)
+            .or_insert_with(Vec::new)
+            .push(cid.clone());
+        self.requests
+            .entry(cid)
+            .or_insert_with(Vec::new)
+            .push(WantRequest { deadline, reply });
+    }
+
+    /// Returns true if the block is wanted.
+    pub fn contains(&self, cid: &Cid) -> bool {
+        self.requests.contains_key(cid)
+    }
+
+    /// Returns the number of wanted blocks.
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// Returns true if no blocks are wanted.
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Returns the wanted blocks in unspecified order.
+    pub fn cids(&self) -> impl Iterator<Item = &Cid> {
+        self.requests.keys()
+    }
+
+    /// Answers all the requests for the block. Returns false if the block was not wanted.
+    pub fn complete(&mut self, block: &Block) -> bool {
+        match self.requests.remove(block.cid()) {
+            Some(requests) => {
+                for req in requests {
+                    // some tx may be dropped, regardless
+                    let _ = req.reply.send(Ok(block.clone()));
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drops the want along with its requests. Returns false if the block was not wanted.
+    pub fn remove(&mut self, cid: &Cid) -> bool {
+        self.requests.remove(cid).is_some()
+    }
+
+    /// Returns the earliest deadline of the requests, if any.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.deadlines.keys().next().copied()
+    }
+
+    /// Fails the requests whose deadline has passed. Returns the wants no request waits for
+    /// anymore.
+    pub fn expire(&mut self, now: Instant) -> Vec<Cid> {
+        let mut ended = vec![];
+        while let Some(at) = self.next_deadline() {
+            if at > now {
+                break;
+            }
+            let cids = self.deadlines.remove(&at).unwrap_or_default();
+            for cid in cids {
+                if self.retain_requests(&cid, |req| req.deadline > now) {
+                    ended.push(cid);
+                }
+            }
+        }
+        ended
+    }
+
+    /// Drops the requests of the API users which went away. Returns the wants no request waits
+    /// for anymore.
+    pub fn drop_abandoned(&mut self) -> Vec<Cid> {
+        let cids = self.requests.keys().cloned().collect::<Vec<_>>();
+        cids.into_iter()
+            .filter(|cid| self.retain_requests(cid, |req| !req.reply.is_canceled()))
+            .collect()
+    }
+
+    /// Fails the requests for the block not passing `keep` with a timeout, dropping the want if
+    /// none is left. Returns true if the want was dropped.
+    fn retain_requests<F: Fn(&WantRequest) -> bool>(&mut self, cid: &Cid, keep: F) -> bool {
+        let requests = match self.requests.get_mut(cid) {
+            Some(requests) => requests,
+            None => return false,
+        };
+        let (pending, timed_out): (Vec<_>, Vec<_>) = requests.drain(..).partition(keep);
+        *requests = pending;
+        for req in timed_out {
+            let _ = req.reply.send(Err(BitswapError::Timeout));
+        }
+        if requests.is_empty() {
+            self.requests.remove(cid);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Paces the resending of the whole wantlist to all connected peers.
+#[derive(Debug)]
+pub(crate) struct Rebroadcast {
+    interval: Duration,
+    last: Instant,
+}
+
+impl Rebroadcast {
+    pub fn new(interval: Duration, now: Instant) -> Self {
+        Rebroadcast {
+            interval,
+            last: now,
+        }
+    }
+
+    /// Returns true if the wantlist should be resent now, starting a new interval.
+    pub fn due(&mut self, now: Instant) -> bool {
+        if now.saturating_duration_since(self.last) >= self.interval {
+            self.last = now;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cid::Codec;
+    use multihash::Sha2_256;
+
+    fn block(i: u8) -> Block {
+        let data = vec![i; 16];
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+        Block::new(data.into_boxed_slice(), cid)
+    }
+
+    #[test]
+    fn requests_time_out_at_their_deadline() {
+        let mut wants = PendingWants::default();
+        let now = Instant::now();
+        let block = block(0);
+        let (tx1, mut rx1) = oneshot::channel();
+        let (tx2, mut rx2) = oneshot::channel();
+
+        wants.add(block.cid().to_owned(), now + Duration::from_secs(2), tx2);
+        wants.add(block.cid().to_owned(), now + Duration::from_secs(1), tx1);
+        assert_eq!(wants.next_deadline(), Some(now + Duration::from_secs(1)));
+
+        assert!(wants.expire(now).is_empty());
+
+        // the earlier request fails, the want stays for the later one
+        assert!(wants.expire(now + Duration::from_secs(1)).is_empty());
+        assert!(matches!(
+            rx1.try_recv(),
+            Ok(Some(Err(BitswapError::Timeout)))
+        ));
+        assert!(wants.contains(block.cid()));
+        assert_eq!(wants.next_deadline(), Some(now + Duration::from_secs(2)));
+
+        assert_eq!(
+            wants.expire(now + Duration::from_secs(2)),
+            vec![block.cid().to_owned()]
+        );
+        assert!(matches!(
+            rx2.try_recv(),
+            Ok(Some(Err(BitswapError::Timeout)))
+        ));
+        assert!(wants.is_empty());
+        assert_eq!(wants.next_deadline(), None);
+    }
+
+    #[test]
+    fn completed_requests_do_not_time_out() {
+        let mut wants = PendingWants::default();
+        let now = Instant::now();
+        let block = block(0);
+        let (tx, mut rx) = oneshot::channel();
+
+        wants.add(block.cid().to_owned(), now + Duration::from_secs(1), tx);
+        assert!(wants.complete(&block));
+        assert!(!wants.complete(&block));
+        assert_eq!(rx.try_recv().unwrap().unwrap().unwrap(), block);
+
+        // the stale deadline is skipped
+        assert!(wants.expire(now + Duration::from_secs(1)).is_empty());
+        assert_eq!(wants.next_deadline(), None);
+    }
+
+    #[test]
+    fn abandoned_requests_are_dropped() {
+        let mut wants = PendingWants::default();
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let (tx1, rx1) = oneshot::channel();
+        let (tx2, _rx2) = oneshot::channel();
+
+        wants.add(block(0).cid().to_owned(), deadline, tx1);
+        wants.add(block(1).cid().to_owned(), deadline, tx2);
+        drop(rx1);
+
+        assert_eq!(wants.drop_abandoned(), vec![block(0).cid().to_owned()]);
+        assert_eq!(wants.len(), 1);
+        assert!(wants.contains(block(1).cid()));
+    }
+
+    #[test]
+    fn rebroadcast_once_per_interval() {
+        let now = Instant::now();
+        let interval = Duration::from_secs(10);
+        let mut rebroadcast = Rebroadcast::new(interval, now);
+
+        assert!(!rebroadcast.due(now));
+        assert!(!rebroadcast.due(now + interval / 2));
+        assert!(rebroadcast.due(now + interval));
+        assert!(!rebroadcast.due(now + interval + interval / 2));
+        assert!(rebroadcast.due(now + 2 * interval));
+    }
+}
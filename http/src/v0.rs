@@ -10,13 +10,16 @@ pub mod block;
 pub mod bootstrap;
 pub mod dag;
 pub mod dht;
+pub mod files;
 pub mod id;
 pub mod ipns;
+pub mod object;
 pub mod pin;
 pub mod pubsub;
 pub mod refs;
 pub mod root_files;
 pub mod swarm;
+pub mod tar;
 pub mod version;
 
 pub mod support;
@@ -83,6 +86,7 @@ pub fn routes<T: IpfsTypes>(
         and_boxed!(warp::path!("cat"), root_files::cat(ipfs)),
         and_boxed!(warp::path!("dns"), ipns::dns(ipfs)),
         and_boxed!(warp::path!("get"), root_files::get(ipfs)),
+        and_boxed!(warp::path!("ls"), root_files::ls(ipfs)),
         and_boxed!(warp::path!("refs" / "local"), refs::local(ipfs)),
         and_boxed!(warp::path!("refs"), refs::refs(ipfs)),
         and_boxed!(warp::path!("resolve"), ipns::resolve(ipfs)),
@@ -119,6 +123,26 @@ pub fn routes<T: IpfsTypes>(
             and_boxed!(warp::path!("provide"), dht::provide(ipfs)),
             and_boxed!(warp::path!("query"), dht::get_closest_peers(ipfs)),
         )),
+        warp::path("files").and(combine!(
+            and_boxed!(warp::path!("cp"), files::cp(ipfs)),
+            and_boxed!(warp::path!("flush"), files::flush(ipfs)),
+            and_boxed!(warp::path!("ls"), files::ls(ipfs)),
+            and_boxed!(warp::path!("mkdir"), files::mkdir(ipfs)),
+            and_boxed!(warp::path!("mv"), files::mv(ipfs)),
+            and_boxed!(warp::path!("read"), files::read(ipfs)),
+            and_boxed!(warp::path!("rm"), files::rm(ipfs)),
+            and_boxed!(warp::path!("stat"), files::stat(ipfs)),
+            and_boxed!(warp::path!("write"), files::write(ipfs)),
+        )),
+        warp::path("object").and(combine!(
+            and_boxed!(warp::path!("diff"), object::diff(ipfs)),
+            and_boxed!(warp::path!("patch" / "add-link"), object::add_link(ipfs)),
+            and_boxed!(
+                warp::path!("patch" / "append-data"),
+                object::append_data(ipfs)
+            ),
+            and_boxed!(warp::path!("patch" / "rm-link"), object::rm_link(ipfs)),
+        )),
         warp::path("pubsub").and(combine!(
             and_boxed!(warp::path!("peers"), pubsub::peers(ipfs)),
             and_boxed!(warp::path!("ls"), pubsub::list_subscriptions(ipfs)),
@@ -140,6 +164,10 @@ pub fn routes<T: IpfsTypes>(
             and_boxed!(warp::path!("ls"), pin::list(ipfs)),
             and_boxed!(warp::path!("rm"), pin::rm(ipfs)),
         )),
+        warp::path("tar").and(combine!(
+            and_boxed!(warp::path!("add"), tar::add(ipfs)),
+            and_boxed!(warp::path!("cat"), tar::cat(ipfs)),
+        )),
         warp::path!("config" / ..).and_then(not_implemented),
         warp::path!("dht" / "get").and_then(not_implemented),
         warp::path!("dht" / "put").and_then(not_implemented),
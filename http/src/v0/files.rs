@@ -0,0 +1,473 @@
+//! `/api/v0/files/*` endpoints over the mutable file system, see
+//! https://docs.ipfs.io/reference/http/api/#api-v0-files-cp for the go-ipfs counterparts.
+
+use crate::v0::support::option_parsing::ParseError;
+use crate::v0::support::{with_ipfs, OnlyMultipartFailure, StreamResponseText, StringError};
+use bytes::{Buf, Bytes};
+use futures::stream::{Stream, TryStreamExt};
+use ipfs::unixfs::ll::NodeKind;
+use ipfs::unixfs::mfs::{MfsError, WriteOptions};
+use ipfs::{Ipfs, IpfsTypes};
+use mime::Mime;
+use mpart_async::server::{MultipartError, MultipartStream};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use warp::{query, reply, Filter, Rejection, Reply};
+
+/// The written bytes are collected into pieces of this size, each of which is written separately
+/// so that the whole body is never held in memory.
+const WRITE_PIECE_SIZE: usize = 1024 * 1024;
+
+fn default_flush() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MkdirArgs {
+    arg: String,
+    #[serde(default)]
+    parents: bool,
+    #[serde(default = "default_flush")]
+    flush: bool,
+}
+
+pub fn mkdir<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<MkdirArgs>())
+        .and_then(mkdir_inner)
+}
+
+async fn mkdir_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: MkdirArgs,
+) -> Result<impl Reply, Rejection> {
+    ipfs.files_mkdir(&args.arg, args.parents, args.flush)
+        .await
+        .map_err(StringError::from)?;
+    Ok(reply())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WriteArgs {
+    arg: String,
+    #[serde(default)]
+    offset: u64,
+    #[serde(default)]
+    create: bool,
+    #[serde(default)]
+    truncate: bool,
+    #[serde(default)]
+    parents: bool,
+    /// Maximum number of bytes to write from the body.
+    count: Option<usize>,
+    #[serde(default = "default_flush")]
+    flush: bool,
+}
+
+pub fn write<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<WriteArgs>())
+        .and(warp::header::<Mime>("content-type")) // TODO: rejects if missing
+        .and(warp::body::stream())
+        .and_then(write_inner)
+}
+
+async fn write_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: WriteArgs,
+    mime: Mime,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin,
+) -> Result<impl Reply, Rejection> {
+    let boundary = mime
+        .get_param("boundary")
+        .map(|v| v.to_string())
+        .ok_or_else(|| StringError::from("missing 'boundary' on content-type"))?;
+
+    let mut fields = MultipartStream::new(
+        Bytes::from(boundary),
+        body.map_ok(|mut buf| buf.copy_to_bytes(buf.remaining())),
+    );
+
+    let parsing = |e: MultipartError| StringError::from(OnlyMultipartFailure::from(e));
+
+    // like with the other endpoints taking a single file, the field is expected to be named
+    // "data" or "file"
+    let mut field = loop {
+        let mut field = fields
+            .try_next()
+            .await
+            .map_err(parsing)?
+            .ok_or_else(|| StringError::from(OnlyMultipartFailure::NotFound))?;
+
+        let name = field
+            .name()
+            .map_err(|_| StringError::from(OnlyMultipartFailure::UnparseableFieldName))?;
+
+        if name == "data" || name == "file" {
+            break field;
+        }
+
+        while field.try_next().await.map_err(parsing)?.is_some() {}
+    };
+
+    let mut opts = WriteOptions {
+        offset: args.offset,
+        create: args.create,
+        truncate: args.truncate,
+        parents: args.parents,
+    };
+
+    let mut remaining = args.count.unwrap_or(usize::MAX);
+    let mut piece = Vec::new();
+
+    loop {
+        let next = if remaining > 0 {
+            field.try_next().await.map_err(parsing)?
+        } else {
+            None
+        };
+
+        if let Some(bytes) = next.as_ref() {
+            let taken = bytes.len().min(remaining);
+            piece.extend_from_slice(&bytes[..taken]);
+            remaining -= taken;
+        }
+
+        let last = next.is_none();
+
+        if piece.len() >= WRITE_PIECE_SIZE || last {
+            ipfs.files_write(&args.arg, &piece, opts, last && args.flush)
+                .await
+                .map_err(StringError::from)?;
+
+            // the file exists after the first piece, and must not be truncated again
+            opts.offset += piece.len() as u64;
+            opts.create = false;
+            opts.truncate = false;
+            piece.clear();
+        }
+
+        if last {
+            break;
+        }
+    }
+
+    Ok(reply())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReadArgs {
+    arg: String,
+    #[serde(default)]
+    offset: u64,
+    count: Option<u64>,
+}
+
+pub fn read<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<ReadArgs>())
+        .and_then(read_inner)
+}
+
+async fn read_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: ReadArgs) -> Result<impl Reply, Rejection> {
+    let stat = ipfs
+        .files_stat(&args.arg)
+        .await
+        .map_err(StringError::from)?;
+
+    if stat.kind != NodeKind::File {
+        return Err(StringError::from(MfsError::NotAFile(args.arg)).into());
+    }
+
+    let end = match args.count {
+        Some(count) => args.offset.saturating_add(count),
+        None => stat.size,
+    };
+
+    let range = args.offset..end.max(args.offset);
+
+    let stream = ipfs::unixfs::cat(ipfs, stat.cid, Some(range))
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(StreamResponseText(stream))
+}
+
+/// Arguments for `files/mv` and `files/cp`, which take the source and destination as two `arg`
+/// fields.
+#[derive(Debug)]
+pub struct SourceDestination {
+    source: String,
+    destination: String,
+    flush: bool,
+}
+
+impl<'a> TryFrom<&'a str> for SourceDestination {
+    type Error = ParseError<'a>;
+
+    fn try_from(q: &'a str) -> Result<Self, Self::Error> {
+        use ParseError::*;
+
+        let mut args = Vec::with_capacity(2);
+        let mut flush = None;
+
+        for (key, value) in url::form_urlencoded::parse(q.as_bytes()) {
+            match &*key {
+                "arg" => args.push(value.into_owned()),
+                "flush" if flush.is_none() => match value.parse::<bool>() {
+                    Ok(value) => flush = Some(value),
+                    Err(_) => return Err(InvalidBoolean(key, value)),
+                },
+                "flush" => return Err(DuplicateField(key)),
+                _ => {
+                    // ignore unknown fields
+                }
+            }
+        }
+
+        if args.len() != 2 {
+            return Err(InvalidValue(
+                Cow::Borrowed("arg"),
+                Cow::Owned(format!("expected source and destination, got {:?}", args)),
+            ));
+        }
+
+        let destination = args.pop().unwrap();
+        let source = args.pop().unwrap();
+
+        Ok(SourceDestination {
+            source,
+            destination,
+            flush: flush.unwrap_or(true),
+        })
+    }
+}
+
+fn source_destination() -> impl Filter<Extract = (SourceDestination,), Error = Rejection> + Clone {
+    warp::filters::query::raw().and_then(|q: String| {
+        let res = SourceDestination::try_from(q.as_str())
+            .map_err(StringError::from)
+            .map_err(warp::reject::custom);
+
+        futures::future::ready(res)
+    })
+}
+
+pub fn mv<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(source_destination()).and_then(mv_inner)
+}
+
+async fn mv_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: SourceDestination,
+) -> Result<impl Reply, Rejection> {
+    ipfs.files_mv(&args.source, &args.destination, args.flush)
+        .await
+        .map_err(StringError::from)?;
+    Ok(reply())
+}
+
+pub fn cp<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(source_destination()).and_then(cp_inner)
+}
+
+async fn cp_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: SourceDestination,
+) -> Result<impl Reply, Rejection> {
+    ipfs.files_cp(&args.source, &args.destination, args.flush)
+        .await
+        .map_err(StringError::from)?;
+    Ok(reply())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RmArgs {
+    arg: String,
+    #[serde(default)]
+    recursive: bool,
+    /// Same as `recursive` as there is nothing else to force.
+    #[serde(default)]
+    force: bool,
+    #[serde(default = "default_flush")]
+    flush: bool,
+}
+
+pub fn rm<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(query::<RmArgs>()).and_then(rm_inner)
+}
+
+async fn rm_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: RmArgs) -> Result<impl Reply, Rejection> {
+    ipfs.files_rm(&args.arg, args.recursive || args.force, args.flush)
+        .await
+        .map_err(StringError::from)?;
+    Ok(reply())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatArgs {
+    arg: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct StatResponse {
+    hash: String,
+    size: u64,
+    cumulative_size: u64,
+    blocks: usize,
+    #[serde(rename = "Type")]
+    kind: &'static str,
+}
+
+pub fn stat<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<StatArgs>())
+        .and_then(stat_inner)
+}
+
+async fn stat_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: StatArgs) -> Result<impl Reply, Rejection> {
+    let stat = ipfs
+        .files_stat(&args.arg)
+        .await
+        .map_err(StringError::from)?;
+
+    let kind = match stat.kind {
+        NodeKind::Directory | NodeKind::ShardedDirectory => "directory",
+        NodeKind::File | NodeKind::Symlink => "file",
+    };
+
+    Ok(reply::json(&StatResponse {
+        hash: stat.cid.to_string(),
+        size: stat.size,
+        cumulative_size: stat.cumulative_size,
+        blocks: stat.blocks,
+        kind,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LsArgs {
+    arg: Option<String>,
+    /// When false, only the names of the entries are returned like in go-ipfs.
+    #[serde(default)]
+    long: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct LsResponse {
+    entries: Vec<LsEntry>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct LsEntry {
+    name: String,
+    /// 0 for files, 1 for directories
+    #[serde(rename = "Type")]
+    kind: u8,
+    size: u64,
+    hash: String,
+}
+
+pub fn ls<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(query::<LsArgs>()).and_then(ls_inner)
+}
+
+async fn ls_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: LsArgs) -> Result<impl Reply, Rejection> {
+    let path = args.arg.as_deref().unwrap_or("/");
+    let entries = ipfs.files_ls(path).await.map_err(StringError::from)?;
+
+    let entries = entries
+        .into_iter()
+        .map(|entry| {
+            if args.long {
+                let kind = match entry.kind {
+                    NodeKind::Directory | NodeKind::ShardedDirectory => 1,
+                    NodeKind::File | NodeKind::Symlink => 0,
+                };
+
+                LsEntry {
+                    name: entry.name,
+                    kind,
+                    size: entry.size,
+                    hash: entry.cid.to_string(),
+                }
+            } else {
+                LsEntry {
+                    name: entry.name,
+                    kind: 0,
+                    size: 0,
+                    hash: String::new(),
+                }
+            }
+        })
+        .collect();
+
+    Ok(reply::json(&LsResponse { entries }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FlushArgs {
+    arg: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct FlushResponse {
+    cid: String,
+}
+
+pub fn flush<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<FlushArgs>())
+        .and_then(flush_inner)
+}
+
+async fn flush_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: FlushArgs,
+) -> Result<impl Reply, Rejection> {
+    let path = args.arg.as_deref().unwrap_or("/");
+    let cid = ipfs.files_flush(path).await.map_err(StringError::from)?;
+
+    Ok(reply::json(&FlushResponse {
+        cid: cid.to_string(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SourceDestination;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn source_and_destination() {
+        let args = SourceDestination::try_from("arg=%2Fa&arg=%2Fb%2Fc&flush=false").unwrap();
+        assert_eq!(args.source, "/a");
+        assert_eq!(args.destination, "/b/c");
+        assert!(!args.flush);
+
+        SourceDestination::try_from("arg=%2Fa").unwrap_err();
+    }
+}
@@ -0,0 +1,388 @@
+//! `/api/v0/object/patch/*` endpoints for editing existing UnixFs trees and
+//! `/api/v0/object/diff` for comparing trees, see
+//! https://docs.ipfs.io/reference/http/api/#api-v0-object-patch-add-link for the go-ipfs
+//! counterparts.
+
+use crate::v0::support::option_parsing::ParseError;
+use crate::v0::support::{try_only_named_multipart, with_ipfs, StringError};
+use bytes::Buf;
+use cid::Cid;
+use futures::stream::Stream;
+use ipfs::dag::ResolvedNode;
+use ipfs::diff::{Change, ChangeKind};
+use ipfs::{Ipfs, IpfsPath, IpfsTypes};
+use mime::Mime;
+use serde_json::json;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use warp::{reply, Filter, Rejection, Reply};
+
+/// The appended bytes are collected into memory up to this limit.
+const APPEND_SIZE_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Arguments shared by the patch endpoints: the root followed by the endpoint specific `arg`
+/// fields.
+#[derive(Debug)]
+pub struct PatchArgs {
+    root: Cid,
+    args: Vec<String>,
+    create: bool,
+}
+
+impl<'a> TryFrom<&'a str> for PatchArgs {
+    type Error = ParseError<'a>;
+
+    fn try_from(q: &'a str) -> Result<Self, Self::Error> {
+        use ParseError::*;
+
+        let mut args = Vec::new();
+        let mut create = None;
+
+        for (key, value) in url::form_urlencoded::parse(q.as_bytes()) {
+            match &*key {
+                "arg" => args.push(value.into_owned()),
+                "create" if create.is_none() => match value.parse::<bool>() {
+                    Ok(value) => create = Some(value),
+                    Err(_) => return Err(InvalidBoolean(key, value)),
+                },
+                "create" => return Err(DuplicateField(key)),
+                _ => {
+                    // ignore unknown fields
+                }
+            }
+        }
+
+        if args.is_empty() {
+            return Err(MissingArg);
+        }
+
+        let root = args.remove(0);
+        let root = Cid::try_from(root.trim_start_matches("/ipfs/"))
+            .map_err(|e| InvalidCid(Cow::Owned(root), e))?;
+
+        Ok(PatchArgs {
+            root,
+            args,
+            create: create.unwrap_or(false),
+        })
+    }
+}
+
+impl PatchArgs {
+    /// Returns the arguments after the root, checking that there are as many as expected.
+    fn expect_args(&self, expected: &[&str]) -> Result<&[String], StringError> {
+        if self.args.len() != expected.len() {
+            return Err(StringError::from(format!(
+                "expected the root and {}, got {} arguments",
+                expected.join(" and "),
+                self.args.len() + 1
+            )));
+        }
+        Ok(&self.args)
+    }
+}
+
+fn patch_args() -> impl Filter<Extract = (PatchArgs,), Error = Rejection> + Clone {
+    warp::filters::query::raw().and_then(|q: String| {
+        let res = PatchArgs::try_from(q.as_str())
+            .map_err(StringError::from)
+            .map_err(warp::reject::custom);
+
+        futures::future::ready(res)
+    })
+}
+
+/// Adds a link named by the second `arg` to the `Cid` in the third `arg`; the name can be a path
+/// with `create` creating the missing directories.
+pub fn add_link<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(patch_args()).and_then(add_link_inner)
+}
+
+async fn add_link_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: PatchArgs,
+) -> Result<impl Reply, Rejection> {
+    let (name, target) = match args.expect_args(&["name", "ref"])? {
+        [name, target] => (name, target),
+        _ => unreachable!("the count of the arguments was checked"),
+    };
+
+    let target = Cid::try_from(target.trim_start_matches("/ipfs/")).map_err(StringError::from)?;
+
+    let root = ipfs
+        .patch_add_link(&args.root, name, target, args.create)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(reply::json(&json!({ "Hash": root.to_string() })))
+}
+
+/// Removes the link named by the second `arg`, which can be a path.
+pub fn rm_link<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(patch_args()).and_then(rm_link_inner)
+}
+
+async fn rm_link_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: PatchArgs,
+) -> Result<impl Reply, Rejection> {
+    let name = &args.expect_args(&["name"])?[0];
+
+    let root = ipfs
+        .patch_rm_link(&args.root, name)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(reply::json(&json!({ "Hash": root.to_string() })))
+}
+
+/// Appends the posted bytes to the file. Unlike with go-ipfs, which appends to the `Data` of any
+/// dag-pb node, the root needs to be a UnixFs file and the bytes are appended to its contents.
+pub fn append_data<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(patch_args())
+        .and(warp::header::<Mime>("content-type")) // TODO: rejects if missing
+        .and(warp::body::stream())
+        .and_then(append_data_inner)
+}
+
+async fn append_data_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: PatchArgs,
+    mime: Mime,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin,
+) -> Result<impl Reply, Rejection> {
+    args.expect_args(&[])?;
+
+    let boundary = mime
+        .get_param("boundary")
+        .map(|v| v.to_string())
+        .ok_or_else(|| StringError::from("missing 'boundary' on content-type"))?;
+
+    let data = try_only_named_multipart(&["data", "file"], APPEND_SIZE_LIMIT, boundary, body)
+        .await
+        .map_err(StringError::from)?;
+
+    let root = ipfs
+        .append_to_file(&args.root, &data)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(reply::json(&json!({ "Hash": root.to_string() })))
+}
+
+/// The two paths of `/api/v0/object/diff`.
+#[derive(Debug)]
+pub struct DiffArgs {
+    a: IpfsPath,
+    b: IpfsPath,
+}
+
+impl<'a> TryFrom<&'a str> for DiffArgs {
+    type Error = StringError;
+
+    fn try_from(q: &'a str) -> Result<Self, Self::Error> {
+        let mut paths = Vec::with_capacity(2);
+
+        for (key, value) in url::form_urlencoded::parse(q.as_bytes()) {
+            if key == "arg" {
+                paths.push(IpfsPath::try_from(&*value).map_err(StringError::from)?);
+            }
+            // ignore unknown fields, such as verbose which only affects the cli output
+        }
+
+        let mut paths = paths.into_iter();
+        match (paths.next(), paths.next(), paths.next()) {
+            (Some(a), Some(b), None) => Ok(DiffArgs { a, b }),
+            _ => Err(StringError::from("expected two paths")),
+        }
+    }
+}
+
+/// Compares the trees at the two paths, responding with the changes in the same format as
+/// go-ipfs.
+pub fn diff<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(warp::filters::query::raw().and_then(|q: String| {
+            futures::future::ready(DiffArgs::try_from(q.as_str()).map_err(warp::reject::custom))
+        }))
+        .and_then(diff_inner)
+}
+
+async fn diff_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: DiffArgs) -> Result<impl Reply, Rejection> {
+    let a = resolve_block(&ipfs, args.a).await?;
+    let b = resolve_block(&ipfs, args.b).await?;
+
+    let changes = ipfs.diff(&a, &b).await.map_err(StringError::from)?;
+
+    let changes = changes
+        .into_iter()
+        .map(
+            |Change {
+                 kind,
+                 path,
+                 before,
+                 after,
+             }| {
+                // the numbering of the go-ipfs dagutils.ChangeType
+                let kind = match kind {
+                    ChangeKind::Added => 0,
+                    ChangeKind::Removed => 1,
+                    ChangeKind::Modified => 2,
+                };
+                let link = |cid: Option<Cid>| cid.map(|cid| json!({ "/": cid.to_string() }));
+
+                json!({
+                    "Type": kind,
+                    "Path": path,
+                    "Before": link(before),
+                    "After": link(after),
+                })
+            },
+        )
+        .collect::<Vec<_>>();
+
+    Ok(reply::json(&json!({ "Changes": changes })))
+}
+
+async fn resolve_block<T: IpfsTypes>(ipfs: &Ipfs<T>, path: IpfsPath) -> Result<Cid, StringError> {
+    let (resolved, _) = ipfs
+        .dag()
+        .resolve(path, true)
+        .await
+        .map_err(StringError::from)?;
+
+    match resolved {
+        ResolvedNode::Block(block) => Ok(block.cid),
+        _ => Err(StringError::from("the path does not end in a block")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiffArgs, PatchArgs};
+    use cid::Cid;
+    use ipfs::Node;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn patch_args() {
+        let args = PatchArgs::try_from(
+            "arg=QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn&arg=a%2Fb&create=true",
+        )
+        .unwrap();
+        assert_eq!(args.args, &["a/b"]);
+        assert!(args.create);
+
+        PatchArgs::try_from("arg=foo").unwrap_err();
+        PatchArgs::try_from("create=true").unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn add_link_append_and_rm_link() {
+        let ipfs = Node::new("test_node").await;
+
+        let file = ipfs
+            .add_reader(&b"foo"[..], Default::default())
+            .await
+            .unwrap();
+
+        // the empty directory is stored when the mutable file system is first used
+        let empty = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+        ipfs.files_flush("/").await.unwrap();
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!(
+                "/object/patch/add-link?arg={}&arg=dir%2Ffile&arg={}&create=true",
+                empty, file.cid
+            ))
+            .reply(&warp::path!("object" / "patch" / "add-link").and(super::add_link(&ipfs)))
+            .await;
+
+        assert_eq!(response.status(), 200, "{:?}", response.body());
+        let root: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let root = root["Hash"].as_str().unwrap();
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/object/patch/append-data?arg={}", file.cid))
+            .header("content-type", "multipart/form-data; boundary=boundary")
+            .body(
+                "--boundary\r\n\
+                Content-Disposition: form-data; name=\"data\"\r\n\
+                Content-Type: application/octet-stream\r\n\r\n\
+                bar\r\n\
+                --boundary--\r\n",
+            )
+            .reply(&warp::path!("object" / "patch" / "append-data").and(super::append_data(&ipfs)))
+            .await;
+
+        assert_eq!(response.status(), 200, "{:?}", response.body());
+        let appended: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+
+        let expected = ipfs
+            .add_reader(&b"foobar"[..], Default::default())
+            .await
+            .unwrap();
+        assert_eq!(appended["Hash"], expected.cid.to_string());
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/object/patch/rm-link?arg={}&arg=dir", root))
+            .reply(&warp::path!("object" / "patch" / "rm-link").and(super::rm_link(&ipfs)))
+            .await;
+
+        assert_eq!(response.status(), 200, "{:?}", response.body());
+        let removed: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(removed["Hash"], empty);
+    }
+
+    #[tokio::test]
+    async fn diff() {
+        let ipfs = Node::new("test_node").await;
+
+        let file = ipfs
+            .add_reader(&b"foo"[..], Default::default())
+            .await
+            .unwrap();
+
+        let empty = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+        ipfs.files_flush("/").await.unwrap();
+        let empty = Cid::try_from(empty).unwrap();
+
+        let root = ipfs
+            .patch_add_link(&empty, "file", file.cid.clone(), false)
+            .await
+            .unwrap();
+
+        DiffArgs::try_from(&*format!("arg={}", root)).unwrap_err();
+
+        let response = warp::test::request()
+            .path(&format!("/object/diff?arg={}&arg=/ipfs/{}", empty, root))
+            .reply(&warp::path!("object" / "diff").and(super::diff(&ipfs)))
+            .await;
+
+        assert_eq!(response.status(), 200, "{:?}", response.body());
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "Changes": [{
+                    "Type": 0,
+                    "Path": "file",
+                    "Before": null,
+                    "After": { "/": file.cid.to_string() },
+                }]
+            })
+        );
+    }
+}
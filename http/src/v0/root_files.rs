@@ -1,22 +1,30 @@
 use crate::v0::support::{
-    with_ipfs, MaybeTimeoutExt, StreamResponseText, StringError, StringSerialized,
+    with_ipfs, MaybeTimeoutExt, StreamResponse, StreamResponseText, StringError, StringSerialized,
 };
 use async_stream::try_stream;
-use bytes::Bytes;
-use futures::stream::TryStream;
+use bytes::{BufMut, Bytes, BytesMut};
+use flate2::{write::GzEncoder, Compression};
+use futures::stream::{Stream, TryStream};
 use ipfs::unixfs::ll::walk::{self, ContinuedWalk, Walker};
-use ipfs::unixfs::{ll::file::FileReadFailed, TraversalFailed};
+use ipfs::unixfs::ll::{node_info, NodeKind};
+use ipfs::unixfs::{ll::file::FileReadFailed, ReadAhead, StartingPoint, TraversalFailed};
 use ipfs::{dag::ResolveError, Block, Ipfs, IpfsPath, IpfsTypes};
 use serde::Deserialize;
 use std::fmt;
+use std::io::Write;
 use std::path::Path;
 use warp::{query, Filter, Rejection, Reply};
 
 mod tar_helper;
 use tar_helper::TarHelper;
 
+mod zip_helper;
+use zip_helper::ZipHelper;
+
 mod add;
 
+mod ls;
+
 #[derive(Debug, Deserialize)]
 pub struct AddArgs {
     // unknown meaning; ignoring it doesn't fail any tests
@@ -28,18 +36,76 @@ pub struct AddArgs {
     /// When true, a new directory is created to hold more than 1 root level directories.
     #[serde(default, rename = "wrap-with-directory")]
     wrap_with_directory: bool,
+    /// Chunking algorithm: `size-{size}`, `rabin`, `rabin-{avg}`, `rabin-{min}-{avg}-{max}` or
+    /// `buzhash`.
+    chunker: Option<String>,
+    /// When true, the files are added using the trickle layout instead of the balanced one.
+    #[serde(default)]
+    trickle: bool,
+    /// When true, the file contents are stored as raw blocks. Defaults to true with Cid version 1.
+    #[serde(rename = "raw-leaves")]
+    raw_leaves: Option<bool>,
+    /// The Cid version of the created blocks, 0 or 1.
+    #[serde(rename = "cid-version")]
+    cid_version: Option<u8>,
+    /// When true, the blocks up to `inline-limit` bytes are inlined in the Cids.
+    #[serde(default)]
+    inline: bool,
+    /// The largest block size to inline, in bytes. Defaults to 32.
+    #[serde(rename = "inline-limit")]
+    inline_limit: Option<usize>,
+    /// When true, the `mode` headers of the multipart fields are stored.
+    #[serde(default, rename = "preserve-mode")]
+    preserve_mode: bool,
+    /// When true, the `mtime` and `mtime-nsecs` headers of the multipart fields are stored.
+    #[serde(default, rename = "preserve-mtime")]
+    preserve_mtime: bool,
+    /// When true, only the Cids are calculated and nothing is stored.
+    #[serde(default, rename = "only-hash")]
+    only_hash: bool,
+    /// When true, the entries with a name beginning with a dot are added as well. By default they
+    /// are skipped, except for the top level entries.
+    #[serde(default)]
+    hidden: bool,
+    /// `.gitignore` style rules for the entries to skip, one per `exclude` argument. More rules
+    /// can be posted in an `ignore-rules` field before the entries.
+    #[serde(skip)]
+    exclude: Vec<String>,
+    /// When true, the entries are expected to be posted in sorted order, depth first, and the
+    /// directories are added as soon as they are complete instead of keeping the whole tree in
+    /// memory until the end. Not supported by go-ipfs.
+    #[serde(default)]
+    sorted: bool,
 }
 
 pub fn add<T: IpfsTypes>(
     ipfs: &Ipfs<T>,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
     with_ipfs(ipfs)
-        .and(query::<AddArgs>())
+        .and(add_args())
         .and(warp::header::<mime::Mime>("content-type")) // TODO: rejects if missing
         .and(warp::body::stream())
         .and_then(add::add_inner)
 }
 
+/// Deserializes the [`AddArgs`] and gathers the repeated `exclude` arguments, which the plain
+/// query deserialization does not allow.
+fn add_args() -> impl Filter<Extract = (AddArgs,), Error = Rejection> + Clone {
+    query::<AddArgs>()
+        .and(
+            warp::filters::query::raw()
+                .or(warp::any().map(String::new))
+                .unify(),
+        )
+        .map(|mut args: AddArgs, q: String| {
+            args.exclude = url::form_urlencoded::parse(q.as_bytes())
+                .filter(|(key, _)| key == "exclude")
+                .map(|(_, value)| value.into_owned())
+                .collect();
+            args
+        })
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CatArgs {
     arg: StringSerialized<IpfsPath>,
@@ -86,10 +152,54 @@ async fn cat_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: CatArgs) -> Result<impl Re
     Ok(StreamResponseText(stream))
 }
 
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LsArgs {
+    arg: StringSerialized<IpfsPath>,
+    /// When true, the entries are loaded to find out if they are files or directories.
+    #[serde(default = "default_true", rename = "resolve-type")]
+    resolve_type: bool,
+    /// When true, the file sizes of the entries are reported. Requires loading the entries.
+    #[serde(default = "default_true")]
+    size: bool,
+    timeout: Option<StringSerialized<humantime::Duration>>,
+}
+
+pub fn ls<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<LsArgs>())
+        .and_then(ls::ls_inner)
+}
+
 #[derive(Deserialize)]
 struct GetArgs {
     arg: StringSerialized<IpfsPath>,
     timeout: Option<StringSerialized<humantime::Duration>>,
+    /// `true` or `false` like with go-ipfs, where a tar archive is returned in either case unless
+    /// a single file is compressed without archiving, or `zip` for a zip archive.
+    archive: Option<String>,
+    /// When true, the tar archive or the single file is compressed with gzip, or the files in a
+    /// zip archive are deflated.
+    #[serde(default)]
+    compress: bool,
+    /// The compression level from 1 to 9.
+    #[serde(rename = "compression-level")]
+    compression_level: Option<u32>,
+}
+
+/// The output of `get` selected by the `archive` argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Archive {
+    /// Not archiving, but a tar archive is still used to transport anything but a single
+    /// compressed file.
+    No,
+    Tar,
+    Zip,
 }
 
 pub fn get<T: IpfsTypes>(
@@ -99,7 +209,7 @@ pub fn get<T: IpfsTypes>(
 }
 
 async fn get_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: GetArgs) -> Result<impl Reply, Rejection> {
-    use futures::stream::TryStreamExt;
+    use futures::stream::{StreamExt, TryStreamExt};
 
     let path = args.arg.into_inner();
 
@@ -109,10 +219,91 @@ async fn get_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: GetArgs) -> Result<impl Re
         .await
         .map_err(StringError::from)?
         .map_err(StringError::from)?;
-    Ok(StreamResponseText(walk(ipfs, block).into_stream()))
+
+    let archive = match args.archive.as_deref() {
+        None | Some("false") => Archive::No,
+        Some("true") => Archive::Tar,
+        Some("zip") => Archive::Zip,
+        Some(other) => {
+            return Err(StringError::from(format!("unsupported archive: {:?}", other)).into())
+        }
+    };
+
+    let compression = match (args.compress, args.compression_level) {
+        (false, _) => None,
+        (true, None) => Some(Compression::default()),
+        (true, Some(level)) if (1..=9).contains(&level) => Some(Compression::new(level)),
+        (true, Some(_)) => {
+            return Err(StringError::from("compression level must be between 1 and 9").into())
+        }
+    };
+
+    let is_file = block.cid.codec() == cid::Codec::Raw
+        || matches!(node_info(&block.data), Ok(info) if info.kind == NodeKind::File);
+
+    // the HTTP api uses the final Cid name as the root name in the generated archive
+    let name = block.cid.to_string();
+
+    let (st, content_type) = match (archive, compression) {
+        (Archive::Zip, compression) => (
+            walk_zip(ipfs, block, name, compression)
+                .into_stream()
+                .boxed(),
+            "application/zip",
+        ),
+        (Archive::No, Some(level)) if is_file => {
+            let st = ipfs::unixfs::cat(ipfs, StartingPoint::Right(block), None)
+                .await
+                .map_err(StringError::from)?
+                .map_ok(Bytes::from)
+                .map_err(GetError::Reading);
+
+            (gzip(st, level).boxed(), "application/gzip")
+        }
+        (_, Some(level)) => (
+            gzip(walk(ipfs, block, name).into_stream(), level).boxed(),
+            "application/gzip",
+        ),
+        (_, None) => (
+            walk(ipfs, block, name).into_stream().boxed(),
+            "application/x-tar",
+        ),
+    };
+
+    Ok(StreamResponse(st, content_type))
+}
+
+/// Compresses the stream with gzip.
+fn gzip<S, E>(st: S, level: Compression) -> impl Stream<Item = Result<Bytes, E>> + 'static
+where
+    S: Stream<Item = Result<Bytes, E>> + 'static,
+    E: 'static,
+{
+    use futures::stream::TryStreamExt;
+
+    let mut encoder = GzEncoder::new(BytesMut::new().writer(), level);
+
+    try_stream! {
+        futures::pin_mut!(st);
+
+        while let Some(bytes) = st.try_next().await? {
+            encoder.write_all(&bytes).expect("writing to memory cannot fail");
+
+            let compressed = encoder.get_mut().get_mut().split();
+            if !compressed.is_empty() {
+                yield compressed.freeze();
+            }
+        }
+
+        let rest = encoder.finish().expect("writing to memory cannot fail").into_inner();
+        yield rest.freeze();
+    }
 }
 
-async fn resolve_dagpb<T: IpfsTypes>(ipfs: &Ipfs<T>, path: IpfsPath) -> Result<Block, StringError> {
+pub(super) async fn resolve_dagpb<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    path: IpfsPath,
+) -> Result<Block, StringError> {
     let (resolved, _) = ipfs
         .dag()
         .resolve(path, true)
@@ -122,30 +313,35 @@ async fn resolve_dagpb<T: IpfsTypes>(ipfs: &Ipfs<T>, path: IpfsPath) -> Result<B
     resolved.into_unixfs_block().map_err(StringError::from)
 }
 
-fn walk<Types: IpfsTypes>(
+/// Walks the tree as a tar archive, with the given name for the root. With an empty name the root
+/// directory itself is left out and the entries are relative to it.
+pub(super) fn walk<Types: IpfsTypes>(
     ipfs: Ipfs<Types>,
     Block {
         cid: root,
         data: first_block_data,
     }: Block,
+    name: String,
 ) -> impl TryStream<Ok = Bytes, Error = GetError> + 'static {
     let mut cache = None;
     let mut tar_helper = TarHelper::with_capacity(16 * 1024);
 
-    // the HTTP api uses the final Cid name as the root name in the generated tar
-    // archive.
-    let name = root.to_string();
     let mut walker = Walker::new(root, name);
 
     let mut buffer = Some(first_block_data);
 
+    // the blocks are loaded concurrently but consumed in order
+    let mut read_ahead = ReadAhead::new(ipfs);
+
     try_stream! {
         while walker.should_continue() {
             let data = match buffer.take() {
                 Some(first) => first,
                 None => {
-                    let (next, _) = walker.pending_links();
-                    let Block { data, .. } = ipfs.get_block(next).await?;
+                    let (next, rest) = walker.pending_links();
+                    read_ahead.prefetch(std::iter::once(next).chain(rest));
+                    let next = next.to_owned();
+                    let Block { data, .. } = read_ahead.get_block(&next).await?;
                     data
                 }
             };
@@ -182,6 +378,10 @@ fn walk<Types: IpfsTypes>(
                     }
                 },
                 ContinuedWalk::Directory(_, path, metadata) | ContinuedWalk::RootDirectory(_, path, metadata) => {
+                    if path.as_os_str().is_empty() {
+                        continue;
+                    }
+
                     for bytes in tar_helper.apply_directory(path, metadata)?.iter_mut() {
                         if let Some(bytes) = bytes.take() {
                             yield bytes;
@@ -204,13 +404,78 @@ fn walk<Types: IpfsTypes>(
     }
 }
 
+/// Walks the tree as a zip archive, with the given name for the root.
+fn walk_zip<Types: IpfsTypes>(
+    ipfs: Ipfs<Types>,
+    Block {
+        cid: root,
+        data: first_block_data,
+    }: Block,
+    name: String,
+    compression: Option<Compression>,
+) -> impl TryStream<Ok = Bytes, Error = GetError> + 'static {
+    let mut cache = None;
+    let mut zip_helper = ZipHelper::new(compression);
+
+    let mut walker = Walker::new(root, name);
+
+    let mut buffer = Some(first_block_data);
+
+    // the blocks are loaded concurrently but consumed in order
+    let mut read_ahead = ReadAhead::new(ipfs);
+
+    try_stream! {
+        while walker.should_continue() {
+            let data = match buffer.take() {
+                Some(first) => first,
+                None => {
+                    let (next, rest) = walker.pending_links();
+                    read_ahead.prefetch(std::iter::once(next).chain(rest));
+                    let next = next.to_owned();
+                    let Block { data, .. } = read_ahead.get_block(&next).await?;
+                    data
+                }
+            };
+
+            match walker.next(&data, &mut cache)? {
+                ContinuedWalk::Bucket(..) => {}
+                ContinuedWalk::File(segment, _, path, metadata, size) => {
+                    if segment.is_first() {
+                        yield zip_helper.apply_file(path, metadata, size);
+                    }
+
+                    if !segment.as_ref().is_empty() {
+                        let contents = zip_helper.buffer_file_contents(segment.as_ref());
+                        if !contents.is_empty() {
+                            yield contents;
+                        }
+                    }
+
+                    if segment.is_last() {
+                        yield zip_helper.finish_file();
+                    }
+                },
+                ContinuedWalk::Directory(_, path, metadata) | ContinuedWalk::RootDirectory(_, path, metadata) => {
+                    yield zip_helper.apply_directory(path, metadata);
+                },
+                ContinuedWalk::Symlink(bytes, _, path, metadata) => {
+                    yield zip_helper.apply_symlink(path, bytes, metadata);
+                },
+            };
+        }
+
+        yield zip_helper.finish();
+    }
+}
+
 #[derive(Debug)]
-enum GetError {
+pub(super) enum GetError {
     NonUtf8Symlink,
     InvalidFileName(Vec<u8>),
     InvalidLinkName(Vec<u8>),
     Walk(walk::Error),
     Loading(ipfs::Error),
+    Reading(TraversalFailed),
 }
 
 impl From<ipfs::Error> for GetError {
@@ -234,6 +499,7 @@ impl fmt::Display for GetError {
             Loading(e) => write!(fmt, "loading failed: {}", e),
             InvalidFileName(x) => write!(fmt, "filename cannot be put inside tar: {:?}", x),
             InvalidLinkName(x) => write!(fmt, "symlink name cannot be put inside tar: {:?}", x),
+            Reading(e) => write!(fmt, "{}", e),
         }
     }
 }
@@ -242,6 +508,7 @@ impl std::error::Error for GetError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             GetError::Walk(e) => Some(e),
+            GetError::Reading(e) => Some(e),
             _ => None,
         }
     }
@@ -357,6 +624,20 @@ mod tests {
         assert_eq!(found, expected);
     }
 
+    /// Blocks of a file of four blocks, with the contents `foobar\n`.
+    const MULTIBLOCK_FILE: &[&[u8]] = &[
+        // the root, QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6
+        &hex!("12280a221220fef9fe1804942b35e19e145a03f9c9d5ca9c997dda0a9416f3f515a52f1b3ce11200180a12280a221220dfb94b75acb208fd4873d84872af58bd65c731770a7d4c0deeb4088e87390bfe1200180a12280a221220054497ae4e89812c83276a48e3e679013a788b7c0eb02712df15095c02d6cd2c1200180a12280a221220cc332ceb37dea7d3d7c00d1393117638d3ed963575836c6d44a24951e444cf5d120018090a0c080218072002200220022001"),
+        // first bytes: fo
+        &hex!("0a0808021202666f1802"),
+        // ob
+        &hex!("0a08080212026f621802"),
+        // ar
+        &hex!("0a080802120261721802"),
+        // \n
+        &hex!("0a07080212010a1801"),
+    ];
+
     #[tokio::test]
     async fn get_multiblock_file() {
         let ipfs = Node::new("test_node").await;
@@ -397,6 +678,196 @@ mod tests {
         assert_eq!(found, expected);
     }
 
+    #[tokio::test]
+    async fn get_compressed_file() {
+        use std::io::Read;
+
+        let ipfs = Node::new("test_node").await;
+
+        drop(put_all_blocks(&ipfs, MULTIBLOCK_FILE).await.unwrap());
+
+        let filter = super::get(&ipfs);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/get?arg=QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6&compress=true&compression-level=9")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "application/gzip");
+
+        // without archive only the file contents are compressed
+        let mut contents = Vec::new();
+        flate2::read::GzDecoder::new(response.body().as_ref())
+            .read_to_end(&mut contents)
+            .unwrap();
+
+        assert_eq!(contents, b"foobar\n");
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/get?arg=QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6&archive=true&compress=true")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "application/gzip");
+
+        let mut archive = Vec::new();
+        flate2::read::GzDecoder::new(response.body().as_ref())
+            .read_to_end(&mut archive)
+            .unwrap();
+
+        let expected = vec![Entry::File(
+            "QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6".into(),
+            7,
+            b"foobar\n".to_vec(),
+        )];
+
+        assert_eq!(get_archive_entries(archive), expected);
+    }
+
+    #[tokio::test]
+    async fn get_zip_archive() {
+        use std::io::Read;
+
+        let ipfs = Node::new("test_node").await;
+
+        drop(put_all_blocks(&ipfs, MULTIBLOCK_FILE).await.unwrap());
+
+        let filter = super::get(&ipfs);
+
+        for (query, method) in &[
+            ("archive=zip", zip::CompressionMethod::Stored),
+            (
+                "archive=zip&compress=true",
+                zip::CompressionMethod::Deflated,
+            ),
+        ] {
+            let response = warp::test::request()
+                .method("POST")
+                .path(&format!(
+                    "/get?arg=QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6&{}",
+                    query
+                ))
+                .reply(&filter)
+                .await;
+
+            assert_eq!(response.status(), 200);
+            assert_eq!(response.headers()["content-type"], "application/zip");
+
+            let mut archive =
+                zip::ZipArchive::new(std::io::Cursor::new(response.body().as_ref())).unwrap();
+
+            assert_eq!(archive.len(), 1);
+
+            let mut file = archive.by_index(0).unwrap();
+            assert_eq!(
+                file.name(),
+                "QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6"
+            );
+            assert_eq!(file.compression(), *method);
+            assert_eq!(file.unix_mode(), Some(0o100644));
+
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents).unwrap();
+            assert_eq!(contents, b"foobar\n");
+        }
+    }
+
+    #[tokio::test]
+    async fn get_with_invalid_compression_level() {
+        let ipfs = Node::new("test_node").await;
+
+        drop(put_all_blocks(&ipfs, MULTIBLOCK_FILE).await.unwrap());
+
+        let filter = super::get(&ipfs);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/get?arg=QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6&compress=true&compression-level=10")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 500);
+    }
+
+    #[tokio::test]
+    async fn ls_plain_and_sharded_directories() {
+        use ipfs::unixfs::ll::dir::builder::{BufferingTreeBuilder, TreeOptions};
+
+        let ipfs = Node::new("test_node").await;
+
+        let file = put_all_blocks(&ipfs, MULTIBLOCK_FILE).await.unwrap()[0].clone();
+
+        for &threshold in &[None, Some(1)] {
+            let mut opts = TreeOptions::default();
+            opts.sharding_threshold(threshold);
+
+            let mut builder = BufferingTreeBuilder::new(opts);
+            for name in &["c", "a", "b"] {
+                builder.put_link(name, file.clone(), 7).unwrap();
+            }
+
+            let mut root = None;
+            for node in builder.build() {
+                let node = node.unwrap();
+                root = Some(node.cid.clone());
+                ipfs.put_block(Block::new(node.block, node.cid))
+                    .await
+                    .unwrap();
+            }
+            let root = root.unwrap();
+
+            let filter = super::ls(&ipfs);
+
+            let response = warp::test::request()
+                .method("POST")
+                .path(&format!("/ls?arg={}", root))
+                .reply(&filter)
+                .await;
+
+            assert_eq!(response.status(), 200);
+
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            let object = &body["Objects"][0];
+            assert_eq!(object["Hash"], root.to_string());
+
+            let mut links = object["Links"].as_array().unwrap().clone();
+            links.sort_by_key(|link| link["Name"].as_str().unwrap().to_owned());
+
+            let expected = ["a", "b", "c"]
+                .iter()
+                .map(|name| {
+                    serde_json::json!({
+                        "Name": name,
+                        "Hash": file.to_string(),
+                        "Size": 7,
+                        "Type": 2,
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(links, expected, "threshold {:?}", threshold);
+
+            let response = warp::test::request()
+                .method("POST")
+                .path(&format!("/ls?arg={}&resolve-type=false&size=false", root))
+                .reply(&filter)
+                .await;
+
+            assert_eq!(response.status(), 200);
+
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            let links = body["Objects"][0]["Links"].as_array().unwrap();
+            assert_eq!(links.len(), 3);
+            assert!(links
+                .iter()
+                .all(|link| link["Type"] == 0 && link["Size"] == 0));
+        }
+    }
+
     fn get_archive_entries(bytes: impl AsRef<[u8]>) -> Vec<Entry> {
         let mut cursor = std::io::Cursor::new(bytes.as_ref());
 
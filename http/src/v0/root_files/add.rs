@@ -5,10 +5,13 @@ use cid::Cid;
 use futures::stream::{Stream, StreamExt, TryStreamExt};
 use ipfs::unixfs::ll::{
     dir::builder::{
-        BufferingTreeBuilder, TreeBuildingFailed, TreeConstructionFailed, TreeNode, TreeOptions,
+        BufferingTreeBuilder, OwnedTreeNode, StreamingTreeBuilder, StreamingTreeBuildingFailed,
+        TreeBuildingFailed, TreeConstructionFailed,
     },
-    file::adder::FileAdder,
+    file::adder::{Chunker, FileAdder},
+    Metadata,
 };
+use ipfs::unixfs::{AddOptions, IgnoreRules};
 use ipfs::{Block, Ipfs, IpfsTypes};
 use mime::Mime;
 use mpart_async::server::{MultipartError, MultipartStream};
@@ -28,12 +31,61 @@ pub(super) async fn add_inner<T: IpfsTypes>(
         .map(|v| v.to_string())
         .ok_or_else(|| StringError::from("missing 'boundary' on content-type"))?;
 
+    let chunker = opts
+        .chunker
+        .as_deref()
+        .map(parse_chunker)
+        .transpose()?
+        .unwrap_or_default();
+
+    let cid_version = match opts.cid_version {
+        None | Some(0) => cid::Version::V0,
+        Some(1) => cid::Version::V1,
+        Some(other) => {
+            return Err(StringError::from(format!("unsupported cid-version: {}", other)).into())
+        }
+    };
+
+    let inline_limit = opts.inline_limit.unwrap_or(DEFAULT_INLINE_LIMIT);
+    if opts.inline && inline_limit > INLINE_LIMIT_MAX {
+        return Err(StringError::from(format!(
+            "inline-limit {} exceeds the maximum of {} bytes",
+            inline_limit, INLINE_LIMIT_MAX
+        ))
+        .into());
+    }
+
+    let mut rules = IgnoreRules::default();
+
+    for rule in &opts.exclude {
+        rules.add(rule);
+    }
+
+    // the files and directories are added the same way as with `Ipfs::add_path`
+    let add_opts = AddOptions {
+        chunker,
+        trickle: opts.trickle,
+        raw_leaves: opts.raw_leaves,
+        cid_version,
+        inline_limit: if opts.inline {
+            Some(inline_limit)
+        } else {
+            None
+        },
+        wrap_with_directory: opts.wrap_with_directory,
+        hidden: opts.hidden,
+        preserve_mode: opts.preserve_mode,
+        preserve_mtime: opts.preserve_mtime,
+        only_hash: opts.only_hash,
+        ..Default::default()
+    };
+
     let st = MultipartStream::new(
         Bytes::from(boundary),
         body.map_ok(|mut buf| buf.copy_to_bytes(buf.remaining())),
     );
 
-    let st = add_stream(ipfs, st, opts);
+    let st = add_stream(ipfs, st, opts, add_opts, rules);
 
     // map the errors into json objects; as we can't return them as trailers yet
 
@@ -67,12 +119,16 @@ enum AddError {
     Parsing(MultipartError),
     Header(MultipartError),
     InvalidFilename(std::str::Utf8Error),
+    InvalidIgnoreRules(std::str::Utf8Error),
+    IgnoreRulesTooLarge,
     UnsupportedField(String),
     UnsupportedContentType(String),
     ResponseSerialization(serde_json::Error),
     Persisting(ipfs::Error),
     TreeGathering(TreeBuildingFailed),
+    SortedTreeGathering(StreamingTreeBuildingFailed),
     TreeBuilding(TreeConstructionFailed),
+    InvalidMetadata(&'static str, String),
 }
 
 impl From<MultipartError> for AddError {
@@ -88,12 +144,16 @@ impl fmt::Display for AddError {
             Parsing(me) => write!(fmt, "invalid request body: {}", me),
             Header(me) => write!(fmt, "invalid multipart header(s): {}", me),
             InvalidFilename(e) => write!(fmt, "invalid multipart filename: {:?}", e),
+            InvalidIgnoreRules(e) => write!(fmt, "invalid ignore rules: {}", e),
+            IgnoreRulesTooLarge => write!(fmt, "ignore rules exceed the maximum of {} bytes", IGNORE_RULES_MAX),
             UnsupportedField(name) => write!(fmt, "unsupported field name: {:?}", name),
             UnsupportedContentType(t) => write!(fmt, "unsupported content-type: {:?} (supported: application/{{octet-stream,x-directory}})", t),
             ResponseSerialization(e) => write!(fmt, "progress serialization failed: {}", e),
             Persisting(e) => write!(fmt, "put_block failed: {}", e),
             TreeGathering(g) => write!(fmt, "invalid directory tree: {}", g),
+            SortedTreeGathering(g) => write!(fmt, "invalid directory tree: {}", g),
             TreeBuilding(b) => write!(fmt, "constructed invalid directory tree: {}", b),
+            InvalidMetadata(header, value) => write!(fmt, "invalid {} header: {:?}", header, value),
         }
     }
 }
@@ -104,6 +164,8 @@ fn add_stream<St, E>(
     ipfs: Ipfs<impl IpfsTypes>,
     mut fields: MultipartStream<St, E>,
     opts: AddArgs,
+    add_opts: AddOptions,
+    mut rules: IgnoreRules,
 ) -> impl Stream<Item = Result<Bytes, AddError>> + Send + 'static
 where
     St: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
@@ -111,12 +173,13 @@ where
 {
     async_stream::try_stream! {
 
-        let mut tree_opts = TreeOptions::default();
-        if opts.wrap_with_directory {
-            tree_opts.wrap_with_directory();
-        }
+        let tree_opts = add_opts.tree_options();
 
-        let mut tree = BufferingTreeBuilder::new(tree_opts);
+        let mut tree = if opts.sorted {
+            Tree::Streaming(StreamingTreeBuilder::new(tree_opts))
+        } else {
+            Tree::Buffering(BufferingTreeBuilder::new(tree_opts))
+        };
         let mut buffer = BytesMut::new();
 
         while let Some(mut field) = fields
@@ -125,6 +188,26 @@ where
         {
 
             let field_name = field.name().map_err(AddError::Header)?;
+
+            if field_name == "ignore-rules" {
+                // the rules apply to the entries posted after them
+                let mut contents = BytesMut::new();
+                while let Some(next) = field.try_next().await.map_err(AddError::Parsing)? {
+                    let _ = if contents.len() + next.len() > IGNORE_RULES_MAX {
+                        Err(AddError::IgnoreRulesTooLarge)
+                    } else {
+                        Ok(())
+                    }?;
+                    contents.extend_from_slice(&next);
+                }
+
+                let contents = std::str::from_utf8(&contents).map_err(AddError::InvalidIgnoreRules)?;
+                for rule in contents.lines() {
+                    rules.add(rule);
+                }
+                continue;
+            }
+
             let filename = field.filename().map_err(AddError::Header)?;
             let filename = percent_encoding::percent_decode_str(filename)
                 .decode_utf8()
@@ -143,6 +226,12 @@ where
 
             let content_type = field.content_type().map_err(AddError::Header)?;
 
+            if skipped(&filename, content_type == "application/x-directory", &add_opts, &rules) {
+                // the skipped part needs to be fully consumed as well
+                while field.try_next().await.map_err(AddError::Parsing)?.is_some() {}
+                continue;
+            }
+
             let next = match content_type {
                 "application/octet-stream" => {
 
@@ -153,7 +242,8 @@ where
                         Ok(())
                     }?;
 
-                    let mut adder = FileAdder::default();
+                    let metadata = field_metadata(field.headers(), &opts)?;
+                    let mut adder = add_opts.file_adder(metadata);
                     // how many bytes we have stored as blocks
                     let mut total_written = 0u64;
                     // how many bytes of input we have read
@@ -167,7 +257,7 @@ where
 
                         match next {
                             Some(next) => {
-                                let (read, saved_any, written) = push_all(&ipfs, &mut adder, next).await?;
+                                let (read, saved_any, written) = push_all(&ipfs, &mut adder, next, opts.only_hash).await?;
                                 total_written += written;
                                 total_read += read;
 
@@ -202,7 +292,7 @@ where
                         // response in as well
                     }
 
-                    let (root, subtotal) = import_all(&ipfs, adder.finish())
+                    let (root, subtotal) = import_all(&ipfs, adder.finish(), opts.only_hash)
                         .await
                         .map_err(AddError::Persisting)?
                         // there was a bug in ipfs-unixfs however in general the "push" operation
@@ -215,8 +305,7 @@ where
 
                     // using the filename as the path since we can tolerate a single empty named file
                     // however the second one will cause issues
-                    tree.put_link(&filename, root.clone(), total_written)
-                        .map_err(AddError::TreeGathering)?;
+                    tree.put_link(&filename, root.clone(), total_written)?;
 
                     let filename: Cow<'_, str> = if filename.is_empty() {
                         // cid needs to be repeated if no filename was given; in which case there
@@ -236,7 +325,7 @@ where
 
                     buffer.put(&b"\r\n"[..]);
 
-                    Ok(buffer.split().freeze())
+                    Ok(Some(buffer.split().freeze()))
                 },
                 "application/x-directory" => {
                     // dirs are of the form "dir-{1,2,3,..}"
@@ -246,50 +335,260 @@ where
                         Ok(())
                     }?;
 
+                    let metadata = field_metadata(field.headers(), &opts)?;
+
                     // we need to fully consume this part, even though there shouldn't be anything
-                    // except for the already parsed *but* ignored headers
+                    // except for the already parsed headers
                     while field.try_next().await.map_err(AddError::Parsing)?.is_some() {}
 
-                    // while at the moment we don't parse the mtime, mtime-nsec headers and mode
-                    // those should be reflected in the metadata. this will still add an empty
-                    // directory which is a good thing.
-                    tree.set_metadata(&filename, ipfs::unixfs::ll::Metadata::default())
-                        .map_err(AddError::TreeGathering)?;
-                    continue;
+                    // this will also add an empty directory which is a good thing.
+                    tree.set_metadata(&filename, metadata)?;
+                    Ok(None)
                 }
                 unsupported => {
                     Err(AddError::UnsupportedContentType(unsupported.to_string()))
                 }
             }?;
 
-            yield next;
+            if let Some(next) = next {
+                yield next;
+            }
+
+            // the directories completed by the latest entry, only when streaming the tree
+            for node in tree.completed().map_err(AddError::TreeBuilding)? {
+                if directory_added(&ipfs, node, opts.only_hash, &mut buffer).await? {
+                    yield buffer.split().freeze();
+                }
+            }
+        }
+
+        let mut iter = tree.finish();
+
+        while let Some(res) = iter.next() {
+            let node = res.map_err(AddError::TreeBuilding)?;
+
+            if directory_added(&ipfs, node, opts.only_hash, &mut buffer).await? {
+                yield buffer.split().freeze();
+            }
         }
+    }
+}
+
+/// The directory tree of the added entries, either buffered until the whole request has been
+/// read or, with the `sorted` option, streamed as the directories are completed.
+enum Tree {
+    Buffering(BufferingTreeBuilder),
+    Streaming(StreamingTreeBuilder),
+}
+
+impl Tree {
+    fn put_link(&mut self, full_path: &str, target: Cid, total_size: u64) -> Result<(), AddError> {
+        match self {
+            Tree::Buffering(tree) => tree
+                .put_link(full_path, target, total_size)
+                .map_err(AddError::TreeGathering),
+            Tree::Streaming(tree) => tree
+                .put_link(full_path, target, total_size)
+                .map_err(AddError::SortedTreeGathering),
+        }
+    }
+
+    fn set_metadata(&mut self, full_path: &str, metadata: Metadata) -> Result<(), AddError> {
+        match self {
+            Tree::Buffering(tree) => tree
+                .set_metadata(full_path, metadata)
+                .map_err(AddError::TreeGathering),
+            Tree::Streaming(tree) => tree
+                .set_metadata(full_path, metadata)
+                .map_err(AddError::SortedTreeGathering),
+        }
+    }
+
+    /// Returns the directories completed so far, which are always none when buffering.
+    fn completed(&mut self) -> Result<Vec<OwnedTreeNode>, TreeConstructionFailed> {
+        match self {
+            Tree::Buffering(_) => Ok(Vec::new()),
+            Tree::Streaming(tree) => tree.completed().collect(),
+        }
+    }
+
+    fn finish(
+        self,
+    ) -> Box<dyn Iterator<Item = Result<OwnedTreeNode, TreeConstructionFailed>> + Send> {
+        match self {
+            Tree::Buffering(tree) => Box::new(tree.build()),
+            Tree::Streaming(tree) => Box::new(tree.finish()),
+        }
+    }
+}
+
+/// Stores the directory node unless `only_hash` is given and writes the response line for it,
+/// returning false for the inner nodes of sharded directories, which are not reported.
+async fn directory_added(
+    ipfs: &Ipfs<impl IpfsTypes>,
+    node: OwnedTreeNode,
+    only_hash: bool,
+    buffer: &mut BytesMut,
+) -> Result<bool, AddError> {
+    let OwnedTreeNode {
+        path,
+        cid,
+        total_size,
+        block,
+        bucket,
+    } = node;
+
+    if !only_hash {
+        ipfs.put_block(Block {
+            cid: cid.clone(),
+            data: block,
+        })
+        .await
+        .map_err(AddError::Persisting)?;
+    }
 
-        let mut iter = tree.build();
+    if bucket {
+        return Ok(false);
+    }
 
-        while let Some(res) = iter.next_borrowed() {
-            let TreeNode { path, cid, total_size, block } = res.map_err(AddError::TreeBuilding)?;
+    serde_json::to_writer(
+        (&mut *buffer).writer(),
+        &Response::Added {
+            name: Cow::Owned(path),
+            hash: Quoted(&cid),
+            size: Quoted(total_size),
+        },
+    )
+    .map_err(AddError::ResponseSerialization)?;
 
-            // shame we need to allocate once again here..
-            ipfs.put_block(Block { cid: cid.to_owned(), data: block.into() }).await.map_err(AddError::Persisting)?;
+    buffer.put(&b"\r\n"[..]);
 
-            serde_json::to_writer((&mut buffer).writer(), &Response::Added {
-                name: Cow::Borrowed(path),
-                hash: Quoted(cid),
-                size: Quoted(total_size),
-            }).map_err(AddError::ResponseSerialization)?;
+    Ok(true)
+}
 
-            buffer.put(&b"\r\n"[..]);
+/// Returns true if the posted entry should not be added. The top level entries are always added
+/// and the rest are skipped like the entries of the directories walked by `Ipfs::add_path`.
+fn skipped(filename: &str, is_dir: bool, opts: &AddOptions, rules: &IgnoreRules) -> bool {
+    match filename.find('/') {
+        Some(i) => opts.skips(&filename[i + 1..], is_dir, rules),
+        None => false,
+    }
+}
 
-            yield buffer.split().freeze();
+/// Reads the `mode`, `mtime` and `mtime-nsecs` headers of a multipart field, keeping only the ones
+/// asked to be preserved. The mode is in octal, like js-ipfs-http-client sends it.
+fn field_metadata(headers: &warp::http::HeaderMap, opts: &AddArgs) -> Result<Metadata, AddError> {
+    let header = |name: &'static str| {
+        headers
+            .get(name)
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| AddError::InvalidMetadata(name, format!("{:?}", value)))
+            })
+            .transpose()
+    };
+
+    let mut metadata = Metadata::default();
+
+    if opts.preserve_mode {
+        if let Some(mode) = header("mode")? {
+            let mode = u32::from_str_radix(mode, 8)
+                .map_err(|_| AddError::InvalidMetadata("mode", mode.to_owned()))?;
+            metadata = metadata.with_mode(mode);
         }
     }
+
+    if opts.preserve_mtime {
+        if let Some(mtime) = header("mtime")? {
+            let seconds = mtime
+                .parse::<i64>()
+                .map_err(|_| AddError::InvalidMetadata("mtime", mtime.to_owned()))?;
+            let nanos = match header("mtime-nsecs")? {
+                Some(nanos) => nanos
+                    .parse::<u32>()
+                    .map_err(|_| AddError::InvalidMetadata("mtime-nsecs", nanos.to_owned()))?,
+                None => 0,
+            };
+            metadata = metadata.with_mtime(seconds, nanos);
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// The default `inline-limit`, same as in go-ipfs.
+const DEFAULT_INLINE_LIMIT: usize = 32;
+
+/// The largest accepted `inline-limit`, same as in go-ipfs.
+const INLINE_LIMIT_MAX: usize = 127;
+
+/// The largest accepted `ignore-rules` field, in bytes.
+const IGNORE_RULES_MAX: usize = 64 * 1024;
+
+/// The largest chunk size accepted, same as in go-ipfs.
+const CHUNK_SIZE_LIMIT: usize = 1024 * 1024;
+
+/// The default chunk size, used as the average chunk size for `rabin`.
+const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Parses the `chunker` argument the same way as go-ipfs.
+fn parse_chunker(s: &str) -> Result<Chunker, StringError> {
+    let parse_size = |s: &str| {
+        s.parse::<usize>()
+            .map_err(|e| StringError::from(format!("invalid chunker size {:?}: {}", s, e)))
+    };
+
+    let mut parts = s.split('-');
+
+    let chunker = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some("size"), Some(size), None, None) => match parse_size(size)? {
+            0 => return Err(StringError::from("chunker size must be greater than zero")),
+            size => Chunker::Size(size),
+        },
+        (Some("rabin"), None, None, None) => Chunker::rabin(DEFAULT_CHUNK_SIZE),
+        (Some("rabin"), Some(avg), None, None) => Chunker::rabin(parse_size(avg)?),
+        (Some("rabin"), Some(min), Some(avg), Some(max)) => Chunker::Rabin {
+            min: parse_size(min)?,
+            avg: parse_size(avg)?,
+            max: parse_size(max)?,
+        },
+        (Some("buzhash"), None, None, None) => Chunker::Buzhash,
+        _ => return Err(StringError::from(format!("unsupported chunker: {:?}", s))),
+    };
+
+    let max = match chunker {
+        Chunker::Size(size) => size,
+        Chunker::Rabin { min, avg, max } => {
+            if min < 64 {
+                return Err(StringError::from("rabin min must be at least 64 bytes"));
+            }
+            if min > avg || avg > max {
+                return Err(StringError::from(
+                    "rabin sizes must be given in order: min, avg, max",
+                ));
+            }
+            max
+        }
+        // the fixed sizes are within the limit
+        Chunker::Buzhash => return Ok(chunker),
+    };
+
+    if max > CHUNK_SIZE_LIMIT {
+        return Err(StringError::from(format!(
+            "chunks larger than {} bytes are not supported",
+            CHUNK_SIZE_LIMIT
+        )));
+    }
+
+    Ok(chunker)
 }
 
 async fn push_all(
     ipfs: &Ipfs<impl IpfsTypes>,
     adder: &mut FileAdder,
     next: Bytes,
+    only_hash: bool,
 ) -> Result<(u64, bool, u64), AddError> {
     let mut read = 0usize;
     let mut saved_any = false;
@@ -299,7 +598,7 @@ async fn push_all(
         let (iter, used) = adder.push(&next.slice(read..));
         read += used;
 
-        let maybe_tuple = import_all(&ipfs, iter)
+        let maybe_tuple = import_all(&ipfs, iter, only_hash)
             .await
             .map_err(AddError::Persisting)?;
 
@@ -313,9 +612,12 @@ async fn push_all(
     Ok((read as u64, saved_any, total_written))
 }
 
+/// Stores the blocks unless `only_hash` is given, returning the `Cid` of the last one and the
+/// total size of the blocks.
 async fn import_all(
     ipfs: &Ipfs<impl IpfsTypes>,
     iter: impl Iterator<Item = (Cid, Vec<u8>)>,
+    only_hash: bool,
 ) -> Result<Option<(Cid, u64)>, ipfs::Error> {
     // TODO: use FuturesUnordered
     let mut last: Option<Cid> = None;
@@ -323,6 +625,12 @@ async fn import_all(
 
     for (cid, data) in iter {
         total += data.len() as u64;
+
+        if only_hash {
+            last = Some(cid);
+            continue;
+        }
+
         let block = Block {
             cid,
             data: data.into_boxed_slice(),
@@ -409,6 +717,158 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn add_sorted_directories() {
+        let ipfs = tokio_ipfs().await;
+
+        let body = ["dir/a/file", "dir/b/file", "dir/c"]
+            .iter()
+            .map(|path| {
+                format!(
+                    "--boundary\r\n\
+                    Content-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+                    Content-Type: application/octet-stream\r\n\r\n\
+                    {}\r\n",
+                    path, path
+                )
+            })
+            .chain(std::iter::once("--boundary--\r\n".to_owned()))
+            .collect::<String>();
+
+        let mut added = Vec::new();
+
+        for query in &["", "?sorted=true"] {
+            let response = warp::test::request()
+                .path(&format!("/add{}", query))
+                .header("content-type", "multipart/form-data; boundary=boundary")
+                .body(body.clone())
+                .reply(&add(&ipfs))
+                .await;
+
+            let lines = std::str::from_utf8(response.body())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .map(|line| (line["Name"].to_string(), line["Hash"].to_string()))
+                .collect::<Vec<_>>();
+
+            added.push(lines);
+        }
+
+        let names = |lines: &[(String, String)]| {
+            lines
+                .iter()
+                .map(|(name, _)| name.trim_matches('"').to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(&added[0]),
+            &["dir/a/file", "dir/b/file", "dir/c", "dir/b", "dir/a", "dir"]
+        );
+        // the streamed directories are reported as soon as they are complete
+        assert_eq!(
+            names(&added[1]),
+            &["dir/a/file", "dir/b/file", "dir/a", "dir/c", "dir/b", "dir"]
+        );
+
+        for lines in &mut added {
+            lines.sort();
+        }
+        assert_eq!(added[0], added[1]);
+    }
+
+    #[tokio::test]
+    async fn add_skips_hidden_and_ignored_entries() {
+        let ipfs = tokio_ipfs().await;
+
+        let fields = |paths: &[&str]| {
+            std::iter::once(
+                "--boundary\r\n\
+                Content-Disposition: form-data; name=\"ignore-rules\"\r\n\
+                Content-Type: text/plain\r\n\r\n\
+                # only the logs\n*.log\r\n"
+                    .to_owned(),
+            )
+            .chain(paths.iter().map(|path| {
+                format!(
+                    "--boundary\r\n\
+                    Content-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+                    Content-Type: application/octet-stream\r\n\r\n\
+                    {}\r\n",
+                    path, path
+                )
+            }))
+            .chain(std::iter::once("--boundary--\r\n".to_owned()))
+            .collect::<String>()
+        };
+
+        let body = fields(&["dir/.hidden", "dir/a.log", "dir/file"]);
+
+        let mut names = Vec::new();
+
+        for query in &["", "?hidden=true"] {
+            let response = warp::test::request()
+                .path(&format!("/add{}", query))
+                .header("content-type", "multipart/form-data; boundary=boundary")
+                .body(body.clone())
+                .reply(&add(&ipfs))
+                .await;
+
+            let added = std::str::from_utf8(response.body())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .map(|line| line["Name"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>();
+
+            names.push(added);
+        }
+
+        assert_eq!(names[0], &["dir/file", "dir"]);
+        assert_eq!(names[1], &["dir/.hidden", "dir/file", "dir"]);
+    }
+
+    #[test]
+    fn chunker_argument() {
+        use super::parse_chunker;
+        use ipfs::unixfs::ll::file::adder::Chunker;
+
+        assert!(matches!(
+            parse_chunker("size-1000"),
+            Ok(Chunker::Size(1000))
+        ));
+        assert!(matches!(
+            parse_chunker("rabin"),
+            Ok(Chunker::Rabin {
+                min: 87381,
+                avg: 262144,
+                max: 393216
+            })
+        ));
+        assert!(matches!(
+            parse_chunker("rabin-64-128-256"),
+            Ok(Chunker::Rabin {
+                min: 64,
+                avg: 128,
+                max: 256
+            })
+        ));
+        assert!(matches!(parse_chunker("buzhash"), Ok(Chunker::Buzhash)));
+
+        for invalid in &[
+            "size-0",
+            "size-2000000",
+            "size-",
+            "rabin-1-2-3",
+            "rabin-300-200-400",
+            "rabin-1-2",
+            "fastcdc",
+        ] {
+            assert!(parse_chunker(invalid).is_err(), "{}", invalid);
+        }
+    }
+
     async fn tokio_ipfs() -> ipfs::Ipfs<ipfs::TestTypes> {
         let options = ipfs::IpfsOptions::inmemory_with_generated_keys();
         ipfs::UninitializedIpfs::new(options).start().await.unwrap()
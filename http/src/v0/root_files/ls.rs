@@ -0,0 +1,80 @@
+use super::LsArgs;
+use crate::v0::support::{MaybeTimeoutExt, StringError, StringSerialized};
+use futures::stream::TryStreamExt;
+use ipfs::unixfs::{ll::NodeKind, DirEntry};
+use ipfs::{Ipfs, IpfsTypes};
+use serde::Serialize;
+use warp::{reply, Rejection, Reply};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct LsResponse {
+    objects: Vec<LsObject>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct LsObject {
+    hash: String,
+    links: Vec<LsLink>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct LsLink {
+    name: String,
+    hash: String,
+    size: u64,
+    /// The UnixFs data type: 1 for directories, 2 for files, 4 for symlinks and 0 if unresolved.
+    #[serde(rename = "Type")]
+    kind: u8,
+}
+
+impl LsLink {
+    fn new(entry: DirEntry, size: bool) -> Self {
+        let (kind, filesize) = match entry.info {
+            Some(info) => {
+                let kind = match info.kind {
+                    NodeKind::Directory | NodeKind::ShardedDirectory => 1,
+                    NodeKind::File => 2,
+                    NodeKind::Symlink => 4,
+                };
+                (kind, info.filesize)
+            }
+            None => (0, 0),
+        };
+
+        LsLink {
+            name: entry.name,
+            hash: entry.cid.to_string(),
+            size: if size { filesize } else { 0 },
+            kind,
+        }
+    }
+}
+
+pub(super) async fn ls_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: LsArgs,
+) -> Result<impl Reply, Rejection> {
+    let path = args.arg.into_inner();
+    let hash = path.to_string();
+    let resolve = args.resolve_type || args.size;
+
+    let listing = async { ipfs.ls(path, resolve).await?.try_collect::<Vec<_>>().await };
+
+    let entries = listing
+        .maybe_timeout(args.timeout.map(StringSerialized::into_inner))
+        .await
+        .map_err(StringError::from)?
+        .map_err(StringError::from)?;
+
+    let links = entries
+        .into_iter()
+        .map(|entry| LsLink::new(entry, args.size))
+        .collect();
+
+    Ok(reply::json(&LsResponse {
+        objects: vec![LsObject { hash, links }],
+    }))
+}
@@ -0,0 +1,476 @@
+//! Zip helper is internal to `/get` implementation, writing the zip archive in a single pass for
+//! streaming. The sizes and checksums of the files follow their contents in data descriptors and
+//! the central directory is written at the end, keeping only a small record per entry in memory.
+//! Zip64 extensions are used for files which could exceed the 4 GiB limit of the plain format and
+//! for the archives with too many entries or a too large central directory.
+use bytes::{buf::BufMut, Bytes, BytesMut};
+use flate2::{write::DeflateEncoder, Compression};
+use ipfs::unixfs::ll::Metadata;
+use std::io::Write;
+use std::path::Path;
+
+const LOCAL_FILE_HEADER: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR: u32 = 0x0807_4b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x0606_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR: u32 = 0x0706_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+
+/// Sizes and crc follow the contents in a data descriptor.
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+/// The names are in utf-8.
+const FLAG_UTF8: u16 = 1 << 11;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+/// The upper byte of "version made by", for the unix file modes in the external attributes.
+const MADE_BY_UNIX: u16 = 3 << 8;
+
+/// Files from this size on are written with the zip64 extensions, leaving room for deflate
+/// growing incompressible contents a little.
+const ZIP64_THRESHOLD: u64 = 0xF000_0000;
+
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+/// The MS-DOS directory attribute in the low byte of the external attributes.
+const DOS_DIRECTORY: u32 = 0x10;
+
+/// The record of an entry kept for the central directory.
+struct Entry {
+    name: String,
+    flags: u16,
+    method: u16,
+    zip64: bool,
+    dos_time: (u16, u16),
+    mtime: Option<i32>,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    offset: u64,
+    external_attributes: u32,
+}
+
+/// The file whose contents are being written.
+struct OpenFile {
+    hasher: crc32fast::Hasher,
+    encoder: Option<DeflateEncoder<bytes::buf::Writer<BytesMut>>>,
+    size: u64,
+    compressed_size: u64,
+    entry: Entry,
+}
+
+/// Zip helper is internal to `get` implementation. Like `TarHelper`, it renders the headers and
+/// the contents into `bytes::Bytes`, but it needs to be finished to write the central directory.
+pub(super) struct ZipHelper {
+    bytes: BytesMut,
+    /// The number of bytes returned so far, used as the offsets of the entries.
+    written: u64,
+    entries: Vec<Entry>,
+    compression: Option<Compression>,
+    file: Option<OpenFile>,
+}
+
+impl ZipHelper {
+    /// Creates a new helper, deflating the file contents if `compression` is given.
+    pub(super) fn new(compression: Option<Compression>) -> Self {
+        ZipHelper {
+            bytes: BytesMut::new(),
+            written: 0,
+            entries: Vec::new(),
+            compression,
+            file: None,
+        }
+    }
+
+    /// Returns the local header of the file, which is to be followed by the contents through
+    /// `buffer_file_contents` and `finish_file`.
+    pub(super) fn apply_file(
+        &mut self,
+        path: &Path,
+        metadata: &Metadata,
+        total_size: u64,
+    ) -> Bytes {
+        assert!(self.file.is_none(), "previous file was not finished");
+
+        let zip64 = total_size >= ZIP64_THRESHOLD;
+        let method = if self.compression.is_some() {
+            METHOD_DEFLATED
+        } else {
+            METHOD_STORED
+        };
+
+        let entry = Entry {
+            name: zip_name(path, false),
+            flags: FLAG_DATA_DESCRIPTOR | FLAG_UTF8,
+            method,
+            zip64,
+            dos_time: dos_time(metadata),
+            mtime: mtime(metadata),
+            crc: 0,
+            compressed_size: 0,
+            size: 0,
+            offset: self.written,
+            external_attributes: unix_mode(metadata, S_IFREG, 0o644) << 16,
+        };
+
+        self.put_local_header(&entry);
+
+        let encoder = self
+            .compression
+            .map(|level| DeflateEncoder::new(BytesMut::new().writer(), level));
+
+        self.file = Some(OpenFile {
+            hasher: crc32fast::Hasher::new(),
+            encoder,
+            size: 0,
+            compressed_size: 0,
+            entry,
+        });
+
+        self.split()
+    }
+
+    /// Returns the contents as they are stored, which can be empty when compressing.
+    pub(super) fn buffer_file_contents(&mut self, contents: &[u8]) -> Bytes {
+        let file = self.file.as_mut().expect("file was not started");
+
+        file.hasher.update(contents);
+        file.size += contents.len() as u64;
+
+        let out = match file.encoder.as_mut() {
+            Some(encoder) => {
+                encoder
+                    .write_all(contents)
+                    .expect("writing to memory cannot fail");
+                encoder.get_mut().get_mut().split().freeze()
+            }
+            None => Bytes::copy_from_slice(contents),
+        };
+
+        file.compressed_size += out.len() as u64;
+        self.written += out.len() as u64;
+        out
+    }
+
+    /// Returns the rest of the compressed contents and the data descriptor of the file.
+    pub(super) fn finish_file(&mut self) -> Bytes {
+        let OpenFile {
+            hasher,
+            encoder,
+            size,
+            mut compressed_size,
+            mut entry,
+        } = self.file.take().expect("file was not started");
+
+        if let Some(encoder) = encoder {
+            let rest = encoder
+                .finish()
+                .expect("writing to memory cannot fail")
+                .into_inner();
+            compressed_size += rest.len() as u64;
+            self.bytes.put(rest);
+        }
+
+        entry.crc = hasher.finalize();
+        entry.size = size;
+        entry.compressed_size = compressed_size;
+
+        self.bytes.put_u32_le(DATA_DESCRIPTOR);
+        self.bytes.put_u32_le(entry.crc);
+        if entry.zip64 {
+            self.bytes.put_u64_le(entry.compressed_size);
+            self.bytes.put_u64_le(entry.size);
+        } else {
+            self.bytes.put_u32_le(entry.compressed_size as u32);
+            self.bytes.put_u32_le(entry.size as u32);
+        }
+
+        self.entries.push(entry);
+        self.split()
+    }
+
+    pub(super) fn apply_directory(&mut self, path: &Path, metadata: &Metadata) -> Bytes {
+        let entry = Entry {
+            name: zip_name(path, true),
+            flags: FLAG_UTF8,
+            method: METHOD_STORED,
+            zip64: false,
+            dos_time: dos_time(metadata),
+            mtime: mtime(metadata),
+            crc: 0,
+            compressed_size: 0,
+            size: 0,
+            offset: self.written,
+            external_attributes: unix_mode(metadata, S_IFDIR, 0o755) << 16 | DOS_DIRECTORY,
+        };
+
+        self.put_local_header(&entry);
+        self.entries.push(entry);
+        self.split()
+    }
+
+    /// Returns the entry for the symlink, which is stored like in the zip archives created on
+    /// unix: as a file of the target path with the symlink file type in the unix mode.
+    pub(super) fn apply_symlink(
+        &mut self,
+        path: &Path,
+        target: &[u8],
+        metadata: &Metadata,
+    ) -> Bytes {
+        let entry = Entry {
+            name: zip_name(path, false),
+            flags: FLAG_UTF8,
+            method: METHOD_STORED,
+            zip64: false,
+            dos_time: dos_time(metadata),
+            mtime: mtime(metadata),
+            crc: crc32fast::hash(target),
+            compressed_size: target.len() as u64,
+            size: target.len() as u64,
+            offset: self.written,
+            external_attributes: unix_mode(metadata, S_IFLNK, 0o644) << 16,
+        };
+
+        self.put_local_header(&entry);
+        self.bytes.put_slice(target);
+        self.entries.push(entry);
+        self.split()
+    }
+
+    /// Returns the central directory which ends the archive.
+    pub(super) fn finish(mut self) -> Bytes {
+        assert!(self.file.is_none(), "last file was not finished");
+
+        let start = self.written;
+
+        for entry in &self.entries {
+            let mut extra = BytesMut::new();
+
+            let size = u32_or_max(entry.size, entry.zip64);
+            let compressed_size = u32_or_max(entry.compressed_size, entry.zip64);
+            let offset = u32_or_max(entry.offset, false);
+
+            if size == u32::MAX || offset == u32::MAX {
+                // only the fields which did not fit are included, in this order
+                let mut fields = BytesMut::new();
+                if size == u32::MAX {
+                    fields.put_u64_le(entry.size);
+                }
+                if compressed_size == u32::MAX {
+                    fields.put_u64_le(entry.compressed_size);
+                }
+                if offset == u32::MAX {
+                    fields.put_u64_le(entry.offset);
+                }
+                extra.put_u16_le(0x0001);
+                extra.put_u16_le(fields.len() as u16);
+                extra.put(fields);
+            }
+
+            put_timestamp(&mut extra, entry.mtime);
+
+            let version = if entry.zip64 || offset == u32::MAX {
+                VERSION_ZIP64
+            } else {
+                VERSION_DEFAULT
+            };
+
+            self.bytes.put_u32_le(CENTRAL_DIRECTORY_HEADER);
+            self.bytes.put_u16_le(MADE_BY_UNIX | version);
+            self.bytes.put_u16_le(version);
+            self.bytes.put_u16_le(entry.flags);
+            self.bytes.put_u16_le(entry.method);
+            self.bytes.put_u16_le(entry.dos_time.0);
+            self.bytes.put_u16_le(entry.dos_time.1);
+            self.bytes.put_u32_le(entry.crc);
+            self.bytes.put_u32_le(compressed_size);
+            self.bytes.put_u32_le(size);
+            self.bytes.put_u16_le(entry.name.len() as u16);
+            self.bytes.put_u16_le(extra.len() as u16);
+            // comment length, disk number and internal attributes
+            self.bytes.put_u16_le(0);
+            self.bytes.put_u16_le(0);
+            self.bytes.put_u16_le(0);
+            self.bytes.put_u32_le(entry.external_attributes);
+            self.bytes.put_u32_le(offset);
+            self.bytes.put_slice(entry.name.as_bytes());
+            self.bytes.put(extra);
+        }
+
+        let count = self.entries.len() as u64;
+        let size = self.bytes.len() as u64;
+        let end = start + size;
+
+        if count >= u64::from(u16::MAX)
+            || size >= u64::from(u32::MAX)
+            || start >= u64::from(u32::MAX)
+        {
+            self.bytes.put_u32_le(ZIP64_END_OF_CENTRAL_DIRECTORY);
+            // the size of the rest of the record
+            self.bytes.put_u64_le(44);
+            self.bytes.put_u16_le(MADE_BY_UNIX | VERSION_ZIP64);
+            self.bytes.put_u16_le(VERSION_ZIP64);
+            self.bytes.put_u32_le(0);
+            self.bytes.put_u32_le(0);
+            self.bytes.put_u64_le(count);
+            self.bytes.put_u64_le(count);
+            self.bytes.put_u64_le(size);
+            self.bytes.put_u64_le(start);
+
+            self.bytes
+                .put_u32_le(ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR);
+            self.bytes.put_u32_le(0);
+            self.bytes.put_u64_le(end);
+            self.bytes.put_u32_le(1);
+        }
+
+        self.bytes.put_u32_le(END_OF_CENTRAL_DIRECTORY);
+        self.bytes.put_u16_le(0);
+        self.bytes.put_u16_le(0);
+        self.bytes.put_u16_le(count.min(u64::from(u16::MAX)) as u16);
+        self.bytes.put_u16_le(count.min(u64::from(u16::MAX)) as u16);
+        self.bytes.put_u32_le(u32_or_max(size, false));
+        self.bytes.put_u32_le(u32_or_max(start, false));
+        // comment length
+        self.bytes.put_u16_le(0);
+
+        self.bytes.split().freeze()
+    }
+
+    fn put_local_header(&mut self, entry: &Entry) {
+        let mut extra = BytesMut::new();
+
+        if entry.zip64 {
+            // the sizes follow in the data descriptor
+            extra.put_u16_le(0x0001);
+            extra.put_u16_le(16);
+            extra.put_u64_le(0);
+            extra.put_u64_le(0);
+        }
+
+        put_timestamp(&mut extra, entry.mtime);
+
+        let (crc, compressed_size, size) = if entry.flags & FLAG_DATA_DESCRIPTOR != 0 {
+            let unknown = if entry.zip64 { u32::MAX } else { 0 };
+            (0, unknown, unknown)
+        } else {
+            (entry.crc, entry.compressed_size as u32, entry.size as u32)
+        };
+
+        self.bytes.put_u32_le(LOCAL_FILE_HEADER);
+        self.bytes.put_u16_le(if entry.zip64 {
+            VERSION_ZIP64
+        } else {
+            VERSION_DEFAULT
+        });
+        self.bytes.put_u16_le(entry.flags);
+        self.bytes.put_u16_le(entry.method);
+        self.bytes.put_u16_le(entry.dos_time.0);
+        self.bytes.put_u16_le(entry.dos_time.1);
+        self.bytes.put_u32_le(crc);
+        self.bytes.put_u32_le(compressed_size);
+        self.bytes.put_u32_le(size);
+        self.bytes.put_u16_le(entry.name.len() as u16);
+        self.bytes.put_u16_le(extra.len() as u16);
+        self.bytes.put_slice(entry.name.as_bytes());
+        self.bytes.put(extra);
+    }
+
+    fn split(&mut self) -> Bytes {
+        self.written += self.bytes.len() as u64;
+        self.bytes.split().freeze()
+    }
+}
+
+/// Returns the value, or `u32::MAX` if it does not fit or is to be found in the zip64 extra
+/// field.
+fn u32_or_max(value: u64, zip64: bool) -> u32 {
+    if zip64 || value >= u64::from(u32::MAX) {
+        u32::MAX
+    } else {
+        value as u32
+    }
+}
+
+/// Writes the modification time as the "extended timestamp" extra field, which unlike the MS-DOS
+/// time is in UTC and has a resolution of a second.
+fn put_timestamp(extra: &mut BytesMut, mtime: Option<i32>) {
+    if let Some(mtime) = mtime {
+        extra.put_u16_le(0x5455);
+        extra.put_u16_le(5);
+        // only the modification time is present
+        extra.put_u8(1);
+        extra.put_i32_le(mtime);
+    }
+}
+
+/// Zip archives always use slashes and the directory names end in one.
+fn zip_name(path: &Path, directory: bool) -> String {
+    let mut name = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    if directory {
+        name.push('/');
+    }
+
+    name
+}
+
+fn unix_mode(metadata: &Metadata, file_type: u32, default_mode: u32) -> u32 {
+    file_type
+        | metadata
+            .mode()
+            .map(|mode| mode & 0o7777)
+            .unwrap_or(default_mode)
+}
+
+fn mtime(metadata: &Metadata) -> Option<i32> {
+    use std::convert::TryFrom;
+
+    metadata
+        .mtime()
+        .and_then(|(seconds, _)| i32::try_from(seconds).ok())
+}
+
+/// Returns the MS-DOS time and date of the modification time, clamped to the years from 1980 to
+/// 2107 the format allows. Without the modification time this is the earliest possible time, like
+/// the tar archives have the unix epoch.
+fn dos_time(metadata: &Metadata) -> (u16, u16) {
+    // 1980-01-01 and 2107-12-31 23:59:58
+    const MIN: i64 = 315_532_800;
+    const MAX: i64 = 4_354_819_198;
+
+    let seconds = metadata
+        .mtime()
+        .map(|(seconds, _)| seconds)
+        .unwrap_or(MIN)
+        .max(MIN)
+        .min(MAX);
+
+    let days = seconds.div_euclid(86_400);
+    let secs_of_day = seconds.rem_euclid(86_400);
+
+    // civil date from the days since the unix epoch, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let time = (secs_of_day / 3600) << 11 | (secs_of_day % 3600 / 60) << 5 | (secs_of_day % 60) / 2;
+    let date = (year - 1980) << 9 | month << 5 | day;
+
+    (time as u16, date as u16)
+}
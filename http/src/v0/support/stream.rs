@@ -8,6 +8,8 @@ use warp::Reply;
 
 pub struct StreamResponseJson<S>(pub S);
 pub struct StreamResponseText<S>(pub S);
+/// A streamed response of the given content type, such as an archive.
+pub struct StreamResponse<S>(pub S, pub &'static str);
 
 impl<S> Reply for StreamResponseJson<S>
 where
@@ -31,6 +33,17 @@ where
     }
 }
 
+impl<S> Reply for StreamResponse<S>
+where
+    S: TryStream + Send + 'static,
+    S::Ok: Into<Bytes>,
+    S::Error: StdError + Send + Sync + 'static,
+{
+    fn into_response(self) -> warp::reply::Response {
+        inner_into_response(self.0, self.1)
+    }
+}
+
 fn inner_into_response<S>(stream: S, content_type: &'static str) -> warp::reply::Response
 where
     S: TryStream + Send + 'static,
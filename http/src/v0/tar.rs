@@ -0,0 +1,200 @@
+//! `/api/v0/tar/add` and `/api/v0/tar/cat` for converting between tar archives and UnixFs trees.
+
+use crate::v0::root_files::{resolve_dagpb, walk};
+use crate::v0::support::{
+    with_ipfs, MaybeTimeoutExt, StreamResponseText, StringError, StringSerialized,
+};
+use bytes::{Buf, Bytes};
+use futures::stream::{Stream, TryStreamExt};
+use ipfs::unixfs::{ll::NodeKind, AddOptions};
+use ipfs::{Ipfs, IpfsPath, IpfsTypes};
+use mime::Mime;
+use mpart_async::server::MultipartStream;
+use serde::Deserialize;
+use serde_json::json;
+use std::io;
+use tokio_util::io::StreamReader;
+use warp::{query, reply, Filter, Rejection, Reply};
+
+#[derive(Debug, Deserialize)]
+pub struct AddArgs {
+    /// When true, the modes of the entries in the archive are stored.
+    #[serde(default, rename = "preserve-mode")]
+    preserve_mode: bool,
+    /// When true, the modification times of the entries in the archive are stored.
+    #[serde(default, rename = "preserve-mtime")]
+    preserve_mtime: bool,
+}
+
+/// Adds the tar archive posted as the first multipart field, responding with the `Cid` of the
+/// directory wrapping the entries of the archive.
+pub fn add<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<AddArgs>())
+        .and(warp::header::<Mime>("content-type")) // TODO: rejects if missing
+        .and(warp::body::stream())
+        .and_then(add_inner)
+}
+
+async fn add_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: AddArgs,
+    content_type: Mime,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>> + Send + Unpin + 'static,
+) -> Result<impl Reply, Rejection> {
+    let boundary = content_type
+        .get_param("boundary")
+        .map(|v| v.to_string())
+        .ok_or_else(|| StringError::from("missing 'boundary' on content-type"))?;
+
+    let mut fields = MultipartStream::new(
+        Bytes::from(boundary),
+        body.map_ok(|mut buf| buf.copy_to_bytes(buf.remaining())),
+    );
+
+    let field = fields
+        .try_next()
+        .await
+        .map_err(StringError::from)?
+        .ok_or_else(|| StringError::from("missing the tar archive"))?;
+
+    let name = field.filename().map(String::from).unwrap_or_default();
+
+    let opts = AddOptions {
+        preserve_mode: args.preserve_mode,
+        preserve_mtime: args.preserve_mtime,
+        ..Default::default()
+    };
+
+    // the archive is added while it is being received
+    let reader = StreamReader::new(field.map_err(|e| io::Error::new(io::ErrorKind::Other, e)));
+
+    let added = ipfs
+        .add_tar(reader, opts)
+        .await
+        .map_err(StringError::from)?;
+
+    let root = added
+        .last()
+        .ok_or_else(|| StringError::from("the tar archive was empty"))?;
+
+    Ok(reply::json(&json!({
+        "Name": name,
+        "Hash": root.cid.to_string(),
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CatArgs {
+    arg: StringSerialized<IpfsPath>,
+    timeout: Option<StringSerialized<humantime::Duration>>,
+}
+
+/// Exports the entries of a directory as a tar archive, the reverse of `/tar/add`. Unlike with
+/// `/get`, the directory itself is not included in the archive.
+pub fn cat<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(query::<CatArgs>()).and_then(cat_inner)
+}
+
+async fn cat_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: CatArgs) -> Result<impl Reply, Rejection> {
+    let path = args.arg.into_inner();
+
+    // FIXME: this timeout is only for the first step, should be for the whole walk!
+    let block = resolve_dagpb(&ipfs, path)
+        .maybe_timeout(args.timeout.map(StringSerialized::into_inner))
+        .await
+        .map_err(StringError::from)?
+        .map_err(StringError::from)?;
+
+    let info = ipfs::unixfs::ll::node_info(&block.data).map_err(StringError::from)?;
+
+    match info.kind {
+        NodeKind::Directory | NodeKind::ShardedDirectory => {}
+        _ => return Err(StringError::from(format!("not a directory: {}", block.cid)).into()),
+    }
+
+    // with an empty root name the entries are relative to the directory
+    Ok(StreamResponseText(
+        walk(ipfs, block, String::new()).into_stream(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use ipfs::Node;
+    use std::io::Read;
+
+    #[tokio::test]
+    async fn add_and_cat() {
+        let ipfs = Node::new("test_node").await;
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(7);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "dir/file", &b"foobar\n"[..])
+            .unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        let mut body = Vec::new();
+        body.extend_from_slice(
+            b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"archive.tar\"\r\n\
+            Content-Type: application/x-tar\r\n\r\n",
+        );
+        body.extend_from_slice(&archive);
+        body.extend_from_slice(b"\r\n--boundary--\r\n");
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/tar/add")
+            .header("content-type", "multipart/form-data; boundary=boundary")
+            .body(body)
+            .reply(&warp::path!("tar" / "add").and(super::add(&ipfs)))
+            .await;
+
+        assert_eq!(response.status(), 200);
+
+        let added: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(added["Name"], "archive.tar");
+        let hash = added["Hash"].as_str().unwrap();
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/tar/cat?arg={}", hash))
+            .reply(&warp::path!("tar" / "cat").and(super::cat(&ipfs)))
+            .await;
+
+        assert_eq!(response.status(), 200);
+
+        let mut archive = tar::Archive::new(&response.body()[..]);
+        let mut entries = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().to_string_lossy().into_owned();
+                let path = path.trim_end_matches('/').to_owned();
+                let mut contents = Vec::new();
+                entry.read_to_end(&mut contents).unwrap();
+                (path, contents)
+            })
+            .collect::<Vec<_>>();
+
+        entries.sort();
+
+        assert_eq!(
+            entries,
+            vec![
+                ("dir".to_owned(), Vec::new()),
+                ("dir/file".to_owned(), b"foobar\n".to_vec())
+            ]
+        );
+    }
+}
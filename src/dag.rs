@@ -1,7 +1,7 @@
 //! `ipfs.dag` interface implementation around [`Ipfs`].
 
 use crate::error::Error;
-use crate::ipld::{decode_ipld, encode_ipld, Ipld};
+use crate::ipld::{decode_ipld, encode_ipld, from_ipld, to_ipld, Ipld};
 use crate::path::{IpfsPath, SlashedPath};
 use crate::repo::RepoTypes;
 use crate::{Block, Ipfs};
@@ -11,6 +11,7 @@ use ipfs_unixfs::{
     dir::{Cache, ShardedLookup},
     resolve, MaybeResolved,
 };
+use serde::{de::DeserializeOwned, Serialize};
 use std::convert::TryFrom;
 use std::error::Error as StdError;
 use std::iter::Peekable;
@@ -216,6 +217,22 @@ impl<Types: RepoTypes> IpldDag<Types> {
         Ipld::try_from(node)
     }
 
+    /// Returns the `Cid` of a newly inserted dag-cbor block.
+    ///
+    /// The `value` is converted to `Ipld` with [`to_ipld`] before inserting it with
+    /// [`IpldDag::put`].
+    pub async fn put_serde<T: Serialize + ?Sized>(&self, value: &T) -> Result<Cid, Error> {
+        let data = to_ipld(value)?;
+        self.put(data, Codec::DagCBOR).await
+    }
+
+    /// Resolves a `Cid`-rooted path to a document "node" like [`IpldDag::get`], converting the
+    /// resolved `Ipld` to a `T` with [`from_ipld`].
+    pub async fn get_serde<T: DeserializeOwned>(&self, path: IpfsPath) -> Result<T, Error> {
+        let data = self.get(path).await?;
+        Ok(from_ipld(data)?)
+    }
+
     /// Resolves a `Cid`-rooted path to a document "node."
     ///
     /// The return value has two kinds of meanings depending on whether links should be followed or
@@ -367,10 +384,12 @@ impl ResolvedNode {
         }
     }
 
-    /// Unwraps the dagpb block variant and turns others into UnexpectedResolved.
+    /// Unwraps the dagpb or raw block variant and turns others into UnexpectedResolved. Raw
+    /// blocks are the raw leaves of files, or whole files if they fit in a single block.
     /// This is useful wherever unixfs operations are continued after resolving an IpfsPath.
     pub fn into_unixfs_block(self) -> Result<Block, UnexpectedResolved> {
-        if self.source().codec() != cid::Codec::DagProtobuf {
+        let codec = self.source().codec();
+        if codec != cid::Codec::DagProtobuf && codec != cid::Codec::Raw {
             Err(UnexpectedResolved::UnexpectedCodec(
                 cid::Codec::DagProtobuf,
                 self,
@@ -600,6 +619,69 @@ mod tests {
     use super::*;
     use crate::{make_ipld, Node};
 
+    #[tokio::test]
+    async fn put_and_get_serde() {
+        use serde::Deserialize;
+        use std::collections::BTreeMap;
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Status {
+            Draft,
+            Published { at: u64 },
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Post {
+            title: String,
+            tags: Vec<String>,
+            status: Status,
+            #[serde(with = "crate::ipld::link")]
+            previous: Cid,
+            extra: Option<BTreeMap<String, i32>>,
+        }
+
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs);
+
+        let previous = dag.put(make_ipld!("first"), Codec::DagCBOR).await.unwrap();
+
+        let post = Post {
+            title: String::from("second"),
+            tags: vec![String::from("a"), String::from("b")],
+            status: Status::Published { at: 1234 },
+            previous: previous.clone(),
+            extra: None,
+        };
+
+        let cid = dag.put_serde(&post).await.unwrap();
+        assert_eq!(cid.codec(), Codec::DagCBOR);
+
+        // the fields are stored as plain ipld, and the cid as a link
+        let res = dag.get(IpfsPath::from(cid.clone())).await.unwrap();
+        let expected = make_ipld!({
+            "title": "second",
+            "tags": ["a", "b"],
+            "status": { "Published": { "at": 1234 } },
+            "previous": previous.clone(),
+            "extra": null,
+        });
+        assert_eq!(res, expected);
+
+        let found: Post = dag.get_serde(IpfsPath::from(cid.clone())).await.unwrap();
+        assert_eq!(found, post);
+
+        // the link can be followed with a path
+        let path = IpfsPath::from(cid).sub_path("previous").unwrap();
+        let found: String = dag.get_serde(path).await.unwrap();
+        assert_eq!(found, "first");
+
+        assert_eq!(
+            crate::ipld::to_ipld(&Status::Draft).unwrap(),
+            make_ipld!("Draft")
+        );
+        assert!(crate::ipld::from_ipld::<Post>(make_ipld!([1, 2, 3])).is_err());
+    }
+
     #[tokio::test]
     async fn test_resolve_root_cid() {
         let Node { ipfs, .. } = Node::new("test_node").await;
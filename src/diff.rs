@@ -0,0 +1,490 @@
+//! Structural `diff` of two dag-pb or other supported IPLD trees.
+
+use crate::ipld::{dag_pb::PbNode, decode_ipld, BlockError, Ipld};
+use crate::unixfs::ll::{Metadata, NodeKind};
+use crate::{Ipfs, IpfsTypes};
+use cid::{Cid, Codec};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The kind of a [`Change`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The path exists only in the second tree.
+    Added,
+    /// The path exists only in the first tree.
+    Removed,
+    /// The path exists in both trees but points to different documents, which could not be
+    /// compared further.
+    Modified,
+}
+
+/// A single difference between two trees, as returned by [`Ipfs::diff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// The kind of the change.
+    pub kind: ChangeKind,
+    /// The path of the changed entry from the roots, empty for the roots themselves.
+    pub path: String,
+    /// The `Cid` at the path in the first tree, if any.
+    pub before: Option<Cid>,
+    /// The `Cid` at the path in the second tree, if any.
+    pub after: Option<Cid>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DiffError {
+    #[error("ipld document parsing failed")]
+    Block(#[from] BlockError),
+    #[error("loading failed")]
+    Loading(#[from] crate::Error),
+}
+
+/// The comparable parts of a loaded document.
+enum Node {
+    /// A document which is compared only by its `Cid`, such as a file.
+    Leaf,
+    /// A UnixFs directory with its metadata.
+    Directory(Entries, Metadata),
+    /// A document with named links, and the rest of its contents which are compared as a whole.
+    Links(BTreeMap<String, Cid>, Ipld),
+}
+
+/// The entries of a UnixFs directory. The entries of a sharded directory are collected only when
+/// it is compared to a plain one, as two shards are compared bucket by bucket.
+enum Entries {
+    Plain(BTreeMap<String, Cid>),
+    Sharded(PbNode),
+}
+
+impl Entries {
+    async fn collect<T: IpfsTypes>(
+        self,
+        ipfs: &Ipfs<T>,
+    ) -> Result<BTreeMap<String, Cid>, DiffError> {
+        match self {
+            Entries::Plain(entries) => Ok(entries),
+            Entries::Sharded(root) => {
+                let mut entries = BTreeMap::new();
+                flatten_shard(ipfs, root, &mut entries).await?;
+                Ok(entries)
+            }
+        }
+    }
+}
+
+/// Walks the two trees from the roots in lockstep, only descending into the links with the same
+/// name when they point to different documents. UnixFs directories, including the sharded ones,
+/// are compared by the names of the entries and their metadata, files and symlinks as a whole,
+/// and other dag-pb and IPLD documents by the links within them. The changes are returned ordered
+/// by the path.
+pub(crate) async fn diff<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    a: &Cid,
+    b: &Cid,
+) -> Result<Vec<Change>, DiffError> {
+    let mut changes = Vec::new();
+    let mut pending = vec![(String::new(), a.to_owned(), b.to_owned())];
+
+    while let Some((path, a, b)) = pending.pop() {
+        if a == b {
+            continue;
+        }
+
+        let (a_links, b_links) = match (load(ipfs, &a).await?, load(ipfs, &b).await?) {
+            (Node::Directory(a_entries, a_metadata), Node::Directory(b_entries, b_metadata)) => {
+                if a_metadata != b_metadata {
+                    changes.push(modified(&path, &a, &b));
+                }
+
+                match (a_entries, b_entries) {
+                    (Entries::Sharded(a_root), Entries::Sharded(b_root)) => {
+                        shard_differences(ipfs, a_root, b_root).await?
+                    }
+                    (a_entries, b_entries) => (
+                        a_entries.collect(ipfs).await?,
+                        b_entries.collect(ipfs).await?,
+                    ),
+                }
+            }
+            (Node::Links(a_links, a_rest), Node::Links(b_links, b_rest)) => {
+                if a_rest != b_rest {
+                    changes.push(modified(&path, &a, &b));
+                }
+                (a_links, b_links)
+            }
+            _ => {
+                changes.push(modified(&path, &a, &b));
+                continue;
+            }
+        };
+
+        let names = a_links
+            .keys()
+            .chain(b_links.keys())
+            .collect::<BTreeSet<_>>();
+
+        for name in names {
+            let child = if path.is_empty() {
+                name.to_owned()
+            } else {
+                format!("{}/{}", path, name)
+            };
+
+            match (a_links.get(name), b_links.get(name)) {
+                (Some(before), Some(after)) => {
+                    pending.push((child, before.to_owned(), after.to_owned()))
+                }
+                (Some(before), None) => changes.push(Change {
+                    kind: ChangeKind::Removed,
+                    path: child,
+                    before: Some(before.to_owned()),
+                    after: None,
+                }),
+                (None, Some(after)) => changes.push(Change {
+                    kind: ChangeKind::Added,
+                    path: child,
+                    before: None,
+                    after: Some(after.to_owned()),
+                }),
+                (None, None) => unreachable!("the name came from either of the links"),
+            }
+        }
+    }
+
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+fn modified(path: &str, before: &Cid, after: &Cid) -> Change {
+    Change {
+        kind: ChangeKind::Modified,
+        path: path.to_owned(),
+        before: Some(before.to_owned()),
+        after: Some(after.to_owned()),
+    }
+}
+
+/// Loads the document for comparing it by the links within it.
+async fn load<T: IpfsTypes>(ipfs: &Ipfs<T>, cid: &Cid) -> Result<Node, DiffError> {
+    if cid.codec() == Codec::Raw {
+        return Ok(Node::Leaf);
+    }
+
+    let block = ipfs.get_block(cid).await?;
+
+    if cid.codec() != Codec::DagProtobuf {
+        let mut ipld = decode_ipld(cid, &block.data)?;
+        let mut links = BTreeMap::new();
+        take_links(&mut ipld, String::new(), &mut links);
+        return Ok(Node::Links(links, ipld));
+    }
+
+    let info = crate::unixfs::ll::node_info(&block.data).ok();
+    let node = PbNode::from_bytes(&block.data).map_err(BlockError::from)?;
+
+    let info = match info {
+        Some(info) if info.kind == NodeKind::ShardedDirectory => {
+            return Ok(Node::Directory(Entries::Sharded(node), info.metadata))
+        }
+        Some(info) if info.kind == NodeKind::File || info.kind == NodeKind::Symlink => {
+            return Ok(Node::Leaf)
+        }
+        info => info,
+    };
+
+    let count = node.links.len();
+    let links = node
+        .links
+        .into_iter()
+        .map(|link| (link.name, link.cid))
+        .collect::<BTreeMap<_, _>>();
+
+    if links.len() != count {
+        // the links cannot be told apart by their names
+        return Ok(Node::Leaf);
+    }
+
+    match info {
+        // the directories are compared by the entries and the metadata so that a plain one
+        // equals the sharded one with the same entries
+        Some(info) if info.kind == NodeKind::Directory => {
+            Ok(Node::Directory(Entries::Plain(links), info.metadata))
+        }
+        _ => Ok(Node::Links(links, Ipld::Bytes(node.data))),
+    }
+}
+
+async fn load_pb<T: IpfsTypes>(ipfs: &Ipfs<T>, cid: &Cid) -> Result<PbNode, DiffError> {
+    let block = ipfs.get_block(cid).await?;
+    Ok(PbNode::from_bytes(&block.data).map_err(BlockError::from)?)
+}
+
+/// Moves the links out of the document, leaving `Null` in their place, and collects them with the
+/// paths to them within the document.
+fn take_links(ipld: &mut Ipld, path: String, links: &mut BTreeMap<String, Cid>) {
+    let join = |key: &dyn std::fmt::Display| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}/{}", path, key)
+        }
+    };
+
+    match ipld {
+        Ipld::Link(_) => {
+            if let Ipld::Link(cid) = std::mem::replace(ipld, Ipld::Null) {
+                links.insert(path, cid);
+            }
+        }
+        Ipld::List(list) => {
+            for (index, item) in list.iter_mut().enumerate() {
+                take_links(item, join(&index), links);
+            }
+        }
+        Ipld::Map(map) => {
+            for (key, value) in map.iter_mut() {
+                take_links(value, join(key), links);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns the link names of a HAMT shard node by the bucket, with the names of the entries or
+/// an empty name for the nested shard nodes.
+fn buckets(node: PbNode) -> BTreeMap<String, (String, Cid)> {
+    node.links
+        .into_iter()
+        .filter(|link| link.name.len() >= 2 && link.name.is_char_boundary(2))
+        .map(|link| {
+            let (bucket, name) = link.name.split_at(2);
+            (bucket.to_owned(), (name.to_owned(), link.cid))
+        })
+        .collect()
+}
+
+/// Compares the two shards bucket by bucket from the root nodes, skipping the identical buckets,
+/// and returns the entries of the differing buckets.
+async fn shard_differences<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    a: PbNode,
+    b: PbNode,
+) -> Result<(BTreeMap<String, Cid>, BTreeMap<String, Cid>), DiffError> {
+    let mut a_entries = BTreeMap::new();
+    let mut b_entries = BTreeMap::new();
+    let mut pending = Vec::new();
+    let mut next = Some((a, b));
+
+    while let Some((a, b)) = next.take() {
+        let a = buckets(a);
+        let b = buckets(b);
+
+        let indices = a.keys().chain(b.keys()).collect::<BTreeSet<_>>();
+
+        for index in indices {
+            match (a.get(index), b.get(index)) {
+                (Some(a), Some(b)) if a == b => {}
+                (Some((a_name, a)), Some((b_name, b)))
+                    if a_name.is_empty() && b_name.is_empty() =>
+                {
+                    pending.push((a.to_owned(), b.to_owned()))
+                }
+                (a, b) => {
+                    for (link, entries) in vec![(a, &mut a_entries), (b, &mut b_entries)] {
+                        match link {
+                            Some((name, cid)) if name.is_empty() => {
+                                flatten_shard(ipfs, load_pb(ipfs, cid).await?, entries).await?
+                            }
+                            Some((name, cid)) => {
+                                entries.insert(name.to_owned(), cid.to_owned());
+                            }
+                            None => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some((a, b)) = pending.pop() {
+            next = Some((load_pb(ipfs, &a).await?, load_pb(ipfs, &b).await?));
+        }
+    }
+
+    Ok((a_entries, b_entries))
+}
+
+/// Collects all of the entries of the shard node and the nodes below it.
+async fn flatten_shard<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    node: PbNode,
+    entries: &mut BTreeMap<String, Cid>,
+) -> Result<(), DiffError> {
+    let mut pending = Vec::new();
+    let mut next = Some(node);
+
+    while let Some(node) = next.take() {
+        for (_, (name, cid)) in buckets(node) {
+            if name.is_empty() {
+                pending.push(cid);
+            } else {
+                entries.insert(name, cid);
+            }
+        }
+
+        if let Some(cid) = pending.pop() {
+            next = Some(load_pb(ipfs, &cid).await?);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Change, ChangeKind};
+    use crate::unixfs::AddOptions;
+    use crate::{make_ipld, Block, Node};
+    use cid::Cid;
+    use ipfs_unixfs::dir::builder::{BufferingTreeBuilder, TreeOptions};
+    use ipfs_unixfs::Metadata;
+
+    /// Builds a directory of the files at the paths, sharding all of the directories when
+    /// `sharded`.
+    async fn directory(ipfs: &Node, files: &[(&str, &[u8])], sharded: bool) -> Cid {
+        directory_with_modes(ipfs, files, &[], sharded).await
+    }
+
+    /// Builds a directory like [`directory`] with the modes of the directories at the paths.
+    async fn directory_with_modes(
+        ipfs: &Node,
+        files: &[(&str, &[u8])],
+        modes: &[(&str, u32)],
+        sharded: bool,
+    ) -> Cid {
+        let mut opts = TreeOptions::default();
+        opts.sharding_threshold(if sharded { Some(1) } else { None });
+
+        let mut tree = BufferingTreeBuilder::new(opts);
+        for (path, mode) in modes {
+            tree.set_metadata(
+                &format!("root/{}", path),
+                Metadata::default().with_mode(*mode),
+            )
+            .unwrap();
+        }
+        for (path, contents) in files {
+            let added = ipfs
+                .add_reader(*contents, AddOptions::default())
+                .await
+                .unwrap();
+            tree.put_link(&format!("root/{}", path), added.cid, added.size)
+                .unwrap();
+        }
+
+        let mut root = None;
+        for node in tree.build() {
+            let node = node.unwrap();
+            root = Some(node.cid.clone());
+            ipfs.put_block(Block::new(node.block, node.cid))
+                .await
+                .unwrap();
+        }
+        root.unwrap()
+    }
+
+    fn summary(changes: &[Change]) -> Vec<(ChangeKind, &str)> {
+        changes
+            .iter()
+            .map(|change| (change.kind, change.path.as_str()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn directories() {
+        let ipfs = Node::new("test_node").await;
+
+        let before: &[(&str, &[u8])] = &[
+            ("a/b/same", b"same"),
+            ("a/b/changed", b"before"),
+            ("a/removed", b"removed"),
+            ("c", b"c"),
+        ];
+        let after: &[(&str, &[u8])] = &[
+            ("a/b/same", b"same"),
+            ("a/b/changed", b"after"),
+            ("a/added/file", b"added"),
+            ("c", b"c"),
+        ];
+
+        let expected = vec![
+            (ChangeKind::Added, "a/added"),
+            (ChangeKind::Modified, "a/b/changed"),
+            (ChangeKind::Removed, "a/removed"),
+        ];
+
+        for &(a_sharded, b_sharded) in &[(false, false), (true, true), (false, true)] {
+            let a = directory(&ipfs, before, a_sharded).await;
+            let b = directory(&ipfs, after, b_sharded).await;
+
+            let changes = ipfs.diff(&a, &b).await.unwrap();
+            assert_eq!(summary(&changes), expected, "{} {}", a_sharded, b_sharded);
+
+            let reversed = ipfs.diff(&b, &a).await.unwrap();
+            assert_eq!(reversed.len(), changes.len());
+            assert_eq!(reversed[0].kind, ChangeKind::Removed);
+            assert_eq!(reversed[0].before, changes[0].after);
+        }
+
+        let a = directory(&ipfs, before, false).await;
+        assert!(ipfs.diff(&a, &a).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn directory_metadata() {
+        let ipfs = Node::new("test_node").await;
+
+        let files: &[(&str, &[u8])] = &[("a/b/file", b"file"), ("c", b"c")];
+
+        for &sharded in &[false, true] {
+            let a = directory_with_modes(&ipfs, files, &[("a/b", 0o755)], sharded).await;
+            let b = directory_with_modes(&ipfs, files, &[("a/b", 0o700)], sharded).await;
+
+            let changes = ipfs.diff(&a, &b).await.unwrap();
+            assert_eq!(
+                summary(&changes),
+                vec![(ChangeKind::Modified, "a/b")],
+                "{}",
+                sharded
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn dag_cbor_documents() {
+        let ipfs = Node::new("test_node").await;
+
+        let x = ipfs.put_dag(make_ipld!("x")).await.unwrap();
+        let y = ipfs.put_dag(make_ipld!("y")).await.unwrap();
+
+        let a = ipfs
+            .put_dag(make_ipld!({ "name": "a", "links": [x.clone(), y.clone()] }))
+            .await
+            .unwrap();
+        let b = ipfs
+            .put_dag(make_ipld!({ "name": "b", "links": [x.clone()], "extra": y }))
+            .await
+            .unwrap();
+
+        let changes = ipfs.diff(&a, &b).await.unwrap();
+        assert_eq!(
+            summary(&changes),
+            vec![
+                (ChangeKind::Modified, ""),
+                (ChangeKind::Added, "extra"),
+                (ChangeKind::Removed, "links/1"),
+            ]
+        );
+        assert_eq!(changes[0].before, Some(a));
+        assert_eq!(changes[0].after, Some(b));
+    }
+}
@@ -0,0 +1,223 @@
+//! Deserialization of any `serde::de::DeserializeOwned` type from [`Ipld`].
+
+use crate::ipld::{link, Ipld, SerdeError};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Unexpected};
+use std::collections::btree_map;
+use std::convert::TryFrom;
+use std::vec;
+
+/// Deserializes a `T` from the `ipld`.
+///
+/// This is the inverse of [`to_ipld`](super::to_ipld): `Ipld::Null` can be deserialized as the
+/// unit, unit structs and `None`, and enums are expected to be externally tagged. `Ipld::Link`s
+/// can be deserialized as `Cid`s with the help of [`link`].
+pub fn from_ipld<T: DeserializeOwned>(ipld: Ipld) -> Result<T, SerdeError> {
+    T::deserialize(Deserializer(ipld))
+}
+
+/// Deserializer over an owned `Ipld`.
+struct Deserializer(Ipld);
+
+impl Deserializer {
+    fn unexpected(&self) -> Unexpected<'_> {
+        match &self.0 {
+            Ipld::Null => Unexpected::Unit,
+            Ipld::Bool(b) => Unexpected::Bool(*b),
+            Ipld::Integer(i) => match i64::try_from(*i) {
+                Ok(i) => Unexpected::Signed(i),
+                Err(_) => Unexpected::Other("integer"),
+            },
+            Ipld::Float(f) => Unexpected::Float(*f),
+            Ipld::String(s) => Unexpected::Str(s),
+            Ipld::Bytes(b) => Unexpected::Bytes(b),
+            Ipld::List(_) => Unexpected::Seq,
+            Ipld::Map(_) => Unexpected::Map,
+            Ipld::Link(_) => Unexpected::Other("link"),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.0 {
+            Ipld::Null => visitor.visit_unit(),
+            Ipld::Bool(b) => visitor.visit_bool(b),
+            Ipld::Integer(i) => {
+                if let Ok(i) = i64::try_from(i) {
+                    visitor.visit_i64(i)
+                } else if let Ok(u) = u64::try_from(i) {
+                    visitor.visit_u64(u)
+                } else {
+                    visitor.visit_i128(i)
+                }
+            }
+            Ipld::Float(f) => visitor.visit_f64(f),
+            Ipld::String(s) => visitor.visit_string(s),
+            Ipld::Bytes(b) => visitor.visit_byte_buf(b),
+            Ipld::List(list) => visitor.visit_seq(SeqDeserializer(list.into_iter())),
+            Ipld::Map(map) => visitor.visit_map(MapDeserializer {
+                iter: map.into_iter(),
+                value: None,
+            }),
+            // the link is only recognized by `link::deserialize` which is done through the
+            // newtype struct
+            link @ Ipld::Link(_) => visitor.visit_newtype_struct(Deserializer(link)),
+        }
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.0 {
+            Ipld::Null => visitor.visit_none(),
+            other => visitor.visit_some(Deserializer(other)),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        if name == link::NAME {
+            return match self.0 {
+                Ipld::Link(cid) => visitor.visit_byte_buf(cid.to_bytes()),
+                _ => Err(de::Error::invalid_type(self.unexpected(), &visitor)),
+            };
+        }
+
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        match self.0 {
+            Ipld::String(variant) => visitor.visit_enum(variant.into_deserializer()),
+            Ipld::Map(map) if map.len() == 1 => {
+                let (variant, value) = map.into_iter().next().expect("checked length");
+                visitor.visit_enum(EnumDeserializer { variant, value })
+            }
+            _ => Err(de::Error::invalid_type(self.unexpected(), &visitor)),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct SeqDeserializer(vec::IntoIter<Ipld>);
+
+impl<'de> de::SeqAccess<'de> for SeqDeserializer {
+    type Error = SerdeError;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, SerdeError> {
+        self.0
+            .next()
+            .map(|ipld| seed.deserialize(Deserializer(ipld)))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct MapDeserializer {
+    iter: btree_map::IntoIter<String, Ipld>,
+    /// The value of the key returned last.
+    value: Option<Ipld>,
+}
+
+impl<'de> de::MapAccess<'de> for MapDeserializer {
+    type Error = SerdeError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, SerdeError> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(key.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, SerdeError> {
+        let value = self
+            .value
+            .take()
+            .expect("next_value_seed is always preceded by next_key_seed");
+        seed.deserialize(Deserializer(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+/// The externally tagged enum variant with its value.
+struct EnumDeserializer {
+    variant: String,
+    value: Ipld,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = SerdeError;
+    type Variant = Deserializer;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Deserializer), SerdeError> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, Deserializer(self.value)))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), SerdeError> {
+        match self.0 {
+            Ipld::Null => Ok(()),
+            _ => Err(de::Error::invalid_type(self.unexpected(), &"unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, SerdeError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: de::Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: de::Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
@@ -0,0 +1,142 @@
+//! Serde helpers for `Cid` fields, to be used with `#[serde(with = "ipfs::ipld::link")]`, and the
+//! [`Link`] newtype for the `Cid`s nested in other types such as `Option` or `Vec`.
+//!
+//! With [`to_ipld`] and [`from_ipld`] the `Cid`s are converted to and from `Ipld::Link`, while
+//! with other serializers they are written as the bytes of the binary representation.
+//!
+//! [`to_ipld`]: super::to_ipld
+//! [`from_ipld`]: super::from_ipld
+
+use cid::Cid;
+use serde::{de, ser};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// The name of the newtype struct used to recognize links while (de)serializing `Ipld`.
+pub(super) const NAME: &str = "$__ipfs_ipld_link";
+
+/// A `Cid` which is serialized as a link, for the places where `#[serde(with = ...)]` cannot be
+/// used.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Link(pub Cid);
+
+impl From<Cid> for Link {
+    fn from(cid: Cid) -> Self {
+        Link(cid)
+    }
+}
+
+impl From<Link> for Cid {
+    fn from(link: Link) -> Self {
+        link.0
+    }
+}
+
+impl ser::Serialize for Link {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize(&self.0, serializer)
+    }
+}
+
+impl<'de> de::Deserialize<'de> for Link {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize(deserializer).map(Link)
+    }
+}
+
+/// Serializes the `Cid` as a link.
+pub fn serialize<S: ser::Serializer>(cid: &Cid, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_newtype_struct(NAME, &CidBytes(&cid.to_bytes()))
+}
+
+/// Deserializes a link into a `Cid`.
+pub fn deserialize<'de, D: de::Deserializer<'de>>(deserializer: D) -> Result<Cid, D::Error> {
+    deserializer.deserialize_newtype_struct(NAME, CidVisitor)
+}
+
+struct CidBytes<'a>(&'a [u8]);
+
+impl ser::Serialize for CidBytes<'_> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+struct CidVisitor;
+
+impl<'de> de::Visitor<'de> for CidVisitor {
+    type Value = Cid;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "a link")
+    }
+
+    fn visit_newtype_struct<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Cid, D::Error> {
+        deserializer.deserialize_bytes(self)
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Cid, E> {
+        Cid::try_from(bytes).map_err(E::custom)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Cid, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element::<u8>()? {
+            bytes.push(byte);
+        }
+        self.visit_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Link;
+    use crate::ipld::{from_ipld, to_ipld, Ipld};
+    use crate::make_ipld;
+    use cid::Cid;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Links {
+        optional: Option<Link>,
+        missing: Option<Link>,
+        list: Vec<Link>,
+        map: BTreeMap<String, Link>,
+    }
+
+    #[test]
+    fn nested_links() {
+        let cid = |s: &str| s.parse::<Cid>().unwrap();
+        let a = cid("QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6");
+        let b = cid("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH");
+
+        let links = Links {
+            optional: Some(Link(a.clone())),
+            missing: None,
+            list: vec![Link(a.clone()), Link(b.clone())],
+            map: vec![(String::from("b"), Link(b.clone()))]
+                .into_iter()
+                .collect(),
+        };
+
+        let ipld = to_ipld(&links).unwrap();
+        assert_eq!(
+            ipld,
+            make_ipld!({
+                "optional": a.clone(),
+                "missing": null,
+                "list": [a, b.clone()],
+                "map": { "b": b },
+            })
+        );
+
+        assert_eq!(from_ipld::<Links>(ipld).unwrap(), links);
+
+        // only links are accepted
+        assert!(from_ipld::<Link>(Ipld::Bytes(b"not a link".to_vec())).is_err());
+    }
+}
@@ -8,8 +8,14 @@
 pub mod dag_cbor;
 pub mod dag_json;
 pub mod dag_pb;
+mod de;
 #[macro_use]
 pub mod ipld_macro;
+pub mod link;
+mod ser;
+
+pub use de::from_ipld;
+pub use ser::to_ipld;
 
 use cid::{Cid, Codec};
 use dag_cbor::DagCborCodec;
@@ -17,6 +23,7 @@ use dag_json::DagJsonCodec;
 use dag_pb::DagPbCodec;
 use multihash::Multihash;
 use std::collections::BTreeMap;
+use std::fmt;
 use thiserror::Error;
 
 /// Ipld
@@ -208,6 +215,24 @@ pub enum IpldError {
     KeyNotFound,
 }
 
+/// Failure to convert between `Ipld` and a type implementing the serde traits, see
+/// [`to_ipld`] and [`from_ipld`].
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct SerdeError(String);
+
+impl serde::ser::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerdeError(msg.to_string())
+    }
+}
+
+impl serde::de::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerdeError(msg.to_string())
+    }
+}
+
 /// Block error.
 #[derive(Debug, Error)]
 pub enum BlockError {
@@ -0,0 +1,348 @@
+//! Serialization of any `serde::Serialize` type into [`Ipld`].
+
+use crate::ipld::{link, Ipld, SerdeError};
+use cid::Cid;
+use serde::ser::{self, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// Serializes the `value` into `Ipld`.
+///
+/// Structs and maps become `Ipld::Map`s, sequences and tuples `Ipld::List`s and the unit, unit
+/// structs and `None` become `Ipld::Null`. Enums are externally tagged, as in the usual json
+/// representation. `Cid`s serialized with [`link`] become `Ipld::Link`s.
+///
+/// Note that `Vec<u8>` is serialized as a list of integers like by any other serde serializer;
+/// `Ipld::Bytes` requires a type serialized through `serialize_bytes`.
+pub fn to_ipld<T: Serialize + ?Sized>(value: &T) -> Result<Ipld, SerdeError> {
+    value.serialize(Serializer)
+}
+
+/// Serializer producing `Ipld`.
+struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Ipld;
+    type Error = SerdeError;
+
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeVariant<SerializeList>;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeVariant<SerializeMap>;
+
+    fn serialize_bool(self, v: bool) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::from(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::from(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::from(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::from(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::from(v))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Ipld, SerdeError> {
+        i128::try_from(v)
+            .map(Ipld::Integer)
+            .map_err(|_| ser::Error::custom("integer is too large for Ipld"))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::from(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::from(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::from(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::from(v))
+    }
+
+    fn serialize_none(self) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Ipld, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::from(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Ipld, SerdeError> {
+        if name == link::NAME {
+            // links are serialized as newtypes around the bytes of the cid
+            return match value.serialize(self)? {
+                Ipld::Bytes(bytes) => Cid::try_from(bytes)
+                    .map(Ipld::Link)
+                    .map_err(ser::Error::custom),
+                _ => Err(ser::Error::custom("links must be serialized as bytes")),
+            };
+        }
+
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Ipld, SerdeError> {
+        Ok(tagged(variant, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeList, SerdeError> {
+        Ok(SerializeList(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeList, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeList, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeList>, SerdeError> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap, SerdeError> {
+        Ok(SerializeMap {
+            map: BTreeMap::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeMap, SerdeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeMap>, SerdeError> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+struct SerializeList(Vec<Ipld>);
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = Ipld;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.0.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::List(self.0))
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = Ipld;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Ipld, SerdeError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = Ipld;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Ipld, SerdeError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+struct SerializeMap {
+    map: BTreeMap<String, Ipld>,
+    /// The key of the next value for `serialize_key` and `serialize_value`.
+    key: Option<String>,
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = Ipld;
+    type Error = SerdeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerdeError> {
+        match key.serialize(Serializer)? {
+            Ipld::String(key) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            _ => Err(ser::Error::custom("map keys must be strings")),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        let key = self
+            .key
+            .take()
+            .expect("serialize_value is always preceded by serialize_key");
+        self.map.insert(key, value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Ipld, SerdeError> {
+        Ok(Ipld::Map(self.map))
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = Ipld;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        self.map
+            .insert(key.to_owned(), value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Ipld, SerdeError> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+/// Wraps the serialized fields of an enum variant as the single value of a map keyed by the
+/// variant name.
+struct SerializeVariant<T> {
+    variant: &'static str,
+    inner: T,
+}
+
+/// The externally tagged representation of an enum variant.
+fn tagged(variant: &'static str, value: Ipld) -> Ipld {
+    let mut map = BTreeMap::new();
+    map.insert(variant.to_owned(), value);
+    Ipld::Map(map)
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeList> {
+    type Ok = Ipld;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> Result<Ipld, SerdeError> {
+        let value = ser::SerializeSeq::end(self.inner)?;
+        Ok(tagged(self.variant, value))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeMap> {
+    type Ok = Ipld;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<Ipld, SerdeError> {
+        let value = ser::SerializeMap::end(self.inner)?;
+        Ok(tagged(self.variant, value))
+    }
+}
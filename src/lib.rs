@@ -24,6 +24,7 @@
 
 pub mod config;
 pub mod dag;
+pub mod diff;
 pub mod error;
 #[macro_use]
 pub mod ipld;
@@ -52,8 +53,9 @@ use std::{
     convert::TryFrom,
     env, fmt,
     ops::{Deref, DerefMut, Range},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::atomic::Ordering,
+    time::Duration,
 };
 
 use self::{
@@ -84,6 +86,8 @@ pub use self::{
     repo::{PinKind, PinMode, RepoTypes},
 };
 pub use bitswap::Block;
+pub use bitswap::BitswapConfig;
+pub use bitswap::ServingPolicy;
 pub use bitswap::BsBlockStore;
 pub use cid::Cid;
 
@@ -254,6 +258,8 @@ pub struct Ipfs<Types: IpfsTypes> {
     repo: Repo<Types>,
     keys: DebuggableKeypair<Keypair>,
     controls: Controls,
+    mfs: unixfs::mfs::Mfs,
+    read_ahead: usize,
 }
 
 impl<Types: IpfsTypes> Clone for Ipfs<Types> {
@@ -263,6 +269,8 @@ impl<Types: IpfsTypes> Clone for Ipfs<Types> {
             repo: self.repo.clone(),
             keys: self.keys.clone(),
             controls: self.controls.clone(),
+            mfs: self.mfs.clone(),
+            read_ahead: self.read_ahead,
         }
     }
 }
@@ -272,6 +280,8 @@ pub struct UninitializedIpfs<Types: IpfsTypes> {
     repo: Repo<Types>,
     keys: Keypair,
     options: IpfsOptions,
+    bitswap: BitswapConfig,
+    read_ahead: usize,
 }
 
 impl<Types: IpfsTypes> UninitializedIpfs<Types> {
@@ -290,9 +300,31 @@ impl<Types: IpfsTypes> UninitializedIpfs<Types> {
             repo,
             keys,
             options,
+            bitswap: Default::default(),
+            read_ahead: unixfs::DEFAULT_READ_AHEAD,
         }
     }
 
+    /// Configures bitswap, by default [`BitswapConfig::default`] is used.
+    pub fn with_bitswap_config(mut self, config: BitswapConfig) -> Self {
+        self.bitswap = config;
+        self
+    }
+
+    /// Decides whom and how fast bitswap serves blocks to, by default everyone is served without
+    /// any rate limits.
+    pub fn with_serving_policy(mut self, policy: ServingPolicy) -> Self {
+        self.bitswap.serving = policy;
+        self
+    }
+
+    /// Sets the number of blocks loaded ahead while reading UnixFs files, by default
+    /// [`unixfs::DEFAULT_READ_AHEAD`]. See [`unixfs::ReadAhead`].
+    pub fn with_read_ahead(mut self, blocks: usize) -> Self {
+        self.read_ahead = blocks;
+        self
+    }
+
     /// Initialize the ipfs node. The returned `Ipfs` value is cloneable, send and sync, and the
     /// future should be spawned on a executor as soon as possible.
     ///
@@ -304,6 +336,8 @@ impl<Types: IpfsTypes> UninitializedIpfs<Types> {
             repo,
             keys,
             mut options,
+            bitswap,
+            read_ahead,
         } = self;
 
         let root_span = options
@@ -323,7 +357,7 @@ impl<Types: IpfsTypes> UninitializedIpfs<Types> {
         // FIXME: mutating options above is an unfortunate side-effect of this call, which could be
         // reordered for less error prone code.
         let swarm_options = SwarmOptions::from(&options);
-        let controls = Controls::build(repo.clone(), swarm_options)
+        let controls = Controls::build(repo.clone(), swarm_options, bitswap)
             .instrument(tracing::trace_span!(parent: &init_span, "swarm"))
             .await;
 
@@ -332,6 +366,8 @@ impl<Types: IpfsTypes> UninitializedIpfs<Types> {
             repo,
             keys: DebuggableKeypair(keys),
             controls,
+            mfs: Default::default(),
+            read_ahead,
         };
 
         Ok(ipfs)
@@ -377,6 +413,16 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     /// Retrieves a block from the local blockstore, or starts fetching from the network or join an
     /// already started fetch.
     pub async fn get_block(&self, cid: &Cid) -> Result<Block, Error> {
+        self.get_block_with_deadline(cid, None).await
+    }
+
+    /// Retrieves a block like [`Ipfs::get_block`], waiting for it from the network at most
+    /// `deadline` or the deadline configured for bitswap when `None`.
+    pub async fn get_block_with_deadline(
+        &self,
+        cid: &Cid,
+        deadline: Option<Duration>,
+    ) -> Result<Block, Error> {
         if let Some(block) = self
             .repo
             .get_block(cid)
@@ -387,7 +433,7 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         } else {
             self.controls
                 .bitswap()
-                .want_block(cid.clone(), 1)
+                .want_block_with_deadline(cid.clone(), 1, deadline)
                 .await
                 .map_err(Error::from)
         }
@@ -594,6 +640,246 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             .await
     }
 
+    /// Adds the file, directory or symlink at the given path of the local filesystem, storing the
+    /// blocks as they are created. Returns the added entries in the order they were added, the
+    /// root being the last.
+    pub async fn add_path(
+        &self,
+        path: impl AsRef<Path>,
+        opts: unixfs::AddOptions,
+    ) -> Result<Vec<unixfs::Added>, unixfs::AddError> {
+        unixfs::add_path(self, path.as_ref(), &opts)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Adds the contents of the reader as a file, storing the blocks as they are created.
+    pub async fn add_reader(
+        &self,
+        reader: impl tokio::io::AsyncRead + Unpin,
+        opts: unixfs::AddOptions,
+    ) -> Result<unixfs::Added, unixfs::AddError> {
+        unixfs::add_reader(self, reader, &opts)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Adds the entries of the tar archive read from the reader, wrapped into a new directory,
+    /// in a single pass over the archive. Returns the added entries in the order they were added,
+    /// the wrapping directory being the last.
+    pub async fn add_tar(
+        &self,
+        reader: impl tokio::io::AsyncRead + Unpin,
+        opts: unixfs::AddOptions,
+    ) -> Result<Vec<unixfs::Added>, unixfs::AddError> {
+        unixfs::add_tar(self, reader, &opts)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Adds a link to the target at the path relative to the root directory, replacing any
+    /// previous link, and returns the new root. With `create` the missing directories on the path
+    /// are created. Sharded directories are supported, and a directory growing past the sharding
+    /// threshold is converted into a sharded one.
+    pub async fn patch_add_link(
+        &self,
+        root: &Cid,
+        path: &str,
+        target: Cid,
+        create: bool,
+    ) -> Result<Cid, unixfs::PatchError> {
+        unixfs::patch_add_link(self, root, path, target, create)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Removes the link at the path relative to the root directory and returns the new root.
+    pub async fn patch_rm_link(&self, root: &Cid, path: &str) -> Result<Cid, unixfs::PatchError> {
+        unixfs::patch_rm_link(self, root, path)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Appends the bytes to the file and returns the root of the new file, reusing all of the
+    /// blocks of the previous file but the last leaf and the link blocks leading to it. Only the
+    /// files added with the default chunker and layout can be appended to.
+    pub async fn append_to_file(&self, file: &Cid, data: &[u8]) -> Result<Cid, unixfs::PatchError> {
+        unixfs::append_to_file(self, file, data)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Compares the two trees, returning the paths which were added, removed or modified in `b`
+    /// compared to `a`. The links pointing to the same documents are not followed.
+    pub async fn diff(&self, a: &Cid, b: &Cid) -> Result<Vec<diff::Change>, diff::DiffError> {
+        diff::diff(self, a, b).instrument(self.span.clone()).await
+    }
+
+    /// Writes the file, directory or symlink at the given path or block to `dest` on the local
+    /// filesystem. See [`unixfs::GetOptions`] for restoring the metadata and resuming.
+    pub async fn get_to_path(
+        &self,
+        starting_point: impl Into<unixfs::StartingPoint>,
+        dest: impl AsRef<Path>,
+        opts: unixfs::GetOptions,
+    ) -> Result<(), unixfs::ExtractionFailed> {
+        // convert early not to worry about the lifetime of parameter
+        let starting_point = starting_point.into();
+        unixfs::get_to_path(self, starting_point, dest.as_ref(), &opts)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Opens the file at the given path or block for reading with [`tokio::io::AsyncRead`] and
+    /// seeking with [`tokio::io::AsyncSeek`].
+    pub async fn open_unixfs(
+        &self,
+        starting_point: impl Into<unixfs::StartingPoint>,
+    ) -> Result<unixfs::UnixfsFile<Types>, unixfs::TraversalFailed> {
+        // convert early not to worry about the lifetime of parameter
+        let starting_point = starting_point.into();
+        unixfs::UnixfsFile::open(self.clone(), starting_point)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Lists the entries of the directory at the given path or block, including the entries of
+    /// sharded directories. With `resolve` each entry is loaded to find out its kind, file size
+    /// and metadata.
+    ///
+    /// See [`unixfs::ls`] for a version which does not borrow the `Ipfs`.
+    pub async fn ls(
+        &self,
+        starting_point: impl Into<unixfs::StartingPoint>,
+        resolve: bool,
+    ) -> Result<
+        impl Stream<Item = Result<unixfs::DirEntry, unixfs::ListingFailed>> + Send + '_,
+        unixfs::ListingFailed,
+    > {
+        // convert early not to worry about the lifetime of parameter
+        let starting_point = starting_point.into();
+        unixfs::ls(self, starting_point, resolve)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Creates a directory in the mutable file system, with `parents` also the missing parent
+    /// directories. With `flush` the new root is persisted right away, otherwise on
+    /// [`Ipfs::files_flush`].
+    pub async fn files_mkdir(
+        &self,
+        path: &str,
+        parents: bool,
+        flush: bool,
+    ) -> Result<(), unixfs::mfs::MfsError> {
+        self.mfs
+            .mkdir(self, path, parents, flush)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Writes the bytes to a file in the mutable file system.
+    pub async fn files_write(
+        &self,
+        path: &str,
+        data: &[u8],
+        opts: unixfs::mfs::WriteOptions,
+        flush: bool,
+    ) -> Result<(), unixfs::mfs::MfsError> {
+        self.mfs
+            .write(self, path, data, opts, flush)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Creates a stream of the bytes of a file in the mutable file system, with the optional byte
+    /// range.
+    ///
+    /// To create an owned version of the stream, please use `ipfs::unixfs::cat` directly with the
+    /// `Cid` from [`Ipfs::files_stat`].
+    pub async fn files_read(
+        &self,
+        path: &str,
+        range: Option<Range<u64>>,
+    ) -> Result<
+        impl Stream<Item = Result<Vec<u8>, unixfs::TraversalFailed>> + Send + '_,
+        unixfs::mfs::MfsError,
+    > {
+        let stat = self.files_stat(path).await?;
+        if stat.kind != unixfs::ll::NodeKind::File {
+            return Err(unixfs::mfs::MfsError::NotAFile(path.to_owned()));
+        }
+
+        unixfs::cat(self, stat.cid, range)
+            .instrument(self.span.clone())
+            .await
+            .map_err(unixfs::mfs::MfsError::Reading)
+    }
+
+    /// Moves a file or a directory in the mutable file system.
+    pub async fn files_mv(
+        &self,
+        source: &str,
+        destination: &str,
+        flush: bool,
+    ) -> Result<(), unixfs::mfs::MfsError> {
+        self.mfs
+            .mv(self, source, destination, flush)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Copies a file or a directory of the mutable file system, or an `/ipfs` or `/ipns` path,
+    /// into the mutable file system.
+    pub async fn files_cp(
+        &self,
+        source: &str,
+        destination: &str,
+        flush: bool,
+    ) -> Result<(), unixfs::mfs::MfsError> {
+        self.mfs
+            .cp(self, source, destination, flush)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Removes a file, or with `recursive` a directory, from the mutable file system.
+    pub async fn files_rm(
+        &self,
+        path: &str,
+        recursive: bool,
+        flush: bool,
+    ) -> Result<(), unixfs::mfs::MfsError> {
+        self.mfs
+            .rm(self, path, recursive, flush)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Returns information about a file or a directory in the mutable file system.
+    pub async fn files_stat(&self, path: &str) -> Result<unixfs::mfs::Stat, unixfs::mfs::MfsError> {
+        self.mfs
+            .stat(self, path)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Lists a directory of the mutable file system.
+    pub async fn files_ls(
+        &self,
+        path: &str,
+    ) -> Result<Vec<unixfs::mfs::Entry>, unixfs::mfs::MfsError> {
+        self.mfs.ls(self, path).instrument(self.span.clone()).await
+    }
+
+    /// Persists the root of the mutable file system, returning the `Cid` at the path.
+    pub async fn files_flush(&self, path: &str) -> Result<Cid, unixfs::mfs::MfsError> {
+        self.mfs
+            .flush(self, path)
+            .instrument(self.span.clone())
+            .await
+    }
+
     /// Resolves a ipns path to an ipld path; currently only supports dnslink resolution.
     pub async fn resolve_ipns(&self, path: &IpfsPath, recursive: bool) -> Result<IpfsPath, Error> {
         async move {
@@ -1016,6 +1302,18 @@ pub struct BitswapStats {
     pub dup_blks_received: u64,
     /// The number of bytes in duplicate blocks received
     pub dup_data_received: u64,
+    /// Blocks dropped because they were not wanted or did not pass the block policy
+    pub unsolicited_blks_received: u64,
+    /// The number of bytes in dropped unsolicited blocks
+    pub unsolicited_data_received: u64,
+    /// Blocks wanted by other peers but not served because of the serving policy
+    pub refused_wants: u64,
+    /// Blocks whose sending was delayed or dropped because of the upload rate limits
+    pub throttled_blks: u64,
+    /// The number of routing queries made to find providers for the wanted blocks
+    pub provider_queries: u64,
+    /// The number of dials made toward the providers of the wanted blocks
+    pub provider_dials: u64,
     /// The current peers
     pub peers: Vec<PeerId>,
     /// The wantlist of the local node
@@ -1033,6 +1331,12 @@ impl From<(bitswap::Stats, Vec<PeerId>, Vec<(Cid, bitswap::Priority)>)> for Bits
             data_received: stats.received_data.load(Ordering::Relaxed),
             dup_blks_received: stats.duplicate_blocks.load(Ordering::Relaxed),
             dup_data_received: stats.duplicate_data.load(Ordering::Relaxed),
+            unsolicited_blks_received: stats.unsolicited_blocks.load(Ordering::Relaxed),
+            unsolicited_data_received: stats.unsolicited_data.load(Ordering::Relaxed),
+            refused_wants: stats.refused_wants.load(Ordering::Relaxed),
+            throttled_blks: stats.throttled_blocks.load(Ordering::Relaxed),
+            provider_queries: stats.provider_queries.load(Ordering::Relaxed),
+            provider_dials: stats.provider_dials.load(Ordering::Relaxed),
             peers,
             wantlist,
         }
@@ -1189,6 +1493,24 @@ mod tests {
         assert_eq!(block, new_block);
     }
 
+    #[tokio::test]
+    async fn test_inlined_block_is_not_stored() {
+        let ipfs = Node::new("test_node").await;
+
+        let data = b"tiny".to_vec().into_boxed_slice();
+        let cid = Cid::new_v1(
+            Codec::Raw,
+            multihash::wrap(multihash::Code::Identity, &data),
+        );
+        let block = Block::new(data, cid);
+
+        let cid: Cid = ipfs.put_block(block.clone()).await.unwrap();
+        assert!(ipfs.repo.list_blocks().await.unwrap().is_empty());
+
+        let new_block = ipfs.get_block(&cid).await.unwrap();
+        assert_eq!(block.data(), new_block.data());
+    }
+
     #[tokio::test]
     async fn test_put_and_get_dag() {
         let ipfs = Node::new("test_node").await;
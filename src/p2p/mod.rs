@@ -22,7 +22,7 @@ use libp2p_rs::floodsub::control::Control as FloodsubControl;
 
 use libp2p_rs::kad::store::MemoryStore;
 
-use bitswap::Bitswap;
+use bitswap::{Bitswap, BitswapConfig};
 
 use libp2p_rs::core::transport::upgrade::TransportUpgrade;
 use libp2p_rs::core::upgrade::Selector;
@@ -88,7 +88,11 @@ impl From<&IpfsOptions> for SwarmOptions {
 }
 
 impl Controls {
-    pub(crate) async fn build<T: RepoTypes>(repo: Repo<T>, options: SwarmOptions) -> Self {
+    pub(crate) async fn build<T: RepoTypes>(
+        repo: Repo<T>,
+        options: SwarmOptions,
+        bitswap_config: BitswapConfig,
+    ) -> Self {
         // start with security layer
         let sec_secio = secio::Config::new(options.keypair.clone());
         // Set up an encrypted TCP transport over the Yamux or Mplex protocol.
@@ -151,7 +155,7 @@ impl Controls {
         swarm = swarm.with_protocol(floodsub);
 
         // bitswap
-        let bitswap = Bitswap::new(repo, kad_control.clone());
+        let bitswap = Bitswap::new(repo, kad_control.clone(), bitswap_config);
         let bitswap_control = bitswap.control();
 
         // register bitswap into Swarm
@@ -46,8 +46,8 @@ pub struct FsDataStore {
     written_bytes: AtomicU64,
 }
 
-/// The column operations are all unimplemented pending at least downscoping of the
-/// DataStore trait itself.
+/// The column values are stored as files named after the keys, in a directory per column next to
+/// the pins.
 #[async_trait]
 impl DataStore for FsDataStore {
     fn new(mut root: PathBuf) -> Self {
@@ -68,20 +68,41 @@ impl DataStore for FsDataStore {
         Ok(())
     }
 
-    async fn contains(&self, _col: Column, _key: &[u8]) -> Result<bool, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn contains(&self, col: Column, key: &[u8]) -> Result<bool, Error> {
+        Ok(tokio::fs::metadata(self.column_path(col, key))
+            .await
+            .is_ok())
     }
 
-    async fn get(&self, _col: Column, _key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn get(&self, col: Column, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        match tokio::fs::read(self.column_path(col, key)).await {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
     }
 
-    async fn put(&self, _col: Column, _key: &[u8], _value: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn put(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let _permit = self.lock.acquire().await;
+
+        let path = self.column_path(col, key);
+        tokio::fs::create_dir_all(path.parent().expect("column directory has to exist")).await?;
+
+        // write and rename so that a crash will not leave a partial value behind
+        let temp = path.with_extension("tmp");
+        tokio::fs::write(&temp, value).await?;
+        tokio::fs::rename(temp, path).await?;
+        Ok(())
     }
 
-    async fn remove(&self, _col: Column, _key: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn remove(&self, col: Column, key: &[u8]) -> Result<(), Error> {
+        let _permit = self.lock.acquire().await;
+
+        match tokio::fs::remove_file(self.column_path(col, key)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
     }
 
     async fn wipe(&self) {
@@ -89,6 +110,15 @@ impl DataStore for FsDataStore {
     }
 }
 
+impl FsDataStore {
+    /// Returns the path of the file holding the value of the key in the column.
+    fn column_path(&self, col: Column, key: &[u8]) -> PathBuf {
+        let mut path = self.path.with_file_name(col.name());
+        path.push(multibase::Base::Base32Lower.encode(key));
+        path
+    }
+}
+
 #[derive(Debug)]
 pub struct FsLock {
     file: Option<File>,
@@ -38,6 +38,11 @@ impl KvDataStore {
     fn get_db(&self) -> &Db {
         self.db.get().unwrap()
     }
+
+    /// Columns are kept in their own trees, apart from the pins in the default tree.
+    fn get_column(&self, col: Column) -> Result<sled::Tree, Error> {
+        Ok(self.get_db().open_tree(col.name())?)
+    }
 }
 
 #[async_trait]
@@ -68,23 +73,29 @@ impl DataStore for KvDataStore {
     }
 
     /// Checks if a key is present in the datastore.
-    async fn contains(&self, _col: Column, _key: &[u8]) -> Result<bool, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn contains(&self, col: Column, key: &[u8]) -> Result<bool, Error> {
+        Ok(self.get_column(col)?.contains_key(key)?)
     }
 
     /// Returns the value associated with a key from the datastore.
-    async fn get(&self, _col: Column, _key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn get(&self, col: Column, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.get_column(col)?.get(key)?.map(|value| value.to_vec()))
     }
 
     /// Puts the value under the key in the datastore.
-    async fn put(&self, _col: Column, _key: &[u8], _value: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn put(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let column = self.get_column(col)?;
+        column.insert(key, value)?;
+        column.flush_async().await?;
+        Ok(())
     }
 
     /// Removes a key-value pair from the datastore.
-    async fn remove(&self, _col: Column, _key: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn remove(&self, col: Column, key: &[u8]) -> Result<(), Error> {
+        let column = self.get_column(col)?;
+        column.remove(key)?;
+        column.flush_async().await?;
+        Ok(())
     }
 
     /// Wipes the datastore.
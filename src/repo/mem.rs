@@ -98,6 +98,7 @@ impl BlockStore for MemBlockStore {
 #[derive(Debug, Default)]
 pub struct MemDataStore {
     ipns: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+    mfs: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
     // this could also be PinDocument however doing any serialization allows to see the required
     // error types easier
     pin: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
@@ -406,6 +407,7 @@ impl DataStore for MemDataStore {
     async fn contains(&self, col: Column, key: &[u8]) -> Result<bool, Error> {
         let map = match col {
             Column::Ipns => &self.ipns,
+            Column::Mfs => &self.mfs,
         };
         let contains = map.lock().await.contains_key(key);
         Ok(contains)
@@ -414,6 +416,7 @@ impl DataStore for MemDataStore {
     async fn get(&self, col: Column, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
         let map = match col {
             Column::Ipns => &self.ipns,
+            Column::Mfs => &self.mfs,
         };
         let value = map.lock().await.get(key).map(|value| value.to_owned());
         Ok(value)
@@ -422,6 +425,7 @@ impl DataStore for MemDataStore {
     async fn put(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(), Error> {
         let map = match col {
             Column::Ipns => &self.ipns,
+            Column::Mfs => &self.mfs,
         };
         map.lock().await.insert(key.to_owned(), value.to_owned());
         Ok(())
@@ -430,6 +434,7 @@ impl DataStore for MemDataStore {
     async fn remove(&self, col: Column, key: &[u8]) -> Result<(), Error> {
         let map = match col {
             Column::Ipns => &self.ipns,
+            Column::Mfs => &self.mfs,
         };
         map.lock().await.remove(key);
         Ok(())
@@ -437,6 +442,7 @@ impl DataStore for MemDataStore {
 
     async fn wipe(&self) {
         self.ipns.lock().await.clear();
+        self.mfs.lock().await.clear();
         self.pin.lock().await.clear();
     }
 }
@@ -220,6 +220,17 @@ pub trait PinStore: Debug + Send + Sync + Unpin + 'static {
 #[derive(Clone, Copy, Debug)]
 pub enum Column {
     Ipns,
+    Mfs,
+}
+
+impl Column {
+    /// The name of the column, used to keep the columns apart in the persistent datastores.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Column::Ipns => "ipns",
+            Column::Mfs => "mfs",
+        }
+    }
 }
 
 /// `PinMode` is the description of pin type for quering purposes.
@@ -380,9 +391,13 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
         }
     }
 
-    /// Puts a block into the block store.
+    /// Puts a block into the block store. Blocks inlined in identity hashed Cids are never
+    /// stored.
     pub async fn put_block(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
         let cid = block.cid.clone();
+        if Block::inlined(&cid).is_some() {
+            return Ok((cid, BlockPut::Existed));
+        }
         let (_cid, res) = self.0.block_store.put(block).await?;
         Ok((cid, res))
     }
@@ -394,6 +409,9 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
 
     /// Retrieves a block from the block store if it's available locally.
     pub async fn get_block_now(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        if let Some(block) = Block::inlined(cid) {
+            return Ok(Some(block));
+        }
         self.0.block_store.get(&cid).await
     }
 
@@ -461,6 +479,24 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
             .await
     }
 
+    /// Get the root of the mutable file system from the datastore.
+    pub async fn get_mfs_root(&self) -> Result<Option<Cid>, Error> {
+        use std::convert::TryFrom;
+
+        match self.0.data_store.get(Column::Mfs, b"root").await? {
+            Some(bytes) => Ok(Some(Cid::try_from(bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Put the root of the mutable file system into the datastore.
+    pub async fn put_mfs_root(&self, root: &Cid) -> Result<(), Error> {
+        self.0
+            .data_store
+            .put(Column::Mfs, b"root", &root.to_bytes())
+            .await
+    }
+
     /// Inserts a direct pin for a `Cid`.
     pub async fn insert_direct_pin(&self, cid: &Cid) -> Result<(), Error> {
         self.0.data_store.insert_direct_pin(cid).await
@@ -507,17 +543,18 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
 #[async_trait]
 impl<TRepoTypes: RepoTypes> BsBlockStore for Repo<TRepoTypes> {
     async fn contains(&self, cid: &Cid) -> Result<bool, Box<dyn error::Error>> {
+        if Block::inlined(cid).is_some() {
+            return Ok(true);
+        }
         self.0.block_store.contains(cid).await.map_err(Error::into)
     }
 
     async fn get(&self, cid: &Cid) -> Result<Option<Block>, Box<dyn error::Error>> {
-        self.0.block_store.get(cid).await.map_err(Error::into)
+        self.get_block_now(cid).await.map_err(Error::into)
     }
 
     async fn put(&self, block: Block) -> Result<(Cid, bool), Box<dyn error::Error>> {
-        self.0
-            .block_store
-            .put(block)
+        self.put_block(block)
             .await
             .map(|(cid, put)| (cid, put == BlockPut::NewBlock))
             .map_err(Error::into)
@@ -0,0 +1,748 @@
+use crate::{Block, Error, Ipfs, IpfsTypes};
+use cid::{Cid, Codec};
+use ipfs_unixfs::{
+    dir::builder::{BufferingTreeBuilder, TreeBuildingFailed, TreeConstructionFailed, TreeOptions},
+    file::adder::{Chunker, FileAdder, TrickleCollector},
+    symlink::serialize_symlink_block_with_metadata,
+    Metadata,
+};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::IgnoreRules;
+
+/// Options for [`Ipfs::add_path`], [`Ipfs::add_reader`] and [`Ipfs::add_tar`]. The defaults match go-ipfs.
+#[derive(Debug, Clone)]
+pub struct AddOptions {
+    /// The chunking algorithm of the file contents.
+    pub chunker: Chunker,
+    /// Use the trickle layout instead of the balanced one.
+    pub trickle: bool,
+    /// Store the file contents as raw blocks. Defaults to true with Cid version 1.
+    pub raw_leaves: Option<bool>,
+    /// The Cid version of the created blocks.
+    pub cid_version: cid::Version,
+    /// Inline the blocks of up to the given amount of bytes into their Cids.
+    pub inline_limit: Option<usize>,
+    /// Wrap the added files and directories into a new directory.
+    pub wrap_with_directory: bool,
+    /// Add the files and directories whose names begin with a dot.
+    pub hidden: bool,
+    /// A file of `.gitignore` style rules for the entries to skip when adding a directory.
+    pub ignore_rules_path: Option<PathBuf>,
+    /// Additional `.gitignore` style rules for the entries to skip when adding a directory.
+    pub exclude: Vec<String>,
+    /// Add the targets of symlinks instead of the symlinks.
+    pub follow_symlinks: bool,
+    /// Store the permissions of the files and directories.
+    pub preserve_mode: bool,
+    /// Store the modification times of the files and directories.
+    pub preserve_mtime: bool,
+    /// Only calculate the `Cid`s without storing any blocks. The root is then neither pinned nor
+    /// provided.
+    pub only_hash: bool,
+    /// Pin the root recursively once everything has been added.
+    pub pin: bool,
+    /// Provide the root on the DHT once everything has been added.
+    pub provide: bool,
+}
+
+impl Default for AddOptions {
+    fn default() -> Self {
+        AddOptions {
+            chunker: Chunker::default(),
+            trickle: false,
+            raw_leaves: None,
+            cid_version: cid::Version::V0,
+            inline_limit: None,
+            wrap_with_directory: false,
+            hidden: false,
+            ignore_rules_path: None,
+            exclude: Vec::new(),
+            follow_symlinks: false,
+            preserve_mode: false,
+            preserve_mtime: false,
+            only_hash: false,
+            pin: false,
+            provide: false,
+        }
+    }
+}
+
+impl AddOptions {
+    /// Returns the adder for a file with the given metadata, configured by these options.
+    pub fn file_adder(&self, metadata: Metadata) -> FileAdder {
+        // same as go-ipfs, raw leaves are the default with cid version 1
+        let raw_leaves = self
+            .raw_leaves
+            .unwrap_or(self.cid_version == cid::Version::V1);
+
+        let builder = FileAdder::builder()
+            .with_chunker(self.chunker.clone())
+            .with_raw_leaves(raw_leaves)
+            .with_cid_version(self.cid_version)
+            .with_metadata(metadata);
+
+        let builder = if self.trickle {
+            builder.with_collector(TrickleCollector::default())
+        } else {
+            builder
+        };
+
+        let builder = match self.inline_limit {
+            Some(limit) => builder.with_inline_limit(limit),
+            None => builder,
+        };
+
+        builder.build()
+    }
+
+    /// Returns the options of the directory tree builder, configured by these options.
+    pub fn tree_options(&self) -> TreeOptions {
+        let mut opts = TreeOptions::default();
+        if self.wrap_with_directory {
+            opts.wrap_with_directory();
+        }
+        opts.cid_version(self.cid_version);
+        if let Some(limit) = self.inline_limit {
+            opts.inline_limit(limit);
+        }
+        opts
+    }
+
+    async fn ignore_rules(&self) -> Result<IgnoreRules, AddError> {
+        let mut rules = match &self.ignore_rules_path {
+            Some(path) => IgnoreRules::parse(
+                &fs::read_to_string(path)
+                    .await
+                    .map_err(|e| AddError::Io(path.to_owned(), e))?,
+            ),
+            None => IgnoreRules::default(),
+        };
+
+        for rule in &self.exclude {
+            rules.add(rule);
+        }
+
+        Ok(rules)
+    }
+
+    /// Returns true if the entry at the `relative` path below an added directory is skipped, as
+    /// either the entry or a directory above it is hidden or it is ignored by the `rules`. Used
+    /// while walking the directories in [`Ipfs::add_path`] and for the entries posted to the HTTP
+    /// API.
+    pub fn skips(&self, relative: &str, is_dir: bool, rules: &IgnoreRules) -> bool {
+        if !self.hidden && relative.split('/').any(|name| name.starts_with('.')) {
+            return true;
+        }
+
+        rules.is_ignored(relative, is_dir)
+    }
+
+    fn metadata(&self, meta: &std::fs::Metadata) -> Metadata {
+        let mut metadata = Metadata::default();
+
+        #[cfg(unix)]
+        {
+            if self.preserve_mode {
+                use std::os::unix::fs::PermissionsExt;
+                metadata = metadata.with_mode(meta.permissions().mode() & 0o7777);
+            }
+        }
+
+        if self.preserve_mtime {
+            if let Ok(modified) = meta.modified() {
+                metadata = match modified.duration_since(UNIX_EPOCH) {
+                    Ok(d) => metadata.with_mtime(d.as_secs() as i64, d.subsec_nanos()),
+                    // only whole seconds before the epoch
+                    Err(e) => metadata.with_mtime(-(e.duration().as_secs() as i64), 0),
+                };
+            }
+        }
+
+        metadata
+    }
+}
+
+/// An added file or directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Added {
+    /// The path of the entry in the added tree, or the `Cid` of a file added from a reader.
+    pub path: String,
+    /// The `Cid` of the entry.
+    pub cid: Cid,
+    /// The cumulative size of the blocks of the entry.
+    pub size: u64,
+}
+
+/// Adds the file, directory or symlink at the given path. The directories are added recursively
+/// and the entries are reported in the order they were added, the root being the last.
+pub(crate) async fn add_path<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    path: &Path,
+    opts: &AddOptions,
+) -> Result<Vec<Added>, AddError> {
+    let root_name = match path.file_name() {
+        Some(name) => name.to_owned(),
+        // paths like "." or ".." need to be resolved for the name
+        None => fs::canonicalize(path)
+            .await
+            .map_err(|e| AddError::Io(path.to_owned(), e))?
+            .file_name()
+            .map(|name| name.to_owned())
+            .unwrap_or_default(),
+    };
+
+    let root_name = root_name
+        .to_str()
+        .ok_or_else(|| AddError::InvalidPath(path.to_owned()))?
+        .to_owned();
+
+    let rules = opts.ignore_rules().await?;
+    let mut tree = BufferingTreeBuilder::new(opts.tree_options());
+    let mut added = Vec::new();
+
+    // depth first with the entries of each directory in order; the directories above each entry
+    // are kept to detect the cycles when following symlinks
+    let mut stack = vec![(path.to_owned(), root_name, Vec::new())];
+
+    while let Some((fs_path, tree_path, ancestors)) = stack.pop() {
+        let io_error = |e| AddError::Io(fs_path.clone(), e);
+
+        let meta = if opts.follow_symlinks {
+            fs::metadata(&fs_path).await
+        } else {
+            fs::symlink_metadata(&fs_path).await
+        }
+        .map_err(io_error)?;
+
+        let file_type = meta.file_type();
+
+        if file_type.is_symlink() {
+            let target = fs::read_link(&fs_path).await.map_err(io_error)?;
+            let target = target
+                .to_str()
+                .ok_or_else(|| AddError::InvalidPath(target.clone()))?;
+
+            let (cid, size) = add_symlink(ipfs, target, opts.metadata(&meta), opts).await?;
+
+            tree.put_link(&tree_path, cid.clone(), size)
+                .map_err(AddError::TreeGathering)?;
+            added.push(Added {
+                path: tree_path,
+                cid,
+                size,
+            });
+        } else if file_type.is_dir() {
+            let ancestors = match file_id(&meta) {
+                Some(id) if opts.follow_symlinks => {
+                    if ancestors.contains(&id) {
+                        return Err(AddError::SymlinkCycle(fs_path));
+                    }
+                    let mut ancestors = ancestors;
+                    ancestors.push(id);
+                    ancestors
+                }
+                _ => ancestors,
+            };
+
+            // this also adds empty directories
+            tree.set_metadata(&tree_path, opts.metadata(&meta))
+                .map_err(AddError::TreeGathering)?;
+
+            let mut entries = fs::read_dir(&fs_path).await.map_err(io_error)?;
+            let mut names = Vec::new();
+
+            while let Some(entry) = entries.next_entry().await.map_err(io_error)? {
+                let name = entry.file_name();
+                let name = name
+                    .to_str()
+                    .ok_or_else(|| AddError::InvalidPath(entry.path()))?;
+
+                // the rules are relative to the added directory
+                let relative = match tree_path.find('/') {
+                    Some(i) => format!("{}/{}", &tree_path[i + 1..], name),
+                    None => name.to_owned(),
+                };
+
+                let is_dir = if rules.is_empty() {
+                    // only the rules care about the kind
+                    false
+                } else if opts.follow_symlinks {
+                    fs::metadata(entry.path())
+                        .await
+                        .map(|m| m.is_dir())
+                        .map_err(|e| AddError::Io(entry.path(), e))?
+                } else {
+                    entry
+                        .file_type()
+                        .await
+                        .map(|t| t.is_dir())
+                        .map_err(|e| AddError::Io(entry.path(), e))?
+                };
+
+                if opts.skips(&relative, is_dir, &rules) {
+                    continue;
+                }
+
+                names.push(name.to_owned());
+            }
+
+            names.sort_unstable();
+
+            for name in names.into_iter().rev() {
+                let child = format!("{}/{}", tree_path, name);
+                stack.push((fs_path.join(name), child, ancestors.clone()));
+            }
+        } else if file_type.is_file() {
+            let file = fs::File::open(&fs_path).await.map_err(io_error)?;
+            let adder = opts.file_adder(opts.metadata(&meta));
+
+            let (cid, size) = add_file(ipfs, file, adder, opts)
+                .await
+                .map_err(|e| e.with_path(&fs_path))?;
+
+            tree.put_link(&tree_path, cid.clone(), size)
+                .map_err(AddError::TreeGathering)?;
+            added.push(Added {
+                path: tree_path,
+                cid,
+                size,
+            });
+        } else {
+            return Err(AddError::UnsupportedFileType(fs_path));
+        }
+    }
+
+    build(ipfs, tree, &mut added, opts).await?;
+    finish(ipfs, &added, opts).await?;
+
+    Ok(added)
+}
+
+/// Identifies a directory by its device and inode for detecting the cycles of symlinks, which is
+/// only supported on unix.
+#[cfg(unix)]
+fn file_id(meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Stores the directories of the tree, adding them to the added entries.
+pub(super) async fn build<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    tree: BufferingTreeBuilder,
+    added: &mut Vec<Added>,
+    opts: &AddOptions,
+) -> Result<(), AddError> {
+    for node in tree.build() {
+        let node = node.map_err(AddError::TreeBuilding)?;
+
+        store(ipfs, Block::new(node.block, node.cid.clone()), opts).await?;
+
+        if node.bucket {
+            // the inner nodes of sharded directories are not reported
+            continue;
+        }
+
+        added.push(Added {
+            path: node.path,
+            cid: node.cid,
+            size: node.total_size,
+        });
+    }
+
+    Ok(())
+}
+
+/// Adds the contents of the reader as a single file. Wrapping is not supported as there is no
+/// name for the file.
+pub(crate) async fn add_reader<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    reader: impl AsyncRead + Unpin,
+    opts: &AddOptions,
+) -> Result<Added, AddError> {
+    let adder = opts.file_adder(Metadata::default());
+    let (cid, size) = add_file(ipfs, reader, adder, opts).await?;
+
+    let added = Added {
+        path: cid.to_string(),
+        cid,
+        size,
+    };
+
+    finish(ipfs, std::slice::from_ref(&added), opts).await?;
+
+    Ok(added)
+}
+
+/// Returns the `Cid` of the file and the total size of its blocks.
+pub(super) async fn add_file<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    mut reader: impl AsyncRead + Unpin,
+    mut adder: FileAdder,
+    opts: &AddOptions,
+) -> Result<(Cid, u64), AddError> {
+    let mut buffer = vec![0u8; adder.size_hint().max(8 * 1024)];
+    let mut size = 0;
+
+    loop {
+        let read = reader.read(&mut buffer).await.map_err(AddError::Reading)?;
+
+        if read == 0 {
+            break;
+        }
+
+        let mut consumed = 0;
+        while consumed < read {
+            let (blocks, used) = adder.push(&buffer[consumed..read]);
+            consumed += used;
+            size += import(ipfs, blocks, opts).await?.1;
+        }
+    }
+
+    let (last, subtotal) = import(ipfs, adder.finish(), opts).await?;
+    let cid = last.expect("finish always produces the root block");
+
+    Ok((cid, size + subtotal))
+}
+
+/// Stores the blocks, returning the `Cid` of the last one and the total size of the blocks.
+async fn import<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    blocks: impl Iterator<Item = (Cid, Vec<u8>)>,
+    opts: &AddOptions,
+) -> Result<(Option<Cid>, u64), AddError> {
+    let mut last = None;
+    let mut total = 0;
+
+    for (cid, data) in blocks {
+        total += data.len() as u64;
+        let block = Block::new(data.into_boxed_slice(), cid);
+        last = Some(store(ipfs, block, opts).await?);
+    }
+
+    Ok((last, total))
+}
+
+/// Stores a symlink block with the metadata, returning its `Cid` and size.
+pub(super) async fn add_symlink<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    target: &str,
+    metadata: Metadata,
+    opts: &AddOptions,
+) -> Result<(Cid, u64), AddError> {
+    let mut block = Vec::new();
+    serialize_symlink_block_with_metadata(target, &metadata, &mut block);
+
+    let size = block.len() as u64;
+    Ok((put(ipfs, opts, block).await?, size))
+}
+
+/// Stores a dag-pb block of the `Cid` version of the options, or inlines it into the `Cid` when
+/// it fits the inline limit.
+async fn put<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    opts: &AddOptions,
+    block: Vec<u8>,
+) -> Result<Cid, AddError> {
+    let cid = match opts.inline_limit {
+        Some(limit) if block.len() <= limit => Cid::new_v1(
+            Codec::DagProtobuf,
+            multihash::wrap(multihash::Code::Identity, &block),
+        ),
+        _ => {
+            let mh = multihash::Sha2_256::digest(&block);
+            match opts.cid_version {
+                cid::Version::V0 => {
+                    Cid::new_v0(mh).expect("sha2-256 is supported by cid version 0")
+                }
+                cid::Version::V1 => Cid::new_v1(Codec::DagProtobuf, mh),
+            }
+        }
+    };
+
+    store(ipfs, Block::new(block.into_boxed_slice(), cid), opts).await
+}
+
+/// Stores the block unless only the `Cid`s are calculated.
+async fn store<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    block: Block,
+    opts: &AddOptions,
+) -> Result<Cid, AddError> {
+    if opts.only_hash {
+        return Ok(block.cid);
+    }
+
+    ipfs.put_block(block).await.map_err(AddError::Persisting)
+}
+
+/// Pins and provides the root, which is the last of the added entries.
+pub(super) async fn finish<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    added: &[Added],
+    opts: &AddOptions,
+) -> Result<(), AddError> {
+    let root = match added.last() {
+        Some(root) if !opts.only_hash => &root.cid,
+        _ => return Ok(()),
+    };
+
+    if opts.pin {
+        ipfs.insert_pin(root, true)
+            .await
+            .map_err(AddError::Pinning)?;
+    }
+
+    if opts.provide {
+        ipfs.provide(root.to_owned())
+            .await
+            .map_err(AddError::Providing)?;
+    }
+
+    Ok(())
+}
+
+/// Failures of [`Ipfs::add_path`], [`Ipfs::add_reader`] and [`Ipfs::add_tar`].
+#[derive(Debug, thiserror::Error)]
+pub enum AddError {
+    /// Reading a file or a directory failed.
+    #[error("failed to read {}", .0.display())]
+    Io(PathBuf, #[source] io::Error),
+
+    /// Reading the input of [`Ipfs::add_reader`] failed.
+    #[error("failed to read the input")]
+    Reading(#[source] io::Error),
+
+    /// The path is not valid UTF-8.
+    #[error("path is not valid utf-8: {}", .0.display())]
+    InvalidPath(PathBuf),
+
+    /// The path is not a file, a directory or a symlink.
+    #[error("unsupported file type: {}", .0.display())]
+    UnsupportedFileType(PathBuf),
+
+    /// Following the symlinks led to a directory above the path.
+    #[error("symlink cycle at {}", .0.display())]
+    SymlinkCycle(PathBuf),
+
+    /// Storing a block failed.
+    #[error("put_block failed")]
+    Persisting(#[source] Error),
+
+    /// The added paths did not form a valid tree.
+    #[error("invalid directory tree")]
+    TreeGathering(#[source] TreeBuildingFailed),
+
+    /// Building the directory tree failed.
+    #[error("constructed invalid directory tree")]
+    TreeBuilding(#[source] TreeConstructionFailed),
+
+    /// The tar archive given to [`Ipfs::add_tar`] is malformed.
+    #[error("invalid tar archive: {}", .0)]
+    InvalidArchive(String),
+
+    /// Pinning the root failed.
+    #[error("pinning failed")]
+    Pinning(#[source] Error),
+
+    /// Providing the root failed.
+    #[error("providing failed")]
+    Providing(#[source] Error),
+}
+
+impl AddError {
+    fn with_path(self, path: &Path) -> Self {
+        match self {
+            AddError::Reading(e) => AddError::Io(path.to_owned(), e),
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AddOptions;
+    use crate::Node;
+    use std::fs;
+
+    #[tokio::test]
+    async fn add_directory() {
+        let ipfs = Node::new("test_node").await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a"), b"foobar\n").unwrap();
+        fs::write(root.join("sub/b"), b"barfoo\n").unwrap();
+        fs::write(root.join(".hidden"), b"secret\n").unwrap();
+
+        let added = ipfs.add_path(&root, AddOptions::default()).await.unwrap();
+
+        let mut paths = added.iter().map(|a| a.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths.last(), Some(&"root"));
+
+        paths.sort_unstable();
+        assert_eq!(
+            paths,
+            &["root", "root/a", "root/empty", "root/sub", "root/sub/b"]
+        );
+
+        let opts = AddOptions {
+            hidden: true,
+            ..Default::default()
+        };
+
+        let with_hidden = ipfs.add_path(&root, opts).await.unwrap();
+        assert!(with_hidden.iter().any(|a| a.path == "root/.hidden"));
+        assert_ne!(with_hidden.last(), added.last());
+    }
+
+    #[tokio::test]
+    async fn add_directory_with_ignore_rules() {
+        let ipfs = Node::new("test_node").await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::create_dir_all(root.join("src/target")).unwrap();
+        fs::write(root.join("target/debug/out"), b"binary\n").unwrap();
+        fs::write(root.join("src/target/mod.rs"), b"mod\n").unwrap();
+        fs::write(root.join("src/main.rs"), b"main\n").unwrap();
+        fs::write(root.join("build.log"), b"log\n").unwrap();
+        fs::write(root.join("keep.log"), b"log\n").unwrap();
+
+        let rules = tmp.path().join("ignore");
+        fs::write(&rules, b"# outputs\n/target/\n*.log\n").unwrap();
+
+        let opts = AddOptions {
+            ignore_rules_path: Some(rules),
+            exclude: vec!["!keep.log".into(), "main.rs".into()],
+            ..Default::default()
+        };
+
+        let added = ipfs.add_path(&root, opts).await.unwrap();
+
+        let mut paths = added.iter().map(|a| a.path.as_str()).collect::<Vec<_>>();
+        paths.sort_unstable();
+        assert_eq!(
+            paths,
+            &[
+                "root",
+                "root/keep.log",
+                "root/src",
+                "root/src/target",
+                "root/src/target/mod.rs"
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn add_symlinks() {
+        use super::AddError;
+        use crate::unixfs::ll::{node_info, NodeKind};
+        use std::os::unix::fs::symlink;
+
+        let ipfs = Node::new("test_node").await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("sub/a"), b"foobar\n").unwrap();
+        symlink("sub", root.join("link")).unwrap();
+
+        // the small symlinks are inlined like the files and directories and keep their metadata
+        let opts = AddOptions {
+            inline_limit: Some(64),
+            preserve_mtime: true,
+            ..Default::default()
+        };
+
+        let added = ipfs.add_path(&root, opts).await.unwrap();
+        let link = added.iter().find(|a| a.path == "root/link").unwrap();
+        assert_eq!(link.cid.hash().algorithm(), multihash::Code::Identity);
+
+        let block = ipfs.get_block(&link.cid).await.unwrap();
+        let info = node_info(&block.data).unwrap();
+        assert_eq!(info.kind, NodeKind::Symlink);
+        assert!(info.metadata.mtime().is_some());
+
+        let opts = AddOptions {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+
+        let followed = ipfs.add_path(&root, opts.clone()).await.unwrap();
+        let mut paths = followed.iter().map(|a| a.path.as_str()).collect::<Vec<_>>();
+        paths.sort_unstable();
+        assert_eq!(
+            paths,
+            &["root", "root/link", "root/link/a", "root/sub", "root/sub/a"]
+        );
+
+        // a link to a directory above is a cycle
+        symlink("..", root.join("sub/up")).unwrap();
+
+        match ipfs.add_path(&root, opts).await {
+            Err(AddError::SymlinkCycle(path)) => assert_eq!(path, root.join("link/up")),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn only_hash() {
+        let ipfs = Node::new("test_node").await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a"), vec![1u8; 300 * 1024]).unwrap();
+        fs::write(root.join("sub/b"), b"foobar\n").unwrap();
+
+        let opts = AddOptions {
+            only_hash: true,
+            pin: true,
+            ..Default::default()
+        };
+
+        let hashed = ipfs.add_path(&root, opts).await.unwrap();
+        assert!(ipfs.refs_local().await.unwrap().is_empty());
+
+        let added = ipfs.add_path(&root, AddOptions::default()).await.unwrap();
+        assert_eq!(hashed, added);
+    }
+
+    #[tokio::test]
+    async fn add_reader_matches_path() {
+        let ipfs = Node::new("test_node").await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("file");
+        let content = vec![7u8; 1024 * 1024];
+        fs::write(&path, &content).unwrap();
+
+        let opts = AddOptions {
+            pin: true,
+            ..Default::default()
+        };
+
+        let from_path = ipfs.add_path(&path, opts.clone()).await.unwrap();
+        let from_reader = ipfs.add_reader(&content[..], opts).await.unwrap();
+
+        assert_eq!(from_path.len(), 1);
+        assert_eq!(from_path[0].cid, from_reader.cid);
+        assert_eq!(from_path[0].size, from_reader.size);
+        assert!(ipfs.is_pinned(&from_reader.cid).await.unwrap());
+    }
+}
@@ -9,6 +9,8 @@ use ipfs_unixfs::file::{visit::IdleFileVisit, FileReadFailed};
 use std::borrow::Borrow;
 use std::ops::Range;
 
+use super::ReadAhead;
+
 /// IPFS cat operation, producing a stream of file bytes. This is generic over the different kinds
 /// of ways to own an `Ipfs` value in order to support both operating with borrowed `Ipfs` value
 /// and an owned value. Passing an owned value allows the return value to be `'static`, which can
@@ -49,7 +51,13 @@ where
     let mut cache = None;
     // Start the visit from the root block. We need to move the both components as Options into the
     // stream as we can't yet return them from this Future context.
-    let (visit, bytes) = match visit.start(&data) {
+    let started = if cid.codec() == cid::Codec::Raw {
+        visit.start_raw(&data)
+    } else {
+        visit.start(&data)
+    };
+
+    let (visit, bytes) = match started {
         Ok((bytes, _, _, visit)) => {
             let bytes = if !bytes.is_empty() {
                 Some(bytes.to_vec())
@@ -80,19 +88,18 @@ where
             None => return,
         };
 
+        // the blocks are loaded concurrently but consumed in order
+        let mut read_ahead = ReadAhead::new(ipfs.borrow().clone());
+
         loop {
-            // TODO: if it was possible, it would make sense to start downloading N of these
-            // we could just create an FuturesUnordered which would drop the value right away. that
-            // would probably always cost many unnecessary clones, but it would be nice to "shut"
-            // the subscriber so that it will only resolve to a value but still keep the operation
-            // going. Not that we have any "operation" concept of the Want yet.
-            let (next, _) = visit.pending_links();
+            let (next, rest) = visit.pending_links();
+            read_ahead.prefetch(std::iter::once(next).chain(rest));
+            let next = next.to_owned();
 
-            let borrow = ipfs.borrow();
-            let Block { cid, data } = match borrow.get_block(&next).await {
+            let Block { cid, data } = match read_ahead.get_block(&next).await {
                 Ok(block) => block,
                 Err(e) => {
-                    yield Err(TraversalFailed::Loading(next.to_owned(), e));
+                    yield Err(TraversalFailed::Loading(next, e));
                     return;
                 },
             };
@@ -0,0 +1,449 @@
+use crate::{Block, Ipfs, IpfsTypes};
+use cid::{Cid, Codec};
+use futures::ready;
+use ipfs_unixfs::file::visit::{Cache, FileVisit, IdleFileVisit};
+use ipfs_unixfs::Metadata;
+use std::io::{self, SeekFrom};
+use std::mem;
+use std::ops::Range;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+
+use super::{ReadAhead, StartingPoint, TraversalFailed};
+
+/// A handle for reading a UnixFs file with [`tokio::io::AsyncRead`] and
+/// [`tokio::io::AsyncSeek`], created with [`Ipfs::open_unixfs`].
+///
+/// The intermediate nodes on the path from the root to the current position are kept in memory
+/// along with the byte ranges they cover. Seeking continues the walk from the deepest of them
+/// covering the new position, following only the links covering it, so neither the ancestors nor
+/// the already passed siblings are loaded again. While reading, the next pending blocks are
+/// loaded in the background with a [`ReadAhead`].
+pub struct UnixfsFile<Types: IpfsTypes> {
+    root: Block,
+    size: u64,
+    metadata: Metadata,
+    /// The position of the next byte read.
+    position: u64,
+    walk: Walk,
+    /// The offset in the file of the block the current walk was started from.
+    walk_base: u64,
+    cache: Option<Cache>,
+    /// The bytes of the latest block; the first byte is at `buffer_offset` in the file.
+    buffer: Vec<u8>,
+    buffer_offset: u64,
+    /// How many of the buffered bytes have already been read.
+    consumed: usize,
+    /// The intermediate nodes below the root on the path to the current position, with the byte
+    /// ranges of the file they cover, from the shallowest to the deepest.
+    branch: Vec<(Block, Range<u64>)>,
+    read_ahead: ReadAhead<Types>,
+}
+
+enum Walk {
+    /// The walk needs to be started at the current position from the deepest node covering it.
+    Idle,
+    Walking(FileVisit),
+    Done,
+}
+
+impl<Types: IpfsTypes> UnixfsFile<Types> {
+    pub(crate) async fn open(
+        ipfs: Ipfs<Types>,
+        starting_point: impl Into<StartingPoint>,
+    ) -> Result<Self, TraversalFailed> {
+        let root = match starting_point.into() {
+            StartingPoint::Left(path) => {
+                let (resolved, _) = ipfs
+                    .dag()
+                    .resolve(path, true)
+                    .await
+                    .map_err(TraversalFailed::Resolving)?;
+                resolved
+                    .into_unixfs_block()
+                    .map_err(TraversalFailed::Path)?
+            }
+            StartingPoint::Right(block) => block,
+        };
+
+        // an empty range validates the root without collecting any links
+        let (size, metadata) = start(&root, 0..0)
+            .map(|(_, size, metadata, _)| (size, metadata))
+            .map_err(|e| TraversalFailed::Walking(root.cid.clone(), e))?;
+
+        Ok(UnixfsFile {
+            root,
+            size,
+            metadata,
+            position: 0,
+            walk: Walk::Idle,
+            walk_base: 0,
+            cache: None,
+            buffer: Vec::new(),
+            buffer_offset: 0,
+            consumed: 0,
+            branch: Vec::new(),
+            read_ahead: ReadAhead::new(ipfs),
+        })
+    }
+
+    /// Returns the `Cid` of the file.
+    pub fn cid(&self) -> &Cid {
+        &self.root.cid
+    }
+
+    /// Returns the total size of the file in bytes.
+    pub fn file_size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the metadata of the file.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Fills the buffer with the next bytes of the file, returning false at the end of the file.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<bool>> {
+        loop {
+            if self.position >= self.size {
+                return Poll::Ready(Ok(false));
+            }
+
+            let next = match &self.walk {
+                Walk::Idle => {
+                    // the rest of the branch is not on the path to the position
+                    let position = self.position;
+                    let depth = self
+                        .branch
+                        .iter()
+                        .rposition(|(_, range)| range.contains(&position))
+                        .map(|i| i + 1)
+                        .unwrap_or(0);
+                    self.branch.truncate(depth);
+
+                    let (block, range) = match self.branch.last() {
+                        Some((block, range)) => (block, range.clone()),
+                        None => (&self.root, 0..self.size),
+                    };
+
+                    let target = (position - range.start)..(range.end - range.start);
+                    let (bytes, _, _, visit) =
+                        start(block, target).map_err(|e| walking_failed(&block.cid, e))?;
+
+                    self.buffer.clear();
+                    self.buffer.extend_from_slice(bytes);
+                    self.buffer_offset = position;
+                    self.consumed = 0;
+
+                    self.walk_base = range.start;
+                    self.walk = match visit {
+                        Some(visit) => Walk::Walking(visit),
+                        // nothing was found at the position even if the size says otherwise
+                        None if self.buffer.is_empty() => Walk::Done,
+                        None => Walk::Idle,
+                    };
+                    self.prefetch();
+
+                    if self.buffer.is_empty() {
+                        continue;
+                    }
+                    return Poll::Ready(Ok(true));
+                }
+                Walk::Walking(visit) => visit.pending_links().0.to_owned(),
+                Walk::Done => return Poll::Ready(Ok(false)),
+            };
+
+            let block = ready!(self.poll_block(cx, next))?;
+
+            let visit = match mem::replace(&mut self.walk, Walk::Done) {
+                Walk::Walking(visit) => visit,
+                _ => unreachable!("the block was loaded for the walk"),
+            };
+
+            let range = visit.pending_range();
+            let range = (self.walk_base + range.start)..(self.walk_base + range.end);
+
+            let (bytes, visit) = visit
+                .continue_walk(&block.data, &mut self.cache)
+                .map_err(|e| walking_failed(&block.cid, e))?;
+
+            self.buffer.clear();
+            self.buffer.extend_from_slice(bytes);
+            self.buffer_offset = self.position;
+            self.consumed = 0;
+
+            // once the subtree the walk was started from is done, the walk continues from an
+            // ancestor
+            self.walk = visit.map(Walk::Walking).unwrap_or(Walk::Idle);
+
+            if self.buffer.is_empty() && block.cid.codec() == Codec::DagProtobuf {
+                // an intermediate node, which becomes the deepest on the path
+                let depth = self
+                    .branch
+                    .iter()
+                    .rposition(|(_, outer)| outer.start <= range.start && range.end <= outer.end)
+                    .map(|i| i + 1)
+                    .unwrap_or(0);
+                self.branch.truncate(depth);
+                self.branch.push((block, range));
+            }
+
+            self.prefetch();
+
+            if !self.buffer.is_empty() {
+                return Poll::Ready(Ok(true));
+            }
+        }
+    }
+
+    fn poll_block(&mut self, cx: &mut Context<'_>, cid: Cid) -> Poll<io::Result<Block>> {
+        if let Some((block, _)) = self.branch.iter().find(|(b, _)| b.cid == cid) {
+            return Poll::Ready(Ok(block.clone()));
+        }
+
+        self.read_ahead
+            .poll_block(cx, &cid)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, TraversalFailed::Loading(cid, e)))
+    }
+
+    /// Starts loading the next pending blocks of the walk and stops loading the ones which are
+    /// no longer needed.
+    fn prefetch(&mut self) {
+        let branch = &self.branch;
+
+        match &self.walk {
+            Walk::Walking(visit) => {
+                let (first, rest) = visit.pending_links();
+                let upcoming = std::iter::once(first)
+                    .chain(rest)
+                    .filter(|cid| !branch.iter().any(|(b, _)| &b.cid == *cid));
+                self.read_ahead.prefetch(upcoming);
+            }
+            _ => self.read_ahead.prefetch(None),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.walk = Walk::Idle;
+        self.buffer.clear();
+        self.consumed = 0;
+        self.prefetch();
+    }
+}
+
+impl<Types: IpfsTypes> AsyncRead for UnixfsFile<Types> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            let buffered = &this.buffer[this.consumed..];
+
+            if !buffered.is_empty() {
+                let n = buffered.len().min(buf.remaining());
+                buf.put_slice(&buffered[..n]);
+                this.consumed += n;
+                this.position += n as u64;
+                return Poll::Ready(Ok(()));
+            }
+
+            if !ready!(this.poll_fill(cx))? {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+impl<Types: IpfsTypes> AsyncSeek for UnixfsFile<Types> {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => offset(this.size, delta),
+            SeekFrom::Current(delta) => offset(this.position, delta),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        let buffer_end = this.buffer_offset + this.buffer.len() as u64;
+
+        if target >= this.buffer_offset && target <= buffer_end {
+            // the walk continues from the end of the buffer
+            this.consumed = (target - this.buffer_offset) as usize;
+        } else {
+            this.reset();
+        }
+
+        this.position = target;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+fn start(
+    root: &Block,
+    range: std::ops::Range<u64>,
+) -> Result<(&[u8], u64, Metadata, Option<FileVisit>), ipfs_unixfs::file::FileReadFailed> {
+    let visit = IdleFileVisit::default().with_target_range(range);
+    if root.cid.codec() == Codec::Raw {
+        visit.start_raw(&root.data)
+    } else {
+        visit.start(&root.data)
+    }
+}
+
+fn walking_failed(cid: &Cid, e: ipfs_unixfs::file::FileReadFailed) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        TraversalFailed::Walking(cid.to_owned(), e),
+    )
+}
+
+fn offset(base: u64, delta: i64) -> Option<u64> {
+    if delta >= 0 {
+        base.checked_add(delta as u64)
+    } else {
+        base.checked_sub(delta.wrapping_neg() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ipld::dag_pb::PbNode;
+    use crate::{Block, Ipfs, Node, TestTypes};
+    use cid::Cid;
+    use ipfs_unixfs::file::adder::{BalancedCollector, Chunker, FileAdder};
+    use std::io::SeekFrom;
+    use std::time::Duration;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+
+    #[tokio::test]
+    async fn read_and_seek() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = (0..10_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let (root, _) = put_file(&ipfs, &content).await;
+
+        let mut file = ipfs.open_unixfs(root).await.unwrap();
+        assert_eq!(file.file_size(), content.len() as u64);
+
+        let mut read = Vec::new();
+        file.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, content);
+
+        let cases = [
+            SeekFrom::Start(0),
+            SeekFrom::Start(5555),
+            SeekFrom::Current(-1000),
+            SeekFrom::Current(50),
+            SeekFrom::End(-1),
+            SeekFrom::Start(150),
+            SeekFrom::Start(9_999),
+        ];
+
+        for &seek in &cases {
+            let position = file.seek(seek).await.unwrap() as usize;
+
+            let mut buf = vec![0u8; 321];
+            let n = read_fully(&mut file, &mut buf).await;
+            let expected = &content[position..(position + 321).min(content.len())];
+            assert_eq!(&buf[..n], expected, "after {:?}", seek);
+        }
+
+        assert!(file.seek(SeekFrom::Current(-20_000)).await.is_err());
+
+        file.seek(SeekFrom::End(10)).await.unwrap();
+        assert_eq!(file.read(&mut [0u8; 16]).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn seeking_does_not_reload_the_branch() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = (0..10_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let (root, intermediate) = put_file(&ipfs, &content).await;
+
+        let mut file = ipfs.open_unixfs(root).await.unwrap();
+
+        let mut read = Vec::new();
+        file.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, content);
+
+        // with three 100 byte leaves per link, 9000..9300 is covered by the deepest intermediate
+        // node after this
+        file.seek(SeekFrom::Start(9000)).await.unwrap();
+        assert_eq!(read_fully(&mut file, &mut [0u8; 150]).await, 150);
+
+        // the intermediate nodes on the path are held by the file, the rest of them would need to
+        // be loaded again
+        for cid in intermediate {
+            ipfs.remove_block(cid).await.unwrap();
+        }
+
+        for &position in &[9000usize, 9250, 9020, 9299, 9100] {
+            file.seek(SeekFrom::Start(position as u64)).await.unwrap();
+            let mut buf = vec![0u8; 9300 - position];
+
+            let n = tokio::time::timeout(Duration::from_secs(1), read_fully(&mut file, &mut buf))
+                .await
+                .unwrap_or_else(|_| panic!("tried to load a removed block at {}", position));
+
+            assert_eq!(n, buf.len());
+            assert_eq!(&buf[..], &content[position..9300], "at {}", position);
+        }
+    }
+
+    /// Adds the content with small blocks and links to get a deep tree, returning the root and
+    /// the intermediate nodes below it.
+    async fn put_file(ipfs: &Ipfs<TestTypes>, content: &[u8]) -> (Cid, Vec<Cid>) {
+        let mut adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(100))
+            .with_collector(BalancedCollector::with_branching_factor(3))
+            .build();
+
+        let mut blocks = Vec::new();
+
+        let mut total = 0;
+        while total < content.len() {
+            let (pushed, consumed) = adder.push(&content[total..]);
+            blocks.extend(pushed);
+            total += consumed;
+        }
+        blocks.extend(adder.finish());
+
+        let root = blocks.last().unwrap().0.clone();
+        let mut intermediate = Vec::new();
+
+        for (cid, block) in blocks {
+            if cid != root && !PbNode::from_bytes(&block).unwrap().links.is_empty() {
+                intermediate.push(cid.clone());
+            }
+            ipfs.put_block(Block::new(block.into(), cid)).await.unwrap();
+        }
+
+        (root, intermediate)
+    }
+
+    async fn read_fully(file: &mut (impl AsyncRead + Unpin), buf: &mut [u8]) -> usize {
+        let mut n = 0;
+        while n < buf.len() {
+            match file.read(&mut buf[n..]).await.unwrap() {
+                0 => break,
+                read => n += read,
+            }
+        }
+        n
+    }
+}
@@ -0,0 +1,344 @@
+use crate::{
+    dag::{ResolveError, UnexpectedResolved},
+    Error, Ipfs, IpfsTypes,
+};
+use cid::Cid;
+use ipfs_unixfs::walk::{self, ContinuedWalk, Walker};
+use ipfs_unixfs::Metadata;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use super::{ReadAhead, StartingPoint};
+
+/// Options for [`Ipfs::get_to_path`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GetOptions {
+    /// Restore the permissions stored in the metadata.
+    pub preserve_mode: bool,
+    /// Restore the modification times stored in the metadata.
+    pub preserve_mtime: bool,
+    /// Keep the existing files which already have the expected size, without loading their
+    /// contents, instead of writing them again. Used to continue an interrupted extraction.
+    pub resume: bool,
+}
+
+/// Writes the file, directory or symlink at the given path or block to `dest`, which becomes the
+/// root of the extracted tree.
+///
+/// Existing directories are reused and existing files and symlinks are replaced, but an existing
+/// entry of a different kind is an error. Names which would escape the destination are rejected.
+/// The metadata of directories is restored after their contents have been written.
+pub(crate) async fn get_to_path<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    starting_point: StartingPoint,
+    dest: &Path,
+    opts: &GetOptions,
+) -> Result<(), ExtractionFailed> {
+    let root = match starting_point {
+        StartingPoint::Left(path) => {
+            let (resolved, _) = ipfs
+                .dag()
+                .resolve(path, true)
+                .await
+                .map_err(ExtractionFailed::Resolving)?;
+            resolved
+                .into_unixfs_block()
+                .map_err(ExtractionFailed::Path)?
+        }
+        StartingPoint::Right(block) => block,
+    };
+
+    let mut walker = Walker::new(root.cid, String::new());
+    let mut buffer = Some(root.data);
+    let mut cache = None;
+    let mut read_ahead = ReadAhead::new(ipfs.clone());
+
+    // the file being written
+    let mut file: Option<(fs::File, PathBuf, Metadata)> = None;
+    // the metadata of the directories is restored last, deepest first
+    let mut directories = Vec::new();
+
+    while walker.should_continue() {
+        let next = walker.pending_links().0.to_owned();
+
+        let data = match buffer.take() {
+            Some(first) => first,
+            None => {
+                let (_, rest) = walker.pending_links();
+                read_ahead.prefetch(std::iter::once(&next).chain(rest));
+                read_ahead
+                    .get_block(&next)
+                    .await
+                    .map_err(|e| ExtractionFailed::Loading(next.clone(), e))?
+                    .data
+            }
+        };
+
+        let mut skip = false;
+
+        match walker
+            .next(&data, &mut cache)
+            .map_err(|e| ExtractionFailed::Walking(next.clone(), e))?
+        {
+            ContinuedWalk::Bucket(..) => {}
+            ContinuedWalk::Directory(_, path, metadata)
+            | ContinuedWalk::RootDirectory(_, path, metadata) => {
+                let target = checked(dest, path)?;
+
+                match existing(&target).await? {
+                    Some(meta) if meta.is_dir() => {}
+                    Some(_) => return Err(ExtractionFailed::Conflict(target)),
+                    None => fs::create_dir_all(&target)
+                        .await
+                        .map_err(|e| ExtractionFailed::Io(target.clone(), e))?,
+                }
+
+                directories.push((target, metadata.to_owned()));
+            }
+            ContinuedWalk::File(segment, _, path, metadata, size) => {
+                if segment.is_first() {
+                    let target = checked(dest, path)?;
+
+                    let keep = match existing(&target).await? {
+                        Some(meta) if meta.is_file() => opts.resume && meta.len() == size,
+                        Some(_) => return Err(ExtractionFailed::Conflict(target)),
+                        None => false,
+                    };
+
+                    if keep {
+                        skip = !segment.is_last();
+                        restore(&target, metadata, opts)?;
+                    } else {
+                        let created = fs::File::create(&target)
+                            .await
+                            .map_err(|e| ExtractionFailed::Io(target.clone(), e))?;
+                        file = Some((created, target, metadata.to_owned()));
+                    }
+                }
+
+                if let Some((f, target, _)) = file.as_mut() {
+                    f.write_all(segment.as_bytes())
+                        .await
+                        .map_err(|e| ExtractionFailed::Io(target.clone(), e))?;
+                }
+
+                if segment.is_last() {
+                    if let Some((mut f, target, metadata)) = file.take() {
+                        f.flush()
+                            .await
+                            .map_err(|e| ExtractionFailed::Io(target.clone(), e))?;
+                        drop(f);
+                        restore(&target, &metadata, opts)?;
+                    }
+                }
+            }
+            ContinuedWalk::Symlink(bytes, _, path, _) => {
+                let target = checked(dest, path)?;
+                let link = std::str::from_utf8(bytes)
+                    .map_err(|_| ExtractionFailed::NonUtf8Symlink(target.clone()))?;
+
+                match existing(&target).await? {
+                    Some(meta) if meta.file_type().is_symlink() => {
+                        fs::remove_file(&target)
+                            .await
+                            .map_err(|e| ExtractionFailed::Io(target.clone(), e))?
+                    }
+                    Some(_) => return Err(ExtractionFailed::Conflict(target)),
+                    None => {}
+                }
+
+                symlink(link, &target).await?;
+            }
+        }
+
+        if skip {
+            walker.skip_current_file();
+        }
+    }
+
+    for (target, metadata) in directories.iter().rev() {
+        restore(target, metadata, opts)?;
+    }
+
+    Ok(())
+}
+
+/// Joins the path of an entry to the destination, rejecting any other than plain names so that
+/// the entry cannot escape the destination.
+fn checked(dest: &Path, path: &Path) -> Result<PathBuf, ExtractionFailed> {
+    if path.components().all(|c| matches!(c, Component::Normal(_))) {
+        Ok(dest.join(path))
+    } else {
+        Err(ExtractionFailed::InvalidPath(path.to_owned()))
+    }
+}
+
+/// Returns the metadata of an existing entry without following symlinks.
+async fn existing(path: &Path) -> Result<Option<std::fs::Metadata>, ExtractionFailed> {
+    match fs::symlink_metadata(path).await {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(ExtractionFailed::Io(path.to_owned(), e)),
+    }
+}
+
+#[cfg(unix)]
+async fn symlink(link: &str, target: &Path) -> Result<(), ExtractionFailed> {
+    fs::symlink(link, target)
+        .await
+        .map_err(|e| ExtractionFailed::Io(target.to_owned(), e))
+}
+
+#[cfg(not(unix))]
+async fn symlink(_link: &str, target: &Path) -> Result<(), ExtractionFailed> {
+    Err(ExtractionFailed::UnsupportedSymlink(target.to_owned()))
+}
+
+fn restore(target: &Path, metadata: &Metadata, opts: &GetOptions) -> Result<(), ExtractionFailed> {
+    let io_error = |e| ExtractionFailed::Io(target.to_owned(), e);
+
+    #[cfg(unix)]
+    {
+        if let (true, Some(mode)) = (opts.preserve_mode, metadata.mode()) {
+            use std::os::unix::fs::PermissionsExt;
+            let permissions = std::fs::Permissions::from_mode(mode & 0o7777);
+            std::fs::set_permissions(target, permissions).map_err(io_error)?;
+        }
+    }
+
+    if let (true, Some(mtime)) = (opts.preserve_mtime, metadata.mtime_as_filetime()) {
+        filetime::set_file_mtime(target, mtime).map_err(io_error)?;
+    }
+
+    Ok(())
+}
+
+/// Types of failures which can occur while extracting a tree with [`Ipfs::get_to_path`].
+#[derive(Debug, thiserror::Error)]
+pub enum ExtractionFailed {
+    /// Failure to resolve the given path; does not happen when given a block.
+    #[error("path resolving failed")]
+    Resolving(#[source] ResolveError),
+
+    /// The given path was resolved to non dag-pb block, does not happen when starting the walk
+    /// from a block.
+    #[error("path resolved to unexpected")]
+    Path(#[source] UnexpectedResolved),
+
+    /// Loading of a block during walk failed
+    #[error("loading of {} failed", .0)]
+    Loading(Cid, #[source] Error),
+
+    /// Processing of the block failed
+    #[error("walk failed on {}", .0)]
+    Walking(Cid, #[source] walk::Error),
+
+    /// The path of an entry would escape the destination.
+    #[error("unsafe path in the tree: {}", .0.display())]
+    InvalidPath(PathBuf),
+
+    /// An entry of a different kind already exists at the path.
+    #[error("{} already exists", .0.display())]
+    Conflict(PathBuf),
+
+    /// The target of a symlink is not valid UTF-8.
+    #[error("symlink target is not valid utf-8: {}", .0.display())]
+    NonUtf8Symlink(PathBuf),
+
+    /// Symlinks can only be created on unix platforms.
+    #[error("symlinks are not supported on this platform: {}", .0.display())]
+    UnsupportedSymlink(PathBuf),
+
+    /// Writing to the filesystem failed.
+    #[error("failed to write {}", .0.display())]
+    Io(PathBuf, #[source] io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExtractionFailed, GetOptions};
+    use crate::ipld::dag_pb::{PbLink, PbNode};
+    use crate::unixfs::AddOptions;
+    use crate::{Block, Node};
+    use cid::Cid;
+    use std::fs;
+
+    #[tokio::test]
+    async fn extract_and_resume() {
+        let ipfs = Node::new("test_node").await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("source");
+        fs::create_dir_all(source.join("sub/empty")).unwrap();
+        fs::write(source.join("a"), vec![1u8; 300 * 1024]).unwrap();
+        fs::write(source.join("sub/b"), b"foobar\n").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("sub/b", source.join("link")).unwrap();
+
+        let root = ipfs
+            .add_path(&source, AddOptions::default())
+            .await
+            .unwrap()
+            .pop()
+            .unwrap()
+            .cid;
+
+        let dest = tmp.path().join("dest");
+        ipfs.get_to_path(root.clone(), &dest, GetOptions::default())
+            .await
+            .unwrap();
+
+        assert_eq!(fs::read(dest.join("a")).unwrap(), vec![1u8; 300 * 1024]);
+        assert_eq!(fs::read(dest.join("sub/b")).unwrap(), b"foobar\n");
+        assert!(dest.join("sub/empty").is_dir());
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(dest.join("link")).unwrap(),
+            std::path::Path::new("sub/b")
+        );
+
+        // a missing file is written again, the existing ones are kept
+        fs::remove_file(dest.join("sub/b")).unwrap();
+        let opts = GetOptions {
+            resume: true,
+            ..Default::default()
+        };
+        ipfs.get_to_path(root, &dest, opts).await.unwrap();
+        assert_eq!(fs::read(dest.join("sub/b")).unwrap(), b"foobar\n");
+    }
+
+    #[tokio::test]
+    async fn rejects_escaping_names() {
+        let ipfs = Node::new("test_node").await;
+
+        let file = Block::new(
+            b"foobar\n"[..].into(),
+            Cid::new_v1(cid::Codec::Raw, multihash::Sha2_256::digest(b"foobar\n")),
+        );
+        let file = ipfs.put_block(file).await.unwrap();
+
+        let dir = PbNode {
+            links: vec![PbLink {
+                cid: file,
+                name: "..".into(),
+                size: 7,
+            }],
+            data: vec![8, 1],
+        }
+        .into_bytes();
+        let cid = Cid::new_v0(multihash::Sha2_256::digest(&dir)).unwrap();
+        let cid = ipfs.put_block(Block::new(dir, cid)).await.unwrap();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dest = tmp.path().join("dest");
+
+        match ipfs.get_to_path(cid, &dest, GetOptions::default()).await {
+            Err(ExtractionFailed::InvalidPath(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        assert!(!tmp.path().join("foobar").exists());
+    }
+}
@@ -0,0 +1,312 @@
+/// A set of `.gitignore` style rules for skipping entries while adding directories.
+///
+/// The rules are matched against the paths relative to the added directory, with the components
+/// separated by `/`. Blank lines and lines starting with `#` are ignored, a leading `!` negates
+/// the rule and a trailing `/` makes it match only directories. A rule with a `/` elsewhere than
+/// at the end is matched from the added directory, otherwise it is matched against the names of
+/// the entries at any depth. The wildcards `*`, `?`, `[...]` and `**` are supported, and the last
+/// matching rule wins. As with git, entries within an ignored directory cannot be re-included.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    negated: bool,
+    dir_only: bool,
+    /// Matched against the whole path when anchored, otherwise only against the name.
+    anchored: bool,
+    segments: Vec<String>,
+}
+
+impl IgnoreRules {
+    /// Parses the rules from the contents of an ignore file.
+    pub fn parse(rules: &str) -> Self {
+        let mut parsed = IgnoreRules::default();
+        for line in rules.lines() {
+            parsed.add(line);
+        }
+        parsed
+    }
+
+    /// Adds a single rule after the existing ones.
+    pub fn add(&mut self, rule: &str) {
+        let rule = rule.trim_end();
+
+        if rule.is_empty() || rule.starts_with('#') {
+            return;
+        }
+
+        let (negated, rule) = match rule.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, rule.strip_prefix('\\').unwrap_or(rule)),
+        };
+
+        let (dir_only, rule) = match rule.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, rule),
+        };
+
+        let anchored = rule.contains('/');
+        let rule = rule.strip_prefix('/').unwrap_or(rule);
+
+        if rule.is_empty() {
+            return;
+        }
+
+        self.rules.push(Rule {
+            negated,
+            dir_only,
+            anchored,
+            segments: rule.split('/').map(String::from).collect(),
+        });
+    }
+
+    /// Returns true if there are no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns true if the entry at the relative path, or any of the directories leading to it,
+    /// is ignored.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+
+        let components = path
+            .split('/')
+            .filter(|c| !c.is_empty())
+            .collect::<Vec<_>>();
+
+        (1..=components.len()).any(|end| {
+            let is_dir = is_dir || end < components.len();
+            self.matches(&components[..end], is_dir)
+        })
+    }
+
+    fn matches(&self, components: &[&str], is_dir: bool) -> bool {
+        let mut ignored = false;
+
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+
+            let matched = if rule.anchored {
+                matches_components(&rule.segments, components)
+            } else {
+                match components.last() {
+                    Some(name) => matches_name(rule.segments[0].as_bytes(), name.as_bytes()),
+                    None => false,
+                }
+            };
+
+            if matched {
+                ignored = !rule.negated;
+            }
+        }
+
+        ignored
+    }
+}
+
+/// Matches the segments of a rule against the path components, `**` matching any amount of
+/// components.
+///
+/// Only the latest `**` is backtracked to when a segment does not match, which keeps the matching
+/// linear in the number of segments times the number of components.
+fn matches_components(segments: &[String], components: &[&str]) -> bool {
+    let (mut s, mut c) = (0, 0);
+    // the segment after the latest `**` and the component it is next tried against
+    let mut backtrack = None;
+
+    while c < components.len() {
+        match segments.get(s) {
+            Some(segment) if segment == "**" => {
+                s += 1;
+                backtrack = Some((s, c));
+                continue;
+            }
+            Some(segment) if matches_name(segment.as_bytes(), components[c].as_bytes()) => {
+                s += 1;
+                c += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        match backtrack.as_mut() {
+            Some((after_star, skipped)) => {
+                *skipped += 1;
+                s = *after_star;
+                c = *skipped;
+            }
+            None => return false,
+        }
+    }
+
+    segments[s..].iter().all(|segment| segment == "**")
+}
+
+/// Matches a single segment of a rule against a name, backtracking only to the latest `*` the
+/// same way as `matches_components` does.
+fn matches_name(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+
+    while n < name.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            backtrack = Some((p, n));
+            continue;
+        }
+
+        if let Some(consumed) = matches_byte(&pattern[p..], name[n]) {
+            p += consumed;
+            n += 1;
+            continue;
+        }
+
+        match backtrack.as_mut() {
+            Some((after_star, skipped)) => {
+                *skipped += 1;
+                p = *after_star;
+                n = *skipped;
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// Matches the next single byte wildcard or literal of the pattern against `c`, returning the
+/// length of the matched part of the pattern.
+fn matches_byte(pattern: &[u8], c: u8) -> Option<usize> {
+    let matched = match pattern {
+        [] => return None,
+        [b'?', ..] => 1,
+        [b'[', rest @ ..] => match class(rest) {
+            Some((matcher, remaining)) if matcher(c) => pattern.len() - remaining.len(),
+            Some(_) => return None,
+            // unterminated class is matched as is
+            None if c == b'[' => 1,
+            None => return None,
+        },
+        [b'\\', escaped, ..] if *escaped == c => 2,
+        [b'\\', _, ..] => return None,
+        [p, ..] if *p == c => 1,
+        _ => return None,
+    };
+
+    Some(matched)
+}
+
+/// Parses a character class following a `[`, returning a matcher for a single byte and the rest
+/// of the pattern, or `None` if the class is not terminated.
+fn class(pattern: &[u8]) -> Option<(impl Fn(u8) -> bool + '_, &[u8])> {
+    let (negated, pattern) = match pattern.first() {
+        Some(b'!') | Some(b'^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+
+    // a closing bracket right at the start is a part of the class
+    let end = pattern
+        .iter()
+        .skip(1)
+        .position(|&b| b == b']')
+        .map(|i| i + 1)?;
+    let members = &pattern[..end];
+
+    let matcher = move |c: u8| {
+        let mut i = 0;
+        let mut found = false;
+        while i < members.len() {
+            if i + 2 < members.len() && members[i + 1] == b'-' {
+                found |= members[i] <= c && c <= members[i + 2];
+                i += 3;
+            } else {
+                found |= members[i] == c;
+                i += 1;
+            }
+        }
+        found != negated
+    };
+
+    Some((matcher, &pattern[end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IgnoreRules;
+
+    #[test]
+    fn gitignore_rules() {
+        let rules = IgnoreRules::parse(
+            "# build outputs\n\
+             \n\
+             /target\n\
+             *.log\n\
+             !keep.log\n\
+             cache/\n\
+             docs/**/*.tmp\n\
+             file[0-9].txt\n",
+        );
+
+        let cases = [
+            ("target", true, true),
+            ("target/debug/build", false, true),
+            ("sub/target", true, false),
+            ("a.log", false, true),
+            ("sub/b.log", false, true),
+            ("keep.log", false, false),
+            ("cache", true, true),
+            ("cache", false, false),
+            ("sub/cache/entry", false, true),
+            ("docs/a.tmp", false, true),
+            ("docs/a/b/c.tmp", false, true),
+            ("a.tmp", false, false),
+            ("file1.txt", false, true),
+            ("filea.txt", false, false),
+            ("src/main.rs", false, false),
+        ];
+
+        for &(path, is_dir, expected) in &cases {
+            assert_eq!(
+                rules.is_ignored(path, is_dir),
+                expected,
+                "{} (directory: {})",
+                path,
+                is_dir
+            );
+        }
+    }
+
+    #[test]
+    fn wildcards_match_in_linear_time() {
+        let long_name = "a".repeat(64);
+        let deep_path = vec!["a"; 64].join("/");
+
+        let rules = IgnoreRules::parse("a*a*a*a*a*a*a*a*a*a*b\n/**/a/**/a/**/a/**/a/**/a/**/b\n");
+        assert!(!rules.is_ignored(&long_name, false));
+        assert!(!rules.is_ignored(&deep_path, false));
+
+        let rules = IgnoreRules::parse("a*a*a*a*a*a*a*a*a*a*a\n/**/a/**/a/**/a/**/a/**/a\n");
+        assert!(rules.is_ignored(&long_name, false));
+        assert!(rules.is_ignored(&deep_path, false));
+    }
+
+    #[test]
+    fn escapes_and_classes() {
+        let rules = IgnoreRules::parse("x\\*.txt\nx[ab]?y\nz[\n");
+
+        assert!(rules.is_ignored("x*.txt", false));
+        assert!(!rules.is_ignored("xa.txt", false));
+        assert!(rules.is_ignored("xb1y", false));
+        assert!(!rules.is_ignored("xc1y", false));
+        assert!(rules.is_ignored("z[", false));
+        assert!(!rules.is_ignored("za", false));
+    }
+}
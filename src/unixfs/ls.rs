@@ -0,0 +1,228 @@
+use crate::{
+    dag::{ResolveError, UnexpectedResolved},
+    Block, Error, Ipfs, IpfsTypes,
+};
+use async_stream::stream;
+use cid::{Cid, Codec};
+use futures::stream::{self, Stream, StreamExt};
+use ipfs_unixfs::dir::{self, ListedLink, LookupError};
+use ipfs_unixfs::{walk, NodeInfo};
+use std::borrow::Borrow;
+
+use super::StartingPoint;
+
+/// How many entries are resolved at the same time.
+const RESOLVE_CONCURRENCY: usize = 8;
+
+/// An entry of a listed directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// The name of the entry, empty for the links of a file.
+    pub name: String,
+    /// The `Cid` of the entry.
+    pub cid: Cid,
+    /// The cumulative size of the entry as recorded in the link to it.
+    pub size: u64,
+    /// The kind, file size and metadata of the entry, if the entries were resolved.
+    pub info: Option<NodeInfo>,
+}
+
+/// IPFS ls operation, producing a stream of the entries of a directory. Like [`super::cat`] this
+/// is generic over owning the `Ipfs` so that the stream can be `'static`.
+///
+/// With `resolve` every entry is loaded to find out its kind, file size and metadata; up to
+/// `RESOLVE_CONCURRENCY` entries are loaded at the same time while keeping the order. The buckets
+/// of sharded directories are loaded as the stream is polled. Files are listed as their links,
+/// which have no names.
+pub async fn ls<'a, Types, MaybeOwned>(
+    ipfs: MaybeOwned,
+    starting_point: impl Into<StartingPoint>,
+    resolve: bool,
+) -> Result<impl Stream<Item = Result<DirEntry, ListingFailed>> + Send + 'a, ListingFailed>
+where
+    Types: IpfsTypes,
+    MaybeOwned: Borrow<Ipfs<Types>> + Send + 'a,
+{
+    let block = match starting_point.into() {
+        StartingPoint::Left(path) => {
+            let borrow = ipfs.borrow();
+            let dag = borrow.dag();
+            let (resolved, _) = dag
+                .resolve(path, true)
+                .await
+                .map_err(ListingFailed::Resolving)?;
+            resolved.into_unixfs_block().map_err(ListingFailed::Path)?
+        }
+        StartingPoint::Right(block) => block,
+    };
+
+    let mut cache = None;
+    let mut links = Vec::new();
+
+    // raw leaves have no links
+    let mut sharded = match block.cid.codec() {
+        Codec::DagProtobuf => dir::list(&block.data, &mut links, &mut cache)
+            .map_err(|e| ListingFailed::UnsupportedNode(block.cid.clone(), e))?,
+        _ => None,
+    };
+
+    Ok(stream! {
+        loop {
+            if resolve {
+                let ipfs = ipfs.borrow();
+                let mut entries = stream::iter(links.drain(..))
+                    .map(|link| resolve_entry(ipfs, link))
+                    .buffered(RESOLVE_CONCURRENCY);
+
+                while let Some(entry) = entries.next().await {
+                    match entry {
+                        Ok(entry) => yield Ok(entry),
+                        Err(e) => {
+                            yield Err(e);
+                            return;
+                        }
+                    }
+                }
+            } else {
+                for ListedLink { name, cid, tsize } in links.drain(..) {
+                    yield Ok(DirEntry { name, cid, size: tsize, info: None });
+                }
+            }
+
+            let listing = match sharded.take() {
+                Some(listing) => listing,
+                None => break,
+            };
+
+            let next = listing.pending_links().0.to_owned();
+
+            let continued = match load(ipfs.borrow(), &next).await {
+                Ok(bucket) => listing
+                    .continue_walk(&bucket.data, &mut links, &mut cache)
+                    .map_err(|e| ListingFailed::UnsupportedBucket(next, e)),
+                Err(e) => Err(e),
+            };
+
+            match continued {
+                Ok(listing) => sharded = listing,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+    })
+}
+
+async fn resolve_entry<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    link: ListedLink,
+) -> Result<DirEntry, ListingFailed> {
+    let ListedLink { name, cid, tsize } = link;
+    let block = load(ipfs, &cid).await?;
+    let info = super::block_info(&block).map_err(|e| ListingFailed::UnsupportedEntry(cid, e))?;
+
+    Ok(DirEntry {
+        name,
+        cid: block.cid,
+        size: tsize,
+        info: Some(info),
+    })
+}
+
+async fn load<Types: IpfsTypes>(ipfs: &Ipfs<Types>, cid: &Cid) -> Result<Block, ListingFailed> {
+    ipfs.get_block(cid)
+        .await
+        .map_err(|e| ListingFailed::Loading(cid.to_owned(), e))
+}
+
+/// Types of failures which can occur while listing a directory.
+#[derive(Debug, thiserror::Error)]
+pub enum ListingFailed {
+    /// Failure to resolve the given path; does not happen when given a block.
+    #[error("path resolving failed")]
+    Resolving(#[source] ResolveError),
+
+    /// The given path was resolved to non dag-pb block, does not happen when starting the listing
+    /// from a block.
+    #[error("path resolved to unexpected")]
+    Path(#[source] UnexpectedResolved),
+
+    /// Loading of a block during listing failed
+    #[error("loading of {} failed", .0)]
+    Loading(Cid, #[source] Error),
+
+    /// The listed block is not a dag-pb node or not a supported UnixFs directory
+    #[error("unsupported node {}", .0)]
+    UnsupportedNode(Cid, #[source] dir::ResolveError),
+
+    /// A bucket of a sharded directory could not be listed
+    #[error("unsupported bucket {}", .0)]
+    UnsupportedBucket(Cid, #[source] LookupError),
+
+    /// The resolved entry is not a supported UnixFs node
+    #[error("unsupported entry {}", .0)]
+    UnsupportedEntry(Cid, #[source] Option<walk::Error>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ls;
+    use crate::{Block, Node};
+    use cid::Cid;
+    use futures::stream::TryStreamExt;
+    use ipfs_unixfs::dir::builder::{BufferingTreeBuilder, TreeOptions};
+    use ipfs_unixfs::NodeKind;
+
+    #[tokio::test]
+    async fn sharded_and_plain_directories() {
+        let ipfs = Node::new("test_node").await;
+
+        let file = ipfs.put_block(leaf(b"foobar\n")).await.unwrap();
+
+        for &threshold in &[None, Some(0)] {
+            let mut opts = TreeOptions::default();
+            opts.sharding_threshold(threshold);
+
+            let mut builder = BufferingTreeBuilder::new(opts);
+            for i in 0..300 {
+                builder
+                    .put_link(&format!("dir/{:03}", i), file.clone(), 7)
+                    .unwrap();
+            }
+
+            let mut root = None;
+            for node in builder.build() {
+                let node = node.unwrap();
+                root = Some(node.cid.clone());
+                ipfs.put_block(Block::new(node.block, node.cid))
+                    .await
+                    .unwrap();
+            }
+
+            let entries = ls(&*ipfs, root.unwrap(), true)
+                .await
+                .unwrap()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+
+            let mut names = entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+            names.sort_unstable();
+
+            let expected = (0..300).map(|i| format!("{:03}", i)).collect::<Vec<_>>();
+            assert_eq!(names, expected);
+
+            for entry in &entries {
+                assert_eq!(entry.cid, file);
+                assert_eq!(entry.info.as_ref().unwrap().kind, NodeKind::File);
+                assert_eq!(entry.info.as_ref().unwrap().filesize, 7);
+            }
+        }
+    }
+
+    fn leaf(data: &[u8]) -> Block {
+        let cid = Cid::new_v1(cid::Codec::Raw, multihash::Sha2_256::digest(data));
+        Block::new(data.into(), cid)
+    }
+}
@@ -0,0 +1,887 @@
+//! Mutable File System (MFS), a mutable namespace of UnixFs files and directories.
+//!
+//! The namespace is a tree of UnixFs nodes under a single root directory. Every change creates new
+//! blocks for the changed node and all of its parents up to the root, the `Cid` of which is
+//! persisted in the [`crate::repo::DataStore`] when flushed. The flushed root is pinned
+//! recursively, so that the blocks of the namespace are not removed from the repository.
+//!
+//! Writes to files only create the leaves overlapping the written bytes and the link blocks above
+//! them, and the directories along the path are edited like with [`crate::Ipfs::patch_add_link`]
+//! so that sharded directories are supported as well.
+
+use crate::{
+    dag::{ResolveError, UnexpectedResolved},
+    ipld::dag_pb::PbNode,
+    repo::PinMode,
+    Block, Error, Ipfs, IpfsPath, IpfsTypes,
+};
+use cid::{Cid, Codec};
+use futures::stream::TryStreamExt;
+use ipfs_unixfs::{file::adder::FileAdder, NodeInfo, NodeKind};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use super::nodes::{cumulative_size, empty_directory, put_directory};
+use super::patch::{self, PatchError};
+use super::{ListingFailed, TraversalFailed};
+
+/// The root of the namespace shared by all clones of an [`Ipfs`]. The lock is held for the
+/// duration of each operation so that the changes do not get lost.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Mfs(Arc<Mutex<State>>);
+
+#[derive(Debug, Default)]
+struct State {
+    /// Loaded from the datastore on first use.
+    root: Option<Cid>,
+    /// True if the root has not been persisted since the last change.
+    dirty: bool,
+    /// The persisted root, which is pinned recursively.
+    pinned: Option<Cid>,
+}
+
+/// Information about a file or a directory in the namespace, returned by [`Ipfs::files_stat`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stat {
+    /// The `Cid` of the root node.
+    pub cid: Cid,
+    /// The kind of the node.
+    pub kind: NodeKind,
+    /// The size of the file contents, zero for the other kinds.
+    pub size: u64,
+    /// The size of all of the blocks in the tree.
+    pub cumulative_size: u64,
+    /// The number of links in the root node.
+    pub blocks: usize,
+}
+
+/// An entry of a directory, returned by [`Ipfs::files_ls`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The name of the entry in the directory.
+    pub name: String,
+    /// The `Cid` of the entry.
+    pub cid: Cid,
+    /// The kind of the entry.
+    pub kind: NodeKind,
+    /// The size of the file contents, zero for the other kinds.
+    pub size: u64,
+}
+
+/// Options for [`Ipfs::files_write`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// The offset in the file to start writing at, which cannot be past the end of the file.
+    pub offset: u64,
+    /// Create the file if it does not exist.
+    pub create: bool,
+    /// Truncate the file before writing.
+    pub truncate: bool,
+    /// Create the missing parent directories.
+    pub parents: bool,
+}
+
+/// Failures of the MFS operations.
+#[derive(Debug, thiserror::Error)]
+pub enum MfsError {
+    /// The paths must be absolute and cannot contain `.` or `..` segments.
+    #[error("invalid path: {0:?}")]
+    InvalidPath(String),
+
+    /// Nothing was found at the path.
+    #[error("file does not exist: {0}")]
+    NotFound(String),
+
+    /// The path was expected to point to a directory.
+    #[error("not a directory: {0}")]
+    NotADirectory(String),
+
+    /// The path was expected to point to a file.
+    #[error("not a file: {0}")]
+    NotAFile(String),
+
+    /// There is already an entry at the path.
+    #[error("already exists: {0}")]
+    AlreadyExists(String),
+
+    /// Directories are only removed recursively.
+    #[error("{0} is a directory, use recursive removal")]
+    IsADirectory(String),
+
+    /// The root directory cannot be removed or moved.
+    #[error("cannot remove or move the root directory")]
+    Root,
+
+    /// A directory cannot be moved into itself.
+    #[error("cannot move {0} into itself")]
+    IntoItself(String),
+
+    /// The write would have left a gap in the file.
+    #[error("offset {offset} is past the end of the file of {size} bytes")]
+    OffsetPastEnd {
+        /// The requested offset
+        offset: u64,
+        /// The size of the file
+        size: u64,
+    },
+
+    /// Resolving the `/ipfs` or `/ipns` source path failed.
+    #[error("resolving the source failed")]
+    Resolving(#[source] ResolveError),
+
+    /// The source path resolved to something else than UnixFs.
+    #[error("source is not UnixFs")]
+    Source(#[source] UnexpectedResolved),
+
+    /// A block in the namespace is not a supported UnixFs node.
+    #[error("unsupported node {0}")]
+    UnsupportedNode(Cid, #[source] Option<ipfs_unixfs::walk::Error>),
+
+    /// Reading the contents of a file failed.
+    #[error("reading the file failed")]
+    Reading(#[source] TraversalFailed),
+
+    /// Listing a directory failed.
+    #[error("listing the directory failed")]
+    Listing(#[source] ListingFailed),
+
+    /// Editing a file or a directory failed.
+    #[error("editing failed")]
+    Editing(#[source] PatchError),
+
+    /// Loading or storing of a block or the root failed.
+    #[error("{0}")]
+    Other(#[from] Error),
+}
+
+impl From<PatchError> for MfsError {
+    fn from(e: PatchError) -> Self {
+        // the paths of the edits are relative to the root
+        match e {
+            PatchError::NotFound(path) => MfsError::NotFound(format!("/{}", path)),
+            PatchError::NotADirectory(path) => MfsError::NotADirectory(format!("/{}", path)),
+            PatchError::UnsupportedNode(cid, e) => MfsError::UnsupportedNode(cid, e),
+            PatchError::OffsetPastEnd { offset, size } => MfsError::OffsetPastEnd { offset, size },
+            PatchError::Other(e) => MfsError::Other(e),
+            e => MfsError::Editing(e),
+        }
+    }
+}
+
+/// A loaded node of the namespace.
+struct Node {
+    block: Block,
+    info: NodeInfo,
+}
+
+impl Node {
+    fn new(block: Block) -> Result<Self, MfsError> {
+        let info = super::block_info(&block)
+            .map_err(|e| MfsError::UnsupportedNode(block.cid.clone(), e))?;
+        Ok(Node { block, info })
+    }
+
+    /// The size of the whole tree, as recorded in the links to it.
+    fn cumulative_size(&self) -> u64 {
+        cumulative_size(&self.block)
+    }
+}
+
+impl Mfs {
+    /// Creates a directory, and with `parents` any missing parents. With `parents` it is not an
+    /// error if the directory already exists.
+    pub(crate) async fn mkdir<T: IpfsTypes>(
+        &self,
+        ipfs: &Ipfs<T>,
+        path: &str,
+        parents: bool,
+        flush: bool,
+    ) -> Result<(), MfsError> {
+        let segments = segments(path)?;
+        let mut state = self.0.lock().await;
+        let root = state.root(ipfs).await?;
+
+        match lookup(ipfs, &root, &segments).await {
+            Ok(node) if parents && node.info.kind != NodeKind::File => return Ok(()),
+            Ok(_) => return Err(MfsError::AlreadyExists(display(&segments))),
+            Err(MfsError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+
+        let (cid, _) = put_directory(ipfs, empty_directory()).await?;
+        let root = insert(ipfs, &root, &segments, cid, parents).await?;
+        state.update(ipfs, root, flush).await
+    }
+
+    /// Writes the bytes into a file at the given offset. Only the blocks of the file overlapping
+    /// the written bytes are created again.
+    pub(crate) async fn write<T: IpfsTypes>(
+        &self,
+        ipfs: &Ipfs<T>,
+        path: &str,
+        data: &[u8],
+        opts: WriteOptions,
+        flush: bool,
+    ) -> Result<(), MfsError> {
+        let segments = segments(path)?;
+        if segments.is_empty() {
+            return Err(MfsError::NotAFile(display(&segments)));
+        }
+
+        let mut state = self.0.lock().await;
+        let root = state.root(ipfs).await?;
+
+        let file = match lookup(ipfs, &root, &segments).await {
+            Ok(node) if node.info.kind != NodeKind::File => {
+                return Err(MfsError::NotAFile(display(&segments)))
+            }
+            Ok(_) if opts.truncate => None,
+            Ok(node) => Some(node),
+            Err(MfsError::NotFound(_)) if opts.create => None,
+            Err(e) => return Err(e),
+        };
+
+        let size = file.as_ref().map(|node| node.info.filesize).unwrap_or(0);
+        if opts.offset > size {
+            return Err(MfsError::OffsetPastEnd {
+                offset: opts.offset,
+                size,
+            });
+        }
+
+        let cid = match file {
+            Some(node) => patch::write_to_file(ipfs, &node.block.cid, opts.offset, data).await?,
+            None => put_file(ipfs, data).await?,
+        };
+
+        let root = insert(ipfs, &root, &segments, cid, opts.parents).await?;
+        state.update(ipfs, root, flush).await
+    }
+
+    /// Moves the entry to the destination. If the destination is a directory, the entry is moved
+    /// into it.
+    pub(crate) async fn mv<T: IpfsTypes>(
+        &self,
+        ipfs: &Ipfs<T>,
+        source: &str,
+        destination: &str,
+        flush: bool,
+    ) -> Result<(), MfsError> {
+        let source = segments(source)?;
+        let destination = segments(destination)?;
+        let name = *source.last().ok_or(MfsError::Root)?;
+
+        let mut state = self.0.lock().await;
+        let root = state.root(ipfs).await?;
+
+        let node = lookup(ipfs, &root, &source).await?;
+        let destination = target(ipfs, &root, destination, name).await?;
+
+        if destination.starts_with(&source) {
+            return Err(MfsError::IntoItself(display(&source)));
+        }
+
+        let root = remove(ipfs, &root, &source).await?;
+        let root = insert(ipfs, &root, &destination, node.block.cid, false).await?;
+        state.update(ipfs, root, flush).await
+    }
+
+    /// Copies an entry of the namespace or an `/ipfs` or `/ipns` path to the destination. If the
+    /// destination is a directory, the entry is copied into it.
+    pub(crate) async fn cp<T: IpfsTypes>(
+        &self,
+        ipfs: &Ipfs<T>,
+        source: &str,
+        destination: &str,
+        flush: bool,
+    ) -> Result<(), MfsError> {
+        let destination = segments(destination)?;
+
+        let mut state = self.0.lock().await;
+        let root = state.root(ipfs).await?;
+
+        let (node, name) = if source.starts_with("/ipfs/") || source.starts_with("/ipns/") {
+            let path = source
+                .parse::<IpfsPath>()
+                .map_err(|_| MfsError::InvalidPath(source.to_owned()))?;
+            let name = path
+                .iter()
+                .last()
+                .map(|s| s.to_owned())
+                .or_else(|| path.root().cid().map(|cid| cid.to_string()))
+                .unwrap_or_default();
+            let (resolved, _) = ipfs
+                .dag()
+                .resolve(path, true)
+                .await
+                .map_err(MfsError::Resolving)?;
+            let block = resolved.into_unixfs_block().map_err(MfsError::Source)?;
+            (Node::new(block)?, name)
+        } else {
+            let source = segments(source)?;
+            let name = source.last().ok_or(MfsError::Root)?.to_string();
+            (lookup(ipfs, &root, &source).await?, name)
+        };
+
+        let destination = target(ipfs, &root, destination, &name).await?;
+
+        let root = insert(ipfs, &root, &destination, node.block.cid, false).await?;
+        state.update(ipfs, root, flush).await
+    }
+
+    /// Removes the entry, which needs to be `recursive` for directories.
+    pub(crate) async fn rm<T: IpfsTypes>(
+        &self,
+        ipfs: &Ipfs<T>,
+        path: &str,
+        recursive: bool,
+        flush: bool,
+    ) -> Result<(), MfsError> {
+        let segments = segments(path)?;
+        if segments.is_empty() {
+            return Err(MfsError::Root);
+        }
+
+        let mut state = self.0.lock().await;
+        let root = state.root(ipfs).await?;
+
+        let node = lookup(ipfs, &root, &segments).await?;
+        match node.info.kind {
+            NodeKind::Directory | NodeKind::ShardedDirectory if !recursive => {
+                return Err(MfsError::IsADirectory(display(&segments)))
+            }
+            _ => {}
+        }
+
+        let root = remove(ipfs, &root, &segments).await?;
+        state.update(ipfs, root, flush).await
+    }
+
+    /// Returns information about the entry.
+    pub(crate) async fn stat<T: IpfsTypes>(
+        &self,
+        ipfs: &Ipfs<T>,
+        path: &str,
+    ) -> Result<Stat, MfsError> {
+        let segments = segments(path)?;
+        let root = self.0.lock().await.root(ipfs).await?;
+
+        let node = lookup(ipfs, &root, &segments).await?;
+        let blocks = match node.block.cid.codec() {
+            Codec::DagProtobuf => PbNode::from_bytes(&node.block.data)
+                .map_err(Error::new)?
+                .links
+                .len(),
+            _ => 0,
+        };
+
+        Ok(Stat {
+            cumulative_size: node.cumulative_size(),
+            cid: node.block.cid,
+            kind: node.info.kind,
+            size: node.info.filesize,
+            blocks,
+        })
+    }
+
+    /// Lists the entries of a directory, or the file itself.
+    pub(crate) async fn ls<T: IpfsTypes>(
+        &self,
+        ipfs: &Ipfs<T>,
+        path: &str,
+    ) -> Result<Vec<Entry>, MfsError> {
+        let segments = segments(path)?;
+        let root = self.0.lock().await.root(ipfs).await?;
+
+        let node = lookup(ipfs, &root, &segments).await?;
+
+        match node.info.kind {
+            NodeKind::Directory | NodeKind::ShardedDirectory => {}
+            kind => {
+                return Ok(vec![Entry {
+                    name: segments.last().map(|s| s.to_string()).unwrap_or_default(),
+                    cid: node.block.cid,
+                    kind,
+                    size: node.info.filesize,
+                }])
+            }
+        }
+
+        super::ls(ipfs, node.block, true)
+            .await
+            .map_err(MfsError::Listing)?
+            .map_ok(|entry| {
+                let info = entry.info.expect("the entries were resolved");
+                Entry {
+                    name: entry.name,
+                    cid: entry.cid,
+                    kind: info.kind,
+                    size: info.filesize,
+                }
+            })
+            .try_collect()
+            .await
+            .map_err(MfsError::Listing)
+    }
+
+    /// Persists the root if it has changed, returning the `Cid` of the path.
+    pub(crate) async fn flush<T: IpfsTypes>(
+        &self,
+        ipfs: &Ipfs<T>,
+        path: &str,
+    ) -> Result<Cid, MfsError> {
+        let segments = segments(path)?;
+        let mut state = self.0.lock().await;
+        let root = state.root(ipfs).await?;
+
+        if state.dirty {
+            state.persist(ipfs, &root).await?;
+            state.dirty = false;
+        }
+
+        Ok(lookup(ipfs, &root, &segments).await?.block.cid)
+    }
+}
+
+impl State {
+    /// Returns the current root, loading it from the datastore or creating an empty directory on
+    /// the first use.
+    async fn root<T: IpfsTypes>(&mut self, ipfs: &Ipfs<T>) -> Result<Cid, MfsError> {
+        if let Some(root) = self.root.as_ref() {
+            return Ok(root.to_owned());
+        }
+
+        let root = match ipfs.repo.get_mfs_root().await? {
+            Some(root) => root,
+            None => put_directory(ipfs, empty_directory()).await?.0,
+        };
+
+        // the root may have been persisted before it was pinned
+        self.persist(ipfs, &root).await?;
+        self.root = Some(root.clone());
+        Ok(root)
+    }
+
+    /// Pins the root and persists it in the datastore, unpinning the previously persisted root.
+    async fn persist<T: IpfsTypes>(&mut self, ipfs: &Ipfs<T>, root: &Cid) -> Result<(), MfsError> {
+        if self.pinned.as_ref() != Some(root) {
+            let pinned = ipfs
+                .query_pins(vec![root.to_owned()], Some(PinMode::Recursive))
+                .await
+                .is_ok();
+            if !pinned {
+                ipfs.insert_pin(root, true).await?;
+            }
+        }
+
+        ipfs.repo.put_mfs_root(root).await?;
+
+        match self.pinned.replace(root.to_owned()) {
+            Some(previous) if previous != *root => ipfs.remove_pin(&previous, true).await?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    async fn update<T: IpfsTypes>(
+        &mut self,
+        ipfs: &Ipfs<T>,
+        root: Cid,
+        flush: bool,
+    ) -> Result<(), MfsError> {
+        if flush {
+            self.persist(ipfs, &root).await?;
+        }
+        self.dirty = !flush;
+        self.root = Some(root);
+        Ok(())
+    }
+}
+
+/// Splits the absolute path into segments, ignoring empty segments.
+fn segments(path: &str) -> Result<Vec<&str>, MfsError> {
+    if !path.starts_with('/') {
+        return Err(MfsError::InvalidPath(path.to_owned()));
+    }
+
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| match segment {
+            "." | ".." => Err(MfsError::InvalidPath(path.to_owned())),
+            segment => Ok(segment),
+        })
+        .collect()
+}
+
+fn display(segments: &[&str]) -> String {
+    format!("/{}", segments.join("/"))
+}
+
+async fn load<T: IpfsTypes>(ipfs: &Ipfs<T>, cid: &Cid) -> Result<Node, MfsError> {
+    Node::new(ipfs.get_block(cid).await?)
+}
+
+/// Follows the path from the root, loading every directory on the way.
+async fn lookup<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    root: &Cid,
+    segments: &[&str],
+) -> Result<Node, MfsError> {
+    let (_, found) = patch::open_path(ipfs, root, segments, false).await?;
+    let cid = found.ok_or_else(|| MfsError::NotFound(display(segments)))?;
+    load(ipfs, &cid).await
+}
+
+/// Returns the path to place an entry at: into the destination if it is a directory, or at the
+/// destination if nothing exists there yet.
+async fn target<'a, T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    root: &Cid,
+    mut destination: Vec<&'a str>,
+    name: &'a str,
+) -> Result<Vec<&'a str>, MfsError> {
+    match lookup(ipfs, root, &destination).await {
+        Ok(node) if node.info.kind == NodeKind::File => {
+            Err(MfsError::AlreadyExists(display(&destination)))
+        }
+        Ok(_) => {
+            destination.push(name);
+            match lookup(ipfs, root, &destination).await {
+                Ok(_) => Err(MfsError::AlreadyExists(display(&destination))),
+                Err(MfsError::NotFound(_)) => Ok(destination),
+                Err(e) => Err(e),
+            }
+        }
+        Err(MfsError::NotFound(_)) => Ok(destination),
+        Err(e) => Err(e),
+    }
+}
+
+/// Places the link at the path, replacing any previous entry, and returns the new root.
+async fn insert<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    root: &Cid,
+    segments: &[&str],
+    cid: Cid,
+    parents: bool,
+) -> Result<Cid, MfsError> {
+    if segments.is_empty() {
+        return Err(MfsError::Root);
+    }
+    Ok(patch::patch_add_link(ipfs, root, &segments.join("/"), cid, parents).await?)
+}
+
+/// Removes the link at the path, returning the new root.
+async fn remove<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    root: &Cid,
+    segments: &[&str],
+) -> Result<Cid, MfsError> {
+    if segments.is_empty() {
+        return Err(MfsError::Root);
+    }
+    match patch::patch_rm_link(ipfs, root, &segments.join("/")).await {
+        Err(PatchError::NotFound(_)) => Err(MfsError::NotFound(display(segments))),
+        result => Ok(result?),
+    }
+}
+
+/// Stores the file contents with the default options of the adder, returning the `Cid` of the
+/// file.
+async fn put_file<T: IpfsTypes>(ipfs: &Ipfs<T>, content: &[u8]) -> Result<Cid, MfsError> {
+    let mut adder = FileAdder::default();
+    let mut blocks = Vec::new();
+    let mut written = 0;
+
+    while written < content.len() {
+        let (ready, consumed) = adder.push(&content[written..]);
+        blocks.extend(ready);
+        written += consumed;
+    }
+
+    blocks.extend(adder.finish());
+
+    let mut root = None;
+    for (cid, block) in blocks {
+        root = Some(cid.clone());
+        ipfs.put_block(Block::new(block.into_boxed_slice(), cid))
+            .await?;
+    }
+
+    Ok(root.expect("the adder always produces the root block"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MfsError, WriteOptions};
+    use crate::unixfs::AddOptions;
+    use crate::{Block, Node};
+    use futures::stream::TryStreamExt;
+    use ipfs_unixfs::dir::builder::{BufferingTreeBuilder, TreeOptions};
+    use ipfs_unixfs::NodeKind;
+
+    #[tokio::test]
+    async fn write_read_and_list() {
+        let ipfs = Node::new("test_node").await;
+
+        let opts = WriteOptions {
+            create: true,
+            parents: true,
+            ..Default::default()
+        };
+
+        ipfs.files_write("/a/b/foo", b"foobar\n", opts, true)
+            .await
+            .unwrap();
+
+        let opts = WriteOptions {
+            offset: 3,
+            ..Default::default()
+        };
+
+        ipfs.files_write("/a/b/foo", b"baz", opts, true)
+            .await
+            .unwrap();
+
+        let content = ipfs
+            .files_read("/a/b/foo", None)
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(content, b"foobaz\n");
+
+        let entries = ipfs.files_ls("/a/b").await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "foo");
+        assert_eq!(entries[0].kind, NodeKind::File);
+        assert_eq!(entries[0].size, 7);
+
+        let stat = ipfs.files_stat("/a").await.unwrap();
+        assert_eq!(stat.kind, NodeKind::Directory);
+        assert_eq!(stat.blocks, 1);
+
+        let opts = WriteOptions {
+            offset: 8,
+            ..Default::default()
+        };
+
+        match ipfs.files_write("/a/b/foo", b"!", opts, true).await {
+            Err(MfsError::OffsetPastEnd { offset: 8, size: 7 }) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[tokio::test]
+    async fn mkdir_mv_cp_rm() {
+        let ipfs = Node::new("test_node").await;
+
+        // the well known empty directory
+        let root = ipfs.files_flush("/").await.unwrap();
+        assert_eq!(
+            root.to_string(),
+            "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"
+        );
+
+        ipfs.files_mkdir("/dir", false, true).await.unwrap();
+        match ipfs.files_mkdir("/dir", false, true).await {
+            Err(MfsError::AlreadyExists(_)) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+        ipfs.files_mkdir("/dir", true, true).await.unwrap();
+
+        let opts = WriteOptions {
+            create: true,
+            ..Default::default()
+        };
+        ipfs.files_write("/file", b"content", opts, false)
+            .await
+            .unwrap();
+
+        ipfs.files_mv("/file", "/dir", false).await.unwrap();
+        ipfs.files_cp("/dir/file", "/copy", false).await.unwrap();
+
+        let file = ipfs.files_stat("/dir/file").await.unwrap();
+        let copy = ipfs.files_stat("/copy").await.unwrap();
+        assert_eq!(file, copy);
+
+        // copying from the immutable side
+        let path = format!("/ipfs/{}", ipfs.files_flush("/dir").await.unwrap());
+        ipfs.files_cp(&path, "/again", true).await.unwrap();
+        assert_eq!(ipfs.files_stat("/again/file").await.unwrap(), file);
+
+        match ipfs.files_mv("/dir", "/dir/file2", true).await {
+            Err(MfsError::IntoItself(_)) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+
+        match ipfs.files_rm("/dir", false, true).await {
+            Err(MfsError::IsADirectory(_)) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+
+        ipfs.files_rm("/dir", true, true).await.unwrap();
+        ipfs.files_rm("/again", true, true).await.unwrap();
+
+        let names = ipfs
+            .files_ls("/")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, &["copy"]);
+
+        // the root was persisted
+        assert_eq!(
+            ipfs.repo.get_mfs_root().await.unwrap(),
+            Some(ipfs.files_flush("/").await.unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_create_only_the_touched_blocks() {
+        let ipfs = Node::new("test_node").await;
+
+        // three leaves of the default chunker
+        let mut content = (0..600_000u32).map(|i| i as u8).collect::<Vec<_>>();
+
+        let opts = WriteOptions {
+            create: true,
+            ..Default::default()
+        };
+        ipfs.files_write("/file", &content, opts, true)
+            .await
+            .unwrap();
+
+        let writes: &[(usize, usize)] = &[
+            // within the second leaf
+            (300_000, 10),
+            // over the boundary of the first and the second leaf
+            (262_000, 1000),
+            // over the end of the file
+            (599_000, 300_000),
+        ];
+
+        for &(offset, len) in writes {
+            let data = vec![0xff; len];
+            let opts = WriteOptions {
+                offset: offset as u64,
+                ..Default::default()
+            };
+            ipfs.files_write("/file", &data, opts, true).await.unwrap();
+
+            content.resize(content.len().max(offset + len), 0);
+            content[offset..offset + len].copy_from_slice(&data);
+
+            // the same tree as adding the whole file again
+            let expected = ipfs
+                .add_reader(&content[..], AddOptions::default())
+                .await
+                .unwrap();
+            let stat = ipfs.files_stat("/file").await.unwrap();
+            assert_eq!(stat.cid, expected.cid, "write at {}", offset);
+            assert_eq!(stat.size, content.len() as u64);
+            assert_eq!(stat.cumulative_size, expected.size);
+        }
+    }
+
+    #[tokio::test]
+    async fn sharded_directories() {
+        let ipfs = Node::new("test_node").await;
+
+        let file = ipfs
+            .add_reader(&b"foobar\n"[..], AddOptions::default())
+            .await
+            .unwrap();
+
+        let mut opts = TreeOptions::default();
+        opts.sharding_threshold(Some(1));
+        let mut tree = BufferingTreeBuilder::new(opts);
+        for i in 0..16 {
+            tree.put_link(&format!("dir/file-{}", i), file.cid.clone(), file.size)
+                .unwrap();
+        }
+
+        let mut sharded = None;
+        for node in tree.build() {
+            let node = node.unwrap();
+            sharded = Some(node.cid.clone());
+            ipfs.put_block(Block::new(node.block, node.cid))
+                .await
+                .unwrap();
+        }
+
+        let path = format!("/ipfs/{}", sharded.unwrap());
+        ipfs.files_cp(&path, "/sharded", true).await.unwrap();
+
+        let opts = WriteOptions {
+            create: true,
+            ..Default::default()
+        };
+        ipfs.files_write("/sharded/new", b"baz", opts, true)
+            .await
+            .unwrap();
+        ipfs.files_rm("/sharded/file-3", false, true).await.unwrap();
+
+        let mut names = ipfs
+            .files_ls("/sharded")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect::<Vec<_>>();
+        names.sort();
+
+        let mut expected = (0..16)
+            .filter(|&i| i != 3)
+            .map(|i| format!("file-{}", i))
+            .chain(std::iter::once("new".to_owned()))
+            .collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(names, expected);
+
+        let stat = ipfs.files_stat("/sharded").await.unwrap();
+        assert_eq!(stat.kind, NodeKind::ShardedDirectory);
+
+        let content = ipfs
+            .files_read("/sharded/new", None)
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(content, b"baz");
+    }
+
+    #[tokio::test]
+    async fn flushed_root_is_pinned() {
+        let ipfs = Node::new("test_node").await;
+
+        let opts = WriteOptions {
+            create: true,
+            ..Default::default()
+        };
+        ipfs.files_write("/file", b"foobar\n", opts, true)
+            .await
+            .unwrap();
+
+        let first = ipfs.files_flush("/").await.unwrap();
+        let file = ipfs.files_stat("/file").await.unwrap().cid;
+        assert!(ipfs.is_pinned(&first).await.unwrap());
+        assert!(ipfs.is_pinned(&file).await.unwrap());
+        assert!(ipfs.repo.remove_block(&file).await.is_err());
+
+        // unflushed changes do not move the pin
+        ipfs.files_rm("/file", false, false).await.unwrap();
+        assert!(ipfs.is_pinned(&first).await.unwrap());
+
+        let second = ipfs.files_flush("/").await.unwrap();
+        assert_ne!(first, second);
+        assert!(ipfs.is_pinned(&second).await.unwrap());
+        assert!(!ipfs.is_pinned(&first).await.unwrap());
+        assert!(!ipfs.is_pinned(&file).await.unwrap());
+    }
+}
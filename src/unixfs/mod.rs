@@ -1,13 +1,63 @@
 //! Adaptation for `ipfs-unixfs` crate functionality on top of [`crate::Ipfs`].
 //!
-//! Adding files and directory structures is supported but not exposed via an API. See examples and
-//! `ipfs-http`.
+//! Files and directory structures can be added from and extracted to the local filesystem with
+//! [`crate::Ipfs::add_path`] and [`crate::Ipfs::get_to_path`], and added from tar archives with
+//! [`crate::Ipfs::add_tar`]. Existing trees can be edited with [`crate::Ipfs::patch_add_link`],
+//! [`crate::Ipfs::patch_rm_link`] and [`crate::Ipfs::append_to_file`].
 
 pub use ipfs_unixfs as ll;
 
+mod add;
+pub(crate) use add::{add_path, add_reader};
+pub use add::{AddError, AddOptions, Added};
+
 mod cat;
 pub use cat::{cat, StartingPoint, TraversalFailed};
 
+mod file;
+pub use file::UnixfsFile;
+
+mod get;
+pub(crate) use get::get_to_path;
+pub use get::{ExtractionFailed, GetOptions};
+
+mod ignore;
+pub use ignore::IgnoreRules;
+
+mod ls;
+pub use ls::{ls, DirEntry, ListingFailed};
+
+mod nodes;
+
+mod patch;
+pub use patch::PatchError;
+pub(crate) use patch::{append_to_file, patch_add_link, patch_rm_link};
+
+mod read_ahead;
+pub use read_ahead::{ReadAhead, DEFAULT_READ_AHEAD};
+
+mod tar;
+pub(crate) use self::tar::add_tar;
+
+pub mod mfs;
+
+use crate::Block;
+use cid::Codec;
+
+/// Returns the kind, file size and metadata of a UnixFs node. Raw blocks are files without
+/// metadata; `None` is returned as the error for blocks of other codecs.
+pub(crate) fn block_info(block: &Block) -> Result<ll::NodeInfo, Option<ll::walk::Error>> {
+    match block.cid.codec() {
+        Codec::Raw => Ok(ll::NodeInfo {
+            kind: ll::NodeKind::File,
+            filesize: block.data.len() as u64,
+            metadata: Default::default(),
+        }),
+        Codec::DagProtobuf => ll::node_info(&block.data).map_err(Some),
+        _ => Err(None),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
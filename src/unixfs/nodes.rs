@@ -0,0 +1,113 @@
+//! Helpers for the dag-pb nodes of the UnixFs trees edited with [`super::patch`] and
+//! [`super::mfs`].
+
+use crate::{
+    ipld::dag_pb::{PbLink, PbNode},
+    Block, Error, Ipfs, IpfsTypes,
+};
+use cid::{Cid, Codec};
+use ipfs_unixfs::{
+    dir::builder::{BufferingTreeBuilder, TreeOptions},
+    Metadata,
+};
+
+/// The estimated size of a directory past which it is HAMT sharded, the same as the default of
+/// the directory builders and go-ipfs.
+const SHARDING_THRESHOLD: usize = 256 * 1024;
+
+/// Returns a new directory without links or metadata.
+pub(super) fn empty_directory() -> PbNode {
+    PbNode {
+        links: Vec::new(),
+        // UnixFs Data with the Directory type
+        data: vec![0x08, 0x01],
+    }
+}
+
+/// Returns the size of the block and all of the blocks it links to, as recorded in its links.
+pub(super) fn cumulative_size(block: &Block) -> u64 {
+    let links = match block.cid.codec() {
+        Codec::DagProtobuf => PbNode::from_bytes(&block.data)
+            .map(|node| node.links.iter().map(|link| link.size).sum())
+            .unwrap_or(0),
+        _ => 0,
+    };
+
+    block.data.len() as u64 + links
+}
+
+/// Points the named link of the directory to the `Cid`, adding the link if needed.
+pub(super) fn set_link(directory: &mut PbNode, name: &str, cid: Cid, size: u64) {
+    match directory.links.iter_mut().find(|link| link.name == name) {
+        Some(link) => {
+            link.cid = cid;
+            link.size = size;
+        }
+        None => {
+            // keep the links ordered by name, like the directory builders do
+            let index = directory
+                .links
+                .iter()
+                .position(|link| link.name.as_str() > name)
+                .unwrap_or(directory.links.len());
+            let name = name.to_owned();
+            directory.links.insert(index, PbLink { cid, name, size });
+        }
+    }
+}
+
+/// Stores the directory node, returning its `Cid` and cumulative size.
+pub(super) async fn put_directory<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    directory: PbNode,
+) -> Result<(Cid, u64), Error> {
+    let links = directory.links.iter().map(|link| link.size).sum::<u64>();
+    let data = directory.into_bytes();
+    let size = data.len() as u64 + links;
+
+    let mh = multihash::Sha2_256::digest(&data);
+    let cid = Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0");
+
+    ipfs.put_block(Block::new(data, cid.clone())).await?;
+    Ok((cid, size))
+}
+
+/// Returns true if the directory has grown past the sharding threshold, estimating its size from
+/// the lengths of the link names and `Cid`s like the directory builders do.
+pub(super) fn needs_sharding(directory: &PbNode) -> bool {
+    let estimate = directory
+        .links
+        .iter()
+        .map(|link| link.name.len() + link.cid.to_bytes().len())
+        .sum::<usize>();
+
+    estimate > SHARDING_THRESHOLD
+}
+
+/// Stores the links of the directory as a HAMT sharded directory with the metadata, returning the
+/// `Cid` and the cumulative size of the shard root. The shard is the same as the directory
+/// builders would have created for the links.
+pub(super) async fn put_sharded<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    directory: PbNode,
+    metadata: Metadata,
+) -> Result<(Cid, u64), Error> {
+    // the links are put under a made up root, which is the directory sharded
+    let mut tree = BufferingTreeBuilder::new(TreeOptions::default());
+
+    tree.set_metadata("root", metadata).map_err(Error::new)?;
+    for link in directory.links {
+        let path = format!("root/{}", link.name);
+        tree.put_link(&path, link.cid, link.size)
+            .map_err(Error::new)?;
+    }
+
+    let mut root = None;
+    for node in tree.build() {
+        let node = node.map_err(Error::new)?;
+        root = Some((node.cid.clone(), node.total_size));
+        ipfs.put_block(Block::new(node.block, node.cid)).await?;
+    }
+
+    Ok(root.expect("the root is always built"))
+}
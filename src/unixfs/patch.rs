@@ -0,0 +1,948 @@
+//! Editing of existing UnixFs trees. Only the changed nodes and the nodes leading to them are
+//! created again, all of the other blocks of the previous tree are linked to as they are.
+
+use super::nodes::{
+    cumulative_size, empty_directory, needs_sharding, put_directory, put_sharded, set_link,
+};
+use crate::{ipld::dag_pb::PbNode, Block, Error, Ipfs, IpfsTypes};
+use cid::{Cid, Codec};
+use futures::future::{BoxFuture, FutureExt};
+use ipfs_unixfs::{
+    dir::{hamt_hash, hamt_shard_data},
+    file::adder::{read_file_node, rewrite_file_node, FileAdder, FileNode},
+    file::{FileError, FileReadFailed},
+    Metadata, NodeKind,
+};
+
+/// Failures of the editing operations.
+#[derive(Debug, thiserror::Error)]
+pub enum PatchError {
+    /// The paths are relative to the root, and cannot be empty or contain `.` or `..` segments.
+    #[error("invalid path: {0:?}")]
+    InvalidPath(String),
+
+    /// Nothing was found at the path.
+    #[error("no link at {0}")]
+    NotFound(String),
+
+    /// The path was expected to point to a directory.
+    #[error("not a directory: {0}")]
+    NotADirectory(String),
+
+    /// Only files can be appended to.
+    #[error("not a file: {0}")]
+    NotAFile(Cid),
+
+    /// The names hash the same, and cannot be in the same sharded directory.
+    #[error("{0:?} and {1:?} have the same hash")]
+    HashCollision(String, String),
+
+    /// A block in the tree is not a supported UnixFs node.
+    #[error("unsupported node {0}")]
+    UnsupportedNode(Cid, #[source] Option<ipfs_unixfs::walk::Error>),
+
+    /// Only the files of the balanced layout with the default chunker and branching factor of
+    /// the adder can be appended to.
+    #[error("unsupported file layout of {0}")]
+    UnsupportedLayout(Cid),
+
+    /// A node of the file being appended to could not be read.
+    #[error("invalid file node {0}")]
+    InvalidFile(Cid, #[source] FileReadFailed),
+
+    /// Files can only be written to up to their end, leaving no holes.
+    #[error("offset {offset} is past the end of the file of {size} bytes")]
+    OffsetPastEnd {
+        /// The requested offset
+        offset: u64,
+        /// The size of the file
+        size: u64,
+    },
+
+    /// Loading or storing of a block failed.
+    #[error("{0}")]
+    Other(#[from] Error),
+}
+
+/// Adds or replaces the link at the path relative to the root directory, returning the new root.
+/// With `create` the missing directories leading to the link are created. A directory growing past
+/// the sharding threshold is converted into a HAMT sharded directory.
+pub(crate) async fn patch_add_link<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    root: &Cid,
+    path: &str,
+    target: Cid,
+    create: bool,
+) -> Result<Cid, PatchError> {
+    let segments = segments(path)?;
+    let size = cumulative_size(&ipfs.get_block(&target).await?);
+
+    let (mut levels, _) = open_path(ipfs, root, &segments, create).await?;
+
+    let (level, name) = levels
+        .last_mut()
+        .expect("there is a level for every segment");
+    level.set(name, target, size)?;
+
+    store(ipfs, levels).await
+}
+
+/// Removes the link at the path relative to the root directory, returning the new root.
+pub(crate) async fn patch_rm_link<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    root: &Cid,
+    path: &str,
+) -> Result<Cid, PatchError> {
+    let segments = segments(path)?;
+
+    let (mut levels, found) = open_path(ipfs, root, &segments, false).await?;
+
+    if found.is_none() {
+        return Err(PatchError::NotFound(path.to_owned()));
+    }
+
+    let (level, name) = levels
+        .last_mut()
+        .expect("there is a level for every segment");
+    level.remove(name);
+
+    store(ipfs, levels).await
+}
+
+/// Appends the bytes to the file, returning the root of the new file. Only the last leaf of the
+/// file and the link blocks on the right edge of the tree are created again.
+///
+/// The new file is the same as if all of the bytes were added with the default options of the
+/// adder, so the files of any other layout, chunker or branching factor are rejected with
+/// [`PatchError::UnsupportedLayout`] as far as they can be told apart.
+pub(crate) async fn append_to_file<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    file: &Cid,
+    data: &[u8],
+) -> Result<Cid, PatchError> {
+    let block = ipfs.get_block(file).await?;
+    let info =
+        super::block_info(&block).map_err(|e| PatchError::UnsupportedNode(file.clone(), e))?;
+
+    if info.kind != NodeKind::File {
+        return Err(PatchError::NotAFile(file.clone()));
+    }
+
+    // follow the right edge of the tree down to the last leaf, collecting the links before it
+    let mut edge = Vec::new();
+    let mut block = block;
+    let last_leaf = loop {
+        if block.cid.codec() == Codec::Raw {
+            break block;
+        }
+
+        match read_file_node(&block.data) {
+            Ok(FileNode::Links(mut links)) if !links.is_empty() => {
+                let (last, _, _) = links.pop().expect("links were checked not to be empty");
+                edge.push(links);
+                block = ipfs.get_block(&last).await?;
+            }
+            Ok(_) => break block,
+            Err(e) => return Err(PatchError::InvalidFile(block.cid, e)),
+        }
+    };
+
+    let leaf = match last_leaf.cid.codec() {
+        Codec::Raw => &last_leaf.data[..],
+        _ => match read_file_node(&last_leaf.data) {
+            Ok(FileNode::Leaf(bytes)) => bytes,
+            Ok(FileNode::Links(_)) => &[],
+            Err(e) => return Err(PatchError::InvalidFile(last_leaf.cid.clone(), e)),
+        },
+    };
+
+    let leaf_metadata = if edge.is_empty() {
+        info.metadata.clone()
+    } else {
+        Metadata::default()
+    };
+
+    if !balanced_edge(&edge, leaf) || !default_leaf(&last_leaf, leaf, leaf_metadata) {
+        return Err(PatchError::UnsupportedLayout(file.clone()));
+    }
+
+    let mut adder = FileAdder::builder()
+        .with_raw_leaves(last_leaf.cid.codec() == Codec::Raw)
+        .with_cid_version(file.version())
+        .with_metadata(info.metadata)
+        .build();
+
+    let mut blocks = Vec::new();
+
+    let height = edge.len();
+    for (level, links) in edge.into_iter().enumerate() {
+        for (cid, total_size, file_size) in links {
+            blocks.extend(adder.push_subtree(height - level - 1, cid, total_size, file_size));
+        }
+    }
+
+    // the last leaf is chunked again together with the appended bytes
+    for input in [leaf, data].iter_mut() {
+        while !input.is_empty() {
+            let (ready, consumed) = adder.push(input);
+            blocks.extend(ready);
+            *input = &input[consumed..];
+        }
+    }
+
+    blocks.extend(adder.finish());
+
+    let mut root = None;
+    for (cid, block) in blocks {
+        root = Some(cid.clone());
+        ipfs.put_block(Block::new(block.into_boxed_slice(), cid))
+            .await?;
+    }
+
+    Ok(root.expect("the adder always produces the root block"))
+}
+
+/// The chunk size of the default chunker of the adder.
+const CHUNK_SIZE: u64 = 256 * 1024;
+
+/// The branching factor of the default balanced layout of the adder.
+const BRANCHING_FACTOR: usize = 174;
+
+/// Returns true if the nodes on the right edge of the file tree are of the default balanced
+/// layout: every subtree left of the edge is full and no node has more links than the branching
+/// factor allows. The `edge` has the links of each node from the root down, without the last
+/// ones which were followed to the last leaf.
+fn balanced_edge(edge: &[Vec<(Cid, u64, u64)>], last_leaf: &[u8]) -> bool {
+    let height = edge.len();
+
+    last_leaf.len() as u64 <= CHUNK_SIZE
+        && edge.iter().enumerate().all(|(level, links)| {
+            let full = (BRANCHING_FACTOR as u64)
+                .checked_pow((height - level - 1) as u32)
+                .and_then(|leaves| leaves.checked_mul(CHUNK_SIZE));
+
+            links.len() < BRANCHING_FACTOR
+                && links
+                    .iter()
+                    .all(|&(_, _, file_size)| Some(file_size) == full)
+        })
+}
+
+/// Returns true if the adder would create the same leaf for the bytes, which is not the case for
+/// the leaves of the trickle layout. Raw leaves are the same for all layouts.
+fn default_leaf(leaf: &Block, bytes: &[u8], metadata: Metadata) -> bool {
+    if leaf.cid.codec() == Codec::Raw {
+        return true;
+    }
+
+    let mut adder = FileAdder::builder()
+        .with_cid_version(leaf.cid.version())
+        .with_metadata(metadata)
+        .build();
+
+    let mut created = None;
+    let mut input = bytes;
+
+    while !input.is_empty() {
+        let (ready, consumed) = adder.push(input);
+        created = ready.map(|(cid, _)| cid).last().or(created);
+        input = &input[consumed..];
+    }
+
+    created = adder.finish().map(|(cid, _)| cid).last().or(created);
+
+    created.as_ref() == Some(&leaf.cid)
+}
+
+/// Writes the bytes into the file starting at the offset, which cannot be past the end of the
+/// file, returning the root of the new file. Only the leaves overlapping the written range and
+/// the link blocks above them are created again, and the bytes past the end of the file are
+/// appended with [`append_to_file`].
+pub(crate) async fn write_to_file<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    file: &Cid,
+    offset: u64,
+    data: &[u8],
+) -> Result<Cid, PatchError> {
+    let block = ipfs.get_block(file).await?;
+    let info =
+        super::block_info(&block).map_err(|e| PatchError::UnsupportedNode(file.clone(), e))?;
+
+    if info.kind != NodeKind::File {
+        return Err(PatchError::NotAFile(file.clone()));
+    }
+
+    if offset > info.filesize {
+        return Err(PatchError::OffsetPastEnd {
+            offset,
+            size: info.filesize,
+        });
+    }
+
+    let overlapping = (info.filesize - offset).min(data.len() as u64) as usize;
+    let (overwritten, appended) = data.split_at(overlapping);
+
+    let mut root = file.to_owned();
+
+    if !overwritten.is_empty() {
+        root = overwrite(ipfs, block, offset, overwritten).await?.0;
+    }
+
+    if !appended.is_empty() {
+        root = append_to_file(ipfs, &root, appended).await?;
+    }
+
+    Ok(root)
+}
+
+/// Overwrites the bytes of the subtree starting at the offset relative to the start of the
+/// subtree, returning the new `Cid` and the cumulative size of the subtree. The bytes must not
+/// extend past the end of the subtree. The subtrees not overlapping the bytes are not loaded.
+fn overwrite<'a, T: IpfsTypes>(
+    ipfs: &'a Ipfs<T>,
+    block: Block,
+    offset: u64,
+    data: &'a [u8],
+) -> BoxFuture<'a, Result<(Cid, u64), PatchError>> {
+    async move {
+        let start = offset as usize;
+        let end = start + data.len();
+
+        // a leaf shorter than the range of the file linked to it leaves a hole in the file, which
+        // is what reading the file would fail on as well
+        let hole = |cid: &Cid| {
+            PatchError::InvalidFile(cid.clone(), FileError::TreeJumpsBetweenLinks.into())
+        };
+
+        if block.cid.codec() == Codec::Raw {
+            if end > block.data.len() {
+                return Err(hole(&block.cid));
+            }
+            let mut bytes = block.data.to_vec();
+            bytes[start..end].copy_from_slice(data);
+            let cid = Cid::new_v1(Codec::Raw, multihash::Sha2_256::digest(&bytes));
+            let size = bytes.len() as u64;
+            ipfs.put_block(Block::new(bytes.into_boxed_slice(), cid.clone()))
+                .await?;
+            return Ok((cid, size));
+        }
+
+        let invalid = |e| PatchError::InvalidFile(block.cid.clone(), e);
+        let version = block.cid.version();
+
+        let (cid, rewritten) = match read_file_node(&block.data).map_err(invalid)? {
+            FileNode::Leaf(bytes) => {
+                if end > bytes.len() {
+                    return Err(hole(&block.cid));
+                }
+                let mut bytes = bytes.to_vec();
+                bytes[start..end].copy_from_slice(data);
+                rewrite_file_node(&block.data, FileNode::Leaf(&bytes), version)
+            }
+            FileNode::Links(mut links) => {
+                let mut subtree_start = 0;
+                for (cid, total_size, file_size) in links.iter_mut() {
+                    let subtree_end = subtree_start + *file_size;
+                    let from = offset.max(subtree_start);
+                    let to = (offset + data.len() as u64).min(subtree_end);
+
+                    if from < to {
+                        let range = (from - offset) as usize..(to - offset) as usize;
+                        let subtree = ipfs.get_block(cid).await?;
+                        let (new_cid, new_size) =
+                            overwrite(ipfs, subtree, from - subtree_start, &data[range]).await?;
+                        *cid = new_cid;
+                        *total_size = new_size;
+                    }
+
+                    subtree_start = subtree_end;
+                }
+                rewrite_file_node(&block.data, FileNode::Links(links), version)
+            }
+        }
+        .map_err(invalid)?;
+
+        let block = Block::new(rewritten.into_boxed_slice(), cid.clone());
+        let size = cumulative_size(&block);
+        ipfs.put_block(block).await?;
+        Ok((cid, size))
+    }
+    .boxed()
+}
+
+/// Splits the relative path into segments, ignoring empty segments.
+fn segments(path: &str) -> Result<Vec<&str>, PatchError> {
+    let segments = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| match segment {
+            "." | ".." => Err(PatchError::InvalidPath(path.to_owned())),
+            segment => Ok(segment),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if segments.is_empty() {
+        return Err(PatchError::InvalidPath(path.to_owned()));
+    }
+
+    Ok(segments)
+}
+
+/// A directory along the edited path, opened for changing the entry with the given name.
+pub(super) enum Level {
+    Plain(PbNode, Metadata),
+    /// The nodes of the shard from the root down to the bucket of the name.
+    Sharded(Vec<PbNode>, Metadata),
+}
+
+/// Opens the directories along the path, returning them with the names of the entries to change,
+/// and the link at the path if one exists. With `create` the missing directories are created.
+pub(super) async fn open_path<'a, T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    root: &Cid,
+    segments: &[&'a str],
+    create: bool,
+) -> Result<(Vec<(Level, &'a str)>, Option<Cid>), PatchError> {
+    let mut levels = Vec::with_capacity(segments.len());
+    let mut next = Some(root.to_owned());
+
+    for (depth, segment) in segments.iter().enumerate() {
+        let level = match next.take() {
+            Some(cid) => open(ipfs.get_block(&cid).await?, &segments[..depth])?,
+            None if create => Level::Plain(empty_directory(), Metadata::default()),
+            None => return Err(PatchError::NotFound(segments[..depth].join("/"))),
+        };
+
+        let (level, found) = level.lookup(ipfs, segment).await?;
+        next = found;
+        levels.push((level, *segment));
+    }
+
+    Ok((levels, next))
+}
+
+/// Opens the block as a directory, the segments being the path to it.
+fn open(block: Block, segments: &[&str]) -> Result<Level, PatchError> {
+    let info =
+        super::block_info(&block).map_err(|e| PatchError::UnsupportedNode(block.cid.clone(), e))?;
+
+    let node = || PbNode::from_bytes(&block.data).map_err(Error::new);
+
+    match info.kind {
+        NodeKind::Directory => Ok(Level::Plain(node()?, info.metadata)),
+        NodeKind::ShardedDirectory => Ok(Level::Sharded(vec![node()?], info.metadata)),
+        _ => Err(PatchError::NotADirectory(segments.join("/"))),
+    }
+}
+
+impl Level {
+    /// Finds the entry, loading the shard nodes on the way to its bucket.
+    async fn lookup<T: IpfsTypes>(
+        mut self,
+        ipfs: &Ipfs<T>,
+        name: &str,
+    ) -> Result<(Self, Option<Cid>), PatchError> {
+        let nodes = match &mut self {
+            Level::Plain(directory, _) => {
+                let found = directory
+                    .links
+                    .iter()
+                    .find(|link| link.name == name)
+                    .map(|link| link.cid.clone());
+                return Ok((self, found));
+            }
+            Level::Sharded(nodes, _) => nodes,
+        };
+
+        let hash = hamt_hash(name.as_bytes());
+
+        loop {
+            let node = nodes.last().expect("the shard root is always present");
+            let prefix = bucket_name(hash, nodes.len() - 1);
+
+            let link = match node
+                .links
+                .iter()
+                .find(|link| link.name.starts_with(&prefix))
+            {
+                Some(link) => link,
+                None => return Ok((self, None)),
+            };
+
+            if link.name.len() > prefix.len() {
+                let found = Some(link.cid.clone()).filter(|_| link.name[prefix.len()..] == *name);
+                return Ok((self, found));
+            }
+
+            let shard = ipfs.get_block(&link.cid).await?;
+            nodes.push(PbNode::from_bytes(&shard.data).map_err(Error::new)?);
+        }
+    }
+
+    /// Points the entry to the `Cid`, adding it if needed. In a sharded directory a bucket taken
+    /// by another entry is split into a new shard node.
+    fn set(&mut self, name: &str, cid: Cid, size: u64) -> Result<(), PatchError> {
+        let nodes = match self {
+            Level::Plain(directory, _) => {
+                set_link(directory, name, cid, size);
+                return Ok(());
+            }
+            Level::Sharded(nodes, _) => nodes,
+        };
+
+        let hash = hamt_hash(name.as_bytes());
+        let depth = nodes.len() - 1;
+        let prefix = bucket_name(hash, depth);
+
+        let node = nodes.last_mut().expect("the shard root is always present");
+        let other = node
+            .links
+            .iter()
+            .position(|link| link.name.starts_with(&prefix))
+            .map(|index| node.links.remove(index))
+            .filter(|link| link.name[prefix.len()..] != *name);
+
+        if let Some(other) = other {
+            // the bucket was taken by another entry, move both into new shard nodes until the
+            // next bytes of the hashes differ
+            let other_name = other.name[prefix.len()..].to_owned();
+            let other_hash = hamt_hash(other_name.as_bytes());
+
+            for depth in depth + 1.. {
+                if depth == 8 {
+                    return Err(PatchError::HashCollision(name.to_owned(), other_name));
+                }
+
+                let mut node = PbNode {
+                    links: Vec::new(),
+                    data: Vec::new(),
+                };
+
+                let differ = bucket(hash, depth) != bucket(other_hash, depth);
+                if differ {
+                    let entry = format!("{}{}", bucket_name(other_hash, depth), other_name);
+                    set_link(&mut node, &entry, other.cid.clone(), other.size);
+                }
+
+                nodes.push(node);
+
+                if differ {
+                    break;
+                }
+            }
+        }
+
+        let depth = nodes.len() - 1;
+        let node = nodes.last_mut().expect("the shard root is always present");
+        let name = format!("{}{}", bucket_name(hash, depth), name);
+        set_link(node, &name, cid, size);
+        Ok(())
+    }
+
+    /// Removes the entry. In a sharded directory the shard nodes left with a single entry are
+    /// collapsed into their parents.
+    fn remove(&mut self, name: &str) {
+        let nodes = match self {
+            Level::Plain(directory, _) => {
+                directory.links.retain(|link| link.name != name);
+                return;
+            }
+            Level::Sharded(nodes, _) => nodes,
+        };
+
+        let hash = hamt_hash(name.as_bytes());
+        let prefix = bucket_name(hash, nodes.len() - 1);
+
+        let node = nodes.last_mut().expect("the shard root is always present");
+        node.links.retain(|link| !link.name.starts_with(&prefix));
+
+        while nodes.len() > 1 {
+            let node = nodes.last().expect("checked to have more than the root");
+
+            let collapse = match node.links.as_slice() {
+                [] => true,
+                [link] => link.name.len() > 2,
+                _ => false,
+            };
+
+            if !collapse {
+                break;
+            }
+
+            let remaining = nodes.pop().and_then(|mut node| node.links.pop());
+
+            let depth = nodes.len() - 1;
+            let prefix = bucket_name(hash, depth);
+            let parent = nodes
+                .last_mut()
+                .expect("checked to have more than the root");
+            parent.links.retain(|link| !link.name.starts_with(&prefix));
+
+            if let Some(link) = remaining {
+                let name = format!("{}{}", prefix, &link.name[2..]);
+                set_link(parent, &name, link.cid, link.size);
+            }
+        }
+    }
+
+    /// Stores the changed nodes, returning the `Cid` and the cumulative size of the directory.
+    /// The `name` is the entry the shard nodes were opened for. A plain directory grown past the
+    /// sharding threshold is stored as a HAMT sharded directory.
+    async fn store<T: IpfsTypes>(self, ipfs: &Ipfs<T>, name: &str) -> Result<(Cid, u64), Error> {
+        let (mut nodes, metadata) = match self {
+            Level::Plain(directory, metadata) if needs_sharding(&directory) => {
+                return put_sharded(ipfs, directory, metadata).await
+            }
+            Level::Plain(directory, _) => return put_directory(ipfs, directory).await,
+            Level::Sharded(nodes, metadata) => (nodes, metadata),
+        };
+
+        let hash = hamt_hash(name.as_bytes());
+        let mut stored = None;
+
+        while let Some(mut node) = nodes.pop() {
+            let depth = nodes.len();
+
+            if let Some((cid, size)) = stored.take() {
+                let prefix = bucket_name(hash, depth);
+                node.links.retain(|link| !link.name.starts_with(&prefix));
+                set_link(&mut node, &prefix, cid, size);
+            }
+
+            let buckets = node
+                .links
+                .iter()
+                .filter_map(|link| link.name.get(..2))
+                .filter_map(|prefix| u8::from_str_radix(prefix, 16).ok())
+                .collect::<Vec<_>>();
+
+            node.data = if depth == 0 {
+                hamt_shard_data(buckets, &metadata)
+            } else {
+                hamt_shard_data(buckets, &Metadata::default())
+            };
+
+            stored = Some(put_directory(ipfs, node).await?);
+        }
+
+        Ok(stored.expect("the shard root is always present"))
+    }
+}
+
+/// Stores the changed directories from the deepest, linking each to its parent, and returns the
+/// new root.
+async fn store<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    mut levels: Vec<(Level, &str)>,
+) -> Result<Cid, PatchError> {
+    let (level, name) = levels.pop().expect("there is at least one level");
+    let mut stored = level.store(ipfs, name).await?;
+
+    while let Some((mut level, name)) = levels.pop() {
+        let (cid, size) = stored;
+        level.set(name, cid, size)?;
+        stored = level.store(ipfs, name).await?;
+    }
+
+    Ok(stored.0)
+}
+
+/// Returns the bucket of the hash on the given depth of the shard.
+fn bucket(hash: u64, depth: usize) -> u8 {
+    (hash >> (56 - 8 * depth)) as u8
+}
+
+/// Returns the bucket as it prefixes the link names in the shard nodes.
+fn bucket_name(hash: u64, depth: usize) -> String {
+    format!("{:02X}", bucket(hash, depth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PatchError;
+    use crate::unixfs::{AddOptions, Added};
+    use crate::{Block, Node};
+    use cid::Cid;
+    use ipfs_unixfs::dir::builder::{BufferingTreeBuilder, TreeOptions};
+    use ipfs_unixfs::{file::adder::Chunker, NodeKind};
+
+    /// Builds a directory of links to the file at the paths, with the directories sharded past
+    /// the threshold.
+    async fn directory<'a>(
+        ipfs: &Node,
+        paths: impl IntoIterator<Item = &'a String>,
+        file: &Added,
+        sharding_threshold: Option<u64>,
+    ) -> Cid {
+        let mut opts = TreeOptions::default();
+        opts.sharding_threshold(sharding_threshold);
+
+        let mut tree = BufferingTreeBuilder::new(opts);
+        for path in paths {
+            tree.put_link(path, file.cid.clone(), file.size).unwrap();
+        }
+
+        let mut root = None;
+        for node in tree.build() {
+            let node = node.unwrap();
+            root = Some(node.cid.clone());
+            ipfs.put_block(Block::new(node.block, node.cid))
+                .await
+                .unwrap();
+        }
+        root.unwrap()
+    }
+
+    #[tokio::test]
+    async fn add_and_remove_links() {
+        let ipfs = Node::new("test_node").await;
+
+        let file = ipfs
+            .add_reader(&b"foobar\n"[..], AddOptions::default())
+            .await
+            .unwrap();
+
+        let empty = super::put_directory(&ipfs, super::empty_directory())
+            .await
+            .unwrap()
+            .0;
+
+        let paths = vec!["root/a/b/file".to_owned(), "root/c".to_owned()];
+        let expected = directory(&ipfs, &paths, &file, None).await;
+
+        match ipfs
+            .patch_add_link(&empty, "a/b/file", file.cid.clone(), false)
+            .await
+        {
+            Err(PatchError::NotFound(_)) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+
+        let root = ipfs
+            .patch_add_link(&empty, "a/b/file", file.cid.clone(), true)
+            .await
+            .unwrap();
+        let root = ipfs
+            .patch_add_link(&root, "c", file.cid.clone(), false)
+            .await
+            .unwrap();
+        assert_eq!(root, expected);
+
+        match ipfs
+            .patch_add_link(&root, "c/d", file.cid.clone(), true)
+            .await
+        {
+            Err(PatchError::NotADirectory(path)) => assert_eq!(path, "c"),
+            x => panic!("unexpected {:?}", x),
+        }
+
+        let root = ipfs.patch_rm_link(&root, "a/b/file").await.unwrap();
+        let root = ipfs.patch_rm_link(&root, "a").await.unwrap();
+        let root = ipfs.patch_rm_link(&root, "c").await.unwrap();
+        assert_eq!(root, empty);
+
+        match ipfs.patch_rm_link(&root, "c").await {
+            Err(PatchError::NotFound(_)) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[tokio::test]
+    async fn sharded_directories() {
+        let ipfs = Node::new("test_node").await;
+
+        let file = ipfs
+            .add_reader(&b"foobar\n"[..], AddOptions::default())
+            .await
+            .unwrap();
+
+        // enough entries to have some of the buckets split into further shard nodes
+        let paths = (0..64)
+            .map(|i| format!("root/sub/file-{}", i))
+            .collect::<Vec<_>>();
+
+        let all = directory(&ipfs, &paths, &file, Some(1)).await;
+        let half = directory(&ipfs, &paths[..32], &file, Some(1)).await;
+
+        let mut root = half.clone();
+        for path in &paths[32..] {
+            let path = path.trim_start_matches("root/");
+            root = ipfs
+                .patch_add_link(&root, path, file.cid.clone(), false)
+                .await
+                .unwrap();
+        }
+        assert_eq!(root, all);
+
+        for path in &paths[32..] {
+            let path = path.trim_start_matches("root/");
+            root = ipfs.patch_rm_link(&root, path).await.unwrap();
+        }
+        assert_eq!(root, half);
+    }
+
+    #[tokio::test]
+    async fn directory_is_sharded_past_the_threshold() {
+        let ipfs = Node::new("test_node").await;
+
+        let file = ipfs
+            .add_reader(&b"foobar\n"[..], AddOptions::default())
+            .await
+            .unwrap();
+
+        // with the cidv0 links of 34 bytes the estimated size of 1120 entries is just below the
+        // threshold of 256 KiB
+        let paths = (0..1121)
+            .map(|i| format!("root/{:0>200}", i))
+            .collect::<Vec<_>>();
+
+        let below = directory(&ipfs, &paths[..1120], &file, None).await;
+        let expected = directory(&ipfs, &paths, &file, Some(256 * 1024)).await;
+
+        let info = ipfs.get_block(&below).await.unwrap();
+        assert_eq!(
+            super::super::block_info(&info).unwrap().kind,
+            NodeKind::Directory
+        );
+
+        let name = paths[1120].trim_start_matches("root/");
+        let root = ipfs
+            .patch_add_link(&below, name, file.cid.clone(), false)
+            .await
+            .unwrap();
+        assert_eq!(root, expected);
+
+        let info = ipfs.get_block(&root).await.unwrap();
+        assert_eq!(
+            super::super::block_info(&info).unwrap().kind,
+            NodeKind::ShardedDirectory
+        );
+    }
+
+    #[tokio::test]
+    async fn append_to_files() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = (0..900_000u32).map(|i| i as u8).collect::<Vec<_>>();
+
+        let options = || {
+            vec![
+                AddOptions::default(),
+                AddOptions {
+                    raw_leaves: Some(true),
+                    cid_version: cid::Version::V1,
+                    ..Default::default()
+                },
+            ]
+        };
+
+        // a single leaf, and three leaves of which the last one is partial
+        for &split in &[3, 600_000] {
+            for (before, after) in options().into_iter().zip(options()) {
+                let previous = ipfs.add_reader(&content[..split], before).await.unwrap();
+                let expected = ipfs.add_reader(&content[..], after).await.unwrap();
+
+                let appended = ipfs
+                    .append_to_file(&previous.cid, &content[split..])
+                    .await
+                    .unwrap();
+                assert_eq!(appended, expected.cid, "split at {}", split);
+            }
+        }
+
+        let directory = super::put_directory(&ipfs, super::empty_directory())
+            .await
+            .unwrap()
+            .0;
+
+        match ipfs.append_to_file(&directory, b"foobar\n").await {
+            Err(PatchError::NotAFile(_)) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[tokio::test]
+    async fn append_to_unsupported_layouts() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = (0..3000u32).map(|i| i as u8).collect::<Vec<_>>();
+
+        let unsupported = vec![
+            AddOptions {
+                chunker: Chunker::Size(1000),
+                ..Default::default()
+            },
+            AddOptions {
+                chunker: Chunker::Size(1000),
+                trickle: true,
+                ..Default::default()
+            },
+            // the leaves of the trickle layout differ even when the tree is the same
+            AddOptions {
+                chunker: Chunker::Size(256 * 1024),
+                trickle: true,
+                ..Default::default()
+            },
+        ];
+
+        for opts in unsupported {
+            let previous = ipfs.add_reader(&content[..], opts.clone()).await.unwrap();
+
+            match ipfs.append_to_file(&previous.cid, b"foobar\n").await {
+                Err(PatchError::UnsupportedLayout(_)) => {}
+                x => panic!("unexpected {:?} with {:?}", x, opts),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn write_to_invalid_ranges() {
+        use ipfs_unixfs::file::adder::{read_file_node, rewrite_file_node, FileNode};
+
+        let ipfs = Node::new("test_node").await;
+
+        let content = (0..3000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let opts = AddOptions {
+            chunker: Chunker::Size(1000),
+            raw_leaves: Some(true),
+            cid_version: cid::Version::V1,
+            ..Default::default()
+        };
+        let file = ipfs.add_reader(&content[..], opts).await.unwrap();
+
+        match super::write_to_file(&ipfs, &file.cid, 3001, b"foobar\n").await {
+            Err(PatchError::OffsetPastEnd {
+                offset: 3001,
+                size: 3000,
+            }) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+
+        // replace the first leaf with a shorter one, leaving a hole in the file
+        let short = Cid::new_v1(cid::Codec::Raw, multihash::Sha2_256::digest(b"short"));
+        ipfs.put_block(Block::new(
+            b"short".to_vec().into_boxed_slice(),
+            short.clone(),
+        ))
+        .await
+        .unwrap();
+
+        let root = ipfs.get_block(&file.cid).await.unwrap();
+        let mut links = match read_file_node(&root.data).unwrap() {
+            FileNode::Links(links) => links,
+            x => panic!("unexpected {:?}", x),
+        };
+        links[0].0 = short;
+        links[0].1 = 5;
+        let (cid, data) =
+            rewrite_file_node(&root.data, FileNode::Links(links), cid::Version::V1).unwrap();
+        ipfs.put_block(Block::new(data.into_boxed_slice(), cid.clone()))
+            .await
+            .unwrap();
+
+        match super::write_to_file(&ipfs, &cid, 0, b"foobar\n").await {
+            Err(PatchError::InvalidFile(..)) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{Block, Error, Ipfs, IpfsTypes};
+use cid::Cid;
+use futures::future::poll_fn;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::task::JoinHandle;
+
+/// The default of [`crate::UninitializedIpfs::with_read_ahead`].
+pub const DEFAULT_READ_AHEAD: usize = 8;
+
+/// Loads the upcoming blocks of a walk concurrently while the blocks are still consumed in the
+/// order of the walk. At most [`crate::UninitializedIpfs::with_read_ahead`] blocks are loaded
+/// ahead, which bounds the memory held by the loaded but not yet consumed blocks.
+///
+/// The loads are spawned so that they progress even while the consumer is not polling. Loads of
+/// blocks which are no longer upcoming are stopped, as are all loads when this is dropped.
+pub struct ReadAhead<Types: IpfsTypes> {
+    ipfs: Ipfs<Types>,
+    limit: usize,
+    /// The loads in the order of the walk.
+    loading: Vec<(Cid, JoinHandle<Result<Block, Error>>)>,
+}
+
+impl<Types: IpfsTypes> ReadAhead<Types> {
+    /// Creates a read-ahead with the limit configured for the `Ipfs`.
+    pub fn new(ipfs: Ipfs<Types>) -> Self {
+        let limit = ipfs.read_ahead;
+        Self::with_limit(ipfs, limit)
+    }
+
+    /// Creates a read-ahead loading at most `limit` blocks ahead.
+    pub fn with_limit(ipfs: Ipfs<Types>, limit: usize) -> Self {
+        ReadAhead {
+            ipfs,
+            limit,
+            loading: Vec::with_capacity(limit),
+        }
+    }
+
+    /// Starts loading the upcoming blocks, given in the order they will be needed, up to the
+    /// limit. The loads of any other blocks are stopped.
+    pub fn prefetch<'a>(&mut self, upcoming: impl IntoIterator<Item = &'a Cid>) {
+        let mut loading = Vec::with_capacity(self.limit);
+
+        for cid in upcoming.into_iter().take(self.limit) {
+            let handle = match self.loading.iter().position(|(c, _)| c == cid) {
+                Some(i) => self.loading.remove(i).1,
+                None => load(&self.ipfs, cid),
+            };
+
+            loading.push((cid.to_owned(), handle));
+        }
+
+        for (_, handle) in std::mem::replace(&mut self.loading, loading) {
+            handle.abort();
+        }
+    }
+
+    /// Polls for the block, starting to load it if it was not being loaded already.
+    pub fn poll_block(&mut self, cx: &mut Context<'_>, cid: &Cid) -> Poll<Result<Block, Error>> {
+        let i = match self.loading.iter().position(|(c, _)| c == cid) {
+            Some(i) => i,
+            None => {
+                self.loading
+                    .insert(0, (cid.to_owned(), load(&self.ipfs, cid)));
+                0
+            }
+        };
+
+        let res = futures::ready!(Pin::new(&mut self.loading[i].1).poll(cx));
+        self.loading.remove(i);
+
+        Poll::Ready(match res {
+            Ok(res) => res,
+            Err(e) => Err(Error::new(e)),
+        })
+    }
+
+    /// Returns the block, starting to load it if it was not being loaded already.
+    pub async fn get_block(&mut self, cid: &Cid) -> Result<Block, Error> {
+        poll_fn(|cx| self.poll_block(cx, cid)).await
+    }
+}
+
+impl<Types: IpfsTypes> Drop for ReadAhead<Types> {
+    fn drop(&mut self) {
+        // the loads would otherwise keep on waiting for the blocks
+        for (_, handle) in self.loading.drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+fn load<Types: IpfsTypes>(ipfs: &Ipfs<Types>, cid: &Cid) -> JoinHandle<Result<Block, Error>> {
+    let ipfs = ipfs.clone();
+    let cid = cid.to_owned();
+    tokio::spawn(async move { ipfs.get_block(&cid).await })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReadAhead;
+    use crate::repo::mem::{MemBlockStore, MemDataStore, MemLock};
+    use crate::repo::{BlockPut, BlockRm, BlockRmError, BlockStore, RepoTypes};
+    use crate::{Block, Error, IpfsOptions, Node, UninitializedIpfs};
+    use async_trait::async_trait;
+    use cid::{Cid, Codec};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn blocks_in_any_order() {
+        let ipfs = Node::new("test_node").await;
+
+        let mut cids = Vec::new();
+        for i in 0..10u8 {
+            let data = vec![i; 10];
+            let cid = Cid::new_v1(Codec::Raw, multihash::Sha2_256::digest(&data));
+            ipfs.put_block(Block::new(data.into(), cid.clone()))
+                .await
+                .unwrap();
+            cids.push(cid);
+        }
+
+        let mut read_ahead = ReadAhead::with_limit((*ipfs).clone(), 3);
+        read_ahead.prefetch(&cids[..]);
+        assert_eq!(read_ahead.loading.len(), 3);
+
+        // the loads are stopped for the blocks no longer upcoming
+        read_ahead.prefetch(&cids[5..]);
+        assert_eq!(
+            read_ahead
+                .loading
+                .iter()
+                .map(|(cid, _)| cid)
+                .collect::<Vec<_>>(),
+            cids[5..8].iter().collect::<Vec<_>>()
+        );
+
+        // blocks which are not upcoming are loaded on demand
+        for cid in cids.iter().rev() {
+            let block = read_ahead.get_block(cid).await.unwrap();
+            assert_eq!(&block.cid, cid);
+        }
+
+        assert!(read_ahead.loading.is_empty());
+    }
+
+    #[tokio::test]
+    async fn prefetches_in_background_and_aborts_on_drop() {
+        let ipfs =
+            UninitializedIpfs::<DelayedTypes>::new(IpfsOptions::inmemory_with_generated_keys())
+                .with_read_ahead(3)
+                .start()
+                .await
+                .unwrap();
+
+        let mut cids = Vec::new();
+        for i in 0..10u8 {
+            let data = vec![i; 10];
+            let cid = Cid::new_v1(Codec::Raw, multihash::Sha2_256::digest(&data));
+            ipfs.put_block(Block::new(data.into(), cid.clone()))
+                .await
+                .unwrap();
+            cids.push(cid);
+        }
+
+        let (started, finished) = (STARTED.load(SeqCst), FINISHED.load(SeqCst));
+
+        let mut read_ahead = ReadAhead::new(ipfs.clone());
+        read_ahead.prefetch(&cids[..]);
+
+        // the loads progress without the read-ahead being polled
+        tokio::time::sleep(DELAY / 4).await;
+        assert_eq!(STARTED.load(SeqCst), started + 3);
+        assert_eq!(FINISHED.load(SeqCst), finished);
+
+        tokio::time::sleep(DELAY).await;
+        assert_eq!(FINISHED.load(SeqCst), finished + 3);
+
+        let began = Instant::now();
+        for cid in &cids[..3] {
+            assert_eq!(&read_ahead.get_block(cid).await.unwrap().cid, cid);
+        }
+        assert!(
+            began.elapsed() < DELAY,
+            "the blocks had already been loaded"
+        );
+
+        read_ahead.prefetch(&cids[5..]);
+        tokio::time::sleep(DELAY / 4).await;
+        assert_eq!(STARTED.load(SeqCst), started + 6);
+
+        // the loads are stopped when the read-ahead is dropped
+        drop(read_ahead);
+        tokio::time::sleep(DELAY * 2).await;
+        assert_eq!(FINISHED.load(SeqCst), finished + 3);
+    }
+
+    const DELAY: Duration = Duration::from_millis(400);
+
+    static STARTED: AtomicUsize = AtomicUsize::new(0);
+    static FINISHED: AtomicUsize = AtomicUsize::new(0);
+
+    /// In-memory types with a block store taking a while to return the blocks.
+    struct DelayedTypes;
+
+    impl RepoTypes for DelayedTypes {
+        type TBlockStore = DelayedBlockStore;
+        type TDataStore = MemDataStore;
+        type TLock = MemLock;
+    }
+
+    #[derive(Debug)]
+    struct DelayedBlockStore(MemBlockStore);
+
+    #[async_trait]
+    impl BlockStore for DelayedBlockStore {
+        fn new(path: PathBuf) -> Self {
+            DelayedBlockStore(MemBlockStore::new(path))
+        }
+
+        async fn init(&self) -> Result<(), Error> {
+            self.0.init().await
+        }
+
+        async fn open(&self) -> Result<(), Error> {
+            self.0.open().await
+        }
+
+        async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
+            self.0.contains(cid).await
+        }
+
+        async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+            STARTED.fetch_add(1, SeqCst);
+            tokio::time::sleep(DELAY).await;
+            FINISHED.fetch_add(1, SeqCst);
+            self.0.get(cid).await
+        }
+
+        async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+            self.0.put(block).await
+        }
+
+        async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
+            self.0.remove(cid).await
+        }
+
+        async fn list(&self) -> Result<Vec<Cid>, Error> {
+            self.0.list().await
+        }
+
+        async fn wipe(&self) {
+            self.0.wipe().await
+        }
+    }
+}
@@ -0,0 +1,547 @@
+use crate::{Ipfs, IpfsTypes};
+use cid::Cid;
+use ipfs_unixfs::{dir::builder::BufferingTreeBuilder, Metadata};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tar::{EntryType, Header};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::add::{add_file, add_symlink, build, finish};
+use super::{AddError, AddOptions, Added};
+
+const BLOCK_SIZE: usize = 512;
+
+/// The largest accepted GNU long name or PAX extended header, in bytes.
+const MAX_EXTENSION_SIZE: u64 = 1024 * 1024;
+
+/// Adds the regular files, directories, symlinks and hard links of a tar archive in a single pass
+/// over the archive. The entries are wrapped into a new directory, which is the last of the
+/// returned entries, as an archive can contain any amount of top level entries.
+///
+/// The GNU long names and the `path`, `linkpath`, `size` and `mtime` PAX extended headers are
+/// supported. The modes and modification times of the entries are stored as asked in the options.
+pub(crate) async fn add_tar<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    mut reader: impl AsyncRead + Unpin,
+    opts: &AddOptions,
+) -> Result<Vec<Added>, AddError> {
+    let mut tree_opts = opts.tree_options();
+    tree_opts.wrap_with_directory();
+
+    let mut tree = BufferingTreeBuilder::new(tree_opts);
+    let mut added = Vec::new();
+
+    // the added files by their paths, for the hard links
+    let mut files: HashMap<String, (Cid, u64)> = HashMap::new();
+    let mut extensions = Extensions::default();
+    let mut block = [0u8; BLOCK_SIZE];
+
+    while read_header(&mut reader, &mut block).await? {
+        if block.iter().all(|&b| b == 0) {
+            // the end of the archive; the blocks of zeroes following it are not read
+            break;
+        }
+
+        verify_checksum(&block)?;
+
+        let header = Header::from_byte_slice(&block);
+        let entry_type = header.entry_type();
+        let size = header
+            .entry_size()
+            .map_err(|e| invalid(format!("invalid entry size: {}", e)))?;
+
+        match entry_type {
+            EntryType::GNULongName | EntryType::GNULongLink | EntryType::XHeader => {
+                let data = read_extension(&mut reader, size).await?;
+
+                match entry_type {
+                    EntryType::GNULongName => extensions.path = Some(gnu_name(&data)?),
+                    EntryType::GNULongLink => extensions.link_name = Some(gnu_name(&data)?),
+                    _ => extensions.apply_pax(&data)?,
+                }
+                continue;
+            }
+            EntryType::XGlobalHeader => {
+                skip(&mut reader, padded(size)).await?;
+                continue;
+            }
+            _ => {}
+        }
+
+        let ext = std::mem::take(&mut extensions);
+        let size = ext.size.unwrap_or(size);
+
+        let raw_path = match ext.path {
+            Some(path) => path,
+            None => utf8(&header.path_bytes())?,
+        };
+
+        let path = match normalize(&raw_path)? {
+            Some(path) => path,
+            None if entry_type == EntryType::Directory => {
+                // the root of the archive, usually "./", is the wrapping directory
+                skip(&mut reader, padded(size)).await?;
+                continue;
+            }
+            None => return Err(invalid(format!("invalid entry path: {:?}", raw_path))),
+        };
+
+        let mut metadata = Metadata::default();
+
+        if opts.preserve_mode {
+            if let Ok(mode) = header.mode() {
+                metadata = metadata.with_mode(mode & 0o7777);
+            }
+        }
+
+        if opts.preserve_mtime {
+            let mtime = ext
+                .mtime
+                .or_else(|| header.mtime().ok().map(|seconds| (seconds as i64, 0)));
+            if let Some((seconds, nanos)) = mtime {
+                metadata = metadata.with_mtime(seconds, nanos);
+            }
+        }
+
+        let (cid, total) = match entry_type {
+            EntryType::Regular | EntryType::Continuous => {
+                let mut contents = (&mut reader).take(size);
+                let (cid, total) =
+                    add_file(ipfs, &mut contents, opts.file_adder(metadata), opts).await?;
+
+                if contents.limit() > 0 {
+                    return Err(truncated());
+                }
+
+                skip(&mut reader, padded(size) - size).await?;
+                files.insert(path.clone(), (cid.clone(), total));
+                (cid, total)
+            }
+            EntryType::Directory => {
+                skip(&mut reader, padded(size)).await?;
+                // this also adds empty directories
+                tree.set_metadata(&path, metadata)
+                    .map_err(AddError::TreeGathering)?;
+                continue;
+            }
+            EntryType::Symlink => {
+                skip(&mut reader, padded(size)).await?;
+
+                let target = match ext.link_name {
+                    Some(target) => target,
+                    None => match header.link_name_bytes() {
+                        Some(bytes) => utf8(&bytes)?,
+                        None => return Err(invalid(format!("symlink without target: {}", path))),
+                    },
+                };
+
+                add_symlink(ipfs, &target, metadata, opts).await?
+            }
+            EntryType::Link => {
+                skip(&mut reader, padded(size)).await?;
+
+                let target = match ext.link_name {
+                    Some(target) => target,
+                    None => utf8(&header.link_name_bytes().unwrap_or_default())?,
+                };
+
+                // the hard links can only refer to the files earlier in the archive
+                normalize(&target)?
+                    .and_then(|target| files.get(&target))
+                    .cloned()
+                    .ok_or_else(|| invalid(format!("hard link to an unknown file: {}", target)))?
+            }
+            _ => return Err(AddError::UnsupportedFileType(PathBuf::from(path))),
+        };
+
+        tree.put_link(&path, cid.clone(), total)
+            .map_err(AddError::TreeGathering)?;
+        added.push(Added {
+            path,
+            cid,
+            size: total,
+        });
+    }
+
+    build(ipfs, tree, &mut added, opts).await?;
+    finish(ipfs, &added, opts).await?;
+
+    Ok(added)
+}
+
+/// The values of the GNU long name and PAX extended header entries, which apply to the next
+/// entry.
+#[derive(Default)]
+struct Extensions {
+    path: Option<String>,
+    link_name: Option<String>,
+    size: Option<u64>,
+    mtime: Option<(i64, u32)>,
+}
+
+impl Extensions {
+    /// Applies the records of the form `"<length> <key>=<value>\n"` of a PAX extended header.
+    fn apply_pax(&mut self, mut data: &[u8]) -> Result<(), AddError> {
+        let malformed = || invalid("malformed pax extended header".into());
+
+        while !data.is_empty() {
+            let space = data.iter().position(|&b| b == b' ').ok_or_else(malformed)?;
+            let len = std::str::from_utf8(&data[..space])
+                .ok()
+                .and_then(|len| len.parse::<usize>().ok())
+                .filter(|&len| len > space + 1 && len <= data.len())
+                .ok_or_else(malformed)?;
+
+            let record = &data[space + 1..len];
+            if record.last() != Some(&b'\n') {
+                return Err(malformed());
+            }
+            let record = &record[..record.len() - 1];
+
+            let eq = record
+                .iter()
+                .position(|&b| b == b'=')
+                .ok_or_else(malformed)?;
+            let (key, value) = (&record[..eq], &record[eq + 1..]);
+
+            match key {
+                b"path" => self.path = Some(utf8(value)?),
+                b"linkpath" => self.link_name = Some(utf8(value)?),
+                b"size" => {
+                    self.size = Some(
+                        utf8(value)?
+                            .parse()
+                            .map_err(|_| invalid(format!("invalid pax size: {:?}", value)))?,
+                    )
+                }
+                b"mtime" => self.mtime = Some(parse_mtime(&utf8(value)?)?),
+                _ => {}
+            }
+
+            data = &data[len..];
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses the decimal seconds of a PAX `mtime`. Only whole seconds are kept before the epoch.
+fn parse_mtime(value: &str) -> Result<(i64, u32), AddError> {
+    let invalid_mtime = || invalid(format!("invalid pax mtime: {:?}", value));
+
+    let (seconds, fraction) = match value.find('.') {
+        Some(i) => (&value[..i], &value[i + 1..]),
+        None => (value, ""),
+    };
+
+    let seconds = seconds.parse::<i64>().map_err(|_| invalid_mtime())?;
+
+    if seconds < 0 || fraction.is_empty() {
+        return Ok((seconds, 0));
+    }
+
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid_mtime());
+    }
+
+    // the fraction in nanoseconds, ignoring any more precise digits
+    let nanos = fraction
+        .bytes()
+        .chain(std::iter::repeat(b'0'))
+        .take(9)
+        .fold(0u32, |acc, b| acc * 10 + u32::from(b - b'0'));
+
+    Ok((seconds, nanos))
+}
+
+/// Reads the next header block, returning false if the input ended before it.
+async fn read_header(
+    reader: &mut (impl AsyncRead + Unpin),
+    block: &mut [u8; BLOCK_SIZE],
+) -> Result<bool, AddError> {
+    let mut read = 0;
+
+    while read < block.len() {
+        match reader
+            .read(&mut block[read..])
+            .await
+            .map_err(AddError::Reading)?
+        {
+            // archives are accepted without the terminating blocks of zeroes
+            0 if read == 0 => return Ok(false),
+            0 => return Err(truncated()),
+            n => read += n,
+        }
+    }
+
+    Ok(true)
+}
+
+/// Reads the data of a GNU long name or a PAX extended header entry, skipping the padding.
+async fn read_extension(
+    reader: &mut (impl AsyncRead + Unpin),
+    size: u64,
+) -> Result<Vec<u8>, AddError> {
+    if size > MAX_EXTENSION_SIZE {
+        return Err(invalid(format!(
+            "too large extended header: {} bytes",
+            size
+        )));
+    }
+
+    let mut data = vec![0u8; size as usize];
+    reader.read_exact(&mut data).await.map_err(read_error)?;
+    skip(reader, padded(size) - size).await?;
+
+    Ok(data)
+}
+
+async fn skip(reader: &mut (impl AsyncRead + Unpin), amount: u64) -> Result<(), AddError> {
+    let skipped = tokio::io::copy(&mut reader.take(amount), &mut tokio::io::sink())
+        .await
+        .map_err(AddError::Reading)?;
+
+    if skipped < amount {
+        return Err(truncated());
+    }
+
+    Ok(())
+}
+
+/// The size of the data rounded up to whole blocks.
+fn padded(size: u64) -> u64 {
+    let block = BLOCK_SIZE as u64;
+    (size + block - 1) / block * block
+}
+
+/// The checksum is the sum of the header bytes, with the checksum field itself as spaces.
+fn verify_checksum(block: &[u8; BLOCK_SIZE]) -> Result<(), AddError> {
+    let expected = Header::from_byte_slice(block)
+        .cksum()
+        .map_err(|_| invalid("invalid header checksum".into()))?;
+
+    let actual = block[..148]
+        .iter()
+        .chain(&block[156..])
+        .fold(8 * u32::from(b' '), |acc, &b| acc + u32::from(b));
+
+    if actual != expected {
+        return Err(invalid(format!(
+            "header checksum mismatch: expected {}, calculated {}",
+            expected, actual
+        )));
+    }
+
+    Ok(())
+}
+
+/// Normalizes the path of an entry into a path of the tree, returning `None` for the root.
+fn normalize(path: &str) -> Result<Option<String>, AddError> {
+    let mut components = Vec::new();
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return Err(invalid(format!("path escapes the archive: {}", path))),
+            other => components.push(other),
+        }
+    }
+
+    Ok(if components.is_empty() {
+        None
+    } else {
+        Some(components.join("/"))
+    })
+}
+
+/// The GNU long names are terminated by a zero byte.
+fn gnu_name(data: &[u8]) -> Result<String, AddError> {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    utf8(&data[..end])
+}
+
+fn utf8(bytes: &[u8]) -> Result<String, AddError> {
+    std::str::from_utf8(bytes).map(String::from).map_err(|_| {
+        invalid(format!(
+            "name is not valid utf-8: {}",
+            String::from_utf8_lossy(bytes)
+        ))
+    })
+}
+
+fn read_error(e: std::io::Error) -> AddError {
+    if e.kind() == std::io::ErrorKind::UnexpectedEof {
+        truncated()
+    } else {
+        AddError::Reading(e)
+    }
+}
+
+fn truncated() -> AddError {
+    invalid("unexpected end of archive".into())
+}
+
+fn invalid(msg: String) -> AddError {
+    AddError::InvalidArchive(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BLOCK_SIZE;
+    use crate::unixfs::{AddError, AddOptions};
+    use crate::Node;
+    use std::fs;
+
+    fn header(entry_type: tar::EntryType, size: u64) -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_size(size);
+        header.set_mode(0o644);
+        header
+    }
+
+    #[tokio::test]
+    async fn add_tar_matches_path() {
+        let ipfs = Node::new("test_node").await;
+
+        let long_name = format!("sub/{}", "a".repeat(150));
+        let content = vec![3u8; 300 * 1024];
+
+        let mut builder = tar::Builder::new(Vec::new());
+        builder
+            .append_data(&mut header(tar::EntryType::Directory, 0), "./", &[][..])
+            .unwrap();
+        builder
+            .append_data(&mut header(tar::EntryType::Directory, 0), "empty/", &[][..])
+            .unwrap();
+        builder
+            .append_data(
+                &mut header(tar::EntryType::Regular, content.len() as u64),
+                "./file",
+                &content[..],
+            )
+            .unwrap();
+        builder
+            .append_data(
+                &mut header(tar::EntryType::Regular, 7),
+                &long_name,
+                &b"foobar\n"[..],
+            )
+            .unwrap();
+
+        let mut link = header(tar::EntryType::Link, 0);
+        link.set_link_name("file").unwrap();
+        builder.append_data(&mut link, "hardlink", &[][..]).unwrap();
+
+        let mut symlink = header(tar::EntryType::Symlink, 0);
+        symlink.set_link_name("sub/target").unwrap();
+        builder
+            .append_data(&mut symlink, "symlink", &[][..])
+            .unwrap();
+
+        // the path of the next entry given in a pax extended header
+        let pax = b"22 path=sub/pax-named\n";
+        builder
+            .append_data(
+                &mut header(tar::EntryType::XHeader, pax.len() as u64),
+                "PaxHeader",
+                &pax[..],
+            )
+            .unwrap();
+        builder
+            .append_data(
+                &mut header(tar::EntryType::Regular, 4),
+                "short",
+                &b"pax\n"[..],
+            )
+            .unwrap();
+
+        let archive = builder.into_inner().unwrap();
+
+        let from_tar = ipfs
+            .add_tar(&archive[..], AddOptions::default())
+            .await
+            .unwrap();
+
+        let mut paths = from_tar
+            .iter()
+            .map(|a| a.path.as_str())
+            .filter(|p| !p.is_empty())
+            .collect::<Vec<_>>();
+        paths.sort_unstable();
+        assert_eq!(
+            paths,
+            &[
+                "empty",
+                "file",
+                "hardlink",
+                long_name.as_str(),
+                "sub",
+                "sub/pax-named",
+                "symlink"
+            ]
+        );
+
+        // the same tree on the filesystem
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("file"), &content).unwrap();
+        fs::write(root.join("hardlink"), &content).unwrap();
+        fs::write(root.join(&long_name), b"foobar\n").unwrap();
+        fs::write(root.join("sub/pax-named"), b"pax\n").unwrap();
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("sub/target", root.join("symlink")).unwrap();
+
+            let from_path = ipfs.add_path(&root, AddOptions::default()).await.unwrap();
+
+            assert_eq!(from_tar.last().unwrap().cid, from_path.last().unwrap().cid);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_archives() {
+        let ipfs = Node::new("test_node").await;
+
+        let mut builder = tar::Builder::new(Vec::new());
+        builder
+            .append_data(
+                &mut header(tar::EntryType::Regular, 7),
+                "file",
+                &b"foobar\n"[..],
+            )
+            .unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        // truncated in the middle of the contents
+        match ipfs
+            .add_tar(&archive[..BLOCK_SIZE + 3], AddOptions::default())
+            .await
+        {
+            Err(AddError::InvalidArchive(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let mut corrupted = archive.clone();
+        corrupted[0] = b'x';
+        match ipfs.add_tar(&corrupted[..], AddOptions::default()).await {
+            Err(AddError::InvalidArchive(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut escaping = header(tar::EntryType::Regular, 0);
+        escaping.as_old_mut().name[..7].copy_from_slice(b"../file");
+        escaping.set_cksum();
+        builder.append(&escaping, &[][..]).unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        match ipfs.add_tar(&archive[..], AddOptions::default()).await {
+            Err(AddError::InvalidArchive(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -1,6 +1,10 @@
 use cid::{Cid, Codec};
-use ipfs::Block;
+use ipfs::{
+    Block, Ipfs, IpfsOptions, MultiaddrWithPeerId, Node, ServingPolicy, TestTypes,
+    UninitializedIpfs,
+};
 use multihash::Sha2_256;
+use std::convert::TryFrom;
 use std::time::Duration;
 use tokio::time::timeout;
 
@@ -29,6 +33,29 @@ async fn two_node_put_get() {
     assert_eq!(block.data, found_block.data);
 }
 
+// verify that a node configured to never serve blocks refuses the wants of other peers
+#[tokio::test]
+async fn leech_only_node_does_not_serve() {
+    let leech: Ipfs<TestTypes> =
+        UninitializedIpfs::new(IpfsOptions::inmemory_with_generated_keys())
+            .with_serving_policy(ServingPolicy::leech_only())
+            .start()
+            .await
+            .unwrap();
+    let node = Node::new("seeker").await;
+    let block = create_block();
+
+    leech.put_block(block.clone()).await.unwrap();
+    leech
+        .connect(MultiaddrWithPeerId::try_from(node.addrs[0].clone()).unwrap())
+        .await
+        .unwrap();
+
+    let res = timeout(Duration::from_secs(2), node.get_block(&block.cid)).await;
+    assert!(res.is_err(), "the leech served the block");
+    assert!(leech.bitswap_stats().await.unwrap().refused_wants > 0);
+}
+
 // check that a long line of nodes still works with get_block
 #[tokio::test]
 #[ignore]
@@ -0,0 +1,65 @@
+use cid::{Cid, Codec};
+use ipfs::Block;
+use multihash::Sha2_256;
+use std::time::Duration;
+use tokio::time::timeout;
+
+mod common;
+use common::{spawn_nodes, Topology};
+
+fn create_block(i: usize) -> Block {
+    let data = format!("hello block {}\n", i)
+        .into_bytes()
+        .into_boxed_slice();
+    let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+
+    Block { cid, data }
+}
+
+// the first node is not connected to the one holding the block, so the block can only be
+// fetched after the provider has been found through the DHT
+#[tokio::test]
+async fn provider_found_for_unconnected_node() {
+    let nodes = spawn_nodes(3, Topology::Line).await;
+    let block = create_block(0);
+
+    nodes[2].put_block(block.clone()).await.unwrap();
+    let found_block = timeout(Duration::from_secs(20), nodes[0].get_block(&block.cid))
+        .await
+        .expect("get_block did not complete in time")
+        .unwrap();
+
+    assert_eq!(block.data, found_block.data);
+}
+
+// fetching a number of sibling blocks should not need a provider query per block, as the
+// provider found and dialed for the first one is reused for the rest
+#[tokio::test]
+async fn provider_reused_for_sibling_blocks() {
+    const BLOCKS: usize = 10;
+    let nodes = spawn_nodes(3, Topology::Line).await;
+    let blocks = (0..BLOCKS).map(create_block).collect::<Vec<_>>();
+
+    for block in &blocks {
+        nodes[2].put_block(block.clone()).await.unwrap();
+    }
+
+    let first = &blocks[0];
+    timeout(Duration::from_secs(20), nodes[0].get_block(&first.cid))
+        .await
+        .expect("get_block did not complete in time")
+        .unwrap();
+
+    // the provider is now connected, the rest should arrive without further searches
+    for block in &blocks[1..] {
+        let found_block = timeout(Duration::from_secs(5), nodes[0].get_block(&block.cid))
+            .await
+            .expect("get_block for a sibling did not complete in time")
+            .unwrap();
+        assert_eq!(block.data, found_block.data);
+    }
+
+    let stats = nodes[0].bitswap_stats().await.unwrap();
+    assert_eq!(stats.provider_queries, 1);
+    assert_eq!(stats.provider_dials, 1);
+}
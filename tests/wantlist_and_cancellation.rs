@@ -1,7 +1,7 @@
 use cid::Cid;
 use futures::future::{pending, select, Either, FutureExt};
 use futures::future::{AbortHandle, Abortable};
-use ipfs::Node;
+use ipfs::{BitswapConfig, Ipfs, IpfsOptions, Node, TestTypes, UninitializedIpfs};
 use tokio::{
     task,
     time::{sleep, timeout},
@@ -171,3 +171,46 @@ async fn wantlist_cancellation() {
     // ensure that there are no related subscriptions
     // check_cid_subscriptions(&ipfs, &cid, 0).await;
 }
+
+/// Check that a want nobody can satisfy fails once its deadline has passed.
+#[tokio::test]
+async fn want_expires_at_deadline() {
+    let deadline = Duration::from_millis(200);
+    let config = BitswapConfig::default().with_want_deadline(deadline);
+    let ipfs: Ipfs<TestTypes> = UninitializedIpfs::new(IpfsOptions::inmemory_with_generated_keys())
+        .with_bitswap_config(config)
+        .start()
+        .await
+        .unwrap();
+
+    let cid = Cid::try_from("QmSoLPppuBtQSGwKDZT2M73ULpjvfd3aZ6ha4oFGL1KaGa").unwrap();
+    let started = Instant::now();
+    let res = timeout(Duration::from_secs(5), ipfs.get_block(&cid))
+        .await
+        .expect("the want did not expire in time");
+
+    assert!(res.is_err());
+    assert!(started.elapsed() >= deadline);
+    assert!(ipfs.bitswap_wantlist(None).await.unwrap().is_empty());
+}
+
+/// Check that a want fails once the deadline given for it has passed, even when the configured
+/// deadline is longer.
+#[tokio::test]
+async fn want_expires_at_given_deadline() {
+    let ipfs = Node::new("test_node").await;
+
+    let deadline = Duration::from_millis(200);
+    let cid = Cid::try_from("QmSoLPppuBtQSGwKDZT2M73ULpjvfd3aZ6ha4oFGL1KaGa").unwrap();
+    let started = Instant::now();
+    let res = timeout(
+        Duration::from_secs(5),
+        ipfs.get_block_with_deadline(&cid, Some(deadline)),
+    )
+    .await
+    .expect("the want did not expire in time");
+
+    assert!(res.is_err());
+    assert!(started.elapsed() >= deadline);
+    assert!(ipfs.bitswap_wantlist(None).await.unwrap().is_empty());
+}
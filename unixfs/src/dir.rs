@@ -5,7 +5,12 @@ use core::convert::TryFrom;
 use core::fmt;
 
 mod sharded_lookup;
-pub use sharded_lookup::{Cache, LookupError, ShardError, ShardedLookup};
+pub use sharded_lookup::{
+    hamt_hash, hamt_shard_data, Cache, LookupError, ShardError, ShardedLookup,
+};
+
+mod listing;
+pub use listing::{list, ListedLink, ShardedListing};
 
 mod directory;
 pub(crate) use directory::{check_directory_supported, UnexpectedDirectoryProperties};
@@ -10,6 +10,9 @@ pub use iter::{OwnedTreeNode, PostOrderIterator, TreeNode};
 mod buffered;
 pub use buffered::BufferingTreeBuilder;
 
+mod streaming;
+pub use streaming::StreamingTreeBuilder;
+
 mod custom_pb;
 use custom_pb::CustomFlatUnixFs;
 
@@ -55,6 +58,9 @@ impl fmt::Debug for Leaf {
 pub struct TreeOptions {
     block_size_limit: Option<u64>,
     wrap_with_directory: bool,
+    cid_version: cid::Version,
+    inline_limit: Option<usize>,
+    sharding_threshold: Option<u64>,
 }
 
 impl Default for TreeOptions {
@@ -63,6 +69,10 @@ impl Default for TreeOptions {
             // this is just a guess; our bitswap message limit is a bit more
             block_size_limit: Some(512 * 1024),
             wrap_with_directory: false,
+            cid_version: cid::Version::V0,
+            inline_limit: None,
+            // same as go-ipfs
+            sharding_threshold: Some(256 * 1024),
         }
     }
 }
@@ -79,6 +89,27 @@ impl TreeOptions {
     pub fn wrap_with_directory(&mut self) {
         self.wrap_with_directory = true;
     }
+
+    /// Overrides the default Cid version 0 of the directory blocks.
+    pub fn cid_version(&mut self, version: cid::Version) {
+        self.cid_version = version;
+    }
+
+    /// Inlines the directory blocks up to `limit` bytes into the Cids using the identity hash.
+    pub fn inline_limit(&mut self, limit: usize) {
+        self.inline_limit = Some(limit);
+    }
+
+    /// Overrides the default threshold of 256 KiB for HAMT sharding a directory. The size of a
+    /// directory is estimated from the lengths of the link names and Cids, like go-ipfs does. If
+    /// the threshold is set to `None`, no directory will be sharded.
+    pub fn sharding_threshold(&mut self, threshold: Option<u64>) {
+        self.sharding_threshold = threshold;
+    }
+
+    fn cids(&self) -> crate::CidBuilder {
+        crate::CidBuilder::new(self.cid_version, self.inline_limit)
+    }
 }
 
 /// Tree building failure cases.
@@ -124,14 +155,53 @@ impl fmt::Display for TreeBuildingFailed {
 
 impl std::error::Error for TreeBuildingFailed {}
 
+/// Failure cases for `StreamingTreeBuilder` building a tree from the paths given in sorted order.
+#[derive(Debug)]
+pub enum StreamingTreeBuildingFailed {
+    /// The given full path was invalid, the same as with `BufferingTreeBuilder`.
+    InvalidPath(TreeBuildingFailed),
+    /// The given full path was not in the sorted order, possibly reopening an already completed
+    /// directory.
+    UnsortedPath(String),
+}
+
+impl From<TreeBuildingFailed> for StreamingTreeBuildingFailed {
+    fn from(e: TreeBuildingFailed) -> Self {
+        StreamingTreeBuildingFailed::InvalidPath(e)
+    }
+}
+
+impl fmt::Display for StreamingTreeBuildingFailed {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use StreamingTreeBuildingFailed::*;
+
+        match self {
+            InvalidPath(e) => write!(fmt, "{}", e),
+            UnsortedPath(s) => write!(fmt, "path is out of sorted order: {:?}", s),
+        }
+    }
+}
+
+impl std::error::Error for StreamingTreeBuildingFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StreamingTreeBuildingFailed::InvalidPath(e) => Some(e),
+            StreamingTreeBuildingFailed::UnsortedPath(_) => None,
+        }
+    }
+}
+
 /// Failure cases for `PostOrderIterator` creating the tree dag-pb nodes.
 #[derive(Debug)]
 pub enum TreeConstructionFailed {
     /// Failed to serialize the protobuf node for the directory
     Protobuf(quick_protobuf::Error),
-    /// The resulting directory would be too large and HAMT sharding is yet to be implemented or
-    /// denied.
+    /// The resulting directory node would be too large, either because HAMT sharding was denied
+    /// or the sharding threshold is larger than the block size limit.
     TooLargeBlock(u64),
+    /// The two names have the same HAMT hash, so they cannot be placed in the same HAMT sharded
+    /// directory.
+    HashCollision(String, String),
 }
 
 impl fmt::Display for TreeConstructionFailed {
@@ -141,6 +211,7 @@ impl fmt::Display for TreeConstructionFailed {
         match self {
             Protobuf(e) => write!(fmt, "serialization failed: {}", e),
             TooLargeBlock(size) => write!(fmt, "attempted to create block of {} bytes", size),
+            HashCollision(a, b) => write!(fmt, "names {:?} and {:?} have the same HAMT hash", a, b),
         }
     }
 }
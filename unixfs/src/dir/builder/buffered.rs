@@ -191,6 +191,82 @@ mod tests {
     use cid::Cid;
     use core::convert::TryFrom;
 
+    #[test]
+    fn cid_version_1_directories() {
+        let mut opts = TreeOptions::default();
+        opts.cid_version(cid::Version::V1);
+        let mut builder = BufferingTreeBuilder::new(opts);
+
+        // foobar\n
+        let five_block_foobar =
+            Cid::try_from("QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6").unwrap();
+
+        builder
+            .put_link("a/b/c.txt", five_block_foobar, 221)
+            .unwrap();
+
+        let actual = builder
+            .build()
+            .map(|res| res.map(|n| n.cid))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(actual.len(), 2);
+        assert!(
+            actual
+                .iter()
+                .all(|cid| cid.version() == cid::Version::V1
+                    && cid.codec() == cid::Codec::DagProtobuf)
+        );
+    }
+
+    #[test]
+    fn sharded_directory() {
+        use crate::dir::{resolve, MaybeResolved};
+        use std::collections::HashMap;
+
+        let mut opts = TreeOptions::default();
+        opts.sharding_threshold(Some(0));
+        let mut builder = BufferingTreeBuilder::new(opts);
+
+        for i in 0..1000 {
+            builder
+                .put_link(&format!("dir/{}", i), some_cid(i), 1)
+                .unwrap();
+        }
+
+        let nodes = builder.build().collect::<Result<Vec<_>, _>>().unwrap();
+
+        let (root, buckets) = nodes.split_last().unwrap();
+        assert_eq!(root.path, "dir");
+        assert!(!root.bucket);
+        assert!(!buckets.is_empty());
+        assert!(buckets.iter().all(|n| n.bucket && n.path == "dir"));
+
+        let blocks = nodes
+            .iter()
+            .map(|n| (n.cid.clone(), &n.block))
+            .collect::<HashMap<_, _>>();
+
+        for i in 0..1000 {
+            let name = i.to_string();
+            let mut step = resolve(&root.block, &name, &mut None).unwrap();
+
+            let found = loop {
+                match step {
+                    MaybeResolved::Found(cid) => break cid,
+                    MaybeResolved::NeedToLoadMore(lookup) => {
+                        let next = &blocks[lookup.pending_links().0];
+                        step = lookup.continue_walk(next, &mut None).unwrap();
+                    }
+                    MaybeResolved::NotFound => panic!("{} was not found", name),
+                }
+            };
+
+            assert_eq!(found, some_cid(i));
+        }
+    }
+
     #[test]
     fn some_directories() {
         let mut builder = BufferingTreeBuilder::default();
@@ -1,6 +1,10 @@
 use super::{
     CustomFlatUnixFs, DirBuilder, Entry, Leaf, NamedLeaf, TreeConstructionFailed, TreeOptions,
 };
+use crate::dir::sharded_lookup::{hamt_hash, HAMT_FANOUT, HAMT_HASH_TYPE};
+use crate::pb::{UnixFs, UnixFsType};
+use crate::Metadata;
+use alloc::borrow::Cow;
 use cid::Cid;
 use core::fmt;
 use std::collections::HashMap;
@@ -21,15 +25,24 @@ pub struct PostOrderIterator {
     reused_children: Vec<Visited>,
     cid: Option<Cid>,
     total_size: u64,
+    // the rendered nodes of a HAMT sharded directory in reverse order, with the root first
+    shard_nodes: Vec<ShardNode>,
     // from TreeOptions
     opts: TreeOptions,
 }
 
+/// A rendered node of a HAMT sharded directory, waiting to be returned.
+pub(super) struct ShardNode {
+    pub cid: Cid,
+    pub total_size: u64,
+    pub block: Vec<u8>,
+}
+
 /// The link list used to create the directory node. This list is created from a the BTreeMap
 /// inside DirBuilder, and initially it will have `Some` values only for the initial leaves and
 /// `None` values for subnodes which are not yet ready. At the time of use, this list is expected
 /// to have only `Some` values.
-type Leaves = Vec<Option<NamedLeaf>>;
+pub(super) type Leaves = Vec<Option<NamedLeaf>>;
 
 /// The nodes in the visit. We need to do a post-order visit, which starts from a single
 /// `DescentRoot`, followed by N `Descents` where N is the deepest directory in the tree. On each
@@ -55,9 +68,11 @@ enum Visited {
         /// Leaves will be stored directly in this field when there are no DirBuilder descendants,
         /// in the `PostOrderIterator::persisted_cids` otherwise.
         leaves: LeafStorage,
+        metadata: Metadata,
     },
     PostRoot {
         leaves: LeafStorage,
+        metadata: Metadata,
     },
 }
 
@@ -73,96 +88,77 @@ impl PostOrderIterator {
             reused_children: Vec::new(),
             cid: None,
             total_size: 0,
+            shard_nodes: Vec::new(),
             opts,
         }
     }
 
-    fn render_directory(
+    /// Renders the directory as a single node or, if it exceeds the sharding threshold, as a HAMT
+    /// sharded directory. For a sharded directory the nodes are left in `shard_nodes`.
+    fn render(
+        &mut self,
+        leaves: Leaves,
+        metadata: &Metadata,
+    ) -> Result<Leaf, TreeConstructionFailed> {
+        if needs_sharding(&leaves, &self.opts) {
+            let mut nodes = Vec::new();
+            let root = render_sharded(leaves, metadata, &self.opts, &mut nodes)?;
+            nodes.reverse();
+            self.shard_nodes = nodes;
+            Ok(root)
+        } else {
+            Self::render_directory(&leaves, metadata, &mut self.block_buffer, &self.opts)
+        }
+    }
+
+    pub(super) fn render_directory(
         links: &[Option<NamedLeaf>],
+        metadata: &Metadata,
         buffer: &mut Vec<u8>,
-        block_size_limit: &Option<u64>,
+        opts: &TreeOptions,
     ) -> Result<Leaf, TreeConstructionFailed> {
-        use crate::pb::{UnixFs, UnixFsType};
-        use quick_protobuf::{BytesWriter, MessageWrite, Writer};
-        use sha2::{Digest, Sha256};
-
-        // FIXME: ideas on how to turn this into a HAMT sharding on some heuristic. we probably
-        // need to introduce states in to the "iterator":
-        //
-        // 1. bucketization
-        // 2. another post order visit of the buckets?
-        //
-        // the nested post order visit should probably re-use the existing infra ("message
-        // passing") and new ids can be generated by giving this iterator the counter from
-        // BufferedTreeBuilder.
-        //
-        // could also be that the HAMT shard building should start earlier, since the same
-        // heuristic can be detected *at* bufferedtreewriter. there the split would be easier, and
-        // this would "just" be a single node rendering, and not need any additional states..
-
-        let node = CustomFlatUnixFs {
-            links,
-            data: UnixFs {
-                Type: UnixFsType::Directory,
-                ..Default::default()
-            },
+        let mut data = UnixFs {
+            Type: UnixFsType::Directory,
+            ..Default::default()
         };
+        metadata.apply(&mut data);
 
-        let size = node.get_size();
-
-        if let Some(limit) = block_size_limit {
-            let size = size as u64;
-            if *limit < size {
-                // FIXME: this could probably be detected at builder
-                return Err(TreeConstructionFailed::TooLargeBlock(size));
-            }
-        }
-
-        let cap = buffer.capacity();
-
-        if let Some(additional) = size.checked_sub(cap) {
-            buffer.reserve(additional);
-        }
-
-        if let Some(mut needed_zeroes) = size.checked_sub(buffer.len()) {
-            let zeroes = [0; 8];
-
-            while needed_zeroes > 8 {
-                buffer.extend_from_slice(&zeroes[..]);
-                needed_zeroes -= zeroes.len();
-            }
+        render_node(links, data, buffer, opts)
+    }
 
-            buffer.extend(core::iter::repeat(0).take(needed_zeroes));
+    /// Returns the next node of the HAMT sharded directory rendered last.
+    fn next_shard_node(&mut self) -> TreeNode<'_> {
+        let ShardNode {
+            cid,
+            total_size,
+            block,
+        } = self
+            .shard_nodes
+            .pop()
+            .expect("only called when there are shard nodes");
+
+        self.block_buffer = block;
+        self.cid = Some(cid);
+        self.total_size = total_size;
+
+        TreeNode {
+            path: self.full_path.as_str(),
+            cid: self.cid.as_ref().unwrap(),
+            total_size: self.total_size,
+            block: &self.block_buffer,
+            // the root of the sharded directory is the last one
+            bucket: !self.shard_nodes.is_empty(),
         }
-
-        let mut writer = Writer::new(BytesWriter::new(&mut buffer[..]));
-        node.write_message(&mut writer)
-            .map_err(TreeConstructionFailed::Protobuf)?;
-
-        buffer.truncate(size);
-
-        let mh = multihash::wrap(multihash::Code::Sha2_256, &Sha256::digest(&buffer));
-        let cid = Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0");
-
-        let combined_from_links = links
-            .iter()
-            .map(|opt| {
-                opt.as_ref()
-                    .map(|NamedLeaf(_, _, total_size)| total_size)
-                    .unwrap()
-            })
-            .sum::<u64>();
-
-        Ok(Leaf {
-            link: cid,
-            total_size: buffer.len() as u64 + combined_from_links,
-        })
     }
 
     /// Construct the next dag-pb node, if any.
     ///
     /// Returns a `TreeNode` of the latest constructed tree node.
     pub fn next_borrowed(&mut self) -> Option<Result<TreeNode<'_>, TreeConstructionFailed>> {
+        if !self.shard_nodes.is_empty() {
+            return Some(Ok(self.next_shard_node()));
+        }
+
         while let Some(visited) = self.pending.pop() {
             let (name, depth) = match &visited {
                 Visited::DescentRoot(_) => (None, 0),
@@ -186,7 +182,10 @@ impl PostOrderIterator {
                         leaves.into()
                     };
 
-                    self.pending.push(Visited::PostRoot { leaves });
+                    self.pending.push(Visited::PostRoot {
+                        leaves,
+                        metadata: node.metadata,
+                    });
                     self.pending.extend(children.drain(..));
                 }
                 Visited::Descent {
@@ -213,6 +212,7 @@ impl PostOrderIterator {
                         depth,
                         leaves,
                         index,
+                        metadata: node.metadata,
                     });
 
                     self.pending.extend(children.drain(..));
@@ -222,16 +222,12 @@ impl PostOrderIterator {
                     name,
                     leaves,
                     index,
+                    metadata,
                     ..
                 } => {
                     let leaves = leaves.into_inner(&mut self.persisted_cids);
-                    let buffer = &mut self.block_buffer;
 
-                    let leaf = match Self::render_directory(
-                        &leaves,
-                        buffer,
-                        &self.opts.block_size_limit,
-                    ) {
+                    let leaf = match self.render(leaves, &metadata) {
                         Ok(leaf) => leaf,
                         Err(e) => return Some(Err(e)),
                     };
@@ -258,27 +254,26 @@ impl PostOrderIterator {
                         }
                     }
 
+                    if !self.shard_nodes.is_empty() {
+                        return Some(Ok(self.next_shard_node()));
+                    }
+
                     return Some(Ok(TreeNode {
                         path: self.full_path.as_str(),
                         cid: self.cid.as_ref().unwrap(),
                         total_size: self.total_size,
                         block: &self.block_buffer,
+                        bucket: false,
                     }));
                 }
-                Visited::PostRoot { leaves } => {
+                Visited::PostRoot { leaves, metadata } => {
                     let leaves = leaves.into_inner(&mut self.persisted_cids);
 
                     if !self.opts.wrap_with_directory {
                         break;
                     }
 
-                    let buffer = &mut self.block_buffer;
-
-                    let leaf = match Self::render_directory(
-                        &leaves,
-                        buffer,
-                        &self.opts.block_size_limit,
-                    ) {
+                    let leaf = match self.render(leaves, &metadata) {
                         Ok(leaf) => leaf,
                         Err(e) => return Some(Err(e)),
                     };
@@ -286,11 +281,16 @@ impl PostOrderIterator {
                     self.cid = Some(leaf.link.clone());
                     self.total_size = leaf.total_size;
 
+                    if !self.shard_nodes.is_empty() {
+                        return Some(Ok(self.next_shard_node()));
+                    }
+
                     return Some(Ok(TreeNode {
                         path: self.full_path.as_str(),
                         cid: self.cid.as_ref().unwrap(),
                         total_size: self.total_size,
                         block: &self.block_buffer,
+                        bucket: false,
                     }));
                 }
             }
@@ -299,6 +299,178 @@ impl PostOrderIterator {
     }
 }
 
+/// Returns true if the directory should be HAMT sharded, estimating the size of the node like
+/// go-ipfs does.
+pub(super) fn needs_sharding(links: &[Option<NamedLeaf>], opts: &TreeOptions) -> bool {
+    let threshold = match opts.sharding_threshold {
+        Some(threshold) => threshold,
+        None => return false,
+    };
+
+    let estimate = links
+        .iter()
+        .map(|opt| {
+            opt.as_ref()
+                .map(|NamedLeaf(name, cid, _)| name.len() + cid.to_bytes().len())
+                .expect("all links must be present when rendering")
+        })
+        .sum::<usize>();
+
+    estimate as u64 > threshold
+}
+
+/// Renders the directory as a HAMT shard with the fanout of 256, hashing the names with
+/// murmur3-x64-64 like go-ipfs does. The nodes are pushed to `out` in post order, ending with the
+/// root, which is also returned.
+pub(super) fn render_sharded(
+    links: Leaves,
+    metadata: &Metadata,
+    opts: &TreeOptions,
+    out: &mut Vec<ShardNode>,
+) -> Result<Leaf, TreeConstructionFailed> {
+    let mut entries = links
+        .into_iter()
+        .map(|opt| opt.expect("all links must be present when rendering"))
+        .map(|leaf| (hamt_hash(leaf.0.as_bytes()), leaf))
+        .collect::<Vec<_>>();
+
+    // sorting by the hash keeps the entries of each bucket together on every level
+    entries.sort_unstable_by_key(|(hash, _)| *hash);
+
+    render_bucket(entries, 0, metadata, opts, out)
+}
+
+/// Renders a single node of the HAMT shard at `depth`, recursing into the buckets with more than
+/// one entry. The metadata is only written to the root node.
+fn render_bucket(
+    entries: Vec<(u64, NamedLeaf)>,
+    depth: u32,
+    metadata: &Metadata,
+    opts: &TreeOptions,
+    out: &mut Vec<ShardNode>,
+) -> Result<Leaf, TreeConstructionFailed> {
+    let index_of = |hash: u64| (hash >> (56 - 8 * depth)) as u8;
+
+    let mut links = Vec::new();
+    let mut bitfield = [0u8; (HAMT_FANOUT / 8) as usize];
+    let mut entries = entries.into_iter().peekable();
+
+    while let Some((hash, leaf)) = entries.next() {
+        let index = index_of(hash);
+        bitfield[bitfield.len() - 1 - index as usize / 8] |= 1 << (index % 8);
+
+        let mut same_bucket = Vec::new();
+        while let Some((next, _)) = entries.peek() {
+            if index_of(*next) != index {
+                break;
+            }
+            same_bucket.extend(entries.next());
+        }
+
+        if same_bucket.is_empty() {
+            let NamedLeaf(name, cid, total_size) = leaf;
+            let name = format!("{:02X}{}", index, name);
+            links.push(Some(NamedLeaf(name, cid, total_size)));
+        } else if depth == 7 {
+            // all of the 64 bits have been used
+            let (_, other) = same_bucket.swap_remove(0);
+            return Err(TreeConstructionFailed::HashCollision(leaf.0, other.0));
+        } else {
+            same_bucket.insert(0, (hash, leaf));
+            let bucket = render_bucket(same_bucket, depth + 1, &Metadata::default(), opts, out)?;
+            let name = format!("{:02X}", index);
+            links.push(Some(NamedLeaf(name, bucket.link, bucket.total_size)));
+        }
+    }
+
+    // the bitfield is a big endian number without the leading zeroes
+    let first_set = bitfield
+        .iter()
+        .position(|b| *b != 0)
+        .unwrap_or(bitfield.len());
+
+    let mut data = UnixFs {
+        Type: UnixFsType::HAMTShard,
+        Data: Some(Cow::Borrowed(&bitfield[first_set..])),
+        hashType: Some(HAMT_HASH_TYPE),
+        fanout: Some(HAMT_FANOUT),
+        ..Default::default()
+    };
+    metadata.apply(&mut data);
+
+    let mut block = Vec::new();
+    let leaf = render_node(&links, data, &mut block, opts)?;
+
+    out.push(ShardNode {
+        cid: leaf.link.clone(),
+        total_size: leaf.total_size,
+        block,
+    });
+
+    Ok(leaf)
+}
+
+/// Renders the dag-pb node with the given links and UnixFs data into `buffer`.
+fn render_node(
+    links: &[Option<NamedLeaf>],
+    data: UnixFs<'_>,
+    buffer: &mut Vec<u8>,
+    opts: &TreeOptions,
+) -> Result<Leaf, TreeConstructionFailed> {
+    use quick_protobuf::{BytesWriter, MessageWrite, Writer};
+
+    let node = CustomFlatUnixFs { links, data };
+
+    let size = node.get_size();
+
+    if let Some(limit) = opts.block_size_limit {
+        let size = size as u64;
+        if limit < size {
+            // FIXME: this could probably be detected at builder
+            return Err(TreeConstructionFailed::TooLargeBlock(size));
+        }
+    }
+
+    let cap = buffer.capacity();
+
+    if let Some(additional) = size.checked_sub(cap) {
+        buffer.reserve(additional);
+    }
+
+    if let Some(mut needed_zeroes) = size.checked_sub(buffer.len()) {
+        let zeroes = [0; 8];
+
+        while needed_zeroes > 8 {
+            buffer.extend_from_slice(&zeroes[..]);
+            needed_zeroes -= zeroes.len();
+        }
+
+        buffer.extend(core::iter::repeat(0).take(needed_zeroes));
+    }
+
+    let mut writer = Writer::new(BytesWriter::new(&mut buffer[..]));
+    node.write_message(&mut writer)
+        .map_err(TreeConstructionFailed::Protobuf)?;
+
+    buffer.truncate(size);
+
+    let cid = opts.cids().sum(cid::Codec::DagProtobuf, &buffer);
+
+    let combined_from_links = links
+        .iter()
+        .map(|opt| {
+            opt.as_ref()
+                .map(|NamedLeaf(_, _, total_size)| total_size)
+                .unwrap()
+        })
+        .sum::<u64>();
+
+    Ok(Leaf {
+        link: cid,
+        total_size: buffer.len() as u64 + combined_from_links,
+    })
+}
+
 impl Iterator for PostOrderIterator {
     type Item = Result<OwnedTreeNode, TreeConstructionFailed>;
 
@@ -318,6 +490,9 @@ pub struct TreeNode<'a> {
     pub total_size: u64,
     /// Raw dag-pb document.
     pub block: &'a [u8],
+    /// True for the inner nodes of a HAMT sharded directory, which share the path of the
+    /// directory.
+    pub bucket: bool,
 }
 
 impl<'a> fmt::Debug for TreeNode<'a> {
@@ -327,6 +502,7 @@ impl<'a> fmt::Debug for TreeNode<'a> {
             .field("cid", &format_args!("{}", self.cid))
             .field("total_size", &self.total_size)
             .field("size", &self.block.len())
+            .field("bucket", &self.bucket)
             .finish()
     }
 }
@@ -339,6 +515,7 @@ impl TreeNode<'_> {
             cid: self.cid.to_owned(),
             total_size: self.total_size,
             block: self.block.into(),
+            bucket: self.bucket,
         }
     }
 }
@@ -353,6 +530,9 @@ pub struct OwnedTreeNode {
     pub total_size: u64,
     /// Raw dag-pb document.
     pub block: Box<[u8]>,
+    /// True for the inner nodes of a HAMT sharded directory, which share the path of the
+    /// directory.
+    pub bucket: bool,
 }
 
 fn update_full_path(
@@ -0,0 +1,463 @@
+use super::iter::{needs_sharding, render_sharded, Leaves};
+use super::{
+    Leaf, NamedLeaf, OwnedTreeNode, PostOrderIterator, StreamingTreeBuildingFailed,
+    TreeBuildingFailed, TreeConstructionFailed, TreeOptions,
+};
+use crate::Metadata;
+use alloc::collections::VecDeque;
+use cid::Cid;
+
+/// UnixFs directory tree builder for entries given in sorted order, which renders the directories
+/// as soon as they are complete.
+///
+/// The entries need to be given in the order of a depth first walk where the entries of every
+/// directory are sorted by their names, as in comparing the paths component by component. A
+/// directory is complete once an entry outside of it is given, so only the directories on the
+/// path to the latest entry need to be kept in memory instead of the whole tree. The completed
+/// directory nodes are returned by `completed()` and the rest by `finish()`. The nodes are the
+/// same as `BufferingTreeBuilder` would create for the tree, but the subdirectories are returned
+/// in the sorted order instead of the reverse order.
+pub struct StreamingTreeBuilder {
+    /// The directories on the path to the latest entry, starting from the root.
+    open: Vec<Directory>,
+    /// The directories which are complete but not yet rendered, in the post order.
+    closed: VecDeque<Directory>,
+    /// The rendered nodes waiting to be returned.
+    ready: VecDeque<OwnedTreeNode>,
+    opts: TreeOptions,
+}
+
+struct Directory {
+    /// The full path of the directory, empty for the root.
+    path: String,
+    /// The number of path components.
+    depth: usize,
+    /// The index of the link to this directory in the parent's `links`.
+    index: usize,
+    /// The links so far, with `None` for the subdirectories which are yet to be rendered.
+    links: Leaves,
+    metadata: Metadata,
+    /// The name of the latest entry and whether it is a directory.
+    latest: Option<(String, bool)>,
+}
+
+impl Directory {
+    fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or_default()
+    }
+}
+
+enum Target {
+    Link(Leaf),
+    Directory(Metadata),
+}
+
+impl Default for StreamingTreeBuilder {
+    fn default() -> Self {
+        Self::new(TreeOptions::default())
+    }
+}
+
+impl StreamingTreeBuilder {
+    /// Construct a new tree builder with the given configuration.
+    pub fn new(opts: TreeOptions) -> Self {
+        let root = Directory {
+            path: String::new(),
+            depth: 0,
+            index: 0,
+            links: Vec::new(),
+            metadata: Metadata::default(),
+            latest: None,
+        };
+
+        StreamingTreeBuilder {
+            open: vec![root],
+            closed: VecDeque::new(),
+            ready: VecDeque::new(),
+            opts,
+        }
+    }
+
+    /// Registers the given path to be a link to the cid that follows, like
+    /// `BufferingTreeBuilder::put_link` but requiring the path to come after all of the
+    /// previously given paths.
+    pub fn put_link(
+        &mut self,
+        full_path: &str,
+        target: Cid,
+        total_size: u64,
+    ) -> Result<(), StreamingTreeBuildingFailed> {
+        let leaf = Leaf {
+            link: target,
+            total_size,
+        };
+
+        self.put(full_path, Target::Link(leaf))
+    }
+
+    /// Creates the directory at the given path with the metadata, or sets the metadata of a
+    /// directory which is not yet complete.
+    pub fn set_metadata(
+        &mut self,
+        full_path: &str,
+        metadata: Metadata,
+    ) -> Result<(), StreamingTreeBuildingFailed> {
+        self.put(full_path, Target::Directory(metadata))
+    }
+
+    /// Returns the nodes of the directories completed so far. This should be drained after
+    /// every put to keep the memory usage bounded.
+    pub fn completed(
+        &mut self,
+    ) -> impl Iterator<Item = Result<OwnedTreeNode, TreeConstructionFailed>> + '_ {
+        core::iter::from_fn(move || self.next_completed())
+    }
+
+    /// Completes all of the remaining directories and returns their nodes, ending with the root.
+    pub fn finish(mut self) -> impl Iterator<Item = Result<OwnedTreeNode, TreeConstructionFailed>> {
+        // the root is only rendered when wrapping, like with BufferingTreeBuilder
+        let remaining = if self.opts.wrap_with_directory { 0 } else { 1 };
+
+        while self.open.len() > remaining {
+            let dir = self.open.pop().expect("checked length");
+            self.closed.push_back(dir);
+        }
+
+        core::iter::from_fn(move || self.next_completed())
+    }
+
+    fn put(&mut self, full_path: &str, target: Target) -> Result<(), StreamingTreeBuildingFailed> {
+        if full_path.ends_with('/') {
+            return Err(TreeBuildingFailed::PathEndsInSlash(full_path.to_string()).into());
+        }
+
+        if full_path.contains("//") {
+            return Err(TreeBuildingFailed::RepeatSlashesInPath(full_path.to_string()).into());
+        }
+
+        if full_path.starts_with('/') {
+            return Err(TreeBuildingFailed::RootedPath(full_path.to_string()).into());
+        }
+
+        let components = full_path.split('/').collect::<Vec<_>>();
+
+        // the number of leading components which are directories still open
+        let common = self.open[1..]
+            .iter()
+            .zip(components.iter())
+            .take_while(|(dir, component)| dir.name() == **component)
+            .count();
+
+        if common == components.len() {
+            return match target {
+                Target::Directory(metadata) => {
+                    self.open[common].metadata = metadata;
+                    Ok(())
+                }
+                Target::Link(_) => {
+                    Err(TreeBuildingFailed::DuplicatePath(full_path.to_string()).into())
+                }
+            };
+        }
+
+        let name = components[common];
+        let parent = &self.open[common];
+
+        // our first level can be full, depending on the options given
+        if common == 0 && !self.opts.wrap_with_directory && parent.latest.is_some() {
+            return Err(TreeBuildingFailed::TooManyRootLevelEntries.into());
+        }
+
+        match &parent.latest {
+            Some((latest, _)) if name < latest.as_str() => {
+                return Err(StreamingTreeBuildingFailed::UnsortedPath(
+                    full_path.to_string(),
+                ));
+            }
+            Some((latest, is_directory)) if name == latest => {
+                let last = common + 1 == components.len();
+
+                return Err(match (*is_directory, last, target) {
+                    (_, true, Target::Link(_)) => {
+                        TreeBuildingFailed::DuplicatePath(full_path.to_string()).into()
+                    }
+                    (false, _, _) => {
+                        TreeBuildingFailed::LeafAsDirectory(full_path.to_string()).into()
+                    }
+                    // the directory has already been completed
+                    (true, _, _) => {
+                        StreamingTreeBuildingFailed::UnsortedPath(full_path.to_string())
+                    }
+                });
+            }
+            _ => {}
+        }
+
+        while self.open.len() > common + 1 {
+            let dir = self.open.pop().expect("checked length");
+            self.closed.push_back(dir);
+        }
+
+        let (basename, directories) = components[common..]
+            .split_last()
+            .expect("common is less than the number of components");
+
+        for directory in directories {
+            self.open_directory(directory);
+        }
+
+        match target {
+            Target::Link(leaf) => {
+                let parent = self.open.last_mut().expect("the root is always open");
+                parent.links.push(Some(NamedLeaf(
+                    basename.to_string(),
+                    leaf.link,
+                    leaf.total_size,
+                )));
+                parent.latest = Some((basename.to_string(), false));
+            }
+            Target::Directory(metadata) => {
+                self.open_directory(basename);
+                self.open.last_mut().expect("just opened").metadata = metadata;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn open_directory(&mut self, name: &str) {
+        let depth = self.open.len();
+        let parent = self.open.last_mut().expect("the root is always open");
+
+        let path = if parent.path.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", parent.path, name)
+        };
+
+        let index = parent.links.len();
+        // this will be overwritten once the directory has been rendered
+        parent.links.push(None);
+        parent.latest = Some((name.to_string(), true));
+
+        self.open.push(Directory {
+            path,
+            depth,
+            index,
+            links: Vec::new(),
+            metadata: Metadata::default(),
+            latest: None,
+        });
+    }
+
+    fn next_completed(&mut self) -> Option<Result<OwnedTreeNode, TreeConstructionFailed>> {
+        loop {
+            if let Some(node) = self.ready.pop_front() {
+                return Some(Ok(node));
+            }
+
+            let dir = self.closed.pop_front()?;
+
+            if let Err(e) = self.render(dir) {
+                return Some(Err(e));
+            }
+        }
+    }
+
+    /// Renders the directory into `ready` and fills in the link to it in the parent.
+    fn render(&mut self, dir: Directory) -> Result<(), TreeConstructionFailed> {
+        let name = dir.name().to_string();
+        let Directory {
+            path,
+            depth,
+            index,
+            links,
+            metadata,
+            ..
+        } = dir;
+
+        let leaf = if needs_sharding(&links, &self.opts) {
+            let mut nodes = Vec::new();
+            let root = render_sharded(links, &metadata, &self.opts, &mut nodes)?;
+
+            let count = nodes.len();
+            for (i, node) in nodes.into_iter().enumerate() {
+                self.ready.push_back(OwnedTreeNode {
+                    path: path.clone(),
+                    cid: node.cid,
+                    total_size: node.total_size,
+                    block: node.block.into_boxed_slice(),
+                    // the root of the sharded directory is the last one
+                    bucket: i + 1 != count,
+                });
+            }
+
+            root
+        } else {
+            let mut block = Vec::new();
+            let leaf =
+                PostOrderIterator::render_directory(&links, &metadata, &mut block, &self.opts)?;
+
+            self.ready.push_back(OwnedTreeNode {
+                path,
+                cid: leaf.link.clone(),
+                total_size: leaf.total_size,
+                block: block.into_boxed_slice(),
+                bucket: false,
+            });
+
+            leaf
+        };
+
+        if depth > 0 {
+            // the parent is either still open or it was completed after this directory, as the
+            // only directory at its depth in between
+            let parent = match self.closed.iter_mut().find(|d| d.depth + 1 == depth) {
+                Some(parent) => parent,
+                None => &mut self.open[depth - 1],
+            };
+
+            let cell = &mut parent.links[index];
+            assert!(cell.is_none());
+            *cell = Some(NamedLeaf(name, leaf.link, leaf.total_size));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{BufferingTreeBuilder, OwnedTreeNode},
+        StreamingTreeBuilder, StreamingTreeBuildingFailed, TreeBuildingFailed, TreeOptions,
+    };
+    use crate::Metadata;
+    use cid::Cid;
+
+    fn some_cid(number: usize) -> Cid {
+        use multihash::Sha2_256;
+        let mh = Sha2_256::digest(&number.to_le_bytes());
+        Cid::new_v0(mh).unwrap()
+    }
+
+    /// Returns the nodes in a comparable form, sorted as the builders return the subdirectories in
+    /// different orders.
+    fn summary(nodes: &[OwnedTreeNode]) -> Vec<(&str, String, bool)> {
+        let mut summary = nodes
+            .iter()
+            .map(|n| (n.path.as_str(), n.cid.to_string(), n.bucket))
+            .collect::<Vec<_>>();
+        summary.sort();
+        summary
+    }
+
+    /// Builds the sorted paths with both builders, comparing the created nodes.
+    fn assert_same_as_buffering(paths: &[&str], opts: TreeOptions) {
+        let mut buffering = BufferingTreeBuilder::new(opts.clone());
+        let mut streaming = StreamingTreeBuilder::new(opts);
+        let mut streamed = Vec::new();
+
+        for (i, path) in paths.iter().enumerate() {
+            if let Some(dir) = path.strip_suffix("/.") {
+                let metadata = Metadata::default().with_mode(0o700);
+                buffering.set_metadata(dir, metadata.clone()).unwrap();
+                streaming.set_metadata(dir, metadata).unwrap();
+            } else {
+                buffering.put_link(path, some_cid(i), 1).unwrap();
+                streaming.put_link(path, some_cid(i), 1).unwrap();
+            }
+
+            streamed.extend(streaming.completed().map(Result::unwrap));
+        }
+
+        streamed.extend(streaming.finish().map(Result::unwrap));
+
+        let buffered = buffering.build().map(Result::unwrap).collect::<Vec<_>>();
+
+        assert_eq!(summary(&streamed), summary(&buffered));
+    }
+
+    #[test]
+    fn same_as_buffering() {
+        let paths = [
+            "a/b/c/d", "a/b/c/e", "a/b/f", "a/g/.", "a/h/i/.", "a/h/i/j", "a/h/k", "a/l",
+        ];
+
+        assert_same_as_buffering(&paths, TreeOptions::default());
+
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+        assert_same_as_buffering(&paths, opts.clone());
+
+        let paths = ["a", "b/c", "b/d/e", "f/."];
+        assert_same_as_buffering(&paths, opts);
+    }
+
+    #[test]
+    fn sharded_directories() {
+        let mut names = (0..1000).map(|i| format!("dir/{}", i)).collect::<Vec<_>>();
+        names.sort();
+        names.push("dir/z/nested".into());
+        let paths = names.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+
+        let mut opts = TreeOptions::default();
+        opts.sharding_threshold(Some(0));
+        assert_same_as_buffering(&paths, opts);
+    }
+
+    #[test]
+    fn directories_are_returned_when_complete() {
+        let mut builder = StreamingTreeBuilder::default();
+
+        builder.put_link("a/b/c", some_cid(0), 1).unwrap();
+        builder.put_link("a/b/d", some_cid(1), 1).unwrap();
+        assert_eq!(builder.completed().count(), 0);
+
+        builder.put_link("a/e/f", some_cid(2), 1).unwrap();
+        let completed = builder.completed().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].path, "a/b");
+
+        let rest = builder.finish().map(Result::unwrap).collect::<Vec<_>>();
+        let paths = rest.iter().map(|n| n.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, &["a/e", "a"]);
+    }
+
+    #[test]
+    fn unsorted_paths() {
+        use StreamingTreeBuildingFailed::InvalidPath;
+
+        let mut builder = StreamingTreeBuilder::default();
+
+        builder.put_link("a/b/c", some_cid(0), 1).unwrap();
+        builder.put_link("a/d", some_cid(1), 1).unwrap();
+
+        match builder.put_link("a/b/e", some_cid(2), 1) {
+            Err(StreamingTreeBuildingFailed::UnsortedPath(_)) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+
+        match builder.put_link("a/c", some_cid(2), 1) {
+            Err(StreamingTreeBuildingFailed::UnsortedPath(_)) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+
+        match builder.put_link("a/d", some_cid(2), 1) {
+            Err(InvalidPath(TreeBuildingFailed::DuplicatePath(_))) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+
+        match builder.put_link("a/d/e", some_cid(2), 1) {
+            Err(InvalidPath(TreeBuildingFailed::LeafAsDirectory(_))) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+
+        match builder.put_link("b", some_cid(2), 1) {
+            Err(InvalidPath(TreeBuildingFailed::TooManyRootLevelEntries)) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+
+        builder.put_link("a/e", some_cid(2), 1).unwrap();
+    }
+}
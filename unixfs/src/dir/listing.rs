@@ -0,0 +1,224 @@
+use super::{
+    check_directory_supported, try_convert_cid, Cache, LookupError, ResolveError, ShardedLookup,
+};
+use crate::pb::{FlatUnixFs, PBLink, PBNode, ParsingFailed, UnixFsType};
+use crate::InvalidCidInLink;
+use alloc::collections::VecDeque;
+use cid::Cid;
+use core::convert::TryFrom;
+use core::fmt;
+
+/// A link listed from a directory, or from any other `dag-pb` node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedLink {
+    /// The name of the link, without the bucket prefix for entries of HAMT sharded directories.
+    pub name: String,
+    /// The target of the link.
+    pub cid: Cid,
+    /// The cumulative size recorded in the link.
+    pub tsize: u64,
+}
+
+/// Lists the links of a `dag-pb` block, which is usually a UnixFS directory, into `entries`.
+///
+/// The entries of a HAMT sharded directory are spread over multiple blocks; for those a
+/// `ShardedListing` is returned which will need to be continued with the blocks of the pending
+/// buckets. Links of other UnixFS nodes, such as files, are listed as is, meaning they have no
+/// names.
+///
+/// Like with [`resolve`](super::resolve), the third parameter can be used to cache the work
+/// queue between the listings.
+pub fn list(
+    block: &[u8],
+    entries: &mut Vec<ListedLink>,
+    cache: &mut Option<Cache>,
+) -> Result<Option<ShardedListing>, ResolveError> {
+    let links = match FlatUnixFs::try_parse(block) {
+        Ok(hamt) if hamt.data.Type == UnixFsType::HAMTShard => {
+            return Ok(ShardedListing::list_or_start(hamt, entries, cache)?)
+        }
+        Ok(flat) if flat.data.Type == UnixFsType::Directory => {
+            check_directory_supported(flat)?.links
+        }
+        Ok(other) => other.links,
+        Err(ParsingFailed::InvalidUnixFs(_, PBNode { Links: links, .. }))
+        | Err(ParsingFailed::NoData(PBNode { Links: links, .. })) => links,
+        Err(ParsingFailed::InvalidDagPb(e)) => return Err(ResolveError::Read(e)),
+    };
+
+    for (i, link) in links.into_iter().enumerate() {
+        let name = link.Name.as_deref().unwrap_or_default().to_owned();
+        entries.push(convert(i, name, link)?);
+    }
+
+    Ok(None)
+}
+
+fn convert(nth: usize, name: String, link: PBLink<'_>) -> Result<ListedLink, InvalidCidInLink> {
+    let tsize = link.Tsize.unwrap_or_default();
+    let cid = try_convert_cid(nth, link)?;
+    Ok(ListedLink { name, cid, tsize })
+}
+
+/// `ShardedListing` walks over all of the buckets of a HAMT sharded directory, listing the
+/// entries of each bucket as it is continued.
+pub struct ShardedListing {
+    links: VecDeque<Cid>,
+}
+
+impl fmt::Debug for ShardedListing {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "ShardedListing {{ links: {} }}", self.links.len())
+    }
+}
+
+impl ShardedListing {
+    /// Returns the next pending bucket and an iterator over the rest.
+    pub fn pending_links(&self) -> (&Cid, impl Iterator<Item = &Cid>) {
+        let mut iter = self.links.iter();
+        let first = iter.next().expect("Already validated there are links");
+        (first, iter)
+    }
+
+    /// Continues the listing with the block of the first pending bucket, pushing its entries to
+    /// `entries`.
+    ///
+    /// Returns `None` once all of the buckets have been listed.
+    pub fn continue_walk(
+        mut self,
+        next: &[u8],
+        entries: &mut Vec<ListedLink>,
+        cache: &mut Option<Cache>,
+    ) -> Result<Option<ShardedListing>, LookupError> {
+        debug_assert_eq!(Some(self.pending_links().0), self.links.front());
+
+        self.links
+            .pop_front()
+            .expect("Already validated there are links");
+
+        let mut hamt = match FlatUnixFs::try_from(next) {
+            Ok(hamt) if hamt.data.Type == UnixFsType::HAMTShard => hamt,
+            Ok(other) => return Err(LookupError::UnexpectedBucketType(other.data.Type.into())),
+            Err(ParsingFailed::InvalidDagPb(e)) | Err(ParsingFailed::InvalidUnixFs(e, _)) => {
+                *cache = Some(self.links.into());
+                return Err(LookupError::Read(Some(e)));
+            }
+            Err(ParsingFailed::NoData(_)) => {
+                *cache = Some(self.links.into());
+                return Err(LookupError::Read(None));
+            }
+        };
+
+        ShardedLookup::check_supported(&mut hamt)?;
+
+        Self::partition(hamt.links.into_iter(), entries, &mut self.links)?;
+
+        if self.links.is_empty() {
+            *cache = Some(self.links.into());
+            Ok(None)
+        } else {
+            Ok(Some(self))
+        }
+    }
+
+    fn list_or_start(
+        mut hamt: FlatUnixFs<'_>,
+        entries: &mut Vec<ListedLink>,
+        cache: &mut Option<Cache>,
+    ) -> Result<Option<ShardedListing>, LookupError> {
+        ShardedLookup::check_supported(&mut hamt)?;
+
+        let mut links = cache.take().map(|c| c.buffer).unwrap_or_default();
+
+        Self::partition(hamt.links.into_iter(), entries, &mut links)?;
+
+        if links.is_empty() {
+            *cache = Some(links.into());
+            Ok(None)
+        } else {
+            Ok(Some(ShardedListing { links }))
+        }
+    }
+
+    /// Partition the links of a bucket the same way as `ShardedLookup` does: names of only the
+    /// two hex digits of the bucket index are links to the next level of buckets, the rest are
+    /// entries prefixed by the index.
+    fn partition<'a>(
+        iter: impl Iterator<Item = PBLink<'a>>,
+        entries: &mut Vec<ListedLink>,
+        work: &mut VecDeque<Cid>,
+    ) -> Result<(), InvalidCidInLink> {
+        for (i, link) in iter.enumerate() {
+            let name = link.Name.as_deref().unwrap_or_default();
+
+            if name.len() > 2 {
+                let name = name[2..].to_owned();
+                entries.push(convert(i, name, link)?);
+            } else if name.len() == 2 {
+                work.push_back(try_convert_cid(i, link)?);
+            } else {
+                // not a valid link of a bucket, same as in lookup
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::list;
+    use crate::test_support::FakeBlockstore;
+
+    #[test]
+    fn list_sharded_directory() {
+        let blocks = FakeBlockstore::with_fixtures();
+
+        let block = blocks.get_by_str("QmZbFPTnDBMWbQ6iBxQAhuhLz8Nu9XptYS96e7cuf5wvbk");
+
+        let mut entries = Vec::new();
+        let mut cache = None;
+
+        let mut listing = list(block, &mut entries, &mut cache).unwrap();
+        assert!(entries.is_empty(), "root only links to buckets");
+
+        while let Some(walk) = listing {
+            let block = blocks.get_by_cid(walk.pending_links().0);
+            listing = walk.continue_walk(block, &mut entries, &mut cache).unwrap();
+        }
+
+        let mut names = entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        names.sort_unstable();
+
+        assert_eq!(names.len(), 16);
+        assert_eq!(names[0], "long-named-file-003");
+        assert_eq!(names[15], "long-named-file-058");
+
+        for entry in &entries {
+            assert_eq!(
+                entry.cid.to_string(),
+                "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH"
+            );
+            assert_eq!(entry.tsize, 6);
+        }
+    }
+
+    #[test]
+    fn list_plain_directory_and_file() {
+        let blocks = FakeBlockstore::with_fixtures();
+
+        let mut entries = Vec::new();
+        let block = blocks.get_by_str("QmVkvLsSEm2uJx1h5Fqukje8mMPYg393o5C2kMCkF2bBTA");
+        assert!(list(block, &mut entries, &mut None).unwrap().is_none());
+
+        let names = entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, &["foobar.balanced", "foobar.trickle"]);
+
+        entries.clear();
+        let block = blocks.get_by_str("QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6");
+        assert!(list(block, &mut entries, &mut None).unwrap().is_none());
+
+        assert_eq!(entries.len(), 4);
+        assert!(entries.iter().all(|e| e.name.is_empty()));
+    }
+}
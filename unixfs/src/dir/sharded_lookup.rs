@@ -1,16 +1,132 @@
 use super::{try_convert_cid, MaybeResolved, MultipleMatchingLinks, ResolveError};
-use crate::pb::{FlatUnixFs, PBLink, ParsingFailed, UnixFsType};
-use crate::{InvalidCidInLink, UnexpectedNodeType};
+use crate::pb::{FlatUnixFs, PBLink, ParsingFailed, UnixFs, UnixFsType};
+use crate::{InvalidCidInLink, Metadata, UnexpectedNodeType};
 use alloc::borrow::Cow;
 use alloc::collections::VecDeque;
 use cid::Cid;
 use core::convert::TryFrom;
 use core::fmt;
 
+/// The only supported HAMT fanout, same as go-ipfs uses.
+pub(crate) const HAMT_FANOUT: u64 = 256;
+
+/// The only supported HAMT hash type, murmur3-x64-64 as a multicodec.
+pub(crate) const HAMT_HASH_TYPE: u64 = 0x22;
+
+/// Returns the HAMT hash of an entry name, which is the first half of murmur3-x64-128 with zero
+/// seed like in go-ipfs. Starting from the most significant byte, every level of the shard uses
+/// the next byte as the bucket index.
+pub fn hamt_hash(name: &[u8]) -> u64 {
+    const C1: u64 = 0x87c3_7b91_1142_53d5;
+    const C2: u64 = 0x4cf5_ad43_2745_937f;
+
+    fn fmix64(mut k: u64) -> u64 {
+        k ^= k >> 33;
+        k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        k ^= k >> 33;
+        k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        k ^= k >> 33;
+        k
+    }
+
+    fn read_u64(bytes: &[u8]) -> u64 {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(bytes);
+        u64::from_le_bytes(buf)
+    }
+
+    let mut h1 = 0u64;
+    let mut h2 = 0u64;
+
+    let mut chunks = name.chunks_exact(16);
+
+    for chunk in &mut chunks {
+        let k1 = read_u64(&chunk[..8]);
+        let k2 = read_u64(&chunk[8..]);
+
+        h1 ^= k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+        h1 = h1
+            .rotate_left(27)
+            .wrapping_add(h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dc_e729);
+
+        h2 ^= k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+        h2 = h2
+            .rotate_left(31)
+            .wrapping_add(h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x3849_5ab5);
+    }
+
+    let tail = chunks.remainder();
+
+    if !tail.is_empty() {
+        let mut buf = [0u8; 16];
+        buf[..tail.len()].copy_from_slice(tail);
+
+        if tail.len() > 8 {
+            h2 ^= read_u64(&buf[8..])
+                .wrapping_mul(C2)
+                .rotate_left(33)
+                .wrapping_mul(C1);
+        }
+
+        h1 ^= read_u64(&buf[..8])
+            .wrapping_mul(C1)
+            .rotate_left(31)
+            .wrapping_mul(C2);
+    }
+
+    let len = name.len() as u64;
+    h1 ^= len;
+    h2 ^= len;
+
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+
+    h1 = fmix64(h1);
+    h2 = fmix64(h2);
+
+    h1.wrapping_add(h2)
+}
+
+/// Renders the UnixFs data of a HAMT shard node with the given buckets in use, such as when editing
+/// an existing shard. The metadata is only expected on the root node of the shard.
+pub fn hamt_shard_data(buckets: impl IntoIterator<Item = u8>, metadata: &Metadata) -> Vec<u8> {
+    use quick_protobuf::{MessageWrite, Writer};
+
+    let mut bitfield = [0u8; (HAMT_FANOUT / 8) as usize];
+    for index in buckets {
+        bitfield[bitfield.len() - 1 - index as usize / 8] |= 1 << (index % 8);
+    }
+
+    // the bitfield is a big endian number without the leading zeroes
+    let first_set = bitfield
+        .iter()
+        .position(|b| *b != 0)
+        .unwrap_or(bitfield.len());
+
+    let mut data = UnixFs {
+        Type: UnixFsType::HAMTShard,
+        Data: Some(Cow::Borrowed(&bitfield[first_set..])),
+        hashType: Some(HAMT_HASH_TYPE),
+        fanout: Some(HAMT_FANOUT),
+        ..Default::default()
+    };
+    metadata.apply(&mut data);
+
+    let mut out = Vec::with_capacity(data.get_size());
+    let mut writer = Writer::new(&mut out);
+    data.write_message(&mut writer)
+        .expect("unsure how this could fail");
+    out
+}
+
 /// A cache of data structures used while traversing. Reduces allocations when walking over multiple
 /// path segments.
 pub struct Cache {
-    buffer: VecDeque<Cid>,
+    pub(super) buffer: VecDeque<Cid>,
 }
 
 impl From<VecDeque<Cid>> for Cache {
@@ -140,7 +256,7 @@ impl<'needle> ShardedLookup<'needle> {
     pub(crate) fn check_supported(hamt: &mut FlatUnixFs<'_>) -> Result<(), ShardError> {
         assert_eq!(hamt.data.Type, UnixFsType::HAMTShard);
 
-        if hamt.data.fanout != Some(256) || hamt.data.hashType != Some(34) {
+        if hamt.data.fanout != Some(HAMT_FANOUT) || hamt.data.hashType != Some(HAMT_HASH_TYPE) {
             Err(ShardError::UnsupportedProperties {
                 hash_type: hamt.data.hashType,
                 fanout: hamt.data.fanout,
@@ -335,7 +451,7 @@ impl std::error::Error for LookupError {
 
 #[cfg(test)]
 mod tests {
-    use super::{LookupError, MaybeResolved, ShardError, ShardedLookup};
+    use super::{hamt_hash, LookupError, MaybeResolved, ShardError, ShardedLookup};
     use crate::pb::FlatUnixFs;
     use core::convert::TryFrom;
     use hex_literal::hex;
@@ -394,6 +510,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn hamt_hash_picks_the_fixture_buckets() {
+        assert_eq!(hamt_hash(b"hello"), 0xcbd8_a7b3_41bd_9b02);
+        assert_eq!(
+            hamt_hash(b"The quick brown fox jumps over the lazy dog"),
+            0xe34b_bc7b_bc07_1b6c
+        );
+
+        // the links of DIR are named by the most significant byte of the hash
+        for (name, bucket) in &[
+            ("doc", 0x6A),
+            ("Makefile", 0xCD),
+            ("bin", 0xF5),
+            ("formal", 0xB9),
+        ] {
+            assert_eq!(hamt_hash(name.as_bytes()) >> 56, *bucket, "{}", name);
+        }
+    }
+
     #[test]
     fn unsupported_hash_type_or_fanout() {
         use crate::pb::{FlatUnixFs, UnixFs, UnixFsType};
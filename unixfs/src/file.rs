@@ -321,6 +321,44 @@ pub(crate) mod tests {
         assert_eq!(&bytes[..], b"");
     }
 
+    #[test]
+    fn scoped_traversal_on_block_boundaries() {
+        use core::ops::Range;
+
+        let blocks = FakeBlockstore::with_fixtures();
+
+        let start = "QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6";
+
+        // the leaves are two bytes each, and the leaves only touching the range are not loaded
+        let cases: &[(Range<u64>, &[u8], usize)] = &[
+            (0..2, b"fo", 1),
+            (2..4, b"ob", 1),
+            (2..6, b"obar", 2),
+            (1..5, b"ooba", 3),
+        ];
+
+        for (range, expected, leaves) in cases {
+            let visit = IdleFileVisit::default().with_target_range(range.clone());
+            let (content, _, _, mut step) = visit.start(blocks.get_by_str(start)).unwrap();
+
+            let mut bytes = content.to_vec();
+            let mut loaded = 0;
+
+            while let Some(visit) = step {
+                let (first, _) = visit.pending_links();
+                let block = blocks.get_by_cid(first);
+
+                let (content, next_step) = visit.continue_walk(block, &mut None).unwrap();
+                bytes.extend(content);
+                loaded += 1;
+                step = next_step;
+            }
+
+            assert_eq!(&bytes[..], *expected, "{:?}", range);
+            assert_eq!(loaded, *leaves, "{:?}", range);
+        }
+    }
+
     #[test]
     fn trickle_traversal() {
         let blocks = FakeBlockstore::with_fixtures();
@@ -1,11 +1,14 @@
-use cid::Cid;
+use cid::{Cid, Codec, Version};
 
+use super::FileReadFailed;
 use crate::pb::{FlatUnixFs, PBLink, UnixFs, UnixFsType};
+use crate::{CidBuilder, InvalidCidInLink, Metadata};
 use alloc::borrow::Cow;
+use core::convert::TryFrom;
 use core::fmt;
 use quick_protobuf::{MessageWrite, Writer};
 
-use sha2::{Digest, Sha256};
+mod rolling;
 
 /// File tree builder. Implements [`core::default::Default`] which tracks the recent defaults.
 ///
@@ -13,13 +16,18 @@ use sha2::{Digest, Sha256};
 /// chunker and collector.
 ///
 /// Current implementation maintains an internal buffer for the block creation and uses a
-/// non-customizable hash function to produce Cid version 0 links. Currently does not support
-/// inline links.
-#[derive(Default)]
+/// non-customizable hash function to produce Cid version 0 links, unless configured to produce
+/// version 1 links, raw leaves or inline links.
 pub struct FileAdder {
     chunker: Chunker,
     collector: Collector,
+    raw_leaves: bool,
+    cids: CidBuilder,
+    metadata: Metadata,
     block_buffer: Vec<u8>,
+    // true when the block_buffer holds the first completed chunk, which is kept until more input
+    // arrives so that a file of a single chunk can have the metadata in the leaf.
+    holding_chunk: bool,
     // all unflushed links as a flat vec; this is compacted as we grow and need to create a link
     // block for the last N blocks, as decided by the collector.
     // FIXME: this is a cause of likely "accidentally quadratic" behavior visible when adding a
@@ -32,8 +40,11 @@ impl fmt::Debug for FileAdder {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             fmt,
-            "FileAdder {{ chunker: {:?}, block_buffer: {}/{}, unflushed_links: {} }}",
+            "FileAdder {{ chunker: {:?}, raw_leaves: {}, cids: {:?}, metadata: {:?}, block_buffer: {}/{}, unflushed_links: {} }}",
             self.chunker,
+            self.raw_leaves,
+            self.cids,
+            self.metadata,
             self.block_buffer.len(),
             self.block_buffer.capacity(),
             LinkFormatter(&self.unflushed_links),
@@ -110,15 +121,38 @@ impl fmt::Debug for Link {
 }
 
 /// Convenience type to facilitate configuring [`FileAdder`]s.
-#[derive(Default)]
 pub struct FileAdderBuilder {
     chunker: Chunker,
     collector: Collector,
+    raw_leaves: bool,
+    cid_version: Version,
+    inline_limit: Option<usize>,
+    metadata: Metadata,
+}
+
+impl Default for FileAdderBuilder {
+    fn default() -> Self {
+        FileAdderBuilder {
+            chunker: Chunker::default(),
+            collector: Collector::default(),
+            raw_leaves: false,
+            cid_version: Version::V0,
+            inline_limit: None,
+            metadata: Metadata::default(),
+        }
+    }
 }
 
 impl FileAdderBuilder {
     /// Configures the builder to use the given chunker.
+    ///
+    /// # Panics
+    ///
+    /// For [`Chunker::Rabin`] if the sizes are not in order or `min` is less than 64 bytes.
     pub fn with_chunker(self, chunker: Chunker) -> Self {
+        if let Chunker::Rabin { min, avg, max } = chunker {
+            assert!(min >= 64 && min <= avg && avg <= max);
+        }
         FileAdderBuilder { chunker, ..self }
     }
 
@@ -130,18 +164,66 @@ impl FileAdderBuilder {
         }
     }
 
+    /// Configures the builder to store the file contents as raw blocks, without the UnixFs
+    /// wrapping. Raw leaves always use Cid version 1.
+    pub fn with_raw_leaves(self, raw_leaves: bool) -> Self {
+        FileAdderBuilder { raw_leaves, ..self }
+    }
+
+    /// Configures the builder to use the given Cid version for the UnixFs blocks.
+    pub fn with_cid_version(self, cid_version: Version) -> Self {
+        FileAdderBuilder {
+            cid_version,
+            ..self
+        }
+    }
+
+    /// Configures the builder to inline the blocks up to `limit` bytes into the Cids using the
+    /// identity hash, similar to the go-ipfs `--inline`. Inlined blocks are still returned but
+    /// do not need to be stored.
+    pub fn with_inline_limit(self, limit: usize) -> Self {
+        FileAdderBuilder {
+            inline_limit: Some(limit),
+            ..self
+        }
+    }
+
+    /// Configures the builder to store the mode and the modification time in the root block of
+    /// the file. A file which would be a single raw leaf gets a new root block for the metadata.
+    pub fn with_metadata(self, metadata: Metadata) -> Self {
+        FileAdderBuilder { metadata, ..self }
+    }
+
     /// Returns a new FileAdder
     pub fn build(self) -> FileAdder {
-        let FileAdderBuilder { chunker, collector } = self;
+        let FileAdderBuilder {
+            chunker,
+            collector,
+            raw_leaves,
+            cid_version,
+            inline_limit,
+            metadata,
+        } = self;
 
         FileAdder {
             chunker,
             collector,
-            ..Default::default()
+            raw_leaves,
+            cids: CidBuilder::new(cid_version, inline_limit),
+            metadata,
+            block_buffer: Vec::new(),
+            holding_chunk: false,
+            unflushed_links: Vec::new(),
         }
     }
 }
 
+impl Default for FileAdder {
+    fn default() -> Self {
+        FileAdder::builder().build()
+    }
+}
+
 impl FileAdder {
     /// Returns a [`FileAdderBuilder`] for creating a non-default FileAdder.
     pub fn builder() -> FileAdderBuilder {
@@ -158,12 +240,17 @@ impl FileAdder {
 
     /// Called to push new file bytes into the tree builder.
     ///
-    /// Returns the newly created blocks (at most 2) and their respective Cids, and the amount of
+    /// Returns the newly created blocks (at most 3) and their respective Cids, and the amount of
     /// `input` consumed.
     pub fn push(&mut self, input: &[u8]) -> (impl Iterator<Item = (Cid, Vec<u8>)>, usize) {
+        let held = self.flush_held_chunk();
+
         let (accepted, ready) = self.chunker.accept(input, &self.block_buffer);
 
-        if self.block_buffer.is_empty() && ready {
+        // the first chunk is held when the metadata could be stored in it, if it is the only one
+        let hold = ready && !self.metadata.is_empty() && self.unflushed_links.is_empty();
+
+        if self.block_buffer.is_empty() && ready && !hold {
             // save single copy as the caller is giving us whole chunks.
             //
             // TODO: though, this path does make one question if there is any point in keeping
@@ -171,11 +258,21 @@ impl FileAdder {
             // blocks and user takes care of chunking (and buffering)?
             //
             // cat file | my_awesome_chunker | my_brilliant_collector
-            let leaf = Self::flush_buffered_leaf(accepted, &mut self.unflushed_links, false);
+            let leaf = Self::flush_buffered_leaf(
+                accepted,
+                &mut self.unflushed_links,
+                false,
+                self.collector.leaf_type(),
+                self.raw_leaves,
+                self.cids,
+            );
             assert!(leaf.is_some(), "chunk completed, must produce a new block");
             self.block_buffer.clear();
             let links = self.flush_buffered_links(false);
-            (leaf.into_iter().chain(links.into_iter()), accepted.len())
+            (
+                held.into_iter().chain(leaf).chain(links.into_iter()),
+                accepted.len(),
+            )
         } else {
             // slower path as we manage the buffer.
 
@@ -191,12 +288,18 @@ impl FileAdder {
             let (leaf, links) = if !ready {
                 // a new block did not become ready, which means we couldn't have gotten a new cid.
                 (None, Vec::new())
+            } else if hold {
+                self.holding_chunk = true;
+                (None, Vec::new())
             } else {
                 // a new leaf must be output, as well as possibly a new link block
                 let leaf = Self::flush_buffered_leaf(
                     self.block_buffer.as_slice(),
                     &mut self.unflushed_links,
                     false,
+                    self.collector.leaf_type(),
+                    self.raw_leaves,
+                    self.cids,
                 );
                 assert!(leaf.is_some(), "chunk completed, must produce a new block");
                 self.block_buffer.clear();
@@ -204,8 +307,65 @@ impl FileAdder {
 
                 (leaf, links)
             };
-            (leaf.into_iter().chain(links.into_iter()), written)
+            (
+                held.into_iter().chain(leaf).chain(links.into_iter()),
+                written,
+            )
+        }
+    }
+
+    /// Continues the file with an existing subtree of the given height, zero being a leaf. This
+    /// allows appending to a file by pushing the subtrees along the right edge of the previous
+    /// tree from the highest to the lowest, followed by the bytes of the last leaf and then the
+    /// new bytes, reusing all of the other blocks of the previous tree.
+    ///
+    /// Returns the newly created blocks, if any.
+    ///
+    /// # Panics
+    ///
+    /// When bytes have been pushed since the last completed block.
+    pub fn push_subtree(
+        &mut self,
+        height: usize,
+        target: Cid,
+        total_size: u64,
+        file_size: u64,
+    ) -> impl Iterator<Item = (Cid, Vec<u8>)> {
+        let held = self.flush_held_chunk();
+
+        assert!(
+            self.block_buffer.is_empty(),
+            "subtrees can only be pushed between blocks"
+        );
+
+        self.unflushed_links.push(Link {
+            depth: height,
+            target,
+            total_size,
+            file_size,
+        });
+
+        held.into_iter()
+            .chain(self.flush_buffered_links(false).into_iter())
+    }
+
+    /// Creates the leaf for the held first chunk once it is known not to be the only one.
+    fn flush_held_chunk(&mut self) -> Option<(Cid, Vec<u8>)> {
+        if !self.holding_chunk {
+            return None;
         }
+
+        self.holding_chunk = false;
+        let leaf = Self::flush_buffered_leaf(
+            self.block_buffer.as_slice(),
+            &mut self.unflushed_links,
+            false,
+            self.collector.leaf_type(),
+            self.raw_leaves,
+            self.cids,
+        );
+        self.block_buffer.clear();
+        leaf
     }
 
     /// Called after the last [`FileAdder::push`] to finish the tree construction.
@@ -219,10 +379,63 @@ impl FileAdder {
             &self.block_buffer.as_slice(),
             &mut self.unflushed_links,
             true,
+            self.collector.leaf_type(),
+            self.raw_leaves,
+            self.cids,
         );
         let root_links = self.flush_buffered_links(true);
         // should probably error if there is neither?
-        last_leaf.into_iter().chain(root_links.into_iter())
+        let mut blocks = last_leaf
+            .into_iter()
+            .chain(root_links.into_iter())
+            .collect::<Vec<_>>();
+
+        if !self.metadata.is_empty() {
+            let root = self
+                .unflushed_links
+                .pop()
+                .expect("there is always a root link when finishing");
+
+            let rendered_last = blocks.last().map(|(cid, _)| cid) == Some(&root.target);
+
+            let block = if rendered_last && root.target.codec() == Codec::DagProtobuf {
+                let (_, block) = blocks.pop().unwrap();
+                let mut flat = FlatUnixFs::try_from(block.as_slice())
+                    .expect("the root was just rendered as unixfs");
+                self.metadata.apply(&mut flat.data);
+                render_and_hash(&flat, self.cids)
+            } else {
+                // the root has been returned already or is a raw leaf; link to it from a new root
+                let mut links = Vec::with_capacity(1);
+                let mut blocksizes = Vec::with_capacity(1);
+                let mut nested_size = 0;
+                let mut nested_total_size = 0;
+
+                partition_link(
+                    &root,
+                    &mut links,
+                    &mut blocksizes,
+                    &mut nested_size,
+                    &mut nested_total_size,
+                );
+
+                let mut flat = FlatUnixFs {
+                    links,
+                    data: UnixFs {
+                        Type: UnixFsType::File,
+                        filesize: Some(nested_size),
+                        blocksizes,
+                        ..Default::default()
+                    },
+                };
+                self.metadata.apply(&mut flat.data);
+                render_and_hash(&flat, self.cids)
+            };
+
+            blocks.push(block);
+        }
+
+        blocks.into_iter()
     }
 
     /// Returns `None` when the input is empty but there are links, otherwise a new Cid and a
@@ -231,11 +444,27 @@ impl FileAdder {
         input: &[u8],
         unflushed_links: &mut Vec<Link>,
         finishing: bool,
+        leaf_type: UnixFsType,
+        raw_leaves: bool,
+        cids: CidBuilder,
     ) -> Option<(Cid, Vec<u8>)> {
         if input.is_empty() && (!finishing || !unflushed_links.is_empty()) {
             return None;
         }
 
+        if raw_leaves {
+            let cid = cids.sum(Codec::Raw, input);
+
+            unflushed_links.push(Link {
+                depth: 0,
+                target: cid.clone(),
+                total_size: input.len() as u64,
+                file_size: input.len() as u64,
+            });
+
+            return Some((cid, input.to_vec()));
+        }
+
         // for empty unixfs file the bytes is missing but filesize is present.
 
         let data = if !input.is_empty() {
@@ -246,10 +475,17 @@ impl FileAdder {
 
         let filesize = Some(input.len() as u64);
 
+        // the empty file is always a file, regardless of the collector
+        let leaf_type = if input.is_empty() {
+            UnixFsType::File
+        } else {
+            leaf_type
+        };
+
         let inner = FlatUnixFs {
             links: Vec::new(),
             data: UnixFs {
-                Type: UnixFsType::File,
+                Type: leaf_type,
                 Data: data,
                 filesize,
                 // no blocksizes as there are no links
@@ -257,7 +493,7 @@ impl FileAdder {
             },
         };
 
-        let (cid, vec) = render_and_hash(&inner);
+        let (cid, vec) = render_and_hash(&inner, cids);
 
         let total_size = vec.len();
 
@@ -275,7 +511,7 @@ impl FileAdder {
 
     fn flush_buffered_links(&mut self, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
         self.collector
-            .flush_links(&mut self.unflushed_links, finishing)
+            .flush_links(&mut self.unflushed_links, finishing, self.cids)
     }
 
     /// Test helper for collecting all of the produced blocks; probably not a good idea outside
@@ -307,7 +543,78 @@ impl FileAdder {
     }
 }
 
-fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
+/// The contents of a node of an existing file tree, as read by [`read_file_node`].
+#[derive(Debug)]
+pub enum FileNode<'a> {
+    /// A leaf with the bytes of the file.
+    Leaf(&'a [u8]),
+    /// A link block with the `Cid`, the total size and the file size of each linked subtree.
+    Links(Vec<(Cid, u64, u64)>),
+}
+
+/// Reads a dag-pb node of an existing file tree, such as when following the right edge of the
+/// tree for [`FileAdder::push_subtree`]. Raw leaves are the bytes of the file as is and need no
+/// reading.
+pub fn read_file_node(block: &[u8]) -> Result<FileNode<'_>, FileReadFailed> {
+    use super::reader::{FileContent, FileReader};
+
+    let (content, _) = FileReader::from_block(block)?.content();
+
+    match content {
+        FileContent::Bytes(bytes) => Ok(FileNode::Leaf(bytes)),
+        FileContent::Links(links) => links
+            .enumerate()
+            .map(|(nth, (link, range))| {
+                let total_size = link.Tsize.unwrap_or(0);
+                let hash = link.Hash.as_deref().unwrap_or_default();
+                let cid = Cid::try_from(hash).map_err(|e| {
+                    FileReadFailed::InvalidCid(InvalidCidInLink::from((nth, link, e)))
+                })?;
+                Ok((cid, total_size, range.end - range.start))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(FileNode::Links),
+    }
+}
+
+/// Renders a dag-pb node of an existing file tree again with the content replaced, keeping the
+/// metadata and the sizes recorded in the node. This allows overwriting a part of a file by
+/// creating only the leaves overlapping the written range and the link blocks above them.
+///
+/// For a leaf the bytes replace the previous bytes of the leaf; for a link block the `Cid` and
+/// the total size of every link are replaced, in order.
+///
+/// # Panics
+///
+/// When the content is of other kind than the node, when the leaf bytes are not as many as
+/// before, or when the link block would get a different number of links.
+pub fn rewrite_file_node(
+    block: &[u8],
+    content: FileNode<'_>,
+    cid_version: Version,
+) -> Result<(Cid, Vec<u8>), FileReadFailed> {
+    let mut flat = FlatUnixFs::try_from(block)?;
+
+    match content {
+        FileNode::Leaf(bytes) => {
+            let previous = flat.data.Data.as_deref().unwrap_or_default();
+            assert_eq!(previous.len(), bytes.len(), "leaf length cannot change");
+            assert!(flat.links.is_empty(), "only leaves have bytes");
+            flat.data.Data = Some(Cow::Borrowed(bytes));
+        }
+        FileNode::Links(links) => {
+            assert_eq!(flat.links.len(), links.len(), "link count cannot change");
+            for (link, (cid, total_size, _)) in flat.links.iter_mut().zip(links) {
+                link.Hash = Some(Cow::Owned(cid.to_bytes()));
+                link.Tsize = Some(total_size);
+            }
+        }
+    }
+
+    Ok(render_and_hash(&flat, CidBuilder::new(cid_version, None)))
+}
+
+fn render_and_hash(flat: &FlatUnixFs<'_>, cids: CidBuilder) -> (Cid, Vec<u8>) {
     // TODO: as shown in later dagger we don't really need to render the FlatUnixFs fully; we could
     // either just render a fixed header and continue with the body OR links, though the links are
     // a bit more complicated.
@@ -315,8 +622,7 @@ fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
     let mut writer = Writer::new(&mut out);
     flat.write_message(&mut writer)
         .expect("unsure how this could fail");
-    let mh = multihash::wrap(multihash::Code::Sha2_256, &Sha256::digest(&out));
-    let cid = Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0");
+    let cid = cids.sum(Codec::DagProtobuf, &out);
     (cid, out)
 }
 
@@ -325,6 +631,20 @@ fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
 pub enum Chunker {
     /// Size based chunking
     Size(usize),
+    /// Content defined chunking with the rabin fingerprint, compatible with the go-ipfs rabin
+    /// chunker. Chunks are cut when the fingerprint of the last 64 bytes has zeroes as the
+    /// lowest bits, as many as there are in `avg` rounded down to a power of two.
+    Rabin {
+        /// The smallest chunk size, except for the last one. At least 64.
+        min: usize,
+        /// Targeted average chunk size.
+        avg: usize,
+        /// The largest chunk size.
+        max: usize,
+    },
+    /// Content defined chunking with buzhash, with the chunk sizes ranging from 128 KiB to 512
+    /// KiB. Note that the hash table differs from the go-ipfs one, so the chunks will differ.
+    Buzhash,
 }
 
 impl Default for Chunker {
@@ -335,6 +655,15 @@ impl Default for Chunker {
 }
 
 impl Chunker {
+    /// Returns the rabin chunker with the defaults of go-ipfs for the given average size.
+    pub fn rabin(avg: usize) -> Self {
+        Chunker::Rabin {
+            min: avg / 3,
+            avg,
+            max: avg + avg / 2,
+        }
+    }
+
     fn accept<'a>(&mut self, input: &'a [u8], buffered: &[u8]) -> (&'a [u8], bool) {
         use Chunker::*;
 
@@ -345,6 +674,14 @@ impl Chunker {
                 let ready = buffered.len() + l >= *max;
                 (accepted, ready)
             }
+            Rabin { min, avg, max } => {
+                let (l, ready) = rolling::rabin_cut(input, buffered, *min, *avg, *max);
+                (&input[..l], ready)
+            }
+            Buzhash => {
+                let (l, ready) = rolling::buzhash_cut(input, buffered);
+                (&input[..l], ready)
+            }
         }
     }
 
@@ -353,18 +690,22 @@ impl Chunker {
 
         match self {
             Size(max) => *max,
+            Rabin { max, .. } => *max,
+            Buzhash => rolling::BUZHASH_MAX,
         }
     }
 }
 
 /// Collector or layout strategy. For more information, see the [Layout section of the spec].
-/// Currently only the default balanced collector/layout has been implemented.
+/// The default is the balanced collector/layout.
 ///
 /// [Layout section of the spec]: https://github.com/ipfs/specs/blob/master/UNIXFS.md#layout
 #[derive(Debug, Clone)]
 pub enum Collector {
     /// Balanced trees.
     Balanced(BalancedCollector),
+    /// Trickle trees.
+    Trickle(TrickleCollector),
 }
 
 impl Default for Collector {
@@ -374,11 +715,27 @@ impl Default for Collector {
 }
 
 impl Collector {
-    fn flush_links(&mut self, pending: &mut Vec<Link>, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
+    /// The UnixFs type of the leaf blocks, which go-ipfs uses differently for the layouts.
+    fn leaf_type(&self) -> UnixFsType {
+        use Collector::*;
+
+        match self {
+            Balanced(_) => UnixFsType::File,
+            Trickle(_) => UnixFsType::Raw,
+        }
+    }
+
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        finishing: bool,
+        cids: CidBuilder,
+    ) -> Vec<(Cid, Vec<u8>)> {
         use Collector::*;
 
         match self {
-            Balanced(bc) => bc.flush_links(pending, finishing),
+            Balanced(bc) => bc.flush_links(pending, finishing, cids),
+            Trickle(tc) => tc.flush_links(pending, finishing, cids),
         }
     }
 }
@@ -438,7 +795,12 @@ impl BalancedCollector {
     /// In-place compression of the `pending` links to a balanced hierarchy. When `finishing`, the
     /// links will be compressed iteratively from the lowest level to produce a single root link
     /// block.
-    fn flush_links(&mut self, pending: &mut Vec<Link>, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        finishing: bool,
+        cids: CidBuilder,
+    ) -> Vec<(Cid, Vec<u8>)> {
         /*
 
         file    |- - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -|
@@ -557,7 +919,7 @@ impl BalancedCollector {
                         index + first_at
                     );
 
-                    Self::partition_link(
+                    partition_link(
                         link,
                         &mut reused_links,
                         &mut reused_blocksizes,
@@ -578,7 +940,7 @@ impl BalancedCollector {
                     },
                 };
 
-                let (cid, vec) = render_and_hash(&inner);
+                let (cid, vec) = render_and_hash(&inner, cids);
 
                 // start overwriting at the first index of this level, then continue forward on
                 // next iterations.
@@ -619,31 +981,254 @@ impl BalancedCollector {
 
         ret
     }
+}
+
+/// Each link needs to be partitioned into the four mut arguments received by this function in
+/// order to produce the expected UnixFs output.
+fn partition_link(
+    link: &Link,
+    links: &mut Vec<PBLink<'static>>,
+    blocksizes: &mut Vec<u64>,
+    nested_size: &mut u64,
+    nested_total_size: &mut u64,
+) {
+    links.push(PBLink {
+        Hash: Some(link.target.to_bytes().into()),
+        Name: Some("".into()),
+        Tsize: Some(link.total_size),
+    });
+    blocksizes.push(link.file_size);
+    *nested_size += link.file_size;
+    *nested_total_size += link.total_size;
+}
+
+/// TrickleCollector creates trickle trees, most optimized for reading the file sequentially and
+/// appending to it. The layout is the same as in go-ipfs: every node first links up to the
+/// branching factor of leaves, followed by four subtrees of depth one, four subtrees of depth two
+/// and so on. The subtrees have the same structure, up to their depth.
+#[derive(Clone)]
+pub struct TrickleCollector {
+    branching_factor: usize,
+    // the nodes under construction, from the root to the deepest one
+    frames: Vec<TrickleFrame>,
+    // the number of links in pending which have been placed in the frames
+    placed: usize,
+}
+
+/// A trickle node under construction.
+#[derive(Clone, Copy)]
+struct TrickleFrame {
+    /// Index of the first link of this node in the pending links.
+    start: usize,
+    /// The maximum depth of the subtrees of this node, unlimited for the root.
+    max_depth: Option<usize>,
+    /// The depth of the subtrees being added or zero when adding leaves.
+    depth: usize,
+    /// The number of subtrees of `depth` added so far.
+    repeats: usize,
+}
+
+impl TrickleFrame {
+    fn new(start: usize, max_depth: Option<usize>) -> Self {
+        TrickleFrame {
+            start,
+            max_depth,
+            depth: 0,
+            repeats: 0,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        match self.max_depth {
+            Some(max_depth) => self.depth > 0 && self.depth >= max_depth,
+            None => false,
+        }
+    }
+}
+
+impl fmt::Debug for TrickleCollector {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "TrickleCollector {{ branching_factor: {} }}",
+            self.branching_factor
+        )
+    }
+}
 
-    /// Each link needs to be partitioned into the four mut arguments received by this function in
-    /// order to produce the expected UnixFs output.
-    fn partition_link(
-        link: &Link,
-        links: &mut Vec<PBLink<'static>>,
-        blocksizes: &mut Vec<u64>,
-        nested_size: &mut u64,
-        nested_total_size: &mut u64,
+impl Default for TrickleCollector {
+    /// Returns a default collector which matches go-ipfs 0.6
+    fn default() -> Self {
+        Self::with_branching_factor(174)
+    }
+}
+
+impl From<TrickleCollector> for Collector {
+    fn from(t: TrickleCollector) -> Self {
+        Collector::Trickle(t)
+    }
+}
+
+impl TrickleCollector {
+    /// The number of subtrees of each depth in every node.
+    const LAYER_REPEAT: usize = 4;
+
+    /// Configure Trickle collector with the given branching factor, which is the number of leaves
+    /// in every node.
+    pub fn with_branching_factor(branching_factor: usize) -> Self {
+        assert!(branching_factor > 0);
+
+        Self {
+            branching_factor,
+            frames: Vec::new(),
+            placed: 0,
+        }
+    }
+
+    /// Places the new leaves at the end of `pending` to the tree, replacing the links of the
+    /// completed nodes with a link to the new link block. When `finishing`, all of the nodes are
+    /// completed, leaving only the root link.
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        finishing: bool,
+        cids: CidBuilder,
+    ) -> Vec<(Cid, Vec<u8>)> {
+        let mut ret = Vec::new();
+
+        let leaves = pending.split_off(self.placed);
+
+        for leaf in leaves {
+            self.place_leaf(pending, leaf, cids, &mut ret);
+        }
+
+        if finishing {
+            let single_empty_leaf = self.frames.len() == 1
+                && pending.len() == 1
+                && pending[0].depth == 0
+                && pending[0].file_size == 0;
+
+            if single_empty_leaf {
+                // go-ipfs creates a root without any links for an empty file, which is the same
+                // block as the empty leaf unless it is a raw leaf.
+                self.frames.clear();
+
+                if pending[0].target.codec() == Codec::Raw {
+                    pending.clear();
+                    ret.push(Self::render(pending, 0, cids));
+                }
+            }
+
+            while let Some(frame) = self.frames.pop() {
+                ret.push(Self::render(pending, frame.start, cids));
+            }
+        }
+
+        self.placed = pending.len();
+
+        ret
+    }
+
+    fn place_leaf(
+        &mut self,
+        pending: &mut Vec<Link>,
+        leaf: Link,
+        cids: CidBuilder,
+        ret: &mut Vec<(Cid, Vec<u8>)>,
     ) {
-        links.push(PBLink {
-            Hash: Some(link.target.to_bytes().into()),
-            Name: Some("".into()),
-            Tsize: Some(link.total_size),
+        if self.frames.is_empty() {
+            self.frames.push(TrickleFrame::new(pending.len(), None));
+        }
+
+        // the leaf starts new subtrees until reaching one which is still adding leaves
+        while let Some(max_depth) = self.frames.last().map(|f| f.depth).filter(|&d| d > 0) {
+            self.frames
+                .push(TrickleFrame::new(pending.len(), Some(max_depth)));
+        }
+
+        pending.push(leaf);
+
+        let frame = self.frames.last_mut().expect("there is always a frame");
+
+        if pending.len() - frame.start == self.branching_factor {
+            frame.depth = 1;
+            self.complete_frames(pending, cids, ret);
+        }
+    }
+
+    /// Renders the complete nodes, adding them to their parents as subtrees.
+    fn complete_frames(
+        &mut self,
+        pending: &mut Vec<Link>,
+        cids: CidBuilder,
+        ret: &mut Vec<(Cid, Vec<u8>)>,
+    ) {
+        while let Some(frame) = self.frames.last() {
+            if !frame.is_complete() {
+                break;
+            }
+
+            let start = frame.start;
+            self.frames.pop();
+            ret.push(Self::render(pending, start, cids));
+
+            if let Some(parent) = self.frames.last_mut() {
+                parent.repeats += 1;
+                if parent.repeats == Self::LAYER_REPEAT {
+                    parent.depth += 1;
+                    parent.repeats = 0;
+                }
+            }
+        }
+    }
+
+    /// Creates the link block out of the links starting from `start`, replacing them with the
+    /// link to the new block.
+    fn render(pending: &mut Vec<Link>, start: usize, cids: CidBuilder) -> (Cid, Vec<u8>) {
+        let mut links = Vec::with_capacity(pending.len() - start);
+        let mut blocksizes = Vec::with_capacity(pending.len() - start);
+        let mut nested_size = 0;
+        let mut nested_total_size = 0;
+        let mut depth = 0;
+
+        for link in pending.drain(start..) {
+            partition_link(
+                &link,
+                &mut links,
+                &mut blocksizes,
+                &mut nested_size,
+                &mut nested_total_size,
+            );
+            depth = depth.max(link.depth + 1);
+        }
+
+        let inner = FlatUnixFs {
+            links,
+            data: UnixFs {
+                Type: UnixFsType::File,
+                filesize: Some(nested_size),
+                blocksizes,
+                ..Default::default()
+            },
+        };
+
+        let (cid, vec) = render_and_hash(&inner, cids);
+
+        pending.push(Link {
+            depth,
+            target: cid.clone(),
+            total_size: nested_total_size + vec.len() as u64,
+            file_size: nested_size,
         });
-        blocksizes.push(link.file_size);
-        *nested_size += link.file_size;
-        *nested_total_size += link.total_size;
+
+        (cid, vec)
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use super::{BalancedCollector, Chunker, FileAdder};
+    use super::{BalancedCollector, Chunker, FileAdder, TrickleCollector};
     use crate::test_support::FakeBlockstore;
     use cid::Cid;
     use core::convert::TryFrom;
@@ -668,6 +1253,420 @@ mod tests {
         (accepted.len(), ready)
     }
 
+    #[test]
+    fn content_defined_chunks_do_not_depend_on_pushes() {
+        let mut state = 0x2545_f491u32;
+        let content = (0..1_500_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+
+        for chunker in &[Chunker::rabin(4096), Chunker::Buzhash] {
+            let roots = [1000, 65536, content.len()]
+                .iter()
+                .map(|&step| {
+                    let mut adder = FileAdder::builder().with_chunker(chunker.clone()).build();
+                    let mut leaves = 0;
+                    for piece in content.chunks(step) {
+                        let mut written = 0;
+                        while written < piece.len() {
+                            let (blocks, used) = adder.push(&piece[written..]);
+                            leaves += blocks.count();
+                            written += used;
+                        }
+                    }
+                    let root = adder.finish().last().unwrap().0;
+                    (root, leaves)
+                })
+                .collect::<Vec<_>>();
+
+            assert!(roots[0].1 > 1, "{:?} produced a single chunk", chunker);
+            assert!(roots.iter().all(|r| r == &roots[0]), "{:?}", chunker);
+        }
+    }
+
+    #[test]
+    fn trickle_layout() {
+        use crate::file::visit::IdleFileVisit;
+        use crate::pb::FlatUnixFs;
+        use std::collections::HashMap;
+
+        // two bytes per leaf, all different
+        let content = (0..11u16)
+            .flat_map(|i| i.to_be_bytes().to_vec())
+            .collect::<Vec<_>>();
+
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_collector(TrickleCollector::with_branching_factor(2))
+            .build();
+
+        let blocks = adder.collect_blocks(&content, 3);
+
+        // 11 leaves, four subtrees of depth one, one of depth two and the root
+        assert_eq!(blocks.len(), 11 + 4 + 1 + 1);
+
+        let (root, root_block) = blocks.last().unwrap();
+        let root_node = FlatUnixFs::try_from(root_block.as_slice()).unwrap();
+        assert_eq!(root_node.links.len(), 2 + 4 + 1);
+        assert_eq!(root_node.data.filesize, Some(content.len() as u64));
+        assert_eq!(root_node.data.blocksizes, vec![2, 2, 4, 4, 4, 4, 2]);
+
+        let blocks = blocks.iter().cloned().collect::<HashMap<_, _>>();
+
+        let mut read = Vec::<u8>::new();
+        let (first, _, _, mut step) = IdleFileVisit::default().start(&blocks[root]).unwrap();
+        read.extend(first);
+
+        while let Some(visit) = step {
+            let (next, _) = visit.pending_links();
+            let block = &blocks[next];
+            let (bytes, next_step) = visit.continue_walk(block, &mut None).unwrap();
+            read.extend(bytes);
+            step = next_step;
+        }
+
+        assert_eq!(read, content);
+    }
+
+    #[test]
+    fn trickle_matches_go_ipfs() {
+        let blocks = FakeBlockstore::with_fixtures();
+
+        // the same "foobar\n" as go-ipfs 0.5 add --trickle -s size-2
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_collector(TrickleCollector::default())
+            .build();
+
+        let produced = adder.collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(produced.len(), 5);
+
+        for (cid, block) in &produced {
+            assert_eq!(blocks.get_by_cid(cid), block.as_slice());
+        }
+
+        assert_eq!(
+            produced.last().unwrap().0.to_string(),
+            "QmWfQ48ChJUj4vWKFsUDe4646xCBmXgdmNfhjz9T7crywd"
+        );
+    }
+
+    #[test]
+    fn trickle_single_block_file_is_wrapped() {
+        use crate::pb::FlatUnixFs;
+
+        let adder = FileAdder::builder()
+            .with_collector(TrickleCollector::default())
+            .build();
+
+        let blocks = adder.collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 2);
+
+        let root = FlatUnixFs::try_from(blocks[1].1.as_slice()).unwrap();
+        assert_eq!(root.links.len(), 1);
+        assert_eq!(
+            root.links[0].Hash.as_deref(),
+            Some(blocks[0].0.to_bytes().as_slice())
+        );
+    }
+
+    #[test]
+    fn trickle_empty_file() {
+        let adder = FileAdder::builder()
+            .with_collector(TrickleCollector::default())
+            .build();
+
+        let blocks = adder.collect_blocks(&[], 0);
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].1.as_slice(), &hex!("0a 04 08 02 18 00"));
+    }
+
+    #[test]
+    fn raw_leaves_single_block_file() {
+        let adder = FileAdder::builder().with_raw_leaves(true).build();
+
+        let blocks = adder.collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            blocks[0].0.to_string(),
+            "bafkreifoybygix7fh3r3g5rqle3wcnhqldgdg4shzf4k3ulyw3gn7mabt4"
+        );
+        assert_eq!(blocks[0].1.as_slice(), b"foobar\n");
+    }
+
+    #[test]
+    fn rewritten_nodes_match_a_new_tree() {
+        use super::{read_file_node, rewrite_file_node, FileNode};
+        use crate::Metadata;
+
+        let build = |content: &[u8]| {
+            FileAdder::builder()
+                .with_chunker(Chunker::Size(3))
+                .with_metadata(Metadata::default().with_mode(0o644))
+                .build()
+                .collect_blocks(content, 0)
+        };
+
+        let blocks = build(b"foobarbaz");
+        let expected = build(b"foobXrbaz");
+        assert_eq!(blocks.len(), 4);
+
+        // the second leaf and the root change
+        let leaf = match read_file_node(&blocks[1].1).unwrap() {
+            FileNode::Leaf(bytes) => bytes,
+            x => panic!("unexpected {:?}", x),
+        };
+        assert_eq!(leaf, b"bar");
+        let (leaf_cid, leaf_block) =
+            rewrite_file_node(&blocks[1].1, FileNode::Leaf(b"bXr"), cid::Version::V0).unwrap();
+        assert_eq!((&leaf_cid, &leaf_block), (&expected[1].0, &expected[1].1));
+
+        let mut links = match read_file_node(&blocks[3].1).unwrap() {
+            FileNode::Links(links) => links,
+            x => panic!("unexpected {:?}", x),
+        };
+        links[1].0 = leaf_cid;
+        links[1].1 = leaf_block.len() as u64;
+        let root =
+            rewrite_file_node(&blocks[3].1, FileNode::Links(links), cid::Version::V0).unwrap();
+        assert_eq!(root, expected[3]);
+    }
+
+    #[test]
+    fn raw_leaves_multi_block_file() {
+        use crate::file::visit::IdleFileVisit;
+        use crate::pb::FlatUnixFs;
+        use std::collections::HashMap;
+
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_raw_leaves(true)
+            .build();
+
+        let blocks = adder.collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 5);
+        assert!(blocks[..4]
+            .iter()
+            .all(|(cid, _)| cid.codec() == cid::Codec::Raw));
+
+        let (root, root_block) = blocks.last().unwrap();
+        assert_eq!(root.version(), cid::Version::V0);
+
+        let root_node = FlatUnixFs::try_from(root_block.as_slice()).unwrap();
+        assert_eq!(root_node.data.blocksizes, vec![2, 2, 2, 1]);
+        assert_eq!(
+            root_node
+                .links
+                .iter()
+                .map(|link| link.Tsize)
+                .collect::<Vec<_>>(),
+            vec![Some(2), Some(2), Some(2), Some(1)]
+        );
+
+        let blocks = blocks.iter().cloned().collect::<HashMap<_, _>>();
+
+        let mut read = Vec::<u8>::new();
+        let (first, _, _, mut step) = IdleFileVisit::default().start(&blocks[root]).unwrap();
+        read.extend(first);
+
+        while let Some(visit) = step {
+            let (next, _) = visit.pending_links();
+            let block = &blocks[next];
+            let (bytes, next_step) = visit.continue_walk(block, &mut None).unwrap();
+            read.extend(bytes);
+            step = next_step;
+        }
+
+        assert_eq!(read, b"foobar\n");
+    }
+
+    #[test]
+    fn cid_version_1() {
+        let blocks = FakeBlockstore::with_fixtures();
+
+        let adder = FileAdder::builder()
+            .with_cid_version(cid::Version::V1)
+            .build();
+
+        let produced = adder.collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(produced.len(), 1);
+
+        let (cid, block) = &produced[0];
+        assert_eq!(cid.version(), cid::Version::V1);
+        assert_eq!(cid.codec(), cid::Codec::DagProtobuf);
+        assert_eq!(
+            blocks.get_by_str("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL"),
+            block.as_slice()
+        );
+    }
+
+    #[test]
+    fn inlined_leaves() {
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_raw_leaves(true)
+            .with_inline_limit(2)
+            .build();
+
+        let blocks = adder.collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 5);
+
+        for (cid, block) in &blocks[..4] {
+            assert_eq!(cid.hash().algorithm(), multihash::Code::Identity);
+            assert_eq!(cid.hash().digest(), block.as_slice());
+        }
+
+        let (root, _) = blocks.last().unwrap();
+        assert_eq!(root.hash().algorithm(), multihash::Code::Sha2_256);
+        assert_eq!(root.version(), cid::Version::V0);
+    }
+
+    #[test]
+    fn metadata_in_root() {
+        use crate::pb::FlatUnixFs;
+        use crate::Metadata;
+
+        let metadata = Metadata::default()
+            .with_mode(0o755)
+            .with_mtime(1_600_000_000, 42);
+
+        for &raw_leaves in &[false, true] {
+            let adder = FileAdder::builder()
+                .with_chunker(Chunker::Size(2))
+                .with_raw_leaves(raw_leaves)
+                .with_metadata(metadata.clone())
+                .build();
+
+            let blocks = adder.collect_blocks(b"foobar\n", 0);
+
+            let (_, root) = blocks.last().unwrap();
+            let flat = FlatUnixFs::try_from(root.as_slice()).unwrap();
+
+            assert_eq!(Metadata::from(&flat.data), metadata);
+            assert_eq!(flat.data.filesize, Some(7));
+        }
+
+        // single leaves are rewritten or wrapped in a new root
+        for &raw_leaves in &[false, true] {
+            let adder = FileAdder::builder()
+                .with_raw_leaves(raw_leaves)
+                .with_metadata(metadata.clone())
+                .build();
+
+            let blocks = adder.collect_blocks(b"foobar\n", 0);
+
+            assert_eq!(blocks.len(), if raw_leaves { 2 } else { 1 });
+
+            let (_, root) = blocks.last().unwrap();
+            let flat = FlatUnixFs::try_from(root.as_slice()).unwrap();
+
+            assert_eq!(Metadata::from(&flat.data), metadata);
+            assert_eq!(flat.data.filesize, Some(7));
+        }
+
+        // a single leaf of exactly the chunk size is completed before the end of the input, but
+        // still has the metadata in it, whether pushed at once or in pieces
+        for &amt in &[0, 3] {
+            let adder = FileAdder::builder()
+                .with_chunker(Chunker::Size(7))
+                .with_metadata(metadata.clone())
+                .build();
+
+            let blocks = adder.collect_blocks(b"foobar\n", amt);
+
+            assert_eq!(blocks.len(), 1, "pushed {} at a time", amt);
+
+            let flat = FlatUnixFs::try_from(blocks[0].1.as_slice()).unwrap();
+
+            assert_eq!(Metadata::from(&flat.data), metadata);
+            assert_eq!(flat.data.Data.as_deref(), Some(&b"foobar\n"[..]));
+            assert!(flat.links.is_empty());
+        }
+
+        // the held chunk is not lost when more input follows
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(7))
+            .with_metadata(metadata.clone())
+            .build();
+
+        let blocks = adder.collect_blocks(b"foobar\nfoobar\n", 7);
+
+        assert_eq!(blocks.len(), 3);
+        let flat = FlatUnixFs::try_from(blocks[2].1.as_slice()).unwrap();
+        assert_eq!(Metadata::from(&flat.data), metadata);
+        assert_eq!(flat.data.filesize, Some(14));
+        assert_eq!(flat.links.len(), 2);
+    }
+
+    #[test]
+    fn appending_with_subtrees() {
+        use super::{read_file_node, FileNode};
+        use std::collections::HashMap;
+
+        let content = (0..100u8).collect::<Vec<_>>();
+
+        let adder = || {
+            FileAdder::builder()
+                .with_chunker(Chunker::Size(2))
+                .with_collector(BalancedCollector::with_branching_factor(3))
+                .build()
+        };
+
+        // three levels of links with the last leaf of a single byte
+        let previous = adder().collect_blocks(&content[..41], 0);
+        let expected = adder().collect_blocks(&content, 0);
+
+        let (root, _) = previous.last().unwrap();
+        let previous = previous.iter().cloned().collect::<HashMap<_, _>>();
+
+        // the links before the last one on each level of the right edge
+        let mut edge = Vec::new();
+        let mut block = &previous[root];
+        let last_leaf = loop {
+            match read_file_node(block).unwrap() {
+                FileNode::Leaf(bytes) => break bytes.to_vec(),
+                FileNode::Links(mut links) => {
+                    let (last, _, _) = links.pop().unwrap();
+                    edge.push(links);
+                    block = &previous[&last];
+                }
+            }
+        };
+
+        assert_eq!(edge.len(), 3);
+
+        let mut adder = adder();
+        let mut blocks = Vec::new();
+
+        let height = edge.len();
+        for (level, links) in edge.into_iter().enumerate() {
+            for (cid, total_size, file_size) in links {
+                blocks.extend(adder.push_subtree(height - level - 1, cid, total_size, file_size));
+            }
+        }
+
+        let mut appended = last_leaf;
+        appended.extend_from_slice(&content[41..]);
+        blocks.extend(adder.collect_blocks(&appended, 0));
+
+        assert_eq!(blocks.last().unwrap().0, expected.last().unwrap().0);
+
+        // the previous blocks were linked to, not created again
+        assert!(blocks.iter().all(|(cid, _)| !previous.contains_key(cid)));
+    }
+
     #[test]
     fn favourite_single_block_file() {
         let blocks = FakeBlockstore::with_fixtures();
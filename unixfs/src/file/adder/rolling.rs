@@ -0,0 +1,400 @@
+//! Content defined chunking with rolling hashes, following the go-ipfs chunkers.
+//!
+//! Both of the hashes are only functions of the last `WINDOW` bytes, so the state does not need
+//! to be kept between the calls: it is recomputed from the tail of the already buffered bytes.
+
+/// The irreducible polynomial used by the go-ipfs rabin chunker.
+const RABIN_POLYNOMIAL: u64 = 0x3D_F305_DFB2_A805;
+
+/// Degree of [`RABIN_POLYNOMIAL`] less the 8 bits shifted in per byte.
+const RABIN_POLYNOMIAL_SHIFT: u32 = degree(RABIN_POLYNOMIAL) as u32 - 8;
+
+/// Rabin fingerprint lookup tables, for sliding bytes out of the window and for the reduction
+/// modulo the polynomial.
+const RABIN_TABLES: ([u64; 256], [u64; 256]) = rabin_tables();
+
+/// The smallest chunk created by the buzhash chunker.
+pub(super) const BUZHASH_MIN: usize = 128 * 1024;
+
+/// The largest chunk created by the buzhash chunker.
+pub(super) const BUZHASH_MAX: usize = 512 * 1024;
+
+/// Chunk boundary is found when these bits of the buzhash are zero.
+const BUZHASH_MASK: u32 = (1 << 17) - 1;
+
+/// Pseudorandom values for every byte, for the buzhash.
+///
+/// Note: go-ipfs uses a hardcoded table of its own which is not reproduced here, so the buzhash
+/// chunk boundaries will not match go-ipfs.
+const BUZHASH_TABLE: [u32; 256] = buzhash_table();
+
+/// Returns the amount of `input` to accept into the chunk which already contains `buffered`,
+/// and whether the chunk is complete after that.
+pub(super) fn rabin_cut(
+    input: &[u8],
+    buffered: &[u8],
+    min: usize,
+    avg: usize,
+    max: usize,
+) -> (usize, bool) {
+    // the same as go-ipfs, the average is rounded down to the closest power of two
+    let bits = 63 - (avg as u64).leading_zeros();
+    let hash = Rabin {
+        digest: 0,
+        mask: (1 << bits) - 1,
+    };
+    find_cut(hash, input, buffered, min, max)
+}
+
+/// Returns the amount of `input` to accept into the chunk which already contains `buffered`,
+/// and whether the chunk is complete after that.
+pub(super) fn buzhash_cut(input: &[u8], buffered: &[u8]) -> (usize, bool) {
+    find_cut(Buzhash(0), input, buffered, BUZHASH_MIN, BUZHASH_MAX)
+}
+
+trait RollingHash {
+    /// The number of the latest bytes the hash is calculated over.
+    const WINDOW: usize;
+
+    /// Appends a byte without removing the oldest one, used to fill the window.
+    fn push(&mut self, incoming: u8);
+
+    /// Slides the window forward by one byte.
+    fn roll(&mut self, outgoing: u8, incoming: u8);
+
+    fn is_boundary(&self) -> bool;
+}
+
+/// Scans for the first boundary at or after `min` bytes into the chunk, cutting at `max` bytes
+/// at the latest.
+fn find_cut<H: RollingHash>(
+    mut hash: H,
+    input: &[u8],
+    buffered: &[u8],
+    min: usize,
+    max: usize,
+) -> (usize, bool) {
+    debug_assert!(H::WINDOW <= min && min <= max);
+
+    let have = buffered.len();
+    let total = have + input.len();
+    let at = |i: usize| {
+        if i < have {
+            buffered[i]
+        } else {
+            input[i - have]
+        }
+    };
+
+    // length of the chunk at the first boundary check
+    let first = min.max(have + 1);
+
+    if total < first {
+        return (input.len(), false);
+    }
+
+    for i in first - H::WINDOW..first {
+        hash.push(at(i));
+    }
+
+    let mut len = first;
+
+    loop {
+        if hash.is_boundary() || len >= max {
+            return (len - have, true);
+        }
+
+        if len == total {
+            return (input.len(), false);
+        }
+
+        hash.roll(at(len - H::WINDOW), at(len));
+        len += 1;
+    }
+}
+
+struct Rabin {
+    digest: u64,
+    mask: u64,
+}
+
+impl RollingHash for Rabin {
+    const WINDOW: usize = 64;
+
+    fn push(&mut self, incoming: u8) {
+        let index = (self.digest >> RABIN_POLYNOMIAL_SHIFT) as usize;
+        self.digest = ((self.digest << 8) | u64::from(incoming)) ^ RABIN_TABLES.1[index];
+    }
+
+    fn roll(&mut self, outgoing: u8, incoming: u8) {
+        self.digest ^= RABIN_TABLES.0[outgoing as usize];
+        self.push(incoming);
+    }
+
+    fn is_boundary(&self) -> bool {
+        self.digest & self.mask == 0
+    }
+}
+
+struct Buzhash(u32);
+
+impl RollingHash for Buzhash {
+    const WINDOW: usize = 32;
+
+    fn push(&mut self, incoming: u8) {
+        self.0 = self.0.rotate_left(1) ^ BUZHASH_TABLE[incoming as usize];
+    }
+
+    fn roll(&mut self, outgoing: u8, incoming: u8) {
+        // the outgoing byte has been rotated a full circle
+        self.push(incoming);
+        self.0 ^= BUZHASH_TABLE[outgoing as usize];
+    }
+
+    fn is_boundary(&self) -> bool {
+        self.0 & BUZHASH_MASK == 0
+    }
+}
+
+/// Degree of the polynomial, or -1 for zero.
+const fn degree(x: u64) -> i32 {
+    63 - x.leading_zeros() as i32
+}
+
+/// Remainder of the polynomial division.
+const fn modulo(mut x: u64, p: u64) -> u64 {
+    let dp = degree(p);
+    while degree(x) >= dp {
+        x ^= p << (degree(x) - dp) as u32;
+    }
+    x
+}
+
+const fn append_byte(hash: u64, b: u8, p: u64) -> u64 {
+    modulo((hash << 8) | b as u64, p)
+}
+
+const fn rabin_tables() -> ([u64; 256], [u64; 256]) {
+    let mut out = [0u64; 256];
+    let mut reduce = [0u64; 256];
+    let k = degree(RABIN_POLYNOMIAL) as u32;
+
+    let mut b = 0;
+    while b < 256 {
+        // out[b] = hash of the byte followed by the rest of the window as zeroes, so that
+        // xoring it to the digest removes the byte from the window
+        let mut h = append_byte(0, b as u8, RABIN_POLYNOMIAL);
+        let mut i = 0;
+        while i < 64 - 1 {
+            h = append_byte(h, 0, RABIN_POLYNOMIAL);
+            i += 1;
+        }
+        out[b] = h;
+
+        // reduce[b] both reduces the bits shifted over the degree and clears them
+        let shifted = (b as u64) << k;
+        reduce[b] = modulo(shifted, RABIN_POLYNOMIAL) | shifted;
+        b += 1;
+    }
+
+    (out, reduce)
+}
+
+const fn buzhash_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    // xorshift32 with a fixed seed
+    let mut state = 0x6236_e7d5u32;
+    let mut i = 0;
+    while i < 256 {
+        table[i] = state;
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        i += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        buzhash_cut, rabin_cut, Rabin, RollingHash, BUZHASH_MASK, BUZHASH_MAX, BUZHASH_MIN,
+        BUZHASH_TABLE, RABIN_POLYNOMIAL, RABIN_POLYNOMIAL_SHIFT,
+    };
+
+    /// The rabin fingerprint of the window by the definition: the bits of the window as a
+    /// polynomial over GF(2), reduced modulo the polynomial one bit at a time.
+    fn fingerprint(window: &[u8]) -> u64 {
+        let top = 1u64 << (63 - RABIN_POLYNOMIAL.leading_zeros());
+        let mut rem = 0u64;
+        for &b in window {
+            for bit in (0..8).rev() {
+                rem = (rem << 1) | u64::from((b >> bit) & 1);
+                if rem & top != 0 {
+                    rem ^= RABIN_POLYNOMIAL;
+                }
+            }
+        }
+        rem
+    }
+
+    /// Chunk lengths by checking the fingerprint of the 64 bytes ending at every candidate
+    /// position, without any rolling or lookup tables.
+    fn reference_rabin(data: &[u8], min: usize, avg: usize, max: usize) -> Vec<usize> {
+        let mask = (1u64 << (63 - (avg as u64).leading_zeros())) - 1;
+        let mut chunks = Vec::new();
+        let mut start = 0;
+
+        while start < data.len() {
+            let remaining = data.len() - start;
+            let mut len = min.min(remaining);
+            while len < remaining.min(max) {
+                let end = start + len;
+                if fingerprint(&data[end - 64..end]) & mask == 0 {
+                    break;
+                }
+                len += 1;
+            }
+
+            chunks.push(len);
+            start += len;
+        }
+
+        chunks
+    }
+
+    /// Chunk lengths following the go-ipfs buzhash loop: the hash is filled with the 32 bytes
+    /// before the minimum length and then rolled until a boundary, the end or the maximum length.
+    fn reference_buzhash(data: &[u8]) -> Vec<usize> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+
+        while start < data.len() {
+            let buf = &data[start..data.len().min(start + BUZHASH_MAX)];
+
+            if buf.len() <= BUZHASH_MIN {
+                chunks.push(buf.len());
+                break;
+            }
+
+            let mut state = 0u32;
+            let mut i = BUZHASH_MIN - 32;
+            while i < BUZHASH_MIN {
+                state = state.rotate_left(1) ^ BUZHASH_TABLE[buf[i] as usize];
+                i += 1;
+            }
+            while state & BUZHASH_MASK != 0 && i < buf.len() {
+                state = state.rotate_left(1)
+                    ^ BUZHASH_TABLE[buf[i - 32] as usize]
+                    ^ BUZHASH_TABLE[buf[i] as usize];
+                i += 1;
+            }
+
+            chunks.push(i);
+            start += i;
+        }
+
+        chunks
+    }
+
+    fn chunk_with(
+        data: &[u8],
+        step: usize,
+        cut: impl Fn(&[u8], &[u8]) -> (usize, bool),
+    ) -> Vec<usize> {
+        let mut chunks = Vec::new();
+        let mut buffered = Vec::new();
+        let mut remaining = data;
+
+        while !remaining.is_empty() {
+            let input = &remaining[..step.min(remaining.len())];
+            let (accepted, ready) = cut(input, &buffered);
+            buffered.extend_from_slice(&input[..accepted]);
+            remaining = &remaining[accepted..];
+            if ready {
+                chunks.push(buffered.len());
+                buffered.clear();
+            }
+        }
+
+        if !buffered.is_empty() {
+            chunks.push(buffered.len());
+        }
+
+        chunks
+    }
+
+    fn pseudorandom(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rabin_digest_stays_reduced() {
+        let mut hash = Rabin { digest: 0, mask: 0 };
+        for b in pseudorandom(4096) {
+            hash.push(b);
+            assert!(hash.digest >> RABIN_POLYNOMIAL_SHIFT < 256);
+        }
+    }
+
+    #[test]
+    fn rabin_matches_reference() {
+        let data = pseudorandom(64 * 1024);
+        let (min, avg, max) = (256, 1024, 2048);
+        let expected = reference_rabin(&data, min, avg, max);
+
+        assert!(expected.len() > 10);
+        assert!(expected.iter().all(|&len| len <= max));
+
+        for &step in &[1, 63, 1000, 4096, 100_000] {
+            let chunks = chunk_with(&data, step, |input, buffered| {
+                rabin_cut(input, buffered, min, avg, max)
+            });
+            assert_eq!(chunks, expected, "step {}", step);
+        }
+    }
+
+    #[test]
+    fn buzhash_matches_reference() {
+        let data = pseudorandom(3 * 1024 * 1024);
+        let expected = reference_buzhash(&data);
+
+        assert!(expected.len() > 3);
+        assert_eq!(expected.iter().sum::<usize>(), data.len());
+        assert!(expected[..expected.len() - 1]
+            .iter()
+            .all(|len| (BUZHASH_MIN..=BUZHASH_MAX).contains(len)));
+        // at least one of the chunks needs to be cut by the hash for this to be interesting
+        assert!(expected[..expected.len() - 1]
+            .iter()
+            .any(|&len| len != BUZHASH_MIN && len != BUZHASH_MAX));
+
+        for &step in &[1000, 65536, 300_000, data.len()] {
+            assert_eq!(
+                chunk_with(&data, step, buzhash_cut),
+                expected,
+                "step {}",
+                step
+            );
+        }
+    }
+
+    #[test]
+    fn rabin_cuts_zeroes_at_min() {
+        let data = vec![0u8; 10_000];
+        // the digest over zeroes is zero, so every position is a boundary
+        let chunks = chunk_with(&data, 777, |input, buffered| {
+            rabin_cut(input, buffered, 100, 128, 200)
+        });
+        assert_eq!(chunks.iter().sum::<usize>(), data.len());
+        assert!(chunks[..chunks.len() - 1].iter().all(|&len| len == 100));
+    }
+}
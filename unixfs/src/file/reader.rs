@@ -79,6 +79,25 @@ impl<'a> FileReader<'a> {
         Self::from_parts(inner, 0, metadata)
     }
 
+    /// Method for starting the traversal of a file consisting of a single raw block, which has
+    /// no UnixFs wrapping.
+    pub fn from_raw_block(data: &'a [u8]) -> Self {
+        Self::from_raw(data, 0, Metadata::default(), data.len() as u64)
+    }
+
+    /// Raw blocks are all content, and cannot have any links.
+    fn from_raw(data: &'a [u8], offset: u64, metadata: Metadata, file_size: u64) -> Self {
+        Self {
+            offset,
+            end: Ending::Chunk(offset + data.len() as u64),
+            links: Vec::new(),
+            data,
+            blocksizes: Vec::new(),
+            metadata,
+            file_size,
+        }
+    }
+
     pub(crate) fn from_parsed(inner: FlatUnixFs<'a>) -> Result<Self, FileReadFailed> {
         let metadata = Metadata::from(&inner.data);
         Self::from_parts(inner, 0, metadata)
@@ -212,6 +231,23 @@ impl Traversal {
         FileReader::from_continued(self, tree_range.start, next_block)
     }
 
+    /// Continues the walk on the merkle tree with the contents of a raw leaf block, which is
+    /// the file content as is. See [`Traversal::continue_walk`].
+    pub fn continue_raw_walk<'a>(
+        self,
+        next_block: &'a [u8],
+        tree_range: &Range<u64>,
+    ) -> Result<FileReader<'a>, FileReadFailed> {
+        self.last_ending
+            .check_is_suitable_next(self.last_offset, tree_range)?;
+        Ok(FileReader::from_raw(
+            next_block,
+            tree_range.start,
+            self.metadata,
+            self.file_size,
+        ))
+    }
+
     /// Returns the total size of the file.
     pub fn file_size(&self) -> u64 {
         self.file_size
@@ -1,4 +1,4 @@
-use cid::Cid;
+use cid::{Cid, Codec};
 use core::convert::TryFrom;
 use core::ops::Range;
 
@@ -34,6 +34,13 @@ impl IdleFileVisit {
         self.start_from_reader(fr, &mut None)
     }
 
+    /// Begins the visitation of a file which is a single raw block, as files added with raw
+    /// leaves are when they fit into a single chunk.
+    pub fn start_raw(self, block: &'_ [u8]) -> Result<FileVisitResult<'_>, FileReadFailed> {
+        let fr = FileReader::from_raw_block(block);
+        self.start_from_reader(fr, &mut None)
+    }
+
     pub(crate) fn start_from_parsed<'a>(
         self,
         block: FlatUnixFs<'a>,
@@ -145,6 +152,16 @@ impl FileVisit {
         (first, iter)
     }
 
+    /// Returns the byte range of the file covered by the first `pending_link`, relative to the
+    /// block the visit was started from.
+    pub fn pending_range(&self) -> &Range<u64> {
+        &self
+            .pending
+            .last()
+            .expect("the presence of links has been validated")
+            .1
+    }
+
     /// Continues the walk with the data for the first `pending_link` key.
     ///
     /// Returns on success a tuple of bytes and new version of `FileVisit` to continue the visit,
@@ -155,13 +172,17 @@ impl FileVisit {
         cache: &mut Option<Cache>,
     ) -> Result<(&'a [u8], Option<Self>), FileReadFailed> {
         let traversal = self.state;
-        let (_, range) = self
+        let (cid, range) = self
             .pending
             .pop()
             .expect("User called continue_walk there must have been a next link");
 
         // interesting, validation doesn't trigger if the range is the same?
-        let fr = traversal.continue_walk(next, &range)?;
+        let fr = if cid.codec() == Codec::Raw {
+            traversal.continue_raw_walk(next, &range)?
+        } else {
+            traversal.continue_walk(next, &range)?
+        };
         let (content, traversal) = fr.content();
         match content {
             FileContent::Bytes(content) => {
@@ -223,12 +244,13 @@ fn to_pending(
 }
 
 /// Returns true if the blocks byte offsets are interesting for our target range, false otherwise.
-/// If there is no target, all blocks are of interest.
+/// If there is no target, all blocks are of interest. The ranges are half-open so a block ending
+/// where the target starts is not interesting.
 fn block_is_in_target_range(block: &Range<u64>, target: Option<&Range<u64>>) -> bool {
     use core::cmp::{max, min};
 
     if let Some(target) = target {
-        max(block.start, target.start) <= min(block.end, target.end)
+        max(block.start, target.start) < min(block.end, target.end)
     } else {
         true
     }
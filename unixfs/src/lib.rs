@@ -163,6 +163,36 @@ impl Metadata {
         self.mtime()
             .map(|(seconds, nanos)| filetime::FileTime::from_unix_time(seconds, nanos))
     }
+
+    /// Returns the metadata with the given full file mode; see [`Metadata::mode`].
+    pub fn with_mode(self, mode: u32) -> Self {
+        Metadata {
+            mode: Some(mode),
+            ..self
+        }
+    }
+
+    /// Returns the metadata with the given modification time; see [`Metadata::mtime`].
+    pub fn with_mtime(self, seconds: i64, nanos: u32) -> Self {
+        Metadata {
+            mtime: Some((seconds, nanos)),
+            ..self
+        }
+    }
+
+    /// Returns true if neither mode nor mtime has been specified.
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.mtime.is_none()
+    }
+
+    /// Writes the metadata to the UnixFs node.
+    pub(crate) fn apply(&self, data: &mut UnixFs<'_>) {
+        data.mode = self.mode;
+        data.mtime = self.mtime.map(|(seconds, nanos)| pb::unixfs::UnixTime {
+            Seconds: seconds,
+            FractionalNanoseconds: if nanos > 0 { Some(nanos) } else { None },
+        });
+    }
 }
 
 impl<'a> From<&'a UnixFs<'_>> for Metadata {
@@ -176,3 +206,108 @@ impl<'a> From<&'a UnixFs<'_>> for Metadata {
         Metadata { mode, mtime }
     }
 }
+
+/// The kinds of UnixFs nodes, as returned by [`node_info`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    /// A file of one or more blocks.
+    File,
+    /// A directory with all of the entries as the links of the root node.
+    Directory,
+    /// A HAMT sharded directory, where the entries are spread over multiple blocks.
+    ShardedDirectory,
+    /// A symlink.
+    Symlink,
+}
+
+/// What can be known about an UnixFs tree from its root node, see [`node_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeInfo {
+    /// The kind of the node.
+    pub kind: NodeKind,
+    /// The size of the file contents in bytes, zero for the other kinds.
+    pub filesize: u64,
+    /// The metadata stored in the node.
+    pub metadata: Metadata,
+}
+
+/// Reads the kind, file size and metadata of the UnixFs node in the given dag-pb block. Raw leaves
+/// are files of their own length and carry no metadata, so they need no parsing.
+pub fn node_info(block: &[u8]) -> Result<NodeInfo, walk::Error> {
+    use core::convert::TryFrom;
+
+    let flat = pb::FlatUnixFs::try_from(block)?;
+
+    let kind = match flat.data.Type {
+        UnixFsType::Raw | UnixFsType::File => NodeKind::File,
+        UnixFsType::Directory => NodeKind::Directory,
+        UnixFsType::HAMTShard => NodeKind::ShardedDirectory,
+        UnixFsType::Symlink => NodeKind::Symlink,
+        other => return Err(walk::Error::UnsupportedType(other.into())),
+    };
+
+    let filesize = match kind {
+        NodeKind::File => flat.data.filesize.unwrap_or_else(|| {
+            flat.data
+                .Data
+                .as_ref()
+                .map(|data| data.len() as u64)
+                .unwrap_or(0)
+        }),
+        _ => 0,
+    };
+
+    Ok(NodeInfo {
+        kind,
+        filesize,
+        metadata: Metadata::from(&flat.data),
+    })
+}
+
+/// Builds the Cids for the created blocks, following the configured Cid version and inlining.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CidBuilder {
+    version: cid::Version,
+    /// Blocks up to this size are inlined in identity hashed Cids.
+    inline_limit: Option<usize>,
+}
+
+impl Default for CidBuilder {
+    fn default() -> Self {
+        CidBuilder {
+            version: cid::Version::V0,
+            inline_limit: None,
+        }
+    }
+}
+
+impl CidBuilder {
+    pub(crate) fn new(version: cid::Version, inline_limit: Option<usize>) -> Self {
+        CidBuilder {
+            version,
+            inline_limit,
+        }
+    }
+
+    /// Returns the Cid for the block. Inlined blocks and the blocks with other codecs than dag-pb
+    /// always get Cid version 1, as version 0 only supports sha2-256 hashed dag-pb.
+    pub(crate) fn sum(&self, codec: cid::Codec, block: &[u8]) -> cid::Cid {
+        use sha2::{Digest, Sha256};
+
+        match self.inline_limit {
+            Some(limit) if block.len() <= limit => {
+                let mh = multihash::wrap(multihash::Code::Identity, block);
+                cid::Cid::new_v1(codec, mh)
+            }
+            _ => {
+                let mh = multihash::wrap(multihash::Code::Sha2_256, &Sha256::digest(block));
+                match (self.version, codec) {
+                    (cid::Version::V0, cid::Codec::DagProtobuf) => {
+                        cid::Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0")
+                    }
+                    _ => cid::Cid::new_v1(codec, mh),
+                }
+            }
+        }
+    }
+}
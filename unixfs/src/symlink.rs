@@ -4,6 +4,7 @@
 //! this is wrong.
 
 use crate::pb::{FlatUnixFs, UnixFs, UnixFsType};
+use crate::Metadata;
 use alloc::borrow::Cow;
 use quick_protobuf::{MessageWrite, Writer};
 
@@ -11,18 +12,31 @@ use quick_protobuf::{MessageWrite, Writer};
 /// `target_path` is valid relative unix path relative to the place in which this is used but
 /// targets validity cannot really be judged.
 pub fn serialize_symlink_block(target_path: &str, block_buffer: &mut Vec<u8>) {
+    serialize_symlink_block_with_metadata(target_path, &Metadata::default(), block_buffer)
+}
+
+/// Appends a dag-pb block for a symlink like [`serialize_symlink_block`], storing the mode and
+/// modification time of the symlink as well.
+pub fn serialize_symlink_block_with_metadata(
+    target_path: &str,
+    metadata: &Metadata,
+    block_buffer: &mut Vec<u8>,
+) {
     // should this fail or not? protobuf encoding cannot fail here, however we might create a too
     // large block but what's the limit?
     //
     // why not return a (Cid, Vec<u8>) like usually with cidv0? well...
 
+    let mut data = UnixFs {
+        Type: UnixFsType::Symlink,
+        Data: Some(Cow::Borrowed(target_path.as_bytes())),
+        ..Default::default()
+    };
+    metadata.apply(&mut data);
+
     let node = FlatUnixFs {
         links: Vec::new(),
-        data: UnixFs {
-            Type: UnixFsType::Symlink,
-            Data: Some(Cow::Borrowed(target_path.as_bytes())),
-            ..Default::default()
-        },
+        data,
     };
 
     let mut writer = Writer::new(block_buffer);
@@ -54,6 +68,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn symlink_with_metadata() {
+        use super::serialize_symlink_block_with_metadata;
+        use crate::pb::FlatUnixFs;
+        use crate::Metadata;
+
+        let metadata = Metadata::default()
+            .with_mode(0o755)
+            .with_mtime(1_600_000_000, 5);
+
+        let mut buf = Vec::new();
+        serialize_symlink_block_with_metadata("b", &metadata, &mut buf);
+
+        let flat = FlatUnixFs::try_from(&buf[..]).unwrap();
+        assert_eq!(flat.data.Data.as_deref(), Some(&b"b"[..]));
+        assert_eq!(Metadata::from(&flat.data), metadata);
+
+        let mut plain = Vec::new();
+        serialize_symlink_block("b", &mut plain);
+        assert_ne!(plain, buf);
+    }
+
     #[test]
     fn symlinks_in_trees_rooted() {
         use crate::dir::builder::BufferingTreeBuilder;
@@ -4,7 +4,7 @@ use crate::file::{FileError, FileReadFailed};
 use crate::pb::{FlatUnixFs, PBLink, ParsingFailed, UnixFsType};
 use crate::{InvalidCidInLink, Metadata, UnexpectedNodeType};
 use alloc::borrow::Cow;
-use cid::Cid;
+use cid::{Cid, Codec};
 use core::convert::TryFrom;
 use core::fmt;
 use either::Either;
@@ -150,11 +150,26 @@ impl Walker {
             return Ok(ContinuedWalk::File(segment, cid, path, metadata, *sz));
         }
 
-        let flat = FlatUnixFs::try_from(bytes)?;
-        let metadata = Metadata::from(&flat.data);
+        // raw leaves are files of a single block, without the UnixFs wrapping
+        let raw = next
+            .as_ref()
+            .map(|(cid, ..)| cid.codec() == Codec::Raw)
+            .unwrap_or(false);
 
-        match flat.data.Type {
+        let flat = if raw {
+            None
+        } else {
+            Some(FlatUnixFs::try_from(bytes)?)
+        };
+
+        let (node_type, metadata) = match flat.as_ref() {
+            Some(flat) => (flat.data.Type, Metadata::from(&flat.data)),
+            None => (UnixFsType::Raw, Metadata::default()),
+        };
+
+        match node_type {
             UnixFsType::Directory => {
+                let flat = flat.expect("only raw leaves are not parsed");
                 let flat = crate::dir::check_directory_supported(flat)?;
                 let (cid, name, depth) = next.take().expect("validated at new and earlier");
 
@@ -191,6 +206,7 @@ impl Walker {
                 })
             }
             UnixFsType::HAMTShard => {
+                let flat = flat.expect("only raw leaves are not parsed");
                 let flat = crate::dir::check_hamtshard_supported(flat)?;
                 let (cid, name, depth) = next.take().expect("validated at start and this method");
 
@@ -236,8 +252,10 @@ impl Walker {
                 })
             }
             UnixFsType::Raw | UnixFsType::File => {
-                let (bytes, file_size, metadata, step) =
-                    IdleFileVisit::default().start_from_parsed(flat, cache)?;
+                let (bytes, file_size, metadata, step) = match flat {
+                    Some(flat) => IdleFileVisit::default().start_from_parsed(flat, cache)?,
+                    None => IdleFileVisit::default().start_raw(bytes)?,
+                };
                 let (cid, name, depth) = next.take().expect("validated at new and earlier");
                 let file_continues = step.is_some();
 
@@ -269,8 +287,9 @@ impl Walker {
                     file_size,
                 ))
             }
-            UnixFsType::Metadata => Err(Error::UnsupportedType(flat.data.Type.into())),
+            UnixFsType::Metadata => Err(Error::UnsupportedType(node_type.into())),
             UnixFsType::Symlink => {
+                let flat = flat.expect("only raw leaves are not parsed");
                 let contents = match flat.data.Data {
                     Some(Cow::Borrowed(bytes)) if !bytes.is_empty() => bytes,
                     None | Some(Cow::Borrowed(_)) => &[][..],
@@ -309,6 +328,19 @@ impl Walker {
         self.should_continue
     }
 
+    /// Skips the remaining blocks of the file being walked, if any, continuing the walk from the
+    /// next entry. Useful when the contents of the file are not needed after its first block.
+    pub fn skip_current_file(&mut self) {
+        if let Some(InnerEntry {
+            kind: InnerKind::File(visit @ Some(_), _),
+            ..
+        }) = &mut self.current
+        {
+            *visit = None;
+            self.should_continue = self.next.is_some();
+        }
+    }
+
     // TODO: we could easily split a 'static value for a directory or bucket, which would pop all
     // entries at a single level out to do some parallel walking, though the skipping could already
     // be used to do that... Maybe we could return the filevisit on Skipped to save user from
@@ -902,6 +934,59 @@ mod tests {
         }
     }
 
+    #[test]
+    fn skipping_files() {
+        let blocks = FakeBlockstore::with_fixtures();
+
+        let cid = cid::Cid::try_from("QmPTotyhVnnfCu9R4qwR4cdhpi5ENaiP8ZJfdqsm8Dw2jB").unwrap();
+        let mut walker = Walker::new(cid, String::new());
+        let mut files = Vec::new();
+
+        while walker.should_continue() {
+            let (next, _) = walker.pending_links();
+            let block = blocks.get_by_cid(next);
+
+            if let ContinuedWalk::File(segment, _, path, ..) =
+                walker.next(block, &mut None).unwrap()
+            {
+                assert!(segment.is_first());
+                files.push(path.to_owned());
+                walker.skip_current_file();
+            }
+        }
+
+        assert_eq!(
+            files,
+            &[
+                Path::new("QmVkvLsSEm2uJx1h5Fqukje8mMPYg393o5C2kMCkF2bBTA/foobar.balanced"),
+                Path::new("QmVkvLsSEm2uJx1h5Fqukje8mMPYg393o5C2kMCkF2bBTA/foobar.trickle"),
+            ]
+        );
+    }
+
+    #[test]
+    fn top_level_raw_leaf_file() {
+        use sha2::{Digest, Sha256};
+
+        let block = b"foobar\n";
+        let mh = multihash::wrap(multihash::Code::Sha2_256, &Sha256::digest(block));
+        let cid = Cid::new_v1(Codec::Raw, mh);
+
+        let mut walker = Walker::new(cid, String::new());
+
+        match walker.next(block, &mut None).unwrap() {
+            ContinuedWalk::File(segment, _, _, _, size) => {
+                assert_eq!(segment.as_bytes(), block);
+                assert!(segment.is_first());
+                assert!(segment.is_last());
+                assert_eq!(size, block.len() as u64);
+            }
+            x => unreachable!("{:?}", x),
+        }
+
+        assert!(!walker.should_continue());
+    }
+
     trait CountsExt {
         fn checked_removal(&mut self, key: &Path, expected: usize);
     }